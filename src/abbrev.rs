@@ -1,29 +1,25 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The largest grapheme cluster boundary at or before `index`, so we never
+/// cut a family emoji, flag or combining sequence in half
 #[inline]
-fn floor_char_boundary(s: &str, index: usize) -> usize {
+fn floor_grapheme_boundary(s: &str, index: usize) -> usize {
     if index >= s.len() {
         s.len()
     } else {
-        let lower_bound = index.saturating_sub(3);
-        let new_index = s.as_bytes()[lower_bound..=index]
-            .iter()
-            .rposition(|b| is_utf8_char_boundary(*b));
-
-        // SAFETY: we know that the character boundary will be within four bytes
-        unsafe { lower_bound + new_index.unwrap_unchecked() }
+        s.grapheme_indices(true)
+            .map(|(i, _)| i)
+            .take_while(|i| *i <= index)
+            .last()
+            .unwrap_or(0)
     }
 }
 
-#[inline]
-fn is_utf8_char_boundary(c: u8) -> bool {
-    // This is bit magic equivalent to: b < 128 || b >= 192
-    (c as i8) >= -0x40
-}
-
 const ABBREV_SIZE: usize = 10;
 
 pub fn abbrev_str(name: &str) -> String {
     if name.len() > ABBREV_SIZE {
-        let closest = floor_char_boundary(name, ABBREV_SIZE);
+        let closest = floor_grapheme_boundary(name, ABBREV_SIZE);
         format!("{}...", &name[..closest])
     } else {
         name.to_owned()
@@ -31,6 +27,28 @@ pub fn abbrev_str(name: &str) -> String {
 }
 
 pub fn abbreviate(text: &str, len: usize) -> &str {
-    let closest = floor_char_boundary(text, len);
+    let closest = floor_grapheme_boundary(text, len);
     &text[..closest]
 }
+
+/// Collapse whitespace (newlines included) to single spaces, drop control
+/// characters and cut to at most `max_bytes`, ending in an ellipsis when
+/// something was cut
+pub fn summarize(text: &str, max_bytes: usize) -> String {
+    const ELLIPSIS: &str = "…";
+
+    let collapsed: String = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect();
+
+    if collapsed.len() <= max_bytes {
+        return collapsed;
+    }
+
+    let cut = floor_grapheme_boundary(&collapsed, max_bytes.saturating_sub(ELLIPSIS.len()));
+    format!("{}{ELLIPSIS}", collapsed[..cut].trim_end())
+}
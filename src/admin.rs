@@ -0,0 +1,58 @@
+use crate::{db_stats, Error, Notecrumbs};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, Request, Response, StatusCode};
+use std::path::PathBuf;
+
+/// Compare without bailing on the first mismatch, so response timing
+/// doesn't leak how much of the token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Admin routes need `Authorization: Bearer <NOTECRUMBS_ADMIN_TOKEN>`.
+/// Without a configured token they don't exist at all.
+pub fn is_authorized<B>(app: &Notecrumbs, r: &Request<B>) -> bool {
+    let token = if let Some(token) = &app.config.admin_token {
+        token
+    } else {
+        return false;
+    };
+
+    r.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+        .unwrap_or(false)
+}
+
+pub fn unauthorized() -> Result<Response<Full<Bytes>>, Error> {
+    Ok(Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Full::new(Bytes::from("Invalid url\n")))?)
+}
+
+fn json_response(body: Vec<u8>) -> Result<Response<Full<Bytes>>, Error> {
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(body)))?)
+}
+
+/// `/admin/unresolved`: notes and profiles we keep failing to find
+pub fn serve_unresolved(app: &Notecrumbs) -> Result<Response<Full<Bytes>>, Error> {
+    json_response(serde_json::to_vec(&app.unresolved.entries())?)
+}
+
+/// `/admin/db`: how much we've cached in ndb
+pub async fn serve_db(app: &Notecrumbs) -> Result<Response<Full<Bytes>>, Error> {
+    let ndb = app.ndb.clone();
+    let dir = PathBuf::from(&app.config.ndb_dir);
+    let stats = tokio::task::spawn_blocking(move || db_stats::db_stats(&ndb, &dir))
+        .await
+        .map_err(|err| Error::Generic(err.to_string()))?;
+    json_response(serde_json::to_vec(&stats)?)
+}
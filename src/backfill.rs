@@ -0,0 +1,323 @@
+use crate::{
+    error::Result,
+    fetch, metrics,
+    preview_prefs::preview_prefs_filter,
+    relay_health::RelayHealth,
+    render::{convert_filter, fetch_events},
+};
+use lru::LruCache;
+use nostr::types::Timestamp;
+use nostr_sdk::prelude::{EventId, Keys, SyncOptions};
+use nostrdb::{Filter, Ndb, Transaction};
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tracing::{debug, info, warn};
+
+/// How many notes a backfill asks for
+const BACKFILL_LIMIT: u64 = 200;
+
+/// How far back a backfill looks
+const BACKFILL_LOOKBACK_SECS: u64 = 60 * 60 * 24 * 90;
+
+/// Don't backfill the same profile more often than this, no matter how
+/// often it's viewed
+const BACKFILL_COOLDOWN: Duration = Duration::from_secs(60 * 60);
+
+/// How long we let relays stream a backfill before giving up
+const BACKFILL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Event ids per filter when asking for the notes negentropy found, relays
+/// limit how big a filter may be
+const IDS_PER_FILTER: usize = 50;
+
+/// What negentropy told us: the relays that reconciled, and the ids they
+/// have that we don't
+#[derive(Default)]
+struct Reconciled {
+    relays: Vec<String>,
+    missing: HashSet<EventId>,
+}
+
+/// Which relays to backfill from, and what to ask each group of them for.
+/// Relays that reconciled are only asked for the notes we're missing, at
+/// most a backfill's worth, the others for everything matching `notes`.
+/// `always` goes to every relay.
+fn backfill_requests(
+    relays: Vec<String>,
+    reconciled: Reconciled,
+    notes: nostr::Filter,
+    always: Vec<nostr::Filter>,
+) -> Vec<(Vec<String>, Vec<nostr::Filter>)> {
+    let (synced, unsynced): (Vec<String>, Vec<String>) = relays
+        .into_iter()
+        .partition(|relay| reconciled.relays.contains(relay));
+
+    let mut requests = vec![];
+    if !synced.is_empty() {
+        let missing: Vec<EventId> = reconciled
+            .missing
+            .into_iter()
+            .take(BACKFILL_LIMIT as usize)
+            .collect();
+        let mut filters = always.clone();
+        filters.extend(
+            missing
+                .chunks(IDS_PER_FILTER)
+                .map(|ids| nostr::Filter::new().ids(ids.iter().copied())),
+        );
+        requests.push((synced, filters));
+    }
+    if !unsynced.is_empty() {
+        let mut filters = always;
+        filters.push(notes);
+        requests.push((unsynced, filters));
+    }
+
+    requests
+}
+
+/// Fetches deeper profile feeds in the background, one at a time, so
+/// they never compete with requests that are waiting on the relays
+pub struct Backfiller {
+    ndb: Ndb,
+    keys: Keys,
+    relays: Arc<RelayHealth>,
+    recent: Mutex<LruCache<[u8; 32], Instant>>,
+    running: Semaphore,
+}
+
+impl Backfiller {
+    pub fn new(ndb: Ndb, keys: Keys, relays: Arc<RelayHealth>) -> Self {
+        Backfiller {
+            ndb,
+            keys,
+            relays,
+            recent: Mutex::new(LruCache::new(NonZeroUsize::new(4096).unwrap())),
+            running: Semaphore::new(1),
+        }
+    }
+
+    /// Queue a backfill of a profile's notes. Cheap enough to call on
+    /// every view, profiles we backfilled recently are skipped.
+    pub fn schedule(self: &Arc<Self>, pubkey: [u8; 32]) {
+        {
+            let mut recent = self.recent.lock().unwrap();
+            if let Some(at) = recent.get(&pubkey) {
+                if at.elapsed() < BACKFILL_COOLDOWN {
+                    return;
+                }
+            }
+            recent.put(pubkey, Instant::now());
+        }
+
+        let backfiller = self.clone();
+        tokio::spawn(async move {
+            let _permit = match backfiller.running.acquire().await {
+                Ok(permit) => permit,
+                Err(_) => return,
+            };
+
+            if let Err(err) = backfiller.backfill(&pubkey).await {
+                warn!("backfill of {} failed: {err}", hex::encode(pubkey));
+            }
+        });
+    }
+
+    /// Ask the relays which of the notes matching `filter` we don't have,
+    /// by negentropy reconciliation (NIP-77) against the ones we do
+    async fn reconcile(&self, relays: &[String], filter: &Filter) -> Reconciled {
+        let mut reconciled = Reconciled::default();
+        let items: Vec<(EventId, Timestamp)> = {
+            let txn = match Transaction::new(&self.ndb) {
+                Ok(txn) => txn,
+                Err(_) => return reconciled,
+            };
+            match self
+                .ndb
+                .query(&txn, std::slice::from_ref(filter), BACKFILL_LIMIT as i32)
+            {
+                Ok(results) => results
+                    .iter()
+                    .map(|result| {
+                        (
+                            EventId::from_byte_array(*result.note.id()),
+                            Timestamp::from(result.note.created_at()),
+                        )
+                    })
+                    .collect(),
+                Err(_) => return reconciled,
+            }
+        };
+
+        let client = fetch::relay_client(self.keys.clone());
+        for relay in relays {
+            let _ = client.add_relay(relay).await;
+        }
+        client
+            .connect_with_timeout(Duration::from_millis(800))
+            .await;
+        let _open = metrics::OpenRelays::count(&client).await;
+
+        let opts = SyncOptions::default().dry_run();
+        for url in relays {
+            let relay = match client.relay(url.as_str()).await {
+                Ok(relay) => relay,
+                Err(_) => continue,
+            };
+            match relay
+                .sync_with_items(convert_filter(filter), items.clone(), &opts)
+                .await
+            {
+                Ok(reconciliation) => {
+                    reconciled.relays.push(url.clone());
+                    reconciled.missing.extend(reconciliation.remote);
+                }
+                Err(err) => debug!("no negentropy with {url}: {err}"),
+            }
+        }
+
+        let _ = client.disconnect().await;
+
+        reconciled
+    }
+
+    async fn backfill(&self, pubkey: &[u8; 32]) -> Result<()> {
+        let since = Timestamp::now()
+            .as_u64()
+            .saturating_sub(BACKFILL_LOOKBACK_SECS);
+        let notes = Filter::new()
+            .authors([pubkey])
+            .kinds([1])
+            .since(since)
+            .limit(BACKFILL_LIMIT)
+            .build();
+        // the relay list, for the profile relays page
+        let relay_list = Filter::new()
+            .authors([pubkey])
+            .kinds([10002])
+            .limit(1)
+            .build();
+        let preview_prefs = preview_prefs_filter(pubkey);
+
+        debug!("backfilling {}", hex::encode(pubkey));
+
+        // only download the notes we're missing from the relays that can
+        // tell us which ones those are
+        let relays = self.relays.relays();
+        let reconciled = self.reconcile(&relays, &notes).await;
+        let always = vec![convert_filter(&relay_list), convert_filter(&preview_prefs)];
+        let requests = backfill_requests(relays, reconciled, convert_filter(&notes), always);
+
+        let mut count = 0;
+        for (relays, filters) in requests {
+            let described = relays.join(", ");
+            match fetch_events(
+                &self.ndb,
+                self.keys.clone(),
+                relays,
+                filters,
+                BACKFILL_TIMEOUT,
+            )
+            .await
+            {
+                Ok(events) => count += events.len(),
+                Err(err) => warn!("backfill from {described} failed: {err}"),
+            }
+        }
+
+        info!("backfilled {count} notes for {}", hex::encode(pubkey));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(n: u8) -> HashSet<EventId> {
+        (0..n).map(|i| EventId::from_byte_array([i; 32])).collect()
+    }
+
+    fn relays(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(|url| url.to_string()).collect()
+    }
+
+    fn notes() -> nostr::Filter {
+        nostr::Filter::new().kind(nostr::Kind::TextNote).limit(200)
+    }
+
+    fn always() -> Vec<nostr::Filter> {
+        vec![nostr::Filter::new().kind(nostr::Kind::RelayList)]
+    }
+
+    #[test]
+    fn without_negentropy_everything_is_asked_for() {
+        let requests = backfill_requests(
+            relays(&["wss://a", "wss://b"]),
+            Reconciled::default(),
+            notes(),
+            always(),
+        );
+
+        assert_eq!(requests.len(), 1);
+        let (relays, filters) = &requests[0];
+        assert_eq!(relays, &["wss://a", "wss://b"]);
+        assert_eq!(filters, &[always()[0].clone(), notes()]);
+    }
+
+    #[test]
+    fn relays_without_negentropy_still_get_the_notes_filter() {
+        let reconciled = Reconciled {
+            relays: relays(&["wss://a"]),
+            missing: ids(3),
+        };
+        let requests = backfill_requests(
+            relays(&["wss://a", "wss://b"]),
+            reconciled,
+            notes(),
+            always(),
+        );
+
+        assert_eq!(requests.len(), 2);
+        let (synced, filters) = &requests[0];
+        assert_eq!(synced, &["wss://a"]);
+        assert_eq!(filters.len(), 2);
+        assert_eq!(filters[0], always()[0]);
+        assert_eq!(filters[1].ids.as_ref().map(|ids| ids.len()), Some(3));
+
+        let (unsynced, filters) = &requests[1];
+        assert_eq!(unsynced, &["wss://b"]);
+        assert_eq!(filters, &[always()[0].clone(), notes()]);
+    }
+
+    #[test]
+    fn missing_ids_are_capped_and_chunked() {
+        let reconciled = Reconciled {
+            relays: relays(&["wss://a"]),
+            missing: ids(250),
+        };
+        let requests = backfill_requests(relays(&["wss://a"]), reconciled, notes(), always());
+
+        assert_eq!(requests.len(), 1);
+        let chunks: Vec<usize> = requests[0].1[1..]
+            .iter()
+            .map(|filter| filter.ids.as_ref().map_or(0, |ids| ids.len()))
+            .collect();
+        assert_eq!(chunks, [IDS_PER_FILTER; 4]);
+    }
+
+    #[test]
+    fn nothing_missing_asks_only_for_the_rest() {
+        let reconciled = Reconciled {
+            relays: relays(&["wss://a"]),
+            missing: HashSet::new(),
+        };
+        let requests = backfill_requests(relays(&["wss://a"]), reconciled, notes(), always());
+
+        assert_eq!(requests, [(relays(&["wss://a"]), always())]);
+    }
+}
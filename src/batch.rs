@@ -0,0 +1,111 @@
+use crate::{relay_health::RelayHealth, render::fetch_events, Error};
+use nostr::event::kind::Kind;
+use nostr_sdk::prelude::{EventId, Keys, PublicKey};
+use nostrdb::Ndb;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+/// How long a batch waits for other requests to join it
+const BATCH_WINDOW: Duration = Duration::from_millis(10);
+
+/// How long the relays get to answer a batch
+const BATCH_WAIT: Duration = Duration::from_millis(2000);
+
+struct Batch {
+    ids: HashSet<EventId>,
+    profiles: HashSet<PublicKey>,
+    done: watch::Sender<bool>,
+}
+
+/// Coalesces note id and profile lookups that arrive within a few
+/// milliseconds of each other into one query to the default relays,
+/// instead of a subscription per request
+pub struct FilterBatcher {
+    ndb: Ndb,
+    keys: Keys,
+    relays: Arc<RelayHealth>,
+    pending: Mutex<Option<Batch>>,
+}
+
+impl FilterBatcher {
+    pub fn new(ndb: Ndb, keys: Keys, relays: Arc<RelayHealth>) -> Self {
+        FilterBatcher {
+            ndb,
+            keys,
+            relays,
+            pending: Mutex::new(None),
+        }
+    }
+
+    /// Look up notes and profiles into ndb, along with whatever else is
+    /// being looked up right now. Returns when the batch is done.
+    pub async fn fetch(self: &Arc<Self>, ids: Vec<EventId>, profiles: Vec<PublicKey>) {
+        let mut done = {
+            let mut pending = self.pending.lock().unwrap();
+            let batch = pending.get_or_insert_with(|| {
+                let batcher = self.clone();
+                tokio::spawn(async move { batcher.flush().await });
+
+                Batch {
+                    ids: HashSet::new(),
+                    profiles: HashSet::new(),
+                    done: watch::channel(false).0,
+                }
+            });
+            batch.ids.extend(ids);
+            batch.profiles.extend(profiles);
+            batch.done.subscribe()
+        };
+
+        let _ = done.wait_for(|done| *done).await;
+    }
+
+    async fn flush(&self) {
+        tokio::time::sleep(BATCH_WINDOW).await;
+
+        let batch = if let Some(batch) = self.pending.lock().unwrap().take() {
+            batch
+        } else {
+            return;
+        };
+
+        let mut filters = vec![];
+        if !batch.ids.is_empty() {
+            filters.push(nostr::Filter::new().ids(batch.ids.iter().copied()));
+        }
+        if !batch.profiles.is_empty() {
+            filters.push(
+                nostr::Filter::new()
+                    .authors(batch.profiles.iter().copied())
+                    .kind(Kind::Metadata),
+            );
+        }
+
+        debug!(
+            "batched lookup of {} notes and {} profiles",
+            batch.ids.len(),
+            batch.profiles.len()
+        );
+
+        if !filters.is_empty() {
+            let fetched = fetch_events(
+                &self.ndb,
+                self.keys.clone(),
+                self.relays.relays(),
+                filters,
+                BATCH_WAIT,
+            )
+            .await;
+            self.relays
+                .record_reachable(!matches!(fetched, Err(Error::RelaysUnreachable)));
+            if let Err(err) = fetched {
+                warn!("batched lookup failed: {err}");
+            }
+        }
+
+        let _ = batch.done.send(true);
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long a request may take, shared out between its stages so a slow
+/// relay lookup can't eat the time we need to render what we found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    /// Looking up the author's relay list (NIP-65) to find their outbox
+    pub discovery: Duration,
+    pub note: Duration,
+    pub profile: Duration,
+    /// Fetching images and link previews, and rendering
+    pub render: Duration,
+}
+
+impl Budget {
+    /// Split a request's time between its stages
+    pub fn split(total: Duration) -> Budget {
+        Budget {
+            discovery: total * 20 / 100,
+            note: total * 30 / 100,
+            profile: total * 15 / 100,
+            render: total * 35 / 100,
+        }
+    }
+
+    pub fn total(&self) -> Duration {
+        self.discovery + self.note + self.profile + self.render
+    }
+
+    /// Deadlines for a request starting now. Stages end at the sum of
+    /// their share and those before them, so a stage that finishes early
+    /// leaves its time to the next, and one that runs long is cut off
+    /// before it cuts into the later stages' shares.
+    pub fn start(&self) -> Deadlines {
+        let now = Instant::now();
+        Deadlines {
+            discovery: now + self.discovery,
+            note: now + self.discovery + self.note,
+            profile: now + self.discovery + self.note + self.profile,
+            render: now + self.total(),
+        }
+    }
+}
+
+/// When each stage of a request has to be done by, see [`Budget::start`]
+#[derive(Debug, Clone, Copy)]
+pub struct Deadlines {
+    pub discovery: Instant,
+    pub note: Instant,
+    pub profile: Instant,
+    pub render: Instant,
+}
+
+/// How long we may wait for something, at most `wait` and never past
+/// `deadline`
+pub fn wait_until(deadline: Instant, wait: Duration) -> Duration {
+    wait.min(deadline.saturating_duration_since(Instant::now()))
+}
@@ -0,0 +1,108 @@
+use crate::metrics;
+use hyper::body::Bytes;
+use lru::LruCache;
+use nostr_sdk::async_utility::futures_util::future::BoxFuture;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Where rendered cards, pages and not found markers are kept. In memory
+/// by default, or in redis so instances behind a load balancer share
+/// them. Lookups that fail are misses, these are only caches.
+pub trait CacheBackend: Send + Sync {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<Bytes>>;
+
+    fn put(&self, key: String, value: Bytes, ttl: Duration) -> BoxFuture<'_, ()>;
+}
+
+/// A least recently used cache in this process
+pub struct MemoryBackend {
+    /// For the cache metrics
+    name: &'static str,
+    cache: Mutex<LruCache<String, (Instant, Bytes)>>,
+}
+
+impl MemoryBackend {
+    pub fn new(name: &'static str, capacity: NonZeroUsize) -> Self {
+        MemoryBackend {
+            name,
+            cache: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+impl CacheBackend for MemoryBackend {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<Bytes>> {
+        let value = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|(expires, _)| Instant::now() < *expires)
+            .map(|(_, value)| value.clone());
+        Box::pin(async move { value })
+    }
+
+    fn put(&self, key: String, value: Bytes, ttl: Duration) -> BoxFuture<'_, ()> {
+        metrics::cache_put(
+            self.name,
+            &mut self.cache.lock().unwrap(),
+            key,
+            (Instant::now() + ttl, value),
+        );
+        Box::pin(async {})
+    }
+}
+
+/// A redis server shared between instances. Keys are prefixed so
+/// several deployments can share one server.
+pub struct RedisBackend {
+    conn: redis::aio::ConnectionManager,
+}
+
+/// Namespaces our keys in a shared redis
+const REDIS_PREFIX: &str = "notecrumbs:";
+
+impl RedisBackend {
+    pub async fn connect(url: &str) -> redis::RedisResult<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = redis::aio::ConnectionManager::new(client).await?;
+        Ok(RedisBackend { conn })
+    }
+}
+
+impl CacheBackend for RedisBackend {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Option<Bytes>> {
+        let mut conn = self.conn.clone();
+        Box::pin(async move {
+            let value: redis::RedisResult<Option<Vec<u8>>> = redis::cmd("GET")
+                .arg(format!("{REDIS_PREFIX}{key}"))
+                .query_async(&mut conn)
+                .await;
+            match value {
+                Ok(value) => value.map(Bytes::from),
+                Err(err) => {
+                    debug!("redis get {key} failed: {err}");
+                    None
+                }
+            }
+        })
+    }
+
+    fn put(&self, key: String, value: Bytes, ttl: Duration) -> BoxFuture<'_, ()> {
+        let mut conn = self.conn.clone();
+        Box::pin(async move {
+            let stored: redis::RedisResult<()> = redis::cmd("SET")
+                .arg(format!("{REDIS_PREFIX}{key}"))
+                .arg(value.as_ref())
+                .arg("PX")
+                .arg(ttl.as_millis().max(1) as u64)
+                .query_async(&mut conn)
+                .await;
+            if let Err(err) = stored {
+                debug!("redis set {key} failed: {err}");
+            }
+        })
+    }
+}
@@ -0,0 +1,40 @@
+use crate::cache_backend::CacheBackend;
+use crate::metrics;
+use hyper::body::Bytes;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Cards never go stale, but a shared cache shouldn't keep them forever
+const CARD_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Rendered cards, so the og:image fetch that follows an html page
+/// doesn't render again. Keys are content hashes (see
+/// `render::card_cache_key`), so entries never go stale, they're only
+/// evicted.
+pub struct CardCache {
+    backend: Arc<dyn CacheBackend>,
+}
+
+fn backend_key(key: &str) -> String {
+    format!("card:{key}")
+}
+
+impl CardCache {
+    pub fn new(backend: Arc<dyn CacheBackend>) -> Self {
+        CardCache { backend }
+    }
+
+    pub async fn get(&self, key: &str) -> Option<Bytes> {
+        let data = self.backend.get(&backend_key(key)).await;
+        metrics::cache_lookup("cards", data.is_some());
+        data
+    }
+
+    pub async fn contains(&self, key: &str) -> bool {
+        self.backend.get(&backend_key(key)).await.is_some()
+    }
+
+    pub async fn put(&self, key: String, data: Bytes) {
+        self.backend.put(backend_key(&key), data, CARD_TTL).await;
+    }
+}
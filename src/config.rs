@@ -0,0 +1,798 @@
+use crate::{budget::Budget, fonts};
+use nostr_sdk::prelude::{Coordinate, FromBech32, Nip19, RelayUrl};
+use std::fmt;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Which image we advertise as `og:image` when a page is unfurled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OgImagePolicy {
+    /// Always use our rendered png card
+    Generated,
+    /// Use the first media image (note images, article hero image,
+    /// profile banner), falling back to the card
+    Media,
+    /// Use the author's profile picture, falling back to the card
+    Avatar,
+    /// Media when the image is the point of the content, like a photo
+    /// with a short caption or an article's hero image, otherwise the card
+    Auto,
+}
+
+impl fmt::Display for OgImagePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OgImagePolicy::Generated => write!(f, "generated"),
+            OgImagePolicy::Media => write!(f, "media"),
+            OgImagePolicy::Avatar => write!(f, "avatar"),
+            OgImagePolicy::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+impl FromStr for OgImagePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "generated" => Ok(OgImagePolicy::Generated),
+            "media" => Ok(OgImagePolicy::Media),
+            "avatar" => Ok(OgImagePolicy::Avatar),
+            "auto" => Ok(OgImagePolicy::Auto),
+            other => Err(format!(
+                "unknown og:image policy '{other}', expected generated, media, avatar or auto"
+            )),
+        }
+    }
+}
+
+/// Where the homepage feed comes from
+#[derive(Debug, Clone)]
+pub enum HomepageSource {
+    /// The most recent notes we happen to have
+    Recent,
+    /// Recent notes from a single curated relay
+    Relay(String),
+    /// The notes in a NIP-51 curation set (kind 30004 or 30005)
+    Curation(Coordinate),
+}
+
+impl fmt::Display for HomepageSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HomepageSource::Recent => write!(f, "recent"),
+            HomepageSource::Relay(relay) => write!(f, "relay {relay}"),
+            HomepageSource::Curation(coord) => write!(f, "curation set {}", coord.identifier),
+        }
+    }
+}
+
+impl FromStr for HomepageSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s == "recent" {
+            return Ok(HomepageSource::Recent);
+        }
+
+        if s.starts_with("naddr1") {
+            return match Nip19::from_bech32(s) {
+                Ok(Nip19::Coordinate(coord)) if matches!(coord.kind.as_u16(), 30004 | 30005) => {
+                    Ok(HomepageSource::Curation(coord))
+                }
+                Ok(_) => {
+                    Err("expected the naddr of a curation set (kind 30004 or 30005)".to_string())
+                }
+                Err(err) => Err(err.to_string()),
+            };
+        }
+
+        match RelayUrl::parse(s) {
+            Ok(relay) => Ok(HomepageSource::Relay(relay.to_string())),
+            Err(_) => Err(format!(
+                "expected 'recent', a relay url or a curation set naddr, got '{s}'"
+            )),
+        }
+    }
+}
+
+/// Which relays we're willing to connect to. Relay hints and relay lists
+/// come from events anyone can publish, without this they could point us
+/// at any host they like.
+#[derive(Debug, Clone, Default)]
+pub struct RelayPolicy {
+    /// Relay urls or domains we may connect to, anything when empty
+    pub allow: Vec<String>,
+    /// Relay urls or domains we never connect to
+    pub block: Vec<String>,
+}
+
+/// Does a policy entry cover this relay? Entries with a scheme are
+/// relay urls, anything else is a domain and covers its subdomains too.
+fn relay_matches(entry: &str, relay: &RelayUrl, host: &str) -> bool {
+    if entry.contains("://") {
+        return RelayUrl::parse(entry).is_ok_and(|entry| &entry == relay);
+    }
+
+    let domain = entry.trim_matches('.').to_ascii_lowercase();
+    host == domain || host.ends_with(&format!(".{domain}"))
+}
+
+impl RelayPolicy {
+    pub fn allows(&self, relay: &str) -> bool {
+        let host = match reqwest::Url::parse(relay) {
+            Ok(url) => url.host_str().unwrap_or_default().to_ascii_lowercase(),
+            Err(_) => return false,
+        };
+        let relay = match RelayUrl::parse(relay) {
+            Ok(relay) => relay,
+            Err(_) => return false,
+        };
+        let matches = |entry: &String| relay_matches(entry, &relay, &host);
+
+        if self.block.iter().any(matches) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(matches)
+    }
+
+    /// The relays we're allowed to connect to, in the same order
+    pub fn filter(&self, relays: Vec<String>) -> Vec<String> {
+        relays
+            .into_iter()
+            .filter(|relay| {
+                let allowed = self.allows(relay);
+                if !allowed {
+                    debug!("relay policy refused {relay}");
+                }
+                allowed
+            })
+            .collect()
+    }
+}
+
+/// Everything that was wrong with the config, reported all at once so
+/// operators don't have to fix problems one restart at a time
+#[derive(Debug, Default)]
+pub struct ConfigErrors(pub Vec<String>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "invalid configuration:")?;
+        for problem in &self.0 {
+            writeln!(f, "  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// Deployment specific settings, read from the environment at startup
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// NOTECRUMBS_OG_IMAGE: generated | media | avatar | auto
+    pub og_image: OgImagePolicy,
+
+    /// NOTECRUMBS_BASE_URL: the public url we are served from, used for
+    /// canonical and og urls
+    pub base_url: String,
+
+    /// NOTECRUMBS_NEWS_PUBLICATION: publication name in the news sitemap
+    pub news_publication: String,
+
+    /// NOTECRUMBS_NEWS_LANGUAGE: ISO 639 language of the news sitemap
+    pub news_language: String,
+
+    /// NOTECRUMBS_RELAYS: comma separated default relays we fetch from
+    pub relays: Vec<String>,
+
+    /// NOTECRUMBS_REQUEST_BUDGET_MS (formerly TIMEOUT_MS): how long a
+    /// request may take, split between relay discovery, the note and
+    /// profile lookups, and rendering
+    pub budget: Budget,
+
+    /// NOTECRUMBS_FONTS: comma separated font files or urls, used as
+    /// fallbacks for glyphs our bundled fonts don't cover, like Noto
+    /// Sans JP for Japanese cards. Urls have to be pinned to the font's
+    /// checksum, `https://...#sha256={hex}`.
+    pub fonts: Vec<String>,
+
+    /// NOTECRUMBS_EMOJI_FONT: an emoji font file or url, tried before
+    /// egui's bundled emoji. Cards are drawn from a coverage-only glyph
+    /// atlas, so this has to be an outline font like Noto Emoji, color
+    /// bitmap fonts don't render.
+    pub emoji_font: Option<String>,
+
+    /// NOTECRUMBS_FONT_CACHE_DIR: where downloaded fonts are kept
+    pub font_cache_dir: String,
+
+    /// NOTECRUMBS_PFP_CACHE_DIR: where processed avatars are kept
+    pub pfp_cache_dir: String,
+
+    /// NOTECRUMBS_ADMIN_TOKEN: bearer token for /admin routes, which are
+    /// disabled when unset
+    pub admin_token: Option<String>,
+
+    /// NOTECRUMBS_UNRESOLVED_PATH: where we persist entities that failed
+    /// to resolve
+    pub unresolved_path: String,
+
+    /// NOTECRUMBS_HOMEPAGE_FEED: recent | <relay url> | <curation set naddr>
+    pub homepage_feed: HomepageSource,
+
+    /// NOTECRUMBS_SEARCH_RELAYS: comma separated NIP-50 capable relays
+    /// used by /search
+    pub search_relays: Vec<String>,
+
+    /// NOTECRUMBS_CARD_LOCALE: POSIX locale (en_US, de_DE, ja_JP...) for
+    /// pages and cards when the request's Accept-Language names nothing
+    /// we have translations for
+    pub card_locale: String,
+
+    /// NOTECRUMBS_RELAY_ALLOWLIST, NOTECRUMBS_RELAY_BLOCKLIST: comma
+    /// separated relay urls or domains we may or may never connect to
+    pub relay_policy: RelayPolicy,
+
+    /// NOTECRUMBS_MAX_HINTED_RELAYS: how many relays from hints and relay
+    /// lists we keep track of
+    pub max_hinted_relays: NonZeroUsize,
+
+    /// NOTECRUMBS_SOCKS5_PROXY: host:port of a SOCKS5 proxy (eg: tor at
+    /// 127.0.0.1:9050) for relay connections and media fetches
+    pub socks5_proxy: Option<SocketAddr>,
+
+    /// NOTECRUMBS_MAX_LOOKUPS: relay lookups we run at once, requests
+    /// past that get a 503
+    pub max_lookups: NonZeroUsize,
+
+    /// NOTECRUMBS_MAX_RENDERS: card renders we run at once, requests past
+    /// that get a 503
+    pub max_renders: NonZeroUsize,
+
+    /// NOTECRUMBS_LISTEN: the address we serve on
+    pub listen: SocketAddr,
+
+    /// NOTECRUMBS_TLS_CERT, NOTECRUMBS_TLS_KEY: pem certificate chain and
+    /// private key. With both set we serve https ourselves.
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+
+    /// NOTECRUMBS_TRUST_PROXY_HEADERS: build canonical and og urls from
+    /// X-Forwarded-Proto and X-Forwarded-Host instead of the base url.
+    /// Only for deployments behind a proxy that sets them.
+    pub trust_proxy_headers: bool,
+
+    /// NOTECRUMBS_OTLP_ENDPOINT: OpenTelemetry collector (grpc) we export
+    /// request, relay fetch, ndb and render spans to
+    pub otlp_endpoint: Option<String>,
+
+    /// NOTECRUMBS_SLOW_REQUEST_MS: requests taking longer than this are
+    /// logged with their stage timings
+    pub slow_request: Duration,
+
+    /// NOTECRUMBS_NDB_DIR: where nostrdb keeps its database
+    pub ndb_dir: String,
+
+    /// NOTECRUMBS_NDB_MAPSIZE_MB: the most the database may grow to,
+    /// nostrdb's default when unset
+    pub ndb_mapsize_mb: Option<NonZeroUsize>,
+
+    /// NOTECRUMBS_NDB_INGESTER_THREADS: threads ingesting events into
+    /// nostrdb, nostrdb's default when unset
+    pub ndb_ingester_threads: Option<NonZeroUsize>,
+
+    /// NOTECRUMBS_REDIS_URL: keep rendered cards, pages and not found
+    /// entries in redis instead of memory, to share them between
+    /// instances
+    pub redis_url: Option<String>,
+
+    /// NOTECRUMBS_WARMUP: comma separated nevents, naddrs, npubs... to
+    /// fetch and render cards for at startup
+    pub warmup: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            og_image: OgImagePolicy::Generated,
+            base_url: "https://damus.io".to_string(),
+            news_publication: "Damus".to_string(),
+            news_language: "en".to_string(),
+            relays: vec![
+                "wss://relay.damus.io".to_string(),
+                "wss://nostr.wine".to_string(),
+                "wss://nos.lol".to_string(),
+            ],
+            budget: Budget::split(Duration::from_millis(5000)),
+            fonts: vec![],
+            emoji_font: None,
+            font_cache_dir: "font-cache".to_string(),
+            pfp_cache_dir: "pfp-cache".to_string(),
+            admin_token: None,
+            unresolved_path: "unresolved.json".to_string(),
+            homepage_feed: HomepageSource::Recent,
+            search_relays: vec![
+                "wss://relay.nostr.band".to_string(),
+                "wss://search.nos.today".to_string(),
+            ],
+            card_locale: "en_US".to_string(),
+            relay_policy: RelayPolicy::default(),
+            max_hinted_relays: NonZeroUsize::new(200).unwrap(),
+            socks5_proxy: None,
+            max_lookups: NonZeroUsize::new(64).unwrap(),
+            max_renders: NonZeroUsize::new(8).unwrap(),
+            listen: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            tls_cert: None,
+            tls_key: None,
+            trust_proxy_headers: false,
+            otlp_endpoint: None,
+            slow_request: Duration::from_millis(3000),
+            ndb_dir: ".".to_string(),
+            ndb_mapsize_mb: None,
+            ndb_ingester_threads: None,
+            redis_url: None,
+            warmup: vec![],
+        }
+    }
+}
+
+/// Less than this and the stages don't get enough time to do anything
+const MIN_REQUEST_BUDGET: Duration = Duration::from_millis(500);
+
+const MB: u64 = 1024 * 1024;
+
+/// Below this much free disk ndb can't take in much of anything
+const MIN_NDB_FREE_SPACE: u64 = 256 * MB;
+
+/// `path`, or the nearest of its parents that exists when it hasn't been
+/// created yet. That's where it will be created.
+pub fn nearest_existing(path: &Path) -> &Path {
+    match path.ancestors().find(|dir| dir.exists()) {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        // a relative path with nothing of it there yet
+        _ => Path::new("."),
+    }
+}
+
+/// Free space on the filesystem a path is (or would be created) on.
+/// `None` when we can't tell.
+#[cfg(unix)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(nearest_existing(path).as_os_str().as_bytes()).ok()?;
+
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: path is nul terminated and stat is a valid statvfs to fill in
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Reads env vars, remembering every value that failed to parse
+#[derive(Default)]
+struct EnvReader {
+    errors: ConfigErrors,
+}
+
+impl EnvReader {
+    fn parse<T: FromStr>(&mut self, key: &str, default: T) -> T
+    where
+        T::Err: fmt::Display,
+    {
+        match std::env::var(key) {
+            Err(_) => default,
+            Ok(val) => match val.parse() {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    self.errors.0.push(format!("{key}={val}: {err}"));
+                    default
+                }
+            },
+        }
+    }
+
+    /// Like `parse`, for settings that are off when unset or empty
+    fn parse_opt<T: FromStr>(&mut self, key: &str) -> Option<T>
+    where
+        T::Err: fmt::Display,
+    {
+        match std::env::var(key) {
+            Ok(val) if !val.is_empty() => match val.parse() {
+                Ok(parsed) => Some(parsed),
+                Err(err) => {
+                    self.errors.0.push(format!("{key}={val}: {err}"));
+                    None
+                }
+            },
+            _ => None,
+        }
+    }
+}
+
+fn env_list(key: &str, default: Vec<String>) -> Vec<String> {
+    match std::env::var(key) {
+        Err(_) => default,
+        Ok(val) => val
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect(),
+    }
+}
+
+impl Config {
+    /// Load the config from the environment and validate it
+    pub fn from_env() -> Result<Self, ConfigErrors> {
+        let default = Config::default();
+        let mut env = EnvReader::default();
+
+        let config = Config {
+            og_image: env.parse("NOTECRUMBS_OG_IMAGE", default.og_image),
+            base_url: env
+                .parse("NOTECRUMBS_BASE_URL", default.base_url)
+                .trim_end_matches('/')
+                .to_string(),
+            news_publication: env.parse("NOTECRUMBS_NEWS_PUBLICATION", default.news_publication),
+            news_language: env.parse("NOTECRUMBS_NEWS_LANGUAGE", default.news_language),
+            relays: env_list("NOTECRUMBS_RELAYS", default.relays),
+            budget: Budget::split(Duration::from_millis({
+                let fallback = env.parse("TIMEOUT_MS", default.budget.total().as_millis() as u64);
+                env.parse("NOTECRUMBS_REQUEST_BUDGET_MS", fallback)
+            })),
+            fonts: env_list("NOTECRUMBS_FONTS", default.fonts),
+            emoji_font: std::env::var("NOTECRUMBS_EMOJI_FONT")
+                .ok()
+                .filter(|font| !font.is_empty()),
+            font_cache_dir: env.parse("NOTECRUMBS_FONT_CACHE_DIR", default.font_cache_dir),
+            pfp_cache_dir: env.parse("NOTECRUMBS_PFP_CACHE_DIR", default.pfp_cache_dir),
+            admin_token: std::env::var("NOTECRUMBS_ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            unresolved_path: env.parse("NOTECRUMBS_UNRESOLVED_PATH", default.unresolved_path),
+            homepage_feed: env.parse("NOTECRUMBS_HOMEPAGE_FEED", default.homepage_feed),
+            search_relays: env_list("NOTECRUMBS_SEARCH_RELAYS", default.search_relays),
+            card_locale: env.parse("NOTECRUMBS_CARD_LOCALE", default.card_locale),
+            relay_policy: RelayPolicy {
+                allow: env_list("NOTECRUMBS_RELAY_ALLOWLIST", default.relay_policy.allow),
+                block: env_list("NOTECRUMBS_RELAY_BLOCKLIST", default.relay_policy.block),
+            },
+            max_hinted_relays: env.parse("NOTECRUMBS_MAX_HINTED_RELAYS", default.max_hinted_relays),
+            socks5_proxy: env.parse_opt("NOTECRUMBS_SOCKS5_PROXY"),
+            max_lookups: env.parse("NOTECRUMBS_MAX_LOOKUPS", default.max_lookups),
+            max_renders: env.parse("NOTECRUMBS_MAX_RENDERS", default.max_renders),
+            listen: env.parse("NOTECRUMBS_LISTEN", default.listen),
+            tls_cert: env.parse_opt("NOTECRUMBS_TLS_CERT"),
+            tls_key: env.parse_opt("NOTECRUMBS_TLS_KEY"),
+            trust_proxy_headers: env.parse(
+                "NOTECRUMBS_TRUST_PROXY_HEADERS",
+                default.trust_proxy_headers,
+            ),
+            otlp_endpoint: env.parse_opt("NOTECRUMBS_OTLP_ENDPOINT"),
+            slow_request: Duration::from_millis(env.parse(
+                "NOTECRUMBS_SLOW_REQUEST_MS",
+                default.slow_request.as_millis() as u64,
+            )),
+            ndb_dir: env.parse("NOTECRUMBS_NDB_DIR", default.ndb_dir),
+            ndb_mapsize_mb: env.parse_opt("NOTECRUMBS_NDB_MAPSIZE_MB"),
+            ndb_ingester_threads: env.parse_opt("NOTECRUMBS_NDB_INGESTER_THREADS"),
+            redis_url: env.parse_opt("NOTECRUMBS_REDIS_URL"),
+            warmup: env_list("NOTECRUMBS_WARMUP", default.warmup),
+        };
+
+        let mut errors = env.errors;
+        errors.0.extend(config.validate());
+
+        if errors.0.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Check for problems we would otherwise only hit mid-request
+    fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        match self.base_url.parse::<hyper::Uri>() {
+            Ok(uri) => {
+                if !matches!(uri.scheme_str(), Some("http" | "https")) || uri.host().is_none() {
+                    problems.push(format!(
+                        "NOTECRUMBS_BASE_URL={}: expected an absolute http(s) url like https://damus.io",
+                        self.base_url
+                    ));
+                }
+            }
+            Err(err) => problems.push(format!("NOTECRUMBS_BASE_URL={}: {err}", self.base_url)),
+        }
+
+        if self.relays.is_empty() {
+            problems.push("NOTECRUMBS_RELAYS: at least one relay is required".to_string());
+        }
+
+        for relay in &self.relays {
+            if let Err(err) = RelayUrl::parse(relay) {
+                problems.push(format!(
+                    "NOTECRUMBS_RELAYS: invalid relay url '{relay}': {err}"
+                ));
+            } else if !self.relay_policy.allows(relay) {
+                problems.push(format!(
+                    "NOTECRUMBS_RELAYS: '{relay}' is refused by the relay allowlist/blocklist"
+                ));
+            }
+        }
+
+        for relay in &self.search_relays {
+            if let Err(err) = RelayUrl::parse(relay) {
+                problems.push(format!(
+                    "NOTECRUMBS_SEARCH_RELAYS: invalid relay url '{relay}': {err}"
+                ));
+            }
+        }
+
+        let policy = self
+            .relay_policy
+            .allow
+            .iter()
+            .map(|entry| ("NOTECRUMBS_RELAY_ALLOWLIST", entry))
+            .chain(
+                self.relay_policy
+                    .block
+                    .iter()
+                    .map(|entry| ("NOTECRUMBS_RELAY_BLOCKLIST", entry)),
+            );
+        for (key, entry) in policy {
+            if entry.contains("://") {
+                if let Err(err) = RelayUrl::parse(entry) {
+                    problems.push(format!("{key}: invalid relay url '{entry}': {err}"));
+                }
+            }
+        }
+
+        let font_sources = self
+            .fonts
+            .iter()
+            .map(|font| ("NOTECRUMBS_FONTS", font))
+            .chain(
+                self.emoji_font
+                    .iter()
+                    .map(|font| ("NOTECRUMBS_EMOJI_FONT", font)),
+            );
+        for (key, font) in font_sources {
+            let (font, sha256) = fonts::parse_source(font);
+            if fonts::is_url(font) {
+                if let Err(err) = font.parse::<hyper::Uri>() {
+                    problems.push(format!("{key}: invalid font url '{font}': {err}"));
+                } else if sha256.is_none() {
+                    problems.push(format!(
+                        "{key}: font url '{font}' isn't pinned, add #sha256=<hex> to it"
+                    ));
+                }
+            } else if let Err(err) = fonts::check_font_file(font, sha256) {
+                problems.push(format!("{key}: can't use font '{font}': {err}"));
+            }
+        }
+
+        if chrono::Locale::try_from(self.card_locale.as_str()).is_err() {
+            problems.push(format!(
+                "NOTECRUMBS_CARD_LOCALE={}: unknown locale, expected something like en_US",
+                self.card_locale
+            ));
+        }
+
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => {
+                for (var, path) in [("NOTECRUMBS_TLS_CERT", cert), ("NOTECRUMBS_TLS_KEY", key)] {
+                    if let Err(err) = std::fs::metadata(path) {
+                        problems.push(format!("{var}: can't read '{path}': {err}"));
+                    }
+                }
+            }
+            (None, None) => {}
+            _ => problems.push(
+                "NOTECRUMBS_TLS_CERT and NOTECRUMBS_TLS_KEY have to be set together".to_string(),
+            ),
+        }
+
+        if self.budget.total() < MIN_REQUEST_BUDGET {
+            problems.push(format!(
+                "NOTECRUMBS_REQUEST_BUDGET_MS: must be at least {}ms",
+                MIN_REQUEST_BUDGET.as_millis()
+            ));
+        }
+
+        for entity in &self.warmup {
+            match Nip19::from_bech32(crate::nip19::strip_nostr_scheme(entity)) {
+                Ok(nip19) if crate::nip19::nip19_type(&nip19) == "secret" => {
+                    problems.push(format!("NOTECRUMBS_WARMUP: '{entity}' is a secret key"))
+                }
+                Ok(_) => {}
+                Err(err) => problems.push(format!("NOTECRUMBS_WARMUP: invalid '{entity}': {err}")),
+            }
+        }
+
+        if let Some(url) = &self.redis_url {
+            if let Err(err) = redis::Client::open(url.as_str()) {
+                problems.push(format!("NOTECRUMBS_REDIS_URL: {err}"));
+            }
+        }
+
+        let ndb_dir = Path::new(&self.ndb_dir);
+        if ndb_dir.exists() && !ndb_dir.is_dir() {
+            problems.push(format!(
+                "NOTECRUMBS_NDB_DIR: '{}' is not a directory",
+                self.ndb_dir
+            ));
+        } else if let Some(free) = available_space(ndb_dir) {
+            if free < MIN_NDB_FREE_SPACE {
+                problems.push(format!(
+                    "NOTECRUMBS_NDB_DIR: only {}MB free on '{}', we need at least {}MB",
+                    free / MB,
+                    self.ndb_dir,
+                    MIN_NDB_FREE_SPACE / MB
+                ));
+            }
+        }
+
+        problems
+    }
+
+    /// nostrdb's settings
+    pub fn ndb_config(&self) -> nostrdb::Config {
+        let mut config = nostrdb::Config::new();
+        if let Some(mapsize) = self.ndb_mapsize_mb {
+            config = config.set_mapsize(mapsize.get() * MB as usize);
+        }
+        if let Some(threads) = self.ndb_ingester_threads {
+            config = config.set_ingester_threads(threads.get() as i32);
+        }
+        config
+    }
+
+    /// The public url a request reached us at: the base url, or what the
+    /// proxy in front of us says when we trust it. A host that isn't a
+    /// plain hostname is ignored, it ends up in our html.
+    pub fn request_base_url(&self, headers: &hyper::HeaderMap) -> String {
+        if !self.trust_proxy_headers {
+            return self.base_url.clone();
+        }
+
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                // proxies in a chain append theirs, the first is the client's
+                .and_then(|value| value.split(',').next())
+                .map(|value| value.trim())
+        };
+
+        let host = match header("x-forwarded-host") {
+            Some(host)
+                if !host.is_empty()
+                    && host
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':')) =>
+            {
+                host.to_ascii_lowercase()
+            }
+            _ => return self.base_url.clone(),
+        };
+
+        let scheme = match header("x-forwarded-proto") {
+            Some(proto) if proto.eq_ignore_ascii_case("http") => "http",
+            Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+            _ => self
+                .base_url
+                .split_once("://")
+                .map_or("https", |(scheme, _)| scheme),
+        };
+
+        format!("{scheme}://{host}")
+    }
+
+    /// Log what we're running with
+    pub fn log_summary(&self) {
+        info!(
+            "base url: {}{}",
+            self.base_url,
+            if self.trust_proxy_headers {
+                " (or X-Forwarded-Host)"
+            } else {
+                ""
+            }
+        );
+        info!(
+            "listening on {}{}",
+            self.listen,
+            if self.tls_cert.is_some() {
+                " with tls"
+            } else {
+                ""
+            }
+        );
+        info!("og:image policy: {}", self.og_image);
+        info!("relays: {}", self.relays.join(", "));
+        info!(
+            "request budget: {}ms (discovery {}ms, note {}ms, profile {}ms, render {}ms)",
+            self.budget.total().as_millis(),
+            self.budget.discovery.as_millis(),
+            self.budget.note.as_millis(),
+            self.budget.profile.as_millis(),
+            self.budget.render.as_millis()
+        );
+        info!("ndb: {}", self.ndb_dir);
+        if !self.warmup.is_empty() {
+            info!("warming up {} entities", self.warmup.len());
+        }
+        if self.redis_url.is_some() {
+            info!("caching cards, pages and not found entries in redis");
+        }
+        if let Some(mapsize) = self.ndb_mapsize_mb {
+            info!("ndb map size: {mapsize}MB");
+        }
+        if let Some(threads) = self.ndb_ingester_threads {
+            info!("ndb ingester threads: {threads}");
+        }
+        info!("max hinted relays: {}", self.max_hinted_relays);
+        info!(
+            "logging requests slower than {}ms",
+            self.slow_request.as_millis()
+        );
+        info!(
+            "max concurrent lookups: {}, renders: {}",
+            self.max_lookups, self.max_renders
+        );
+        if let Some(endpoint) = &self.otlp_endpoint {
+            info!("exporting traces to {endpoint}");
+        }
+        if let Some(proxy) = &self.socks5_proxy {
+            info!("socks5 proxy: {proxy}");
+        }
+        info!("homepage feed: {}", self.homepage_feed);
+        info!("card locale: {}", self.card_locale);
+        if self.search_relays.is_empty() {
+            info!("search: disabled");
+        } else {
+            info!("search relays: {}", self.search_relays.join(", "));
+        }
+        if !self.relay_policy.allow.is_empty() {
+            info!("relay allowlist: {}", self.relay_policy.allow.join(", "));
+        }
+        if !self.relay_policy.block.is_empty() {
+            info!("relay blocklist: {}", self.relay_policy.block.join(", "));
+        }
+        if let Some(emoji_font) = &self.emoji_font {
+            info!("emoji font: {emoji_font}");
+        }
+        if !self.fonts.is_empty() {
+            info!("fallback fonts: {}", self.fonts.join(", "));
+        }
+        info!(
+            "admin routes: {}",
+            if self.admin_token.is_some() {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        );
+        info!(
+            "news sitemap: {} ({})",
+            self.news_publication, self.news_language
+        );
+    }
+}
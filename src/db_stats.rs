@@ -0,0 +1,84 @@
+use nostrdb::{Filter, Ndb, Transaction};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Events per kind are counted up to this, past it we report the limit
+pub const COUNT_LIMIT: i32 = 100_000;
+
+/// The kinds we count in ndb, the ones we render or fetch
+pub const COUNTED_KINDS: [u64; 8] = [0, 1, 3, 6, 7, 9735, 10002, 30023];
+
+/// What's in ndb, for capacity planning
+#[derive(Debug, Default, Serialize)]
+pub struct DbStats {
+    /// Size of the database file on disk
+    pub size_bytes: Option<u64>,
+    /// Events of each of `COUNTED_KINDS`, up to `COUNT_LIMIT` each
+    pub events: BTreeMap<u64, usize>,
+    pub count_limit: i32,
+    /// Kind 0 events, one per profile
+    pub profiles: usize,
+    /// created_at of the oldest and newest of the counted events
+    pub oldest: Option<u64>,
+    pub newest: Option<u64>,
+}
+
+fn counted_kinds() -> nostrdb::FilterBuilder {
+    Filter::new().kinds(COUNTED_KINDS)
+}
+
+/// The newest counted event created at or before `until`
+fn newest_until(ndb: &Ndb, txn: &Transaction, until: u64) -> Option<u64> {
+    let filter = counted_kinds().until(until).limit(1).build();
+    ndb.query(txn, &[filter], 1)
+        .ok()?
+        .first()
+        .map(|result| result.note.created_at())
+}
+
+/// Queries come back newest first, so find the oldest event by bisecting
+/// on `until`
+fn oldest(ndb: &Ndb, txn: &Transaction, newest: u64) -> u64 {
+    let (mut lo, mut hi) = (0, newest);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if newest_until(ndb, txn, mid).is_some() {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    hi
+}
+
+/// Count what's in ndb. This walks the database, run it off the runtime
+/// threads.
+pub fn db_stats(ndb: &Ndb, dir: &Path) -> DbStats {
+    let mut stats = DbStats {
+        size_bytes: std::fs::metadata(dir.join("data.mdb"))
+            .ok()
+            .map(|file| file.len()),
+        count_limit: COUNT_LIMIT,
+        ..Default::default()
+    };
+
+    let txn = if let Ok(txn) = Transaction::new(ndb) {
+        txn
+    } else {
+        return stats;
+    };
+
+    for kind in COUNTED_KINDS {
+        let filter = Filter::new().kinds([kind]).build();
+        if let Ok(results) = ndb.query(&txn, &[filter], COUNT_LIMIT) {
+            stats.events.insert(kind, results.len());
+        }
+    }
+    stats.profiles = stats.events.get(&0).copied().unwrap_or_default();
+
+    stats.newest = newest_until(ndb, &txn, u64::MAX);
+    stats.oldest = stats.newest.map(|newest| oldest(ndb, &txn, newest));
+
+    stats
+}
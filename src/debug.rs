@@ -0,0 +1,255 @@
+use crate::{
+    html::blocktype_name,
+    render::{NoteAndProfileRenderData, NoteRenderData},
+    Error, Notecrumbs,
+};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, Response, StatusCode};
+use nostr_sdk::prelude::{Nip19, ToBech32};
+use nostrdb::{BlockType, Mention, NdbStrVariant, Note, Transaction};
+use std::io::Write;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// What happened while we were putting a request together, and when.
+/// Shown on the debug page, and logged for slow requests.
+pub struct FetchTimeline {
+    start: Instant,
+    events: Vec<(Duration, String)>,
+    /// Time spent waiting on relays
+    relay_wait: Duration,
+    /// Time spent rendering pages and cards
+    render: Duration,
+}
+
+impl FetchTimeline {
+    pub fn new() -> Self {
+        FetchTimeline {
+            start: Instant::now(),
+            events: vec![],
+            relay_wait: Duration::ZERO,
+            render: Duration::ZERO,
+        }
+    }
+
+    pub fn add_relay_wait(&mut self, waited: Duration) {
+        self.relay_wait += waited;
+    }
+
+    pub fn add_render(&mut self, rendered: Duration) {
+        self.render += rendered;
+    }
+
+    /// Render a page, counting the time as render time
+    pub fn time_render<T>(&mut self, render: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let rendered = render();
+        self.add_render(start.elapsed());
+        rendered
+    }
+
+    /// Warn about a request that took too long, with where the time went
+    pub fn log_slow(&self, path: &str, nip19: &str) {
+        let stages = self
+            .events
+            .iter()
+            .map(|(at, event)| format!("{}ms {event}", at.as_millis()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        warn!(
+            path,
+            nip19,
+            total_ms = self.start.elapsed().as_millis(),
+            relay_wait_ms = self.relay_wait.as_millis(),
+            render_ms = self.render.as_millis(),
+            stages = %stages,
+            "slow request"
+        );
+    }
+
+    /// Note a stage of the request, which is also logged in the
+    /// request's span
+    pub fn mark(&mut self, event: impl Into<String>) {
+        let event = event.into();
+        let elapsed = self.start.elapsed();
+        debug!(elapsed_ms = elapsed.as_millis(), "{event}");
+        self.events.push((elapsed, event));
+    }
+}
+
+impl Default for FetchTimeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn write_tags(data: &mut Vec<u8>, note: &Note) -> std::io::Result<()> {
+    write!(data, "<h2>Tags</h2><table class=\"debug-tags\">")?;
+
+    for tag in note.tags() {
+        write!(data, "<tr>")?;
+        for i in 0..tag.count() {
+            let cell = match tag.get(i).map(|s| s.variant()) {
+                Some(NdbStrVariant::Str(s)) => s.to_string(),
+                Some(NdbStrVariant::Id(id)) => hex::encode(id),
+                None => continue,
+            };
+            write!(data, "<td>{}</td>", html_escape::encode_text(&cell))?;
+        }
+        write!(data, "</tr>")?;
+    }
+
+    write!(data, "</table>")
+}
+
+/// Do we have the thing a mention points at?
+fn mention_status(app: &Notecrumbs, txn: &Transaction, mention: &Mention) -> &'static str {
+    let found = match mention {
+        Mention::Event(ev) => app.ndb.get_note_by_id(txn, ev.id()).is_ok(),
+        Mention::Note(note) => app.ndb.get_note_by_id(txn, note.id()).is_ok(),
+        Mention::Profile(nprofile) => app
+            .ndb
+            .get_profile_by_pubkey(txn, nprofile.pubkey())
+            .is_ok(),
+        Mention::Pubkey(npub) => app.ndb.get_profile_by_pubkey(txn, npub.pubkey()).is_ok(),
+        Mention::Secret(_) => return "redacted",
+        Mention::Relay(_) | Mention::Addr(_) => return "not resolved",
+    };
+
+    if found {
+        "cached"
+    } else {
+        "missing"
+    }
+}
+
+fn write_blocks(
+    data: &mut Vec<u8>,
+    app: &Notecrumbs,
+    txn: &Transaction,
+    note: &Note,
+) -> std::io::Result<()> {
+    write!(
+        data,
+        "<h2>Blocks</h2><table class=\"debug-blocks\"><tr><th>type</th><th>text</th><th>reference</th></tr>"
+    )?;
+
+    let blocks = match note
+        .key()
+        .and_then(|nk| app.ndb.get_blocks_by_key(txn, nk).ok())
+    {
+        Some(blocks) => blocks,
+        None => return write!(data, "</table><p>no parsed blocks</p>"),
+    };
+
+    for block in blocks.iter(note) {
+        let reference = match block.blocktype() {
+            BlockType::MentionBech32 => block
+                .as_mention()
+                .map(|mention| mention_status(app, txn, &mention))
+                .unwrap_or("unparsed"),
+            _ => "",
+        };
+
+        // secrets don't get echoed back, even on the debug page
+        let text = match block.as_mention() {
+            Some(Mention::Secret(_)) => "--redacted--",
+            _ => block.as_str(),
+        };
+
+        write!(
+            data,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            blocktype_name(&block.blocktype()),
+            html_escape::encode_text(text),
+            reference,
+        )?;
+    }
+
+    write!(data, "</table>")
+}
+
+fn write_timeline(data: &mut Vec<u8>, timeline: &FetchTimeline) -> std::io::Result<()> {
+    write!(
+        data,
+        "<h2>Fetch timeline</h2><table class=\"debug-timeline\">"
+    )?;
+    for (at, event) in &timeline.events {
+        write!(
+            data,
+            "<tr><td>{:.1}ms</td><td>{}</td></tr>",
+            at.as_secs_f64() * 1000.0,
+            html_escape::encode_text(event)
+        )?;
+    }
+    write!(data, "</table>")
+}
+
+/// `?debug=1`: everything we know about a note, for figuring out why it
+/// renders the way it does
+pub fn serve_note_debug(
+    app: &Notecrumbs,
+    nip19: &Nip19,
+    note_rd: &NoteAndProfileRenderData,
+    timeline: &FetchTimeline,
+) -> Result<Response<Full<Bytes>>, Error> {
+    let mut data = Vec::new();
+    let bech32 = nip19.to_bech32()?;
+
+    write!(
+        data,
+        r#"<html>
+<head>
+  <title>debug: {0}</title>
+  <meta charset="UTF-8">
+  <meta name="robots" content="noindex">
+</head>
+<body>
+  <h1>{0}</h1>
+"#,
+        bech32
+    )?;
+
+    let txn = Transaction::new(&app.ndb)?;
+
+    match note_rd.note_rd.lookup(&txn, &app.ndb) {
+        Ok(note) => {
+            write!(
+                data,
+                "<h2>Note</h2><pre>{}</pre>",
+                html_escape::encode_text(&note.json()?)
+            )?;
+            write_tags(&mut data, &note)?;
+            write_blocks(&mut data, app, &txn, &note)?;
+        }
+
+        Err(err) => {
+            let id = match note_rd.note_rd {
+                NoteRenderData::Missing(id) => hex::encode(id),
+                NoteRenderData::MissingAddress(coord) => coord.to_string(),
+                NoteRenderData::Note(key) => format!("key {key:?}"),
+            };
+            write!(data, "<h2>Note</h2><p>not found ({id}): {err}</p>")?;
+        }
+    }
+
+    let profile = note_rd
+        .profile_rd
+        .as_ref()
+        .map(|prd| prd.lookup(&txn, &app.ndb).is_ok());
+    let profile_status = match profile {
+        Some(true) => "cached",
+        Some(false) => "missing",
+        None => "unknown author",
+    };
+    write!(data, "<h2>Profile</h2><p>{profile_status}</p>")?;
+
+    write_timeline(&mut data, timeline)?;
+
+    write!(data, "</body></html>")?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(data)))?)
+}
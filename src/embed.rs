@@ -0,0 +1,67 @@
+use crate::{
+    html::dominant_video,
+    render::{self, RenderData},
+    Error, Notecrumbs,
+};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, Response, StatusCode};
+use nostr_sdk::prelude::Nip19;
+use nostrdb::Transaction;
+use std::io::Write;
+
+fn not_found() -> Result<Response<Full<Bytes>>, Error> {
+    Ok(Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Full::new(Bytes::from("Invalid url\n")))?)
+}
+
+/// `/{nevent}/embed`: a bare player for a video note, the page
+/// `twitter:player` points at. It's shown in an iframe, so it's only the
+/// video filling the frame. Only what we already have is embedded, this
+/// never goes out to the relays.
+pub fn serve_embed(app: &Notecrumbs, nip19: &Nip19) -> Result<Response<Full<Bytes>>, Error> {
+    let txn = Transaction::new(&app.ndb)?;
+    let note_rd = match render::get_render_data(&app.ndb, &txn, nip19) {
+        Ok(RenderData::Note(note_rd)) => note_rd,
+        _ => return not_found(),
+    };
+    let note = match note_rd.note_rd.lookup(&txn, &app.ndb) {
+        Ok(note) => note,
+        Err(_) => return not_found(),
+    };
+    let blocks = match note
+        .key()
+        .and_then(|key| app.ndb.get_blocks_by_key(&txn, key).ok())
+    {
+        Some(blocks) => blocks,
+        None => return not_found(),
+    };
+    let video = match dominant_video(&note, &blocks) {
+        Some(video) => video,
+        None => return not_found(),
+    };
+
+    let mut data = Vec::new();
+    write!(
+        data,
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="UTF-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <meta name="robots" content="noindex">
+  <style>html, body {{ margin: 0; height: 100%; background: #000; }} video {{ width: 100%; height: 100%; }}</style>
+</head>
+<body>
+  <video src="{}" controls playsinline preload="metadata"></video>
+</body>
+</html>
+"#,
+        html_escape::encode_double_quoted_attribute(video)
+    )?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/html")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(data)))?)
+}
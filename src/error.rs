@@ -18,8 +18,11 @@ pub enum Error {
     Generic(String),
     Timeout(tokio::time::error::Elapsed),
     Image(image::error::ImageError),
+    Fetch(reqwest::Error),
     Secp(nostr_sdk::secp256k1::Error),
     InvalidUri,
+    /// A url pointing somewhere we won't fetch from
+    Forbidden,
     NotFound,
     /// Profile picture is too big
     #[allow(dead_code)]
@@ -30,6 +33,10 @@ pub enum Error {
     #[allow(dead_code)]
     InvalidProfilePic,
     CantRender,
+    /// Too much going on to take this on right now
+    Overloaded,
+    /// We couldn't connect to a single relay we asked
+    RelaysUnreachable,
     SliceErr,
 }
 
@@ -39,6 +46,12 @@ impl From<image::error::ImageError> for Error {
     }
 }
 
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Fetch(err)
+    }
+}
+
 impl From<http::uri::InvalidUri> for Error {
     fn from(_err: http::uri::InvalidUri) -> Self {
         Error::InvalidUri
@@ -134,9 +147,13 @@ impl fmt::Display for Error {
             Error::TooBig => write!(f, "Profile picture is too big"),
             Error::InvalidProfilePic => write!(f, "Profile picture is corrupt"),
             Error::CantRender => write!(f, "Error rendering"),
+            Error::Overloaded => write!(f, "Too busy"),
+            Error::RelaysUnreachable => write!(f, "Couldn't connect to any relay"),
             Error::Image(err) => write!(f, "Image error: {}", err),
+            Error::Fetch(err) => write!(f, "Fetch error: {}", err),
             Error::Timeout(elapsed) => write!(f, "Timeout error: {}", elapsed),
             Error::InvalidUri => write!(f, "Invalid url"),
+            Error::Forbidden => write!(f, "Refusing to fetch a non public address"),
             Error::Hyper(err) => write!(f, "Hyper error: {}", err),
             Error::Generic(err) => write!(f, "Generic error: {}", err),
             Error::Io(err) => write!(f, "Io error: {}", err),
@@ -0,0 +1,342 @@
+use crate::{
+    abbrev::summarize,
+    html::{is_image, note_tag_value, url_extension},
+    markdown::render_markdown,
+    nip19::{naddr_for_note, nip19_pubkey},
+    Error, Notecrumbs,
+};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, Response, StatusCode};
+use nostr_sdk::prelude::{EventId, Nip19, ToBech32};
+use nostrdb::{Filter, QueryResult, Transaction};
+use serde_json::json;
+use std::io::Write;
+
+/// How many notes a profile feed carries
+const FEED_LIMIT: i32 = 50;
+
+/// Notes don't have titles, we make one out of the start of the content
+const ENTRY_TITLE_MAX_BYTES: usize = 80;
+
+/// How many articles an article feed carries, they're long
+const ARTICLE_FEED_LIMIT: usize = 20;
+
+fn rfc3339(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .unwrap_or_default()
+        .to_rfc3339()
+}
+
+/// RSS 2.0 wants RFC 822 dates
+fn rfc2822(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .unwrap_or_default()
+        .to_rfc2822()
+}
+
+fn image_mime(url: &str) -> String {
+    match url_extension(url).as_deref() {
+        Some("jpg") | None => "image/jpeg".to_string(),
+        Some(ext) => format!("image/{ext}"),
+    }
+}
+
+/// Control characters aren't allowed in XML 1.0, even escaped
+fn xml_text(text: &str) -> String {
+    let text: String = text
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+        .collect();
+    html_escape::encode_text(&text).into_owned()
+}
+
+/// A profile's recent notes, newest first
+fn profile_notes<'a>(
+    app: &Notecrumbs,
+    txn: &'a Transaction,
+    pubkey: &[u8; 32],
+) -> Result<Vec<QueryResult<'a>>, Error> {
+    let filter = Filter::new()
+        .authors([pubkey])
+        .kinds([1])
+        .limit(FEED_LIMIT as u64)
+        .build();
+    let mut results = app.ndb.query(txn, &[filter], FEED_LIMIT)?;
+    results.sort_by_key(|result| std::cmp::Reverse(result.note.created_at()));
+
+    // a thin feed is probably just what we happened to see
+    if results.len() < FEED_LIMIT as usize {
+        app.backfill.schedule(*pubkey);
+    }
+
+    Ok(results)
+}
+
+pub fn profile_name<'a>(app: &Notecrumbs, txn: &'a Transaction, pubkey: &[u8; 32]) -> &'a str {
+    app.ndb
+        .get_profile_by_pubkey(txn, pubkey)
+        .ok()
+        .and_then(|pr| pr.record().profile().and_then(|p| p.name()))
+        .unwrap_or("nostrich")
+}
+
+/// `/{npub}/rss`: an Atom feed of a profile's recent notes
+pub fn serve_profile_atom(
+    app: &Notecrumbs,
+    nip19: &Nip19,
+    base_url: &str,
+) -> Result<Response<Full<Bytes>>, Error> {
+    let pubkey = if let Some(pubkey) = nip19_pubkey(nip19) {
+        pubkey
+    } else {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::from("Invalid url\n")))?);
+    };
+
+    let txn = Transaction::new(&app.ndb)?;
+    let results = profile_notes(app, &txn, &pubkey)?;
+    let name = profile_name(app, &txn, &pubkey);
+
+    let bech32 = nip19.to_bech32()?;
+    let updated = results
+        .first()
+        .map(|result| result.note.created_at())
+        .unwrap_or(0);
+
+    let mut body: Vec<u8> = vec![];
+    write!(
+        body,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <id>{0}/{1}</id>
+  <title>{2} on nostr</title>
+  <updated>{3}</updated>
+  <link rel="alternate" type="text/html" href="{0}/{1}" />
+  <link rel="self" type="application/atom+xml" href="{0}/{1}/rss" />
+  <author>
+    <name>{2}</name>
+    <uri>{0}/{1}</uri>
+  </author>
+"#,
+        html_escape::encode_text(base_url),
+        bech32,
+        html_escape::encode_text(name),
+        rfc3339(updated),
+    )?;
+
+    for result in &results {
+        let note = &result.note;
+        let note_id = if let Some(note_id) = EventId::from_slice(note.id())
+            .ok()
+            .and_then(|id| id.to_bech32().ok())
+        {
+            note_id
+        } else {
+            continue;
+        };
+
+        let title = summarize(note.content(), ENTRY_TITLE_MAX_BYTES);
+
+        write!(
+            body,
+            r#"  <entry>
+    <id>{0}/{1}</id>
+    <title>{2}</title>
+    <updated>{3}</updated>
+    <link rel="alternate" type="text/html" href="{0}/{1}" />
+    <content type="text">{4}</content>
+  </entry>
+"#,
+            html_escape::encode_text(base_url),
+            note_id,
+            html_escape::encode_text(&title),
+            rfc3339(note.created_at()),
+            xml_text(note.content()),
+        )?;
+    }
+
+    writeln!(body, "</feed>")?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(body)))?)
+}
+
+/// `/{npub}/articles.xml`: an RSS feed of a profile's longform articles
+/// with their full content
+pub fn serve_articles_rss(
+    app: &Notecrumbs,
+    nip19: &Nip19,
+    base_url: &str,
+) -> Result<Response<Full<Bytes>>, Error> {
+    let pubkey = if let Some(pubkey) = nip19_pubkey(nip19) {
+        pubkey
+    } else {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::from("Invalid url\n")))?);
+    };
+
+    let txn = Transaction::new(&app.ndb)?;
+    let filter = Filter::new()
+        .authors([&pubkey])
+        .kinds([30023])
+        .limit(100)
+        .build();
+    let mut results = app.ndb.query(&txn, &[filter], 100)?;
+    results.sort_by_key(|result| std::cmp::Reverse(result.note.created_at()));
+
+    // older revisions of an article share its d tag
+    let mut seen: Vec<&str> = vec![];
+    let mut articles = Vec::with_capacity(ARTICLE_FEED_LIMIT);
+    for result in &results {
+        let d = note_tag_value(&result.note, "d").unwrap_or("");
+        if seen.contains(&d) {
+            continue;
+        }
+        seen.push(d);
+
+        if let Some(naddr) = naddr_for_note(&result.note) {
+            articles.push((naddr, &result.note));
+        }
+
+        if articles.len() == ARTICLE_FEED_LIMIT {
+            break;
+        }
+    }
+
+    let name = profile_name(app, &txn, &pubkey);
+
+    let bech32 = nip19.to_bech32()?;
+
+    let mut body: Vec<u8> = vec![];
+    write!(
+        body,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/" xmlns:atom="http://www.w3.org/2005/Atom">
+  <channel>
+    <title>{2}'s articles</title>
+    <link>{0}/{1}</link>
+    <description>Longform articles by {2} on nostr</description>
+    <atom:link rel="self" type="application/rss+xml" href="{0}/{1}/articles.xml" />
+"#,
+        html_escape::encode_text(base_url),
+        bech32,
+        html_escape::encode_text(name),
+    )?;
+
+    for (naddr, note) in articles {
+        // published_at is when the article first went out, created_at is
+        // bumped on every edit
+        let published_at = note_tag_value(note, "published_at")
+            .and_then(|ts| ts.parse::<u64>().ok())
+            .unwrap_or(note.created_at());
+
+        write!(
+            body,
+            r#"    <item>
+      <title>{2}</title>
+      <link>{0}/{1}</link>
+      <guid isPermaLink="true">{0}/{1}</guid>
+      <pubDate>{3}</pubDate>
+"#,
+            html_escape::encode_text(base_url),
+            naddr,
+            xml_text(note_tag_value(note, "title").unwrap_or("Untitled")),
+            rfc2822(published_at),
+        )?;
+
+        if let Some(summary) = note_tag_value(note, "summary") {
+            write!(
+                body,
+                "      <description>{}</description>\n",
+                xml_text(summary)
+            )?;
+        }
+
+        if let Some(image) = note_tag_value(note, "image").filter(|url| is_image(url)) {
+            // we don't know the size without fetching it, 0 is what
+            // readers expect in that case
+            write!(
+                body,
+                "      <enclosure url=\"{}\" length=\"0\" type=\"{}\" />\n",
+                html_escape::encode_double_quoted_attribute(image),
+                image_mime(image),
+            )?;
+        }
+
+        write!(
+            body,
+            "      <content:encoded>{}</content:encoded>\n    </item>\n",
+            xml_text(&render_markdown(note.content())),
+        )?;
+    }
+
+    write!(body, "  </channel>\n</rss>\n")?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(body)))?)
+}
+
+/// `/{npub}.jsonfeed`: a JSON Feed 1.1 of a profile's recent notes
+pub fn serve_profile_json_feed(
+    app: &Notecrumbs,
+    nip19: &Nip19,
+    base_url: &str,
+) -> Result<Response<Full<Bytes>>, Error> {
+    let pubkey = if let Some(pubkey) = nip19_pubkey(nip19) {
+        pubkey
+    } else {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::from("Invalid url\n")))?);
+    };
+
+    let txn = Transaction::new(&app.ndb)?;
+    let results = profile_notes(app, &txn, &pubkey)?;
+    let name = profile_name(app, &txn, &pubkey);
+    let picture = app
+        .ndb
+        .get_profile_by_pubkey(&txn, &pubkey)
+        .ok()
+        .and_then(|pr| pr.record().profile().and_then(|p| p.picture()));
+
+    let bech32 = nip19.to_bech32()?;
+
+    let items: Vec<serde_json::Value> = results
+        .iter()
+        .filter_map(|result| {
+            let note = &result.note;
+            let note_id = EventId::from_slice(note.id()).ok()?.to_bech32().ok()?;
+            Some(json!({
+                "id": format!("{base_url}/{note_id}"),
+                "url": format!("{base_url}/{note_id}"),
+                "content_text": note.content(),
+                "summary": summarize(note.content(), ENTRY_TITLE_MAX_BYTES),
+                "date_published": rfc3339(note.created_at()),
+            }))
+        })
+        .collect();
+
+    let feed = json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": format!("{name} on nostr"),
+        "home_page_url": format!("{base_url}/{bech32}"),
+        "feed_url": format!("{base_url}/{bech32}.jsonfeed"),
+        "authors": [{
+            "name": name,
+            "url": format!("{base_url}/{bech32}"),
+            "avatar": picture,
+        }],
+        "items": items,
+    });
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/feed+json; charset=utf-8")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(serde_json::to_vec(&feed)?)))?)
+}
@@ -0,0 +1,311 @@
+use crate::Error;
+use hyper::body::Bytes;
+use nostr_sdk::prelude::{Connection, ConnectionTarget, Keys, Options};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// The SOCKS5 proxy (eg: tor) all our outbound connections go through,
+/// when there is one
+static PROXY: OnceLock<SocketAddr> = OnceLock::new();
+
+/// Send relay connections and http fetches through a SOCKS5 proxy. Only
+/// clients made after this use it, so call it at startup.
+pub fn set_proxy(proxy: SocketAddr) {
+    let _ = PROXY.set(proxy);
+}
+
+/// A nostr client for talking to relays, through the proxy if we have
+/// one
+pub fn relay_client(keys: Keys) -> nostr_sdk::Client {
+    let builder = nostr_sdk::Client::builder().signer(keys);
+    match PROXY.get() {
+        Some(proxy) => builder
+            .opts(
+                Options::new().connection(
+                    Connection::new()
+                        .proxy(*proxy)
+                        .target(ConnectionTarget::All),
+                ),
+            )
+            .build(),
+        None => builder.build(),
+    }
+}
+
+/// Urls in notes and profiles are attacker controlled. Only addresses on
+/// the public internet are fetched, never our own network.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => is_public_ipv6(ip),
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, _, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // carrier grade nat
+        || (a == 100 && (64..128).contains(&b))
+        // reserved, including 0/8
+        || a == 0
+        || a >= 240)
+}
+
+/// The ipv4 address inside an ipv6 one, for the ranges that route to it
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let v4 = |hi: u16, lo: u16| Ipv4Addr::from(((hi as u32) << 16) | lo as u32);
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, hi, lo] => Some(v4(hi, lo)),
+        // NAT64, 64:ff9b::/96
+        [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => Some(v4(hi, lo)),
+        // 6to4, 2002::/16
+        [0x2002, hi, lo, ..] => Some(v4(hi, lo)),
+        _ => None,
+    }
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = embedded_ipv4(ip) {
+        return is_public_ipv4(ip);
+    }
+
+    let [first, second, third, ..] = ip.segments();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local
+        || (first & 0xfe00) == 0xfc00
+        // link local
+        || (first & 0xffc0) == 0xfe80
+        // local use NAT64, 64:ff9b:1::/48, translates to whatever the
+        // operator likes
+        || (first == 0x64 && second == 0xff9b && third == 1))
+}
+
+/// Resolves hostnames to their public addresses only, so a hostname
+/// pointing at 127.0.0.1 can't be used to reach us
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// The resolver doesn't see hosts that are already ip addresses, those
+/// are checked here
+fn is_allowed_url(url: &reqwest::Url) -> bool {
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+
+    let host = match url.host_str() {
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => return false,
+    };
+
+    match host.parse::<IpAddr>() {
+        Ok(ip) => is_public_ip(ip),
+        Err(_) => true,
+    }
+}
+
+/// Check a url before fetching it
+pub fn check_url(url: &str) -> Result<(), Error> {
+    let parsed = reqwest::Url::parse(url).map_err(|_| Error::InvalidUri)?;
+    if is_allowed_url(&parsed) {
+        Ok(())
+    } else {
+        Err(Error::Forbidden)
+    }
+}
+
+/// Whether to follow a redirect to `url`, after `hops` redirects
+fn check_redirect(
+    url: &reqwest::Url,
+    hops: usize,
+    max_redirects: usize,
+) -> Result<(), &'static str> {
+    if hops > max_redirects {
+        Err("too many redirects")
+    } else if !is_allowed_url(url) {
+        Err("redirect to a non public address")
+    } else {
+        Ok(())
+    }
+}
+
+/// An http client for urls we got from nostr: https capable, limited to
+/// public addresses and following at most `max_redirects` redirects,
+/// each of which is checked like the original url. Behind a proxy,
+/// hostnames are resolved by the proxy (so .onion urls work) and only ip
+/// address urls are checked.
+pub fn client(user_agent: &str, timeout: Duration, max_redirects: usize) -> reqwest::Client {
+    let redirects = Policy::custom(move |attempt| {
+        match check_redirect(attempt.url(), attempt.previous().len(), max_redirects) {
+            Ok(()) => attempt.follow(),
+            Err(problem) => attempt.error(problem),
+        }
+    });
+
+    let mut builder = reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(redirects)
+        .dns_resolver(Arc::new(PublicResolver))
+        .user_agent(user_agent);
+    if let Some(proxy) = PROXY.get() {
+        builder = builder
+            .proxy(reqwest::Proxy::all(format!("socks5h://{proxy}")).expect("socks5 proxy url"));
+    }
+
+    builder.build().expect("fetch client")
+}
+
+/// GET a checked url
+pub async fn get(client: &reqwest::Client, url: &str) -> Result<reqwest::Response, Error> {
+    check_url(url)?;
+    Ok(client.get(url).send().await?.error_for_status()?)
+}
+
+/// Read a response body, giving up on it past `max` bytes. Servers don't
+/// have to send a content length, or tell the truth in it.
+pub async fn read_body(mut res: reqwest::Response, max: usize) -> Result<Bytes, Error> {
+    if res.content_length().unwrap_or(0) as usize > max {
+        return Err(Error::TooBig);
+    }
+
+    let mut body: Vec<u8> = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        if body.len() + chunk.len() > max {
+            return Err(Error::TooBig);
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(Bytes::from(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_ips() {
+        let cases = [
+            ("1.1.1.1", true),
+            ("93.184.216.34", true),
+            ("2606:4700:4700::1111", true),
+            // loopback and unspecified
+            ("127.0.0.1", false),
+            ("127.1.2.3", false),
+            ("0.0.0.0", false),
+            ("::1", false),
+            ("::", false),
+            // rfc1918
+            ("10.0.0.1", false),
+            ("172.16.0.1", false),
+            ("172.31.255.255", false),
+            ("192.168.1.1", false),
+            // link local, cloud metadata lives here
+            ("169.254.169.254", false),
+            ("fe80::1", false),
+            // carrier grade nat
+            ("100.64.0.1", false),
+            ("100.127.255.255", false),
+            ("100.128.0.1", true),
+            // unique local
+            ("fc00::1", false),
+            ("fd12:3456::1", false),
+            // reserved, broadcast and multicast
+            ("240.0.0.1", false),
+            ("255.255.255.255", false),
+            ("224.0.0.1", false),
+            ("ff02::1", false),
+            // v4 mapped
+            ("::ffff:127.0.0.1", false),
+            ("::ffff:10.0.0.1", false),
+            ("::ffff:1.1.1.1", true),
+            // NAT64
+            ("64:ff9b::7f00:1", false),
+            ("64:ff9b::a9fe:a9fe", false),
+            ("64:ff9b::101:101", true),
+            ("64:ff9b:1::101:101", false),
+            // 6to4
+            ("2002:7f00:1::", false),
+            ("2002:c0a8:101::1", false),
+            ("2002:101:101::1", true),
+        ];
+
+        for (ip, public) in cases {
+            assert_eq!(is_public_ip(ip.parse().unwrap()), public, "{ip}");
+        }
+    }
+
+    #[test]
+    fn allowed_urls() {
+        let cases = [
+            ("https://example.com/a.png", true),
+            ("http://1.1.1.1/", true),
+            ("https://[2606:4700:4700::1111]/", true),
+            ("http://127.0.0.1:8080/", false),
+            ("http://[::1]/", false),
+            ("http://[::ffff:192.168.0.1]/", false),
+            ("http://[64:ff9b::a00:1]/", false),
+            ("http://169.254.169.254/latest/meta-data/", false),
+            // decimal and hex forms are normalized by the url parser
+            ("http://2130706433/", false),
+            ("http://0x7f.1/", false),
+            ("file:///etc/passwd", false),
+            ("ftp://example.com/", false),
+        ];
+
+        for (url, allowed) in cases {
+            let parsed = reqwest::Url::parse(url).unwrap();
+            assert_eq!(is_allowed_url(&parsed), allowed, "{url}");
+        }
+    }
+
+    #[test]
+    fn redirects() {
+        let url = |url: &str| reqwest::Url::parse(url).unwrap();
+
+        assert!(check_redirect(&url("https://example.com/"), 0, 3).is_ok());
+        assert!(check_redirect(&url("https://example.com/"), 4, 3).is_err());
+        assert!(check_redirect(&url("http://127.0.0.1/"), 0, 3).is_err());
+        assert!(check_redirect(&url("http://10.1.2.3/admin"), 0, 3).is_err());
+        assert!(check_redirect(&url("http://[fd00::1]/"), 0, 3).is_err());
+        assert!(check_redirect(&url("http://169.254.169.254/"), 0, 3).is_err());
+    }
+
+    #[tokio::test]
+    async fn private_urls_are_never_requested() {
+        let client = client("notecrumbs (test)", Duration::from_secs(1), 0);
+        for url in ["http://127.0.0.1:1/", "http://[::1]:1/", "http://10.0.0.1/"] {
+            assert!(
+                matches!(get(&client, url).await, Err(Error::Forbidden)),
+                "{url}"
+            );
+        }
+    }
+}
@@ -1,21 +1,290 @@
-// TODO: figure out the custom font situation
+use crate::{config::Config, fetch, Error};
+use nostr::hashes::{sha256::Hash as Sha256Hash, Hash};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
 
-pub fn setup_fonts(font_data: &egui::FontData, ctx: &egui::Context) {
+/// Downloaded fonts bigger than this are probably not fonts
+const MAX_FONT_SIZE: usize = 64 * 1024 * 1024;
+
+/// CJK fonts are tens of megabytes, give them a while
+const FETCH_TIMEOUT: Duration = Duration::from_secs(120);
+
+pub fn setup_fonts(fonts: &egui::FontDefinitions, ctx: &egui::Context) {
+    // Tell egui to use these fonts:
+    ctx.set_fonts(fonts.clone());
+}
+
+/// Whether `fonts` have a glyph for every character of each of `texts`
+pub fn covers(fonts: &egui::FontDefinitions, texts: &[&str]) -> Vec<bool> {
+    let ctx = egui::Context::default();
+    setup_fonts(fonts, &ctx);
+
+    // fonts are only loaded once a frame starts
+    let mut covered = vec![];
+    let _ = ctx.run(Default::default(), |ctx| {
+        let font_id = egui::FontId::proportional(20.0);
+        covered = ctx.fonts(|f| {
+            texts
+                .iter()
+                .map(|text| f.has_glyphs(&font_id, text))
+                .collect()
+        });
+    });
+    covered
+}
+
+/// What the NOTECRUMBS_EMOJI_FONT font is registered as
+const EMOJI_FONT_NAME: &str = "emoji";
+
+/// egui's bundled emoji fonts, monochrome
+const EGUI_EMOJI_FONTS: [&str; 2] = ["NotoEmoji-Regular", "emoji-icon-font"];
+
+/// DejaVu Sans, bundled for the arabic and hebrew Noto Sans doesn't have.
+/// It has the arabic presentation forms `render::bidi` shapes into.
+const RTL_FONT: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+const RTL_FONT_NAME: &str = "dejavu_sans";
+
+/// Our bundled font followed by any configured fallback fonts, which egui
+/// uses for glyphs our font doesn't have. Emoji fonts come right after
+/// ours so a fallback with a few stray symbols doesn't take emoji over.
+pub fn font_definitions(
+    font_data: egui::FontData,
+    fallbacks: Vec<(String, egui::FontData)>,
+) -> egui::FontDefinitions {
     let mut fonts = egui::FontDefinitions::default();
 
     // Install my own font (maybe supporting non-latin characters).
     // .ttf and .otf files supported.
-    fonts
-        .font_data
-        .insert("my_font".to_owned(), font_data.clone());
+    fonts.font_data.insert("my_font".to_owned(), font_data);
 
-    // Put my font first (highest priority) for proportional text:
-    fonts
+    let proportional = fonts
         .families
         .entry(egui::FontFamily::Proportional)
-        .or_default()
-        .insert(0, "my_font".to_owned());
+        .or_default();
 
-    // Tell egui to use these fonts:
-    ctx.set_fonts(fonts);
+    proportional.retain(|name| !EGUI_EMOJI_FONTS.contains(&name.as_str()));
+
+    // Put my font first (highest priority) for proportional text, then
+    // the emoji fonts
+    let mut order = vec!["my_font".to_owned()];
+    let (emoji, fallbacks): (Vec<_>, Vec<_>) = fallbacks
+        .into_iter()
+        .partition(|(name, _)| name == EMOJI_FONT_NAME);
+    order.extend(emoji.iter().map(|(name, _)| name.clone()));
+    order.extend(
+        EGUI_EMOJI_FONTS
+            .iter()
+            .filter(|name| fonts.font_data.contains_key(**name))
+            .map(|name| name.to_string()),
+    );
+
+    // Then the fallbacks, in the order they were configured, and last
+    // the rtl font, it has symbols that would take over emoji otherwise
+    order.extend(fallbacks.iter().map(|(name, _)| name.clone()));
+    order.push(RTL_FONT_NAME.to_owned());
+
+    for (i, name) in order.into_iter().enumerate() {
+        proportional.insert(i, name);
+    }
+
+    let fallbacks = emoji.into_iter().chain(fallbacks);
+
+    for (name, data) in fallbacks {
+        fonts.font_data.insert(name, data);
+    }
+    fonts.font_data.insert(
+        RTL_FONT_NAME.to_owned(),
+        egui::FontData::from_static(RTL_FONT),
+    );
+
+    fonts
+}
+
+pub fn is_url(source: &str) -> bool {
+    source.starts_with("https://") || source.starts_with("http://")
+}
+
+/// Split a font source into where it is and the checksum it's pinned to,
+/// from `{file or url}#sha256={hex}`
+pub fn parse_source(source: &str) -> (&str, Option<&str>) {
+    match source.rsplit_once("#sha256=") {
+        Some((location, sha256)) => (location, Some(sha256)),
+        None => (source, None),
+    }
+}
+
+fn sha256_matches(data: &[u8], expected: &str) -> bool {
+    hex::encode(Sha256Hash::hash(data).as_byte_array()).eq_ignore_ascii_case(expected)
+}
+
+fn font_cache_path(cache_dir: &str, url: &str) -> PathBuf {
+    let hash = Sha256Hash::hash(url.as_bytes());
+    Path::new(cache_dir).join(format!("{}.font", hex::encode(hash.as_byte_array())))
+}
+
+async fn download_font(url: &str) -> Result<Vec<u8>, Error> {
+    let client = fetch::client("notecrumbs (fonts)", FETCH_TIMEOUT, 3);
+    let res = fetch::get(&client, url).await?;
+    Ok(fetch::read_body(res, MAX_FONT_SIZE).await?.to_vec())
+}
+
+/// Check that font data is what it was pinned to and that egui can parse
+/// it. egui panics on fonts it can't parse, mid-render.
+fn verify(location: &str, sha256: Option<&str>, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if let Some(sha256) = sha256 {
+        if !sha256_matches(&data, sha256) {
+            return Err(Error::Generic(format!(
+                "{location} doesn't match sha256 {sha256}"
+            )));
+        }
+    }
+    if let Err(err) = ab_glyph::FontRef::try_from_slice(&data) {
+        return Err(Error::Generic(format!("{location} isn't a font: {err}")));
+    }
+    Ok(data)
+}
+
+/// Check a font file the way it'll be loaded, so a bad one is caught by
+/// config validation instead of on the first card
+pub fn check_font_file(location: &str, sha256: Option<&str>) -> Result<(), Error> {
+    verify(location, sha256, std::fs::read(location)?).map(|_| ())
+}
+
+/// Load a font file, or download a font we don't have cached yet. Urls
+/// can change under us, so downloads have to be pinned to a checksum.
+async fn load_font(config: &Config, source: &str) -> Result<Vec<u8>, Error> {
+    let (location, sha256) = parse_source(source);
+    let verified = |data: Vec<u8>| verify(location, sha256, data);
+
+    if !is_url(location) {
+        return verified(std::fs::read(location)?);
+    }
+    if sha256.is_none() {
+        return Err(Error::Generic(format!(
+            "{location} isn't pinned, add #sha256=<hex> to it"
+        )));
+    }
+
+    // a corrupt cached copy is downloaded again
+    let cache_path = font_cache_path(&config.font_cache_dir, source);
+    if let Some(data) = std::fs::read(&cache_path)
+        .ok()
+        .and_then(|data| verified(data).ok())
+    {
+        return Ok(data);
+    }
+
+    info!("downloading font {location}");
+    let data = verified(download_font(location).await?)?;
+
+    std::fs::create_dir_all(&config.font_cache_dir)?;
+    std::fs::write(&cache_path, &data)?;
+
+    Ok(data)
+}
+
+/// Load the configured emoji and fallback fonts. Fonts that fail to load
+/// are skipped, a flaky font host shouldn't keep us from starting.
+pub async fn load_fallback_fonts(config: &Config) -> Vec<(String, egui::FontData)> {
+    let emoji = config
+        .emoji_font
+        .iter()
+        .map(|source| (EMOJI_FONT_NAME.to_string(), source));
+    let fallbacks = config
+        .fonts
+        .iter()
+        .enumerate()
+        .map(|(i, source)| (format!("fallback_{i}"), source));
+
+    let mut fonts = Vec::with_capacity(config.fonts.len() + 1);
+    for (name, source) in emoji.chain(fallbacks) {
+        match load_font(config, source).await {
+            Ok(data) => {
+                info!("loaded fallback font {source}");
+                // fonts live for the whole process, leaking them lets egui
+                // borrow them instead of copying them on every render
+                let data: &'static [u8] = Box::leak(data.into_boxed_slice());
+                fonts.push((name, egui::FontData::from_static(data)));
+            }
+            Err(err) => warn!("skipping fallback font {source}: {err}"),
+        }
+    }
+
+    fonts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_fonts_cover_arabic_and_hebrew() {
+        let bundled = egui::FontData::from_static(include_bytes!("../fonts/NotoSans-Regular.ttf"));
+        let fonts = font_definitions(bundled, vec![]);
+
+        // shaped arabic is all presentation forms, lam-alef included
+        let texts = [
+            "مرحبا",
+            "\u{FEE3}\u{FEAE}\u{FEA3}\u{FE92}\u{FE8E} \u{FEFB}",
+            "שלום עולם",
+        ];
+        assert_eq!(covers(&fonts, &texts), [true, true, true]);
+    }
+
+    #[test]
+    fn emoji_resolve_without_configured_fonts() {
+        let bundled = egui::FontData::from_static(include_bytes!("../fonts/NotoSans-Regular.ttf"));
+        let fonts = font_definitions(bundled, vec![]);
+
+        assert_eq!(covers(&fonts, &["🤙🔥", "gm ☕"]), [true, true]);
+    }
+
+    const DEJAVU: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fonts/DejaVuSans.ttf");
+    const DEJAVU_SHA256: &str = "abdc775b21b1bc470d50c97e790d276f2054b7504e56e5bd3e64f48d68582322";
+
+    #[test]
+    fn sources() {
+        assert_eq!(parse_source("fonts/a.ttf"), ("fonts/a.ttf", None));
+        assert_eq!(
+            parse_source("https://example.com/a.ttf#sha256=abcd"),
+            ("https://example.com/a.ttf", Some("abcd"))
+        );
+    }
+
+    #[tokio::test]
+    async fn pinned_fonts_are_checked() {
+        let config = Config::default();
+
+        let pinned = format!("{DEJAVU}#sha256={DEJAVU_SHA256}");
+        assert!(load_font(&config, &pinned).await.is_ok());
+        let upper = format!("{DEJAVU}#sha256={}", DEJAVU_SHA256.to_uppercase());
+        assert!(load_font(&config, &upper).await.is_ok());
+
+        let wrong = format!("{DEJAVU}#sha256={}", "0".repeat(64));
+        assert!(load_font(&config, &wrong).await.is_err());
+    }
+
+    #[test]
+    fn font_files_are_parsed() {
+        assert!(check_font_file(DEJAVU, None).is_ok());
+        assert!(check_font_file(DEJAVU, Some(DEJAVU_SHA256)).is_ok());
+        assert!(check_font_file(DEJAVU, Some(&"0".repeat(64))).is_err());
+
+        let not_a_font = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+        let err = check_font_file(not_a_font, None).unwrap_err();
+        assert!(err.to_string().contains("isn't a font"), "{err}");
+
+        assert!(check_font_file("fonts/missing.ttf", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn unpinned_urls_are_refused() {
+        let config = Config::default();
+        // refused before anything goes out to the network
+        let err = load_font(&config, "https://example.com/font.ttf")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("isn't pinned"), "{err}");
+    }
 }
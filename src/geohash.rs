@@ -0,0 +1,85 @@
+/// The geohash alphabet, base32 without a, i, l and o
+const ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Longer geohashes are more precise than anyone's location needs
+const MAX_LEN: usize = 12;
+
+/// A decoded geohash: the middle of its cell and how long the cell's
+/// longer side is in degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub lat: f64,
+    pub lon: f64,
+    pub size: f64,
+}
+
+impl Point {
+    /// An OpenStreetMap zoom level that fits the cell
+    pub fn zoom(&self) -> u32 {
+        // zoom 0 shows all 360 degrees, each level halves that
+        let zoom = (360.0 / self.size).log2().floor();
+        zoom.clamp(2.0, 18.0) as u32
+    }
+
+    /// The OpenStreetMap page for this point, with a marker on it
+    pub fn osm_url(&self) -> String {
+        let (lat, lon, zoom) = (self.lat, self.lon, self.zoom());
+        format!("https://www.openstreetmap.org/?mlat={lat:.5}&mlon={lon:.5}#map={zoom}/{lat:.5}/{lon:.5}")
+    }
+}
+
+/// Decode a geohash, `None` when it isn't one. Case doesn't matter.
+pub fn decode(geohash: &str) -> Option<Point> {
+    if geohash.is_empty() || geohash.len() > MAX_LEN {
+        return None;
+    }
+
+    let mut lat = (-90.0, 90.0);
+    let mut lon = (-180.0, 180.0);
+    // bits alternate between longitude and latitude, longitude first
+    let mut is_lon = true;
+
+    for c in geohash.bytes() {
+        let value = ALPHABET.iter().position(|a| *a == c.to_ascii_lowercase())?;
+
+        for shift in (0..5).rev() {
+            let range: &mut (f64, f64) = if is_lon { &mut lon } else { &mut lat };
+            let mid = (range.0 + range.1) / 2.0;
+            if (value >> shift) & 1 == 1 {
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            is_lon = !is_lon;
+        }
+    }
+
+    Some(Point {
+        lat: (lat.0 + lat.1) / 2.0,
+        lon: (lon.0 + lon.1) / 2.0,
+        size: f64::max(lat.1 - lat.0, lon.1 - lon.0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes() {
+        // the example from the geohash wikipedia article
+        let point = decode("ezs42").unwrap();
+        assert!((point.lat - 42.605).abs() < 0.03, "{point:?}");
+        assert!((point.lon - -5.603).abs() < 0.03, "{point:?}");
+        assert_eq!(decode("EZS42"), Some(point));
+
+        assert!(decode("u4pruydqqvj").unwrap().zoom() > decode("u4").unwrap().zoom());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert_eq!(decode(""), None);
+        assert_eq!(decode("ezs4a"), None);
+        assert_eq!(decode("ezs42ezs42ezs42"), None);
+    }
+}
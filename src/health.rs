@@ -0,0 +1,29 @@
+use crate::{Error, Notecrumbs};
+use http_body_util::Full;
+use hyper::{body::Bytes, Response, StatusCode};
+use nostrdb::Transaction;
+
+fn plain(status: StatusCode, body: &'static str) -> Result<Response<Full<Bytes>>, Error> {
+    Ok(Response::builder()
+        .status(status)
+        .body(Full::new(Bytes::from(body)))?)
+}
+
+/// `/healthz`: we're up and answering requests
+pub fn serve_liveness() -> Result<Response<Full<Bytes>>, Error> {
+    plain(StatusCode::OK, "ok\n")
+}
+
+/// `/readyz`: we can actually serve pages, ndb opens and at least one
+/// default relay answered its last probe
+pub fn serve_readiness(app: &Notecrumbs) -> Result<Response<Full<Bytes>>, Error> {
+    if Transaction::new(&app.ndb).is_err() {
+        return plain(StatusCode::SERVICE_UNAVAILABLE, "ndb unavailable\n");
+    }
+
+    if !app.relays.any_answering() {
+        return plain(StatusCode::SERVICE_UNAVAILABLE, "no relay answering\n");
+    }
+
+    plain(StatusCode::OK, "ready\n")
+}
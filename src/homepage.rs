@@ -0,0 +1,200 @@
+use crate::{
+    config::HomepageSource, error::Result, html::write_feed_note, i18n::Locale,
+    relay_health::RelayHealth, render::fetch_events, Error, Notecrumbs,
+};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, Response, StatusCode};
+use nostr::event::kind::Kind;
+use nostr_sdk::prelude::{Event, EventId, Keys};
+use nostrdb::{Filter, Ndb, Note, Transaction};
+use std::io::Write;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How many notes the homepage shows
+const HOMEPAGE_FEED_LIMIT: usize = 20;
+
+/// How often we refresh the homepage feed from its source
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How long we let relays stream a refresh
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The notes on the homepage. For relay and curation sources this is
+/// refreshed in the background, requests only ever read from ndb.
+pub struct HomepageFeed {
+    ndb: Ndb,
+    keys: Keys,
+    source: HomepageSource,
+    relays: Arc<RelayHealth>,
+    ids: RwLock<Vec<[u8; 32]>>,
+}
+
+impl HomepageFeed {
+    pub fn new(ndb: Ndb, keys: Keys, source: HomepageSource, relays: Arc<RelayHealth>) -> Self {
+        HomepageFeed {
+            ndb,
+            keys,
+            source,
+            relays,
+            ids: RwLock::new(vec![]),
+        }
+    }
+
+    /// Keep the feed fresh. Does nothing for the default recent feed,
+    /// which is read straight from ndb.
+    pub async fn refresh_loop(self: Arc<Self>) {
+        if let HomepageSource::Recent = self.source {
+            return;
+        }
+
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            match self.refresh().await {
+                Ok(count) => info!("homepage feed refreshed with {count} notes"),
+                Err(err) => warn!("homepage feed refresh failed: {err}"),
+            }
+        }
+    }
+
+    async fn fetch(&self, relays: Vec<String>, filter: nostr::Filter) -> Result<Vec<Event>> {
+        fetch_events(
+            &self.ndb,
+            self.keys.clone(),
+            relays,
+            vec![filter],
+            REFRESH_TIMEOUT,
+        )
+        .await
+    }
+
+    async fn refresh(&self) -> Result<usize> {
+        let ids = match &self.source {
+            HomepageSource::Recent => return Ok(0),
+
+            HomepageSource::Relay(relay) => {
+                let filter = nostr::Filter::new()
+                    .kind(Kind::TextNote)
+                    .limit(HOMEPAGE_FEED_LIMIT);
+                let mut events = self.fetch(vec![relay.clone()], filter).await?;
+                events.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+                events.iter().map(|ev| ev.id.to_bytes()).collect()
+            }
+
+            HomepageSource::Curation(coord) => {
+                let filter = nostr::Filter::new()
+                    .kind(coord.kind)
+                    .author(coord.public_key)
+                    .identifier(coord.identifier.clone())
+                    .limit(1);
+                let events = self.fetch(self.relays.relays(), filter).await?;
+                let list = events
+                    .iter()
+                    .max_by_key(|ev| ev.created_at)
+                    .ok_or(Error::NotFound)?;
+
+                let ids: Vec<[u8; 32]> = list
+                    .tags
+                    .iter()
+                    .filter_map(|tag| match tag.as_slice() {
+                        [name, id, ..] if name == "e" => EventId::from_hex(id).ok(),
+                        _ => None,
+                    })
+                    .map(|id| id.to_bytes())
+                    .take(HOMEPAGE_FEED_LIMIT)
+                    .collect();
+
+                // pull in the curated notes we don't have yet
+                let missing: Vec<EventId> = {
+                    let txn = Transaction::new(&self.ndb)?;
+                    ids.iter()
+                        .filter(|id| self.ndb.get_note_by_id(&txn, id).is_err())
+                        .filter_map(|id| EventId::from_slice(id).ok())
+                        .collect()
+                };
+                if !missing.is_empty() {
+                    let filter = nostr::Filter::new().ids(missing);
+                    self.fetch(self.relays.relays(), filter).await?;
+                }
+
+                ids
+            }
+        };
+
+        let count = ids.len();
+        *self.ids.write().unwrap() = ids;
+        Ok(count)
+    }
+
+    fn notes<'a>(&self, txn: &'a Transaction) -> Vec<Note<'a>> {
+        if let HomepageSource::Recent = self.source {
+            let filter = Filter::new()
+                .kinds([1])
+                .limit(HOMEPAGE_FEED_LIMIT as u64)
+                .build();
+            let mut notes: Vec<Note<'a>> = self
+                .ndb
+                .query(txn, &[filter], HOMEPAGE_FEED_LIMIT as i32)
+                .map(|results| results.into_iter().map(|r| r.note).collect())
+                .unwrap_or_default();
+            notes.sort_by_key(|note| std::cmp::Reverse(note.created_at()));
+            return notes;
+        }
+
+        self.ids
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|id| self.ndb.get_note_by_id(txn, id).ok())
+            .collect()
+    }
+}
+
+/// `/`: the instance's front page
+pub fn serve_homepage(app: &Notecrumbs, locale: Locale) -> Result<Response<Full<Bytes>>> {
+    let mut data = Vec::new();
+    let txn = Transaction::new(&app.ndb)?;
+
+    write!(
+        data,
+        r#"<html lang="{}">
+<head>
+  <title>Damus</title>
+  <link rel="stylesheet" href="https://damus.io/css/notecrumbs.css" type="text/css" />
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <meta charset="UTF-8">
+</head>
+<body>
+  <main>
+    <div class="container">
+      <div class="homepage-feed">"#,
+        locale.html_lang()
+    )?;
+
+    for note in app.homepage.notes(&txn) {
+        let author = app
+            .ndb
+            .get_profile_by_pubkey(&txn, note.pubkey())
+            .ok()
+            .and_then(|pr| pr.record().profile().and_then(|p| p.name()));
+        write_feed_note(&mut data, &note, Some(author.unwrap_or("nostrich")), locale)?;
+    }
+
+    write!(
+        data,
+        r#"
+      </div>
+    </div>
+  </main>
+</body>
+</html>
+"#
+    )?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/html")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(data)))?)
+}
@@ -1,17 +1,27 @@
 use crate::Error;
 use crate::{
     abbrev::{abbrev_str, abbreviate},
-    render::{NoteAndProfileRenderData, NoteRenderData, ProfileRenderData},
+    geohash,
+    i18n::{Locale, Text},
+    link_preview::{LinkPreview, LinkPreviewCache},
+    markdown::render_markdown,
+    meta::{choose_og_image, og_description, Alternate, Authored, OgMeta, OgVideo, Schema},
+    music::music_embed,
+    nip19::{canonical_bech32, naddr_for_note},
+    oembed::oembed_url,
+    preview_prefs::preview_prefs,
+    qr::qr_svg,
+    render::{lookup_address, NoteAndProfileRenderData, NoteRenderData, ProfileRenderData},
     Notecrumbs,
 };
 use http_body_util::Full;
 use hyper::{body::Bytes, header, Request, Response, StatusCode};
-use nostr_sdk::prelude::{Nip19, ToBech32};
-use nostrdb::{BlockType, Blocks, Filter, Mention, Ndb, Note, Transaction};
+use nostr_sdk::prelude::{Coordinate, EventId, Nip19, PublicKey, ToBech32};
+use nostrdb::{BlockType, Blocks, Filter, Mention, Ndb, NdbStrVariant, Note, Transaction};
 use std::io::Write;
 use tracing::{error, warn};
 
-fn blocktype_name(blocktype: &BlockType) -> &'static str {
+pub fn blocktype_name(blocktype: &BlockType) -> &'static str {
     match blocktype {
         BlockType::MentionBech32 => "mention",
         BlockType::Hashtag => "hashtag",
@@ -22,6 +32,290 @@ fn blocktype_name(blocktype: &BlockType) -> &'static str {
     }
 }
 
+/// The first string value of the first tag with the given name, eg: the
+/// `title` of a longform article
+pub fn note_tag_value<'a>(note: &Note<'a>, name: &str) -> Option<&'a str> {
+    for tag in note.tags() {
+        if tag.count() < 2 {
+            continue;
+        }
+
+        let key = match tag.get(0).map(|s| s.variant()) {
+            Some(NdbStrVariant::Str(key)) => key,
+            _ => continue,
+        };
+
+        if key != name {
+            continue;
+        }
+
+        if let Some(NdbStrVariant::Str(value)) = tag.get(1).map(|s| s.variant()) {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Like [`note_tag_value`], for tags holding an event id. nostrdb stores
+/// those packed, but they may also show up as hex.
+pub fn note_tag_id(note: &Note, name: &str) -> Option<[u8; 32]> {
+    for tag in note.tags() {
+        if tag.count() < 2 {
+            continue;
+        }
+
+        if !matches!(tag.get(0).map(|s| s.variant()), Some(NdbStrVariant::Str(key)) if key == name)
+        {
+            continue;
+        }
+
+        return match tag.get(1).map(|s| s.variant()) {
+            Some(NdbStrVariant::Id(id)) => Some(*id),
+            Some(NdbStrVariant::Str(id)) => hex::decode(id).ok().and_then(|id| id.try_into().ok()),
+            None => None,
+        };
+    }
+
+    None
+}
+
+pub fn url_extension(url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let (_, ext) = path.rsplit_once('.')?;
+    if ext.contains('/') {
+        return None;
+    }
+    Some(ext.to_ascii_lowercase())
+}
+
+pub fn is_image(url: &str) -> bool {
+    matches!(
+        url_extension(url).as_deref(),
+        Some("jpg" | "jpeg" | "png" | "gif" | "webp" | "avif")
+    )
+}
+
+pub fn is_audio(url: &str) -> bool {
+    matches!(
+        url_extension(url).as_deref(),
+        Some("mp3" | "ogg" | "oga" | "wav" | "flac" | "m4a")
+    )
+}
+
+pub fn is_video(url: &str) -> bool {
+    matches!(
+        url_extension(url).as_deref(),
+        Some("mp4" | "webm" | "mov" | "m4v" | "ogv" | "m3u8")
+    )
+}
+
+/// Urls we render ourselves instead of linking to
+fn is_media(url: &str) -> bool {
+    is_image(url) || is_video(url) || is_audio(url) || music_embed(url).is_some()
+}
+
+/// Plain links in a note that could get a link preview
+pub fn note_link_urls(ndb: &Ndb, note_rd: &NoteAndProfileRenderData) -> Vec<String> {
+    let note_key = match note_rd.note_rd {
+        NoteRenderData::Note(note_key) => note_key,
+        NoteRenderData::Missing(_) | NoteRenderData::MissingAddress(_) => return vec![],
+    };
+
+    let txn = if let Ok(txn) = Transaction::new(ndb) {
+        txn
+    } else {
+        return vec![];
+    };
+
+    let (note, blocks) = match (
+        ndb.get_note_by_key(&txn, note_key),
+        ndb.get_blocks_by_key(&txn, note_key),
+    ) {
+        (Ok(note), Ok(blocks)) => (note, blocks),
+        _ => return vec![],
+    };
+
+    blocks
+        .iter(&note)
+        .filter(|block| matches!(block.blocktype(), BlockType::Url))
+        .map(|block| block.as_str())
+        .filter(|url| !is_media(url))
+        .map(|url| url.to_string())
+        .collect()
+}
+
+fn render_link_preview(body: &mut Vec<u8>, url: &str, preview: &LinkPreview) {
+    let host = url
+        .split("://")
+        .nth(1)
+        .and_then(|rest| rest.split('/').next())
+        .unwrap_or(url);
+
+    let _ = write!(
+        body,
+        r#"<a href="{}" class="link-preview">"#,
+        html_escape::encode_double_quoted_attribute(url)
+    );
+
+    if let Some(image) = &preview.image {
+        let _ = write!(
+            body,
+            r#"<img src="{}" class="link-preview-image" loading="lazy" />"#,
+            html_escape::encode_double_quoted_attribute(image)
+        );
+    }
+
+    let _ = write!(
+        body,
+        r#"<div class="link-preview-text"><div class="link-preview-title">{}</div>"#,
+        html_escape::encode_text(&preview.title)
+    );
+
+    if let Some(description) = &preview.description {
+        let _ = write!(
+            body,
+            r#"<div class="link-preview-description">{}</div>"#,
+            html_escape::encode_text(abbreviate(description, 200))
+        );
+    }
+
+    let _ = write!(
+        body,
+        r#"<div class="link-preview-host">{}</div></div></a>"#,
+        html_escape::encode_text(host)
+    );
+}
+
+/// The file name at the end of a url, used as a label for media links
+fn url_filename(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn render_url(body: &mut Vec<u8>, url: &str, previews: &LinkPreviewCache) {
+    if let Some(embed) = music_embed(url) {
+        let _ = write!(
+            body,
+            r#"<div class="music-embed music-embed-{}"><iframe src="{}" height="{}" width="100%" frameborder="0" loading="lazy" allow="autoplay; clipboard-write; encrypted-media; fullscreen"></iframe></div>"#,
+            embed.platform.name(),
+            html_escape::encode_double_quoted_attribute(&embed.embed_url),
+            embed.platform.player_height(embed.is_track),
+        );
+    } else if is_audio(url) {
+        let _ = write!(
+            body,
+            r#"<div class="audio-card"><a href="{0}" class="audio-card-title">{1}</a><audio controls preload="none" src="{0}"></audio></div>"#,
+            html_escape::encode_double_quoted_attribute(url),
+            html_escape::encode_text(url_filename(url)),
+        );
+    } else if let Some(preview) = previews.get(url) {
+        render_link_preview(body, url, &preview);
+    } else {
+        let url = html_escape::encode_text(url);
+        let _ = write!(body, r#"<a href="{}">{}</a>"#, url, url);
+    }
+}
+
+/// The first image url in the note's content, if any
+pub fn first_image<'a>(note: &Note<'a>, blocks: &Blocks<'a>) -> Option<&'a str> {
+    blocks
+        .iter(note)
+        .filter(|block| matches!(block.blocktype(), BlockType::Url))
+        .map(|block| block.as_str())
+        .find(|url| is_image(url))
+}
+
+/// What a note says in words, without its urls and mentions
+fn note_words<'a>(note: &Note<'a>, blocks: &Blocks<'a>) -> String {
+    blocks
+        .iter(note)
+        .filter(|block| matches!(block.blocktype(), BlockType::Text | BlockType::Hashtag))
+        .map(|block| block.as_str())
+        .collect()
+}
+
+/// Image urls from an author's most recent notes, newest first
+pub fn recent_media_urls(
+    ndb: &Ndb,
+    txn: &Transaction,
+    pubkey: &[u8; 32],
+    count: usize,
+) -> Result<Vec<String>, Error> {
+    let filter = Filter::new()
+        .authors([pubkey])
+        .kinds([1])
+        .limit(100)
+        .build();
+    let mut results = ndb.query(txn, &[filter], 100)?;
+    results.sort_by_key(|result| std::cmp::Reverse(result.note.created_at()));
+
+    let mut urls: Vec<String> = Vec::with_capacity(count);
+    for result in &results {
+        let note = &result.note;
+        let blocks = if let Some(blocks) = note
+            .key()
+            .and_then(|key| ndb.get_blocks_by_key(txn, key).ok())
+        {
+            blocks
+        } else {
+            continue;
+        };
+
+        for block in blocks.iter(note) {
+            if matches!(block.blocktype(), BlockType::Url) && is_image(block.as_str()) {
+                let url = block.as_str().to_owned();
+                if !urls.contains(&url) {
+                    urls.push(url);
+                }
+            }
+
+            if urls.len() == count {
+                return Ok(urls);
+            }
+        }
+    }
+
+    Ok(urls)
+}
+
+/// More text than this around a video and the note is about the text
+const VIDEO_CAPTION_MAX: usize = 280;
+
+/// The video a note is all about: its first media link is a video, and
+/// the text around it is no more than a caption. Previews of these notes
+/// should play the video.
+pub fn dominant_video<'a>(note: &Note<'a>, blocks: &Blocks<'a>) -> Option<&'a str> {
+    let mut video = None;
+    let mut caption = 0;
+
+    for block in blocks.iter(note) {
+        match block.blocktype() {
+            BlockType::Url if video.is_none() => {
+                let url = block.as_str();
+                if is_video(url) {
+                    video = Some(url);
+                } else if is_image(url) {
+                    return None;
+                }
+            }
+            BlockType::Text => caption += block.as_str().trim().len(),
+            _ => {}
+        }
+    }
+
+    video.filter(|_| caption <= VIDEO_CAPTION_MAX)
+}
+
+/// The description we advertise for a note: an article's summary, or the
+/// note's own content
+fn note_description(note: &Note) -> String {
+    match note_tag_value(note, "summary") {
+        Some(summary) if note.kind() == 30023 => og_description(summary),
+        _ => og_description(note.content()),
+    }
+}
+
 pub fn serve_note_json(
     ndb: &Ndb,
     note_rd: &NoteAndProfileRenderData,
@@ -34,6 +328,10 @@ pub fn serve_note_json(
             warn!("missing note_id {}", hex::encode(note_id));
             return Err(Error::NotFound);
         }
+        NoteRenderData::MissingAddress(ref coord) => {
+            warn!("missing note {coord}");
+            return Err(Error::NotFound);
+        }
     };
 
     let txn = Transaction::new(ndb)?;
@@ -45,23 +343,14 @@ pub fn serve_note_json(
         return Err(Error::NotFound);
     };
 
-    write!(body, "{{\"note\":{},\"parsed_content\":[", &note.json()?)?;
-
-    if let Ok(blocks) = ndb.get_blocks_by_key(&txn, note_key) {
-        for (i, block) in blocks.iter(&note).enumerate() {
-            if i != 0 {
-                write!(body, ",")?;
-            }
-            write!(
-                body,
-                "{{\"{}\":{}}}",
-                blocktype_name(&block.blocktype()),
-                serde_json::to_string(block.as_str())?
-            )?;
-        }
-    };
+    write!(body, "{{")?;
+    write_note_fields(&mut body, ndb, &txn, &note)?;
 
-    write!(body, "]")?;
+    write!(
+        body,
+        ",\"description\":{}",
+        serde_json::to_string(&note_description(&note))?
+    )?;
 
     if let Ok(results) = ndb.query(
         &txn,
@@ -77,6 +366,26 @@ pub fn serve_note_json(
         }
     }
 
+    let thread = thread_refs(&note);
+    for (name, id) in [("root", thread.root), ("parent", thread.parent)] {
+        write!(body, ",\"{name}\":")?;
+        match id {
+            Some(id) => write_thread_note(&mut body, ndb, &txn, &id)?,
+            None => write!(body, "null")?,
+        }
+    }
+
+    write!(body, ",\"replies\":[")?;
+    for (i, reply) in cached_replies(ndb, &txn, note.id()).iter().enumerate() {
+        if i != 0 {
+            write!(body, ",")?;
+        }
+        write!(body, "{{")?;
+        write_note_fields(&mut body, ndb, &txn, reply)?;
+        write!(body, "}}")?;
+    }
+    write!(body, "]")?;
+
     writeln!(body, "}}")?;
 
     Ok(Response::builder()
@@ -85,13 +394,242 @@ pub fn serve_note_json(
         .body(Full::new(Bytes::from(body)))?)
 }
 
-pub fn render_note_content(body: &mut Vec<u8>, note: &Note, blocks: &Blocks) {
+/// A note's `"note"` and `"parsed_content"` fields for the json output
+fn write_note_fields(
+    body: &mut Vec<u8>,
+    ndb: &Ndb,
+    txn: &Transaction,
+    note: &Note,
+) -> Result<(), Error> {
+    write!(body, "\"note\":{},\"parsed_content\":[", note.json()?)?;
+
+    if let Some(blocks) = note
+        .key()
+        .and_then(|key| ndb.get_blocks_by_key(txn, key).ok())
+    {
+        for (i, block) in blocks.iter(note).enumerate() {
+            if i != 0 {
+                write!(body, ",")?;
+            }
+            write!(
+                body,
+                "{{\"{}\":{}}}",
+                blocktype_name(&block.blocktype()),
+                serde_json::to_string(block.as_str())?
+            )?;
+        }
+    };
+
+    write!(body, "]")?;
+    Ok(())
+}
+
+/// A note elsewhere in the thread: always its id, and the note itself
+/// when we have it
+fn write_thread_note(
+    body: &mut Vec<u8>,
+    ndb: &Ndb,
+    txn: &Transaction,
+    id: &[u8; 32],
+) -> Result<(), Error> {
+    write!(body, "{{\"id\":\"{}\"", hex::encode(id))?;
+    if let Ok(note) = ndb.get_note_by_id(txn, id) {
+        write!(body, ",")?;
+        write_note_fields(body, ndb, txn, &note)?;
+    }
+    write!(body, "}}")?;
+    Ok(())
+}
+
+/// How many replies the json output includes at most
+const JSON_REPLIES_LIMIT: i32 = 50;
+
+/// The direct replies to a note that we have, oldest first so they read
+/// as a conversation
+pub fn cached_replies<'a>(ndb: &Ndb, txn: &'a Transaction, note_id: &[u8; 32]) -> Vec<Note<'a>> {
+    let filter = Filter::new()
+        .kinds([1])
+        .event(note_id)
+        .limit(JSON_REPLIES_LIMIT as u64)
+        .build();
+    let mut replies: Vec<Note<'a>> = ndb
+        .query(txn, &[filter], JSON_REPLIES_LIMIT)
+        .map(|results| results.into_iter().map(|result| result.note).collect())
+        .unwrap_or_default();
+
+    // a note mentioning this one, or replying further down the thread,
+    // isn't a reply to it
+    replies.retain(|reply| thread_refs(reply).parent.as_ref() == Some(note_id));
+    replies.sort_by_key(|reply| reply.created_at());
+    replies
+}
+
+/// Where a reply sits in its thread
+#[derive(Default)]
+pub struct ThreadRefs {
+    pub root: Option<[u8; 32]>,
+    pub parent: Option<[u8; 32]>,
+}
+
+/// The root and parent of a text note, from its `e` tags. NIP-10 marks
+/// them, older clients put the root first and the parent last instead.
+pub fn thread_refs(note: &Note) -> ThreadRefs {
+    if note.kind() != 1 {
+        return ThreadRefs::default();
+    }
+
+    let mut root = None;
+    let mut parent = None;
+    let mut unmarked = vec![];
+
+    for tag in note.tags() {
+        if tag.count() < 2 {
+            continue;
+        }
+
+        if !matches!(
+            tag.get(0).map(|s| s.variant()),
+            Some(NdbStrVariant::Str("e"))
+        ) {
+            continue;
+        }
+
+        let id = match tag.get(1).map(|s| s.variant()) {
+            Some(NdbStrVariant::Id(id)) => *id,
+            Some(NdbStrVariant::Str(id)) => {
+                match hex::decode(id).ok().and_then(|id| id.try_into().ok()) {
+                    Some(id) => id,
+                    None => continue,
+                }
+            }
+            None => continue,
+        };
+
+        match tag.get(3).map(|s| s.variant()) {
+            Some(NdbStrVariant::Str("root")) => root = Some(id),
+            Some(NdbStrVariant::Str("reply")) => parent = Some(id),
+            Some(NdbStrVariant::Str("mention")) => {}
+            _ => unmarked.push(id),
+        }
+    }
+
+    if root.is_none() && parent.is_none() {
+        root = unmarked.first().copied();
+        parent = unmarked.last().copied();
+    }
+
+    ThreadRefs {
+        root,
+        // a direct reply to the root only marks the root
+        parent: parent.or(root),
+    }
+}
+
+/// The `dim WxH` of an image from the note's NIP-92 imeta tags
+fn imeta_dim(note: &Note, url: &str) -> Option<(u32, u32)> {
+    for tag in note.tags() {
+        let values: Vec<&str> = (0..tag.count())
+            .filter_map(|i| match tag.get(i).map(|s| s.variant()) {
+                Some(NdbStrVariant::Str(s)) => Some(s),
+                _ => None,
+            })
+            .collect();
+
+        if values.first() != Some(&"imeta") || !values.contains(&format!("url {url}").as_str()) {
+            continue;
+        }
+
+        let dim = values.iter().find_map(|v| v.strip_prefix("dim "))?;
+        let (w, h) = dim.split_once('x')?;
+        return Some((w.trim().parse().ok()?, h.trim().parse().ok()?));
+    }
+
+    None
+}
+
+/// Show at most this many images in a gallery, the last one gets a +N
+const GALLERY_MAX: usize = 4;
+
+/// The gallery's layout. notecrumbs.css on damus.io doesn't know about
+/// galleries, so note pages carry it themselves.
+const GALLERY_STYLE: &str = r#"<style>
+            .image-gallery { display: grid; grid-template-columns: 1fr 1fr; gap: 4px; border-radius: 12px; overflow: hidden; }
+            .image-gallery-item { position: relative; display: block; }
+            .image-gallery-item img { display: block; width: 100%; height: 100%; object-fit: cover; }
+            .image-gallery-3 .image-gallery-item:first-child { grid-row: span 2; }
+            .image-gallery-more { position: absolute; inset: 0; display: flex; align-items: center; justify-content: center; background: rgba(0, 0, 0, 0.5); color: white; font-size: 2em; }
+          </style>"#;
+
+fn render_gallery(body: &mut Vec<u8>, note: &Note, images: &[&str]) {
+    if let [url] = images {
+        // single images keep their own aspect ratio, if we know it
+        let aspect = imeta_dim(note, url)
+            .map(|(w, h)| format!(r#" style="aspect-ratio: {w} / {h}""#))
+            .unwrap_or_default();
+        let _ = write!(
+            body,
+            r#"<a href="{0}"><img src="{0}" class="note-image" loading="lazy"{1} /></a>"#,
+            html_escape::encode_double_quoted_attribute(url),
+            aspect,
+        );
+        return;
+    }
+
+    // all portrait images get taller cells, otherwise squares
+    let portrait = images
+        .iter()
+        .take(GALLERY_MAX)
+        .all(|url| imeta_dim(note, url).map(|(w, h)| h > w).unwrap_or(false));
+    let cell_aspect = if portrait { "3 / 4" } else { "1 / 1" };
+
+    let shown = images.len().min(GALLERY_MAX);
+    let _ = write!(body, r#"<div class="image-gallery image-gallery-{shown}">"#);
+
+    for (i, url) in images.iter().take(shown).enumerate() {
+        // with three images the stylesheet stretches the first one down the
+        // whole left column, it takes the column's height
+        let aspect = if shown == 3 && i == 0 {
+            "auto"
+        } else {
+            cell_aspect
+        };
+        let url = html_escape::encode_double_quoted_attribute(url);
+
+        let _ = write!(
+            body,
+            r#"<a href="{url}" class="image-gallery-item"><img src="{url}" loading="lazy" style="aspect-ratio: {aspect}" />"#
+        );
+
+        let hidden = images.len() - shown;
+        if i == shown - 1 && hidden > 0 {
+            let _ = write!(body, r#"<span class="image-gallery-more">+{hidden}</span>"#);
+        }
+
+        let _ = write!(body, "</a>");
+    }
+
+    let _ = write!(body, "</div>");
+}
+
+pub fn render_note_content(
+    body: &mut Vec<u8>,
+    note: &Note,
+    blocks: &Blocks,
+    previews: &LinkPreviewCache,
+) {
+    // images are pulled out of the text and shown together at the end
+    let images: Vec<&str> = blocks
+        .iter(note)
+        .filter(|block| matches!(block.blocktype(), BlockType::Url))
+        .map(|block| block.as_str())
+        .filter(|url| is_image(url))
+        .collect();
+
     for block in blocks.iter(note) {
         match block.blocktype() {
-            BlockType::Url => {
-                let url = html_escape::encode_text(block.as_str());
-                let _ = write!(body, r#"<a href="{}">{}</a>"#, url, url);
-            }
+            BlockType::Url if is_image(block.as_str()) => {}
+
+            BlockType::Url => render_url(body, block.as_str(), previews),
 
             BlockType::Hashtag => {
                 let hashtag = html_escape::encode_text(block.as_str());
@@ -139,24 +677,551 @@ pub fn render_note_content(body: &mut Vec<u8>, note: &Note, blocks: &Blocks) {
             }
         };
     }
+
+    if !images.is_empty() {
+        render_gallery(body, note, &images);
+    }
 }
 
-pub fn serve_note_html(
+/// A single note in a feed, linking to its own page
+pub fn write_feed_note(
+    body: &mut Vec<u8>,
+    note: &Note,
+    author: Option<&str>,
+    locale: Locale,
+) -> std::io::Result<()> {
+    let bech32 = if let Some(bech32) = EventId::from_slice(note.id())
+        .ok()
+        .and_then(|id| id.to_bech32().ok())
+    {
+        bech32
+    } else {
+        return Ok(());
+    };
+
+    write!(body, r#"<a class="feed-note" href="/{bech32}">"#)?;
+
+    if let Some(author) = author {
+        write!(
+            body,
+            r#"<div class="note-author-name">{}</div>"#,
+            html_escape::encode_text(author)
+        )?;
+    }
+
+    let now = nostr::types::Timestamp::now().as_u64();
+    write!(
+        body,
+        r#"<div class="note-timestamp" title="{}">{}</div><div class="note-content">{}</div></a>"#,
+        locale.date(note.created_at()),
+        locale.relative_time(note.created_at(), now),
+        html_escape::encode_text(abbreviate(note.content(), 280)),
+    )
+}
+
+/// How many recent notes we show on a profile page
+pub const PROFILE_FEED_RECENT_LIMIT: i32 = 12;
+
+/// A profile's most recent notes, newest first. Returns how many notes we
+/// had cached.
+pub fn render_profile_feed(
+    body: &mut Vec<u8>,
+    ndb: &Ndb,
+    txn: &Transaction,
+    pubkey: &[u8; 32],
+    locale: Locale,
+) -> Result<usize, Error> {
+    let filter = Filter::new()
+        .authors([pubkey])
+        .kinds([1])
+        .limit(PROFILE_FEED_RECENT_LIMIT as u64)
+        .build();
+    let mut results = ndb.query(txn, &[filter], PROFILE_FEED_RECENT_LIMIT)?;
+    results.sort_by_key(|result| std::cmp::Reverse(result.note.created_at()));
+
+    write!(body, r#"<div class="profile-feed">"#)?;
+
+    for result in &results {
+        write_feed_note(body, &result.note, None, locale)?;
+    }
+
+    write!(body, "</div>")?;
+
+    Ok(results.len())
+}
+
+/// Phones and tablets can open `nostr:` links themselves, everyone else
+/// gets a QR code to hand the note off to their phone
+fn is_desktop<B>(r: &Request<B>) -> bool {
+    let ua = r
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .unwrap_or("");
+
+    !["Mobile", "Android", "iPhone", "iPad"]
+        .iter()
+        .any(|mobile| ua.contains(mobile))
+}
+
+fn write_handoff_panel(body: &mut Vec<u8>, bech32: &str, locale: Locale) -> std::io::Result<()> {
+    let qr = if let Some(qr) = qr_svg(&format!("nostr:{bech32}"), 160) {
+        qr
+    } else {
+        return Ok(());
+    };
+
+    write!(
+        body,
+        r#"
+               <div class="handoff-panel">
+                 <div class="handoff-qr">{qr}</div>
+                 <div class="handoff-text">{}</div>
+               </div>"#,
+        locale.text(Text::ScanToOpen)
+    )
+}
+
+/// The note a reaction is for: its last `e` tag, per NIP-25
+fn reaction_target(note: &Note) -> Option<[u8; 32]> {
+    let mut target = None;
+
+    for tag in note.tags() {
+        if tag.count() < 2 {
+            continue;
+        }
+
+        if !matches!(
+            tag.get(0).map(|s| s.variant()),
+            Some(NdbStrVariant::Str("e"))
+        ) {
+            continue;
+        }
+
+        target = match tag.get(1).map(|s| s.variant()) {
+            Some(NdbStrVariant::Id(id)) => Some(*id),
+            Some(NdbStrVariant::Str(id)) => hex::decode(id).ok().and_then(|id| id.try_into().ok()),
+            None => None,
+        }
+        .or(target);
+    }
+
+    target
+}
+
+/// The image for a NIP-30 custom emoji shortcode used in the note
+fn custom_emoji_url<'a>(note: &Note<'a>, shortcode: &str) -> Option<&'a str> {
+    for tag in note.tags() {
+        if tag.count() < 3 {
+            continue;
+        }
+
+        let values: Vec<Option<&'a str>> = (0..3)
+            .map(|i| match tag.get(i).map(|s| s.variant()) {
+                Some(NdbStrVariant::Str(s)) => Some(s),
+                _ => None,
+            })
+            .collect();
+
+        if let [Some("emoji"), Some(code), Some(url)] = values[..] {
+            if code == shortcode {
+                return Some(url);
+            }
+        }
+    }
+
+    None
+}
+
+/// Where a note says it was posted from, from its `g` (geohash) and
+/// `location` tags. Linked to a map when there's a geohash.
+fn location_chip(note: &Note) -> Option<String> {
+    let mut geohashes = vec![];
+    for tag in note.tags() {
+        if tag.count() < 2 {
+            continue;
+        }
+
+        if let (Some(NdbStrVariant::Str("g")), Some(NdbStrVariant::Str(geohash))) = (
+            tag.get(0).map(|s| s.variant()),
+            tag.get(1).map(|s| s.variant()),
+        ) {
+            geohashes.push(geohash);
+        }
+    }
+
+    // clients add a few of different precision, the longest is the best
+    let point = geohashes
+        .into_iter()
+        .filter_map(geohash::decode)
+        .min_by(|a, b| a.size.total_cmp(&b.size));
+    let location = note_tag_value(note, "location")
+        .map(str::trim)
+        .filter(|location| !location.is_empty());
+
+    let label = match (location, &point) {
+        (Some(location), _) => abbreviate(location, 80).to_owned(),
+        (None, Some(point)) => format!("{:.3}, {:.3}", point.lat, point.lon),
+        (None, None) => return None,
+    };
+    let label = html_escape::encode_text(&label);
+
+    Some(match point {
+        Some(point) => format!(
+            r#"<a class="note-location" href="{}" target="_blank" rel="noopener">📍 {label}</a>"#,
+            html_escape::encode_double_quoted_attribute(&point.osm_url())
+        ),
+        None => format!(r#"<div class="note-location">📍 {label}</div>"#),
+    })
+}
+
+/// Less proof of work than this is a few seconds on a phone, not worth
+/// pointing out
+const MIN_POW_BADGE: u32 = 16;
+
+/// The leading zero bits of a note's id
+fn leading_zero_bits(id: &[u8; 32]) -> u32 {
+    let mut bits = 0;
+    for byte in id {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// How much work a note's id shows (NIP-13), when it was mined on
+/// purpose and that's enough to be worth a badge. Only notes with a
+/// `nonce` tag count, and when they commit to a target the id has to
+/// meet it, so a lucky id doesn't pass for more work than was done.
+fn pow_difficulty(note: &Note) -> Option<u32> {
+    let mut target = None;
+    let mut mined = false;
+    for tag in note.tags() {
+        if !matches!(
+            tag.get(0).map(|s| s.variant()),
+            Some(NdbStrVariant::Str("nonce"))
+        ) {
+            continue;
+        }
+
+        mined = true;
+        target = match tag.get(2).map(|s| s.variant()) {
+            Some(NdbStrVariant::Str(target)) => target.trim().parse::<u32>().ok(),
+            _ => None,
+        };
+        break;
+    }
+    if !mined {
+        return None;
+    }
+
+    let bits = leading_zero_bits(note.id());
+    let bits = match target {
+        Some(target) if bits < target => return None,
+        Some(target) => target,
+        None => bits,
+    };
+    (bits >= MIN_POW_BADGE).then_some(bits)
+}
+
+/// The app a note was posted with, from its NIP-89 `client` tag. When
+/// the tag points at a handler (kind 31990) we have, its name wins over
+/// whatever the client called itself.
+fn client_name(ndb: &Ndb, txn: &Transaction, note: &Note) -> Option<String> {
+    for tag in note.tags() {
+        if tag.count() < 2 {
+            continue;
+        }
+
+        let values: Vec<Option<&str>> = (0..tag.count().min(3))
+            .map(|i| match tag.get(i).map(|s| s.variant()) {
+                Some(NdbStrVariant::Str(s)) => Some(s),
+                _ => None,
+            })
+            .collect();
+
+        let (name, handler) = match values[..] {
+            [Some("client"), name] => (name, None),
+            [Some("client"), name, handler] => (name, handler),
+            _ => continue,
+        };
+
+        let handler_name = handler
+            .and_then(|handler| Coordinate::parse(handler).ok())
+            .filter(|coord| coord.kind.as_u16() == 31990)
+            .and_then(|coord| handler_name(ndb, txn, &coord));
+
+        return handler_name
+            .or(name.map(str::to_owned))
+            .map(|name| name.trim().to_owned())
+            .filter(|name| !name.is_empty());
+    }
+
+    None
+}
+
+/// A NIP-89 handler's name: from its own metadata, or its author's
+/// profile when it has none
+fn handler_name(ndb: &Ndb, txn: &Transaction, coord: &Coordinate) -> Option<String> {
+    let handler = lookup_address(ndb, txn, coord).ok()?;
+
+    let metadata: Option<serde_json::Value> = serde_json::from_str(handler.content()).ok();
+    let name = metadata.as_ref().and_then(|metadata| {
+        ["display_name", "name"]
+            .iter()
+            .find_map(|field| metadata.get(field)?.as_str())
+            .filter(|name| !name.trim().is_empty())
+    });
+    if let Some(name) = name {
+        return Some(name.to_owned());
+    }
+
+    let profile = ndb.get_profile_by_pubkey(txn, handler.pubkey()).ok()?;
+    let profile = profile.record().profile()?;
+    profile
+        .display_name()
+        .filter(|name| !name.trim().is_empty())
+        .or(profile.name())
+        .map(str::to_owned)
+}
+
+/// A kind 7 reaction: the emoji, big, and the note it's reacting to
+fn render_reaction(
+    body: &mut Vec<u8>,
+    ndb: &Ndb,
+    txn: &Transaction,
+    note: &Note,
+    locale: Locale,
+) -> std::io::Result<()> {
+    let content = note.content().trim();
+
+    write!(body, r#"<div class="reaction">"#)?;
+
+    let custom = content
+        .strip_prefix(':')
+        .and_then(|c| c.strip_suffix(':'))
+        .and_then(|shortcode| custom_emoji_url(note, shortcode));
+
+    match custom {
+        Some(url) => write!(
+            body,
+            r#"<img class="reaction-emoji" src="{}" alt="{}" />"#,
+            html_escape::encode_double_quoted_attribute(url),
+            html_escape::encode_double_quoted_attribute(content),
+        )?,
+        None => {
+            // "+" is a like, "-" a dislike
+            let emoji = match content {
+                "" | "+" => "❤️",
+                "-" => "👎",
+                other => other,
+            };
+            write!(
+                body,
+                r#"<div class="reaction-emoji">{}</div>"#,
+                html_escape::encode_text(emoji)
+            )?;
+        }
+    }
+
+    write!(
+        body,
+        r#"<div class="reaction-text">{}</div>"#,
+        locale.text(Text::ReactedTo)
+    )?;
+
+    if let Some(target_id) = reaction_target(note) {
+        match ndb.get_note_by_id(txn, &target_id) {
+            Ok(target) => {
+                let author = ndb
+                    .get_profile_by_pubkey(txn, target.pubkey())
+                    .ok()
+                    .and_then(|pr| pr.record().profile().and_then(|p| p.name()));
+                write_feed_note(body, &target, Some(author.unwrap_or("nostrich")), locale)?;
+            }
+            Err(_) => {
+                if let Some(bech32) = EventId::from_slice(&target_id)
+                    .ok()
+                    .and_then(|id| id.to_bech32().ok())
+                {
+                    write!(
+                        body,
+                        r#"<a class="feed-note" href="/{bech32}">{}</a>"#,
+                        locale.text(Text::ANote)
+                    )?;
+                }
+            }
+        }
+    }
+
+    write!(body, "</div>")
+}
+
+/// How many other articles we suggest at the bottom of an article
+const MORE_FROM_AUTHOR_LIMIT: usize = 3;
+
+/// Preview cards for the author's other recent articles
+fn render_more_from_author(
+    body: &mut Vec<u8>,
+    ndb: &Ndb,
+    txn: &Transaction,
+    article: &Note,
+    locale: Locale,
+) -> Result<(), Error> {
+    let filter = Filter::new()
+        .authors([article.pubkey()])
+        .kinds([30023])
+        .limit(20)
+        .build();
+    let mut results = ndb.query(txn, &[filter], 20)?;
+    results.sort_by_key(|result| std::cmp::Reverse(result.note.created_at()));
+
+    let current = note_tag_value(article, "d").unwrap_or("");
+    let mut seen: Vec<&str> = vec![current];
+    let mut cards = Vec::with_capacity(MORE_FROM_AUTHOR_LIMIT);
+
+    for result in &results {
+        // older revisions of an article share its d tag
+        let d = note_tag_value(&result.note, "d").unwrap_or("");
+        if seen.contains(&d) {
+            continue;
+        }
+        seen.push(d);
+
+        if let Some(naddr) = naddr_for_note(&result.note) {
+            cards.push((naddr, &result.note));
+        }
+
+        if cards.len() == MORE_FROM_AUTHOR_LIMIT {
+            break;
+        }
+    }
+
+    if cards.is_empty() {
+        return Ok(());
+    }
+
+    write!(
+        body,
+        r#"<div class="more-from-author"><h3 class="page-heading">{}</h3>"#,
+        locale.text(Text::MoreFromAuthor)
+    )?;
+
+    for (naddr, note) in cards {
+        write!(body, r#"<a class="article-card" href="/{naddr}">"#)?;
+
+        if let Some(image) = note_tag_value(note, "image") {
+            write!(
+                body,
+                r#"<img class="article-card-image" src="{}" loading="lazy" />"#,
+                html_escape::encode_double_quoted_attribute(image)
+            )?;
+        }
+
+        write!(
+            body,
+            r#"<div class="article-card-title">{}</div>"#,
+            html_escape::encode_text(
+                note_tag_value(note, "title").unwrap_or(locale.text(Text::Untitled))
+            )
+        )?;
+
+        if let Some(summary) = note_tag_value(note, "summary") {
+            write!(
+                body,
+                r#"<div class="article-card-summary">{}</div>"#,
+                html_escape::encode_text(abbreviate(summary, 140))
+            )?;
+        }
+
+        write!(body, "</a>")?;
+    }
+
+    write!(body, "</div>")?;
+
+    Ok(())
+}
+
+/// The page cache key for a note page, or `None` if we don't have the
+/// note. Covers everything about the note, its author and the request
+/// that ends up on the page.
+pub fn note_page_key<B>(
     app: &Notecrumbs,
     nip19: &Nip19,
     note_rd: &NoteAndProfileRenderData,
-    _r: Request<hyper::body::Incoming>,
-) -> Result<Response<Full<Bytes>>, Error> {
-    let mut data = Vec::new();
+    r: &Request<B>,
+) -> Option<String> {
+    use nostr::hashes::{sha256, Hash, HashEngine};
 
-    // indices
-    //
-    // 0: name
-    // 1: abbreviated description
-    // 2: hostname
-    // 3: bech32 entity
-    // 5: formatted date
-    // 6: pfp url
+    let txn = Transaction::new(&app.ndb).ok()?;
+    let note = note_rd.note_rd.lookup(&txn, &app.ndb).ok()?;
+    let profile = app
+        .ndb
+        .get_profile_by_pubkey(&txn, note.pubkey())
+        .ok()
+        .and_then(|pr| pr.record().profile());
+
+    let mut engine = sha256::Hash::engine();
+    let mut input = |field: &[u8]| {
+        engine.input(field);
+        engine.input(&[0]);
+    };
+    input(nip19.to_bech32().ok()?.as_bytes());
+    input(note.id());
+    input(profile.and_then(|p| p.name()).unwrap_or("").as_bytes());
+    input(profile.and_then(|p| p.picture()).unwrap_or("").as_bytes());
+    input(app.config.request_base_url(r.headers()).as_bytes());
+    input(&[is_desktop(r) as u8]);
+    input(request_locale(app, r).key().as_bytes());
+
+    Some(hex::encode(
+        sha256::Hash::from_engine(engine).as_byte_array(),
+    ))
+}
+
+/// The locale a page for `r` is in
+pub fn request_locale<B>(app: &Notecrumbs, r: &Request<B>) -> Locale {
+    crate::i18n::request_locale(r.headers(), &app.config.card_locale)
+}
+
+pub fn html_response(page: Bytes) -> Result<Response<Full<Bytes>>, Error> {
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/html")
+        .status(StatusCode::OK)
+        .body(Full::new(page))?)
+}
+
+/// The page for a note or profile we don't have. With the relays
+/// unreachable we can't say it doesn't exist, only that we can't get it
+/// right now.
+pub fn not_found_page(what: &str, unavailable: bool) -> Result<Response<Full<Bytes>>, Error> {
+    let (status, message) = if unavailable {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("{what} unavailable: we can't reach any relays right now, try again in a bit"),
+        )
+    } else {
+        (StatusCode::NOT_FOUND, format!("{what} not found :("))
+    };
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/html")
+        .status(status)
+        .body(Full::new(Bytes::from(message)))?)
+}
+
+/// A note's html page. `unavailable` adds a banner saying we couldn't
+/// fetch what's missing (eg: the author's profile).
+pub fn note_page(
+    app: &Notecrumbs,
+    nip19: &Nip19,
+    note_rd: &NoteAndProfileRenderData,
+    unavailable: bool,
+    r: Request<hyper::body::Incoming>,
+) -> Result<Bytes, Error> {
+    let mut data = Vec::new();
 
     let note_key = match note_rd.note_rd {
         NoteRenderData::Note(note_key) => note_key,
@@ -164,9 +1229,14 @@ pub fn serve_note_html(
             warn!("missing note_id {}", hex::encode(note_id));
             return Err(Error::NotFound);
         }
+        NoteRenderData::MissingAddress(ref coord) => {
+            warn!("missing note {coord}");
+            return Err(Error::NotFound);
+        }
     };
 
     let txn = Transaction::new(&app.ndb)?;
+    let locale = request_locale(app, &r);
 
     let note = if let Ok(note) = app.ndb.get_note_by_key(&txn, note_key) {
         note
@@ -183,8 +1253,7 @@ pub fn serve_note_html(
         }
     });
 
-    let hostname = "https://damus.io";
-    let abbrev_content = html_escape::encode_text(abbreviate(note.content(), 64));
+    let hostname = &app.config.request_base_url(r.headers());
     let profile = profile.and_then(|pr| pr.record().profile());
     let default_pfp_url = "https://damus.io/img/no-profile.svg";
     let pfp_url = profile.and_then(|p| p.picture()).unwrap_or(default_pfp_url);
@@ -194,35 +1263,106 @@ pub fn serve_note_html(
     };
     let bech32 = nip19.to_bech32().unwrap();
 
+    let is_article = note.kind() == 30023;
+    let blocks = app.ndb.get_blocks_by_key(&txn, note_key).ok();
+    let media = if is_article {
+        note_tag_value(&note, "image")
+    } else {
+        blocks
+            .as_ref()
+            .and_then(|blocks| first_image(&note, blocks))
+    };
+    // an article's hero image stands for the article
+    let caption = if is_article {
+        None
+    } else {
+        Some(match &blocks {
+            Some(blocks) => note_words(&note, blocks),
+            None => note.content().to_owned(),
+        })
+    };
+
+    let name = profile.and_then(|p| p.name()).unwrap_or("nostrich");
+    let title = match note_tag_value(&note, "title") {
+        Some(title) if is_article => format!("{title} by {name}"),
+        _ => format!("{name} on nostr"),
+    };
+    let description = note_description(&note);
+
+    let url = format!(
+        "{hostname}/{}",
+        canonical_bech32(&note).unwrap_or_else(|| bech32.clone())
+    );
+    let og_meta = OgMeta {
+        title,
+        description,
+        alternate: Some(Alternate {
+            mime_type: "application/json",
+            url: format!("{hostname}/{bech32}.json"),
+        }),
+        oembed: Some(oembed_url(hostname, &url)),
+        url,
+        image: choose_og_image(
+            app.config.og_image,
+            format!("{hostname}/{bech32}.png"),
+            media,
+            caption.as_deref(),
+            profile.and_then(|p| p.picture()),
+        ),
+        og_type: if is_article { "article" } else { "website" },
+        video: blocks
+            .as_ref()
+            .and_then(|blocks| dominant_video(&note, blocks))
+            .map(|url| OgVideo {
+                url: url.to_owned(),
+                dim: imeta_dim(&note, url),
+                player: format!("{hostname}/{bech32}/embed"),
+            }),
+        schema: {
+            let authored = Authored {
+                author: name.to_owned(),
+                author_url: format!(
+                    "{hostname}/{}",
+                    PublicKey::from_slice(note.pubkey())
+                        .ok()
+                        .and_then(|pk| pk.to_bech32().ok())
+                        .unwrap_or_default()
+                ),
+                published: note.created_at(),
+            };
+            if is_article {
+                Schema::Article(authored)
+            } else {
+                Schema::Posting(authored)
+            }
+        },
+    };
+
     write!(
         data,
         r#"
-        <html>
+        <html lang="{2}">
         <head>
-          <title>{0} on nostr</title>
+          <title>{0}</title>
           <link rel="stylesheet" href="https://damus.io/css/notecrumbs.css" type="text/css" />
           <meta name="viewport" content="width=device-width, initial-scale=1">
-          <meta name="apple-itunes-app" content="app-id=1628663131, app-argument=damus:nostr:{3}"/>
+          <meta name="apple-itunes-app" content="app-id=1628663131, app-argument=damus:nostr:{1}"/>
           <meta charset="UTF-8">
+          {3}
+"#,
+        html_escape::encode_text(&og_meta.title),
+        bech32,
+        locale.html_lang(),
+        GALLERY_STYLE,
+    )?;
 
-          <meta property="og:description" content="{1}" />
-          <meta property="og:image" content="{2}/{3}.png"/>
-          <meta property="og:image:alt" content="{0}: {1}" />
-          <meta property="og:image:height" content="600" />
-          <meta property="og:image:width" content="1200" />
-          <meta property="og:image:type" content="image/png" />
-          <meta property="og:site_name" content="Damus" />
-          <meta property="og:title" content="{0} on nostr" />
-          <meta property="og:url" content="{2}/{3}"/>
-          <meta name="og:type" content="website"/>
-          <meta name="twitter:image:src" content="{2}/{3}.png" />
-          <meta name="twitter:site" content="@damusapp" />
-          <meta name="twitter:card" content="summary_large_image" />
-          <meta name="twitter:title" content="{0} on nostr" />
-          <meta name="twitter:description" content="{1}" />
-      
+    og_meta.write_tags(&mut data)?;
+
+    write!(
+        data,
+        r#"
         </head>
-        <body>
+        <body{3}>
           <main>
             <div class="container">
                  <div class="top-menu">
@@ -235,23 +1375,40 @@ pub fn serve_note_html(
                    </a>
                    -->
                 </div>
-                <h3 class="page-heading">Note</h3>
+                <h3 class="page-heading">{6}</h3>
+                  {4}
                   <div class="note-container">
                       <div class="note">
                         <div class="note-header">
-                           <img src="{5}" class="note-author-avatar" />
+                           <img src="{2}" class="note-author-avatar" />
                            <div class="note-author-name">{0}</div>
                            <div class="note-header-separator">·</div>
-                           <div class="note-timestamp">{4}</div>
+                           <div class="note-timestamp">{1}</div>
+                           {5}
                         </div>
 
                           <div class="note-content">"#,
         profile_name,
-        abbrev_content,
-        hostname,
-        bech32,
-        note.created_at(),
+        locale.date(note.created_at()),
         pfp_url,
+        preview_prefs(&app.ndb, &txn, note.pubkey())
+            .unwrap_or_default()
+            .body_style(),
+        if unavailable {
+            format!(
+                r#"<div class="live-data-unavailable">{}</div>"#,
+                locale.text(Text::LiveDataUnavailable)
+            )
+        } else {
+            String::new()
+        },
+        match pow_difficulty(&note) {
+            Some(bits) => format!(
+                r#"<div class="note-pow" title="Proof of work: {bits} leading zero bits">PoW {bits}</div>"#
+            ),
+            None => String::new(),
+        },
+        locale.text(Text::NoteHeading),
     )?;
 
     let ok = (|| -> Result<(), nostrdb::Error> {
@@ -259,7 +1416,17 @@ pub fn serve_note_html(
         let note = app.ndb.get_note_by_id(&txn, note_id)?;
         let blocks = app.ndb.get_blocks_by_key(&txn, note.key().unwrap())?;
 
-        render_note_content(&mut data, &note, &blocks);
+        if is_article {
+            let _ = write!(
+                data,
+                r#"<div class="article-content">{}</div>"#,
+                render_markdown(note.content())
+            );
+        } else if note.kind() == 7 {
+            let _ = render_reaction(&mut data, &app.ndb, &txn, &note, locale);
+        } else {
+            render_note_content(&mut data, &note, &blocks, &app.link_previews);
+        }
 
         Ok(())
     })();
@@ -269,19 +1436,48 @@ pub fn serve_note_html(
         let _ = write!(data, "{}", html_escape::encode_text(&note.content()));
     }
 
+    let _ = write!(data, "</div>");
+
+    if let Some(location) = location_chip(&note) {
+        let _ = write!(data, "{location}");
+    }
+
+    if let Some(client) = client_name(&app.ndb, &txn, &note) {
+        let _ = write!(
+            data,
+            r#"<div class="note-client">{} {}</div>"#,
+            locale.text(Text::Via),
+            html_escape::encode_text(&client)
+        );
+    }
+
     let _ = write!(
         data,
         r#"
-                       </div>
                    </div>
-                </div>
+                </div>"#
+    );
+
+    if is_article {
+        if let Err(err) = render_more_from_author(&mut data, &app.ndb, &txn, &note, locale) {
+            error!("error rendering more from author: {}", err);
+        }
+    }
+
+    if is_desktop(&r) {
+        let _ = write_handoff_panel(&mut data, &bech32, locale);
+    }
+
+    let _ = write!(
+        data,
+        r#"
                <div class="note-actions-footer">
-                 <a href="nostr:{}" class="muted-link">Open with default Nostr client</a>
+                 <a href="nostr:{0}" class="muted-link">{1}</a>
                </div>
             </main>
             <footer>
                 <span class="footer-note">
-                  <a href="https://damus.io">Damus</a> is a decentralized social network app built on the Nostr protocol.
+                  <a href="https://damus.io">Damus</a> {2}
                 </span>
                 <span class="copyright-note">
                   © Damus Nostr Inc.
@@ -290,11 +1486,46 @@ pub fn serve_note_html(
         </body>
     </html>
     "#,
-        bech32
+        bech32,
+        locale.text(Text::OpenWithClient),
+        locale.text(Text::AboutDamus),
     );
 
-    Ok(Response::builder()
-        .header(header::CONTENT_TYPE, "text/html")
-        .status(StatusCode::OK)
-        .body(Full::new(Bytes::from(data)))?)
+    Ok(Bytes::from(data))
+}
+
+/// Where a bare `note1` should send its visitors, see [`canonical_bech32`].
+/// The query string goes along, it can pick a theme or a language.
+pub fn canonical_note_redirect(
+    ndb: &Ndb,
+    note_rd: &NoteAndProfileRenderData,
+    query: Option<&str>,
+) -> Result<Option<Response<Full<Bytes>>>, Error> {
+    let note_key = match note_rd.note_rd {
+        NoteRenderData::Note(note_key) => note_key,
+        NoteRenderData::Missing(_) | NoteRenderData::MissingAddress(_) => return Ok(None),
+    };
+
+    let txn = Transaction::new(ndb)?;
+    let canonical = if let Some(canonical) = ndb
+        .get_note_by_key(&txn, note_key)
+        .ok()
+        .and_then(|note| canonical_bech32(&note))
+    {
+        canonical
+    } else {
+        return Ok(None);
+    };
+
+    let location = match query.filter(|query| !query.is_empty()) {
+        Some(query) => format!("/{canonical}?{query}"),
+        None => format!("/{canonical}"),
+    };
+
+    Ok(Some(
+        Response::builder()
+            .status(StatusCode::MOVED_PERMANENTLY)
+            .header(header::LOCATION, location)
+            .body(Full::new(Bytes::new()))?,
+    ))
 }
@@ -0,0 +1,122 @@
+use crate::{
+    html::{cached_replies, thread_refs, PROFILE_FEED_RECENT_LIMIT},
+    preview_prefs::preview_prefs_filter,
+    render::{ProfileRenderData, RenderData},
+    Error,
+};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, HeaderMap, Response, StatusCode};
+use nostrdb::{Filter, Ndb, Note, NoteKey, Transaction};
+
+fn profile_note<'a>(
+    ndb: &Ndb,
+    txn: &'a Transaction,
+    profile_rd: &ProfileRenderData,
+) -> Option<Note<'a>> {
+    let record = profile_rd.lookup(txn, ndb).ok()?;
+    let note_key = NoteKey::new(record.record().note_key());
+    ndb.get_note_by_key(txn, note_key).ok()
+}
+
+/// When a profile's page last changed: the newest of the profile, its
+/// preview prefs and its feed. `None` while the feed is thin, notes
+/// backfilled into it can be older than the ones we show. The feed's
+/// relative times don't count, a revalidated page shows them as of
+/// when it was fetched.
+fn profile_last_modified(
+    ndb: &Ndb,
+    txn: &Transaction,
+    profile_rd: &ProfileRenderData,
+) -> Option<u64> {
+    let profile = profile_note(ndb, txn, profile_rd)?;
+    let pubkey = profile.pubkey();
+
+    let feed = Filter::new()
+        .authors([pubkey])
+        .kinds([1])
+        .limit(PROFILE_FEED_RECENT_LIMIT as u64)
+        .build();
+    let notes = ndb.query(txn, &[feed], PROFILE_FEED_RECENT_LIMIT).ok()?;
+    if notes.len() < PROFILE_FEED_RECENT_LIMIT as usize {
+        return None;
+    }
+    let newest_note = notes.iter().map(|result| result.note.created_at()).max();
+
+    let prefs = ndb
+        .query(txn, &[preview_prefs_filter(pubkey)], 1)
+        .ok()
+        .and_then(|results| results.first().map(|result| result.note.created_at()));
+
+    Some(profile.created_at()).max(newest_note).max(prefs)
+}
+
+/// When the content of a page last changed. For a note that's the newest
+/// of the note, its author's profile and its replies, for a profile see
+/// `profile_last_modified`. `None` when we can't tell, so the page goes
+/// without a date instead of one that won't move when what's missing
+/// turns up:
+///
+/// - the note or profile is missing, and we'd only have a partial page
+/// - a thread note it points at isn't cached yet
+pub fn last_modified(ndb: &Ndb, render_data: &RenderData) -> Option<u64> {
+    if !render_data.is_complete() {
+        return None;
+    }
+
+    let txn = Transaction::new(ndb).ok()?;
+    let note = match render_data {
+        RenderData::Note(note_rd) => note_rd.note_rd.lookup(&txn, ndb).ok()?,
+        RenderData::Profile(profile_rd) => {
+            return profile_last_modified(ndb, &txn, profile_rd.as_ref()?)
+        }
+    };
+
+    let refs = thread_refs(&note);
+    let thread_cached = [refs.root, refs.parent]
+        .iter()
+        .flatten()
+        .all(|id| ndb.get_note_by_id(&txn, id).is_ok());
+    if !thread_cached {
+        return None;
+    }
+
+    let profile = render_data
+        .profile_render_data()
+        .and_then(|profile_rd| profile_note(ndb, &txn, profile_rd))
+        .map(|profile| profile.created_at());
+    let newest_reply = cached_replies(ndb, &txn, note.id())
+        .iter()
+        .map(|reply| reply.created_at())
+        .max();
+
+    Some(note.created_at()).max(profile).max(newest_reply)
+}
+
+fn http_date(timestamp: u64) -> Option<String> {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|dt| dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+/// Did the client send an `If-Modified-Since` at or after `modified`?
+pub fn is_fresh(headers: &HeaderMap, modified: u64) -> bool {
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| chrono::DateTime::parse_from_rfc2822(since).ok())
+        .map(|since| since.timestamp() >= modified as i64)
+        .unwrap_or(false)
+}
+
+pub fn not_modified(modified: u64) -> Result<Response<Full<Bytes>>, Error> {
+    let mut builder = Response::builder().status(StatusCode::NOT_MODIFIED);
+    if let Some(date) = http_date(modified) {
+        builder = builder.header(header::LAST_MODIFIED, date);
+    }
+    Ok(builder.body(Full::new(Bytes::new()))?)
+}
+
+pub fn set_last_modified(response: &mut Response<Full<Bytes>>, modified: u64) {
+    if let Some(value) = http_date(modified).and_then(|date| date.parse().ok()) {
+        response.headers_mut().insert(header::LAST_MODIFIED, value);
+    }
+}
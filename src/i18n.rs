@@ -0,0 +1,522 @@
+use crate::fonts;
+use chrono::Locale as DateLocale;
+use tracing::warn;
+
+/// The languages we have translations for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lang {
+    #[default]
+    En,
+    De,
+    Es,
+    Fr,
+    Ja,
+    Pt,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 6] = [Lang::En, Lang::De, Lang::Es, Lang::Fr, Lang::Ja, Lang::Pt];
+
+    /// Everything a card can say in this language, to check our fonts
+    /// have the glyphs for it
+    fn card_text(self) -> String {
+        let locale = Locale {
+            lang: self,
+            dates: self.default_dates(),
+        };
+
+        let mut text: String = [Text::DiscussOnDamus, Text::Notes, Text::Following]
+            .map(|text| locale.text(text))
+            .concat();
+        for counted in [Counted::Replies, Counted::Reposts, Counted::Zaps] {
+            text.push_str(&counted.forms()[self as usize].concat());
+        }
+        // the middle of every month of 2023, for the month names
+        for month in 0..12 {
+            text.push_str(&locale.date(1_673_740_800 + month * 30 * 24 * 60 * 60));
+        }
+        text
+    }
+
+    /// From a language tag's primary subtag, eg: `de` in `de-CH`
+    fn from_subtag(subtag: &str) -> Option<Lang> {
+        match subtag.to_ascii_lowercase().as_str() {
+            "en" => Some(Lang::En),
+            "de" => Some(Lang::De),
+            "es" => Some(Lang::Es),
+            "fr" => Some(Lang::Fr),
+            "ja" => Some(Lang::Ja),
+            "pt" => Some(Lang::Pt),
+            _ => None,
+        }
+    }
+
+    /// How dates look when the request doesn't name a region
+    fn default_dates(self) -> DateLocale {
+        match self {
+            Lang::En => DateLocale::en_US,
+            Lang::De => DateLocale::de_DE,
+            Lang::Es => DateLocale::es_ES,
+            Lang::Fr => DateLocale::fr_FR,
+            Lang::Ja => DateLocale::ja_JP,
+            Lang::Pt => DateLocale::pt_BR,
+        }
+    }
+}
+
+/// The chrome text on our pages and cards. Each has its translations in
+/// `Lang` order.
+#[derive(Debug, Clone, Copy)]
+pub enum Text {
+    NoteHeading,
+    OpenWithClient,
+    ScanToOpen,
+    MoreFromAuthor,
+    Via,
+    Relays,
+    LiveDataUnavailable,
+    DiscussOnDamus,
+    JustNow,
+    SearchPlaceholder,
+    NoResults,
+    ReactedTo,
+    ANote,
+    Untitled,
+    Notes,
+    Following,
+    AboutDamus,
+}
+
+impl Text {
+    fn translations(self) -> [&'static str; 6] {
+        match self {
+            Text::NoteHeading => ["Note", "Notiz", "Nota", "Note", "ノート", "Nota"],
+            Text::OpenWithClient => [
+                "Open with default Nostr client",
+                "Mit dem Standard-Nostr-Client öffnen",
+                "Abrir con el cliente de Nostr predeterminado",
+                "Ouvrir avec le client Nostr par défaut",
+                "デフォルトのNostrクライアントで開く",
+                "Abrir com o cliente Nostr padrão",
+            ],
+            Text::ScanToOpen => [
+                "Scan to open on your phone",
+                "Zum Öffnen auf dem Handy scannen",
+                "Escanea para abrir en tu teléfono",
+                "Scannez pour ouvrir sur votre téléphone",
+                "スキャンしてスマホで開く",
+                "Escaneie para abrir no celular",
+            ],
+            Text::MoreFromAuthor => [
+                "More from this author",
+                "Mehr von diesem Autor",
+                "Más de este autor",
+                "Plus de cet auteur",
+                "この著者の他の記事",
+                "Mais deste autor",
+            ],
+            Text::Via => ["via", "via", "vía", "via", "via", "via"],
+            Text::Relays => ["Relays", "Relays", "Relés", "Relais", "リレー", "Relays"],
+            Text::LiveDataUnavailable => [
+                "Live data unavailable: we can't reach any relays right now, so this may be incomplete.",
+                "Keine Live-Daten: Wir erreichen gerade keine Relays, daher kann das hier unvollständig sein.",
+                "Datos en vivo no disponibles: ahora mismo no podemos conectar con ningún relé, así que esto puede estar incompleto.",
+                "Données en direct indisponibles : aucun relais n'est joignable pour le moment, cette page peut donc être incomplète.",
+                "ライブデータを取得できません。現在どのリレーにも接続できないため、内容が不完全な可能性があります。",
+                "Dados ao vivo indisponíveis: não conseguimos acessar nenhum relay agora, então isto pode estar incompleto.",
+            ],
+            Text::DiscussOnDamus => [
+                "Discuss on Damus ➡",
+                "Auf Damus diskutieren ➡",
+                "Comentar en Damus ➡",
+                "Discuter sur Damus ➡",
+                "Damusで話す ➡",
+                "Comentar no Damus ➡",
+            ],
+            Text::JustNow => [
+                "just now",
+                "gerade eben",
+                "ahora mismo",
+                "à l'instant",
+                "たった今",
+                "agora mesmo",
+            ],
+            Text::SearchPlaceholder => [
+                "Search nostr",
+                "Nostr durchsuchen",
+                "Buscar en nostr",
+                "Rechercher sur nostr",
+                "nostrを検索",
+                "Pesquisar no nostr",
+            ],
+            Text::NoResults => [
+                "No results.",
+                "Keine Ergebnisse.",
+                "Sin resultados.",
+                "Aucun résultat.",
+                "結果はありません。",
+                "Nenhum resultado.",
+            ],
+            Text::ReactedTo => [
+                "reacted to",
+                "hat reagiert auf",
+                "reaccionó a",
+                "a réagi à",
+                "へのリアクション",
+                "reagiu a",
+            ],
+            Text::ANote => [
+                "a note",
+                "eine Notiz",
+                "una nota",
+                "une note",
+                "ノート",
+                "uma nota",
+            ],
+            Text::Untitled => [
+                "Untitled",
+                "Ohne Titel",
+                "Sin título",
+                "Sans titre",
+                "無題",
+                "Sem título",
+            ],
+            Text::Notes => ["notes", "Notizen", "notas", "notes", "ノート", "notas"],
+            Text::Following => [
+                "following",
+                "folgt",
+                "siguiendo",
+                "abonnements",
+                "フォロー中",
+                "seguindo",
+            ],
+            Text::AboutDamus => [
+                "is a decentralized social network app built on the Nostr protocol.",
+                "ist eine dezentrale Social-Network-App auf Basis des Nostr-Protokolls.",
+                "es una app de red social descentralizada construida sobre el protocolo Nostr.",
+                "est une application de réseau social décentralisé basée sur le protocole Nostr.",
+                "は Nostr プロトコル上に構築された分散型ソーシャルネットワークアプリです。",
+                "é um app de rede social descentralizada construído sobre o protocolo Nostr.",
+            ],
+        }
+    }
+}
+
+/// Things we count, with their singular and plural forms in `Lang`
+/// order. `{}` is the count.
+#[derive(Debug, Clone, Copy)]
+pub enum Counted {
+    MinutesAgo,
+    HoursAgo,
+    DaysAgo,
+    Replies,
+    Reposts,
+    Zaps,
+}
+
+impl Counted {
+    fn forms(self) -> [[&'static str; 2]; 6] {
+        match self {
+            Counted::MinutesAgo => [
+                ["{} minute ago", "{} minutes ago"],
+                ["vor {} Minute", "vor {} Minuten"],
+                ["hace {} minuto", "hace {} minutos"],
+                ["il y a {} minute", "il y a {} minutes"],
+                ["{}分前", "{}分前"],
+                ["há {} minuto", "há {} minutos"],
+            ],
+            Counted::HoursAgo => [
+                ["{} hour ago", "{} hours ago"],
+                ["vor {} Stunde", "vor {} Stunden"],
+                ["hace {} hora", "hace {} horas"],
+                ["il y a {} heure", "il y a {} heures"],
+                ["{}時間前", "{}時間前"],
+                ["há {} hora", "há {} horas"],
+            ],
+            Counted::DaysAgo => [
+                ["{} day ago", "{} days ago"],
+                ["vor {} Tag", "vor {} Tagen"],
+                ["hace {} día", "hace {} días"],
+                ["il y a {} jour", "il y a {} jours"],
+                ["{}日前", "{}日前"],
+                ["há {} dia", "há {} dias"],
+            ],
+            Counted::Replies => [
+                ["{} reply", "{} replies"],
+                ["{} Antwort", "{} Antworten"],
+                ["{} respuesta", "{} respuestas"],
+                ["{} réponse", "{} réponses"],
+                ["返信 {}", "返信 {}"],
+                ["{} resposta", "{} respostas"],
+            ],
+            Counted::Reposts => [
+                ["{} repost", "{} reposts"],
+                ["{} Repost", "{} Reposts"],
+                ["{} republicación", "{} republicaciones"],
+                ["{} repartage", "{} repartages"],
+                ["リポスト {}", "リポスト {}"],
+                ["{} repost", "{} reposts"],
+            ],
+            Counted::Zaps => [
+                ["{} zap", "{} zaps"],
+                ["{} Zap", "{} Zaps"],
+                ["{} zap", "{} zaps"],
+                ["{} zap", "{} zaps"],
+                ["ザップ {}", "ザップ {}"],
+                ["{} zap", "{} zaps"],
+            ],
+        }
+    }
+}
+
+/// The languages cards can be drawn in with `fonts`, the rest would come
+/// out as boxes
+pub fn card_langs(fonts: &egui::FontDefinitions) -> Vec<Lang> {
+    let texts: Vec<String> = Lang::ALL.iter().map(|lang| lang.card_text()).collect();
+    let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+
+    Lang::ALL
+        .into_iter()
+        .zip(fonts::covers(fonts, &texts))
+        .filter_map(|(lang, covered)| {
+            if !covered {
+                warn!("no font covers {lang:?}, its cards will be in English");
+            }
+            covered.then_some(lang)
+        })
+        .collect()
+}
+
+/// The locale to answer a request in, `fallback` is the configured POSIX
+/// locale
+pub fn request_locale(headers: &hyper::HeaderMap, fallback: &str) -> Locale {
+    let accept_language = headers
+        .get(hyper::header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok());
+    Locale::negotiate(accept_language, Locale::from_posix(fallback))
+}
+
+/// The language and date format a page or card is rendered in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Locale {
+    pub lang: Lang,
+    dates: DateLocale,
+}
+
+/// How a locale writes out a day. Most put the day first, these don't.
+fn date_pattern(dates: DateLocale) -> &'static str {
+    use DateLocale::*;
+
+    match dates {
+        ja_JP | zh_CN | zh_HK | zh_SG | zh_TW => "%Y年%-m月%-d日",
+        ko_KR => "%Y년 %-m월 %-d일",
+        hu_HU => "%Y. %B %-d.",
+        en_US | en_CA | en_PH => "%B %-d, %Y",
+        _ => "%-d %B %Y",
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale {
+            lang: Lang::En,
+            dates: DateLocale::en_US,
+        }
+    }
+}
+
+impl Locale {
+    /// A POSIX locale like `de_DE`, as configured. Languages we don't
+    /// have translations for still get their dates.
+    pub fn from_posix(name: &str) -> Locale {
+        let lang = name
+            .split(['_', '-', '.'])
+            .next()
+            .and_then(Lang::from_subtag)
+            .unwrap_or_default();
+        let dates = DateLocale::try_from(name).unwrap_or(lang.default_dates());
+        Locale { lang, dates }
+    }
+
+    /// The best match for an `Accept-Language` header, in the order of
+    /// its q values, `fallback` when it names nothing we have
+    pub fn negotiate(accept_language: Option<&str>, fallback: Locale) -> Locale {
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .unwrap_or("")
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let q = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+                (!tag.is_empty() && q > 0.0).then_some((tag, q))
+            })
+            .collect();
+        // stable, so equal q values keep the order they were sent in
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        for (tag, _) in ranges {
+            let mut subtags = tag.split(['-', '_']);
+            let primary = subtags.next().unwrap_or("");
+            let lang = if let Some(lang) = Lang::from_subtag(primary) {
+                lang
+            } else {
+                continue;
+            };
+
+            let dates = subtags
+                .next()
+                .and_then(|region| {
+                    let posix = format!(
+                        "{}_{}",
+                        primary.to_ascii_lowercase(),
+                        region.to_ascii_uppercase()
+                    );
+                    DateLocale::try_from(posix.as_str()).ok()
+                })
+                .unwrap_or(lang.default_dates());
+            return Locale { lang, dates };
+        }
+
+        fallback
+    }
+
+    /// This locale for a card, or English when `card_langs` says the
+    /// fonts can't draw it
+    pub fn for_cards(self, card_langs: &[Lang]) -> Locale {
+        if card_langs.contains(&self.lang) {
+            self
+        } else {
+            Locale::default()
+        }
+    }
+
+    /// Identifies the locale in cache keys
+    pub fn key(&self) -> String {
+        format!("{:?}/{:?}", self.lang, self.dates)
+    }
+
+    /// The `lang` attribute for pages in this locale
+    pub fn html_lang(&self) -> &'static str {
+        match self.lang {
+            Lang::En => "en",
+            Lang::De => "de",
+            Lang::Es => "es",
+            Lang::Fr => "fr",
+            Lang::Ja => "ja",
+            Lang::Pt => "pt",
+        }
+    }
+
+    pub fn text(&self, text: Text) -> &'static str {
+        text.translations()[self.lang as usize]
+    }
+
+    pub fn count(&self, count: u64, counted: Counted) -> String {
+        let [one, many] = counted.forms()[self.lang as usize];
+        let form = if count == 1 { one } else { many };
+        form.replace("{}", &count.to_string())
+    }
+
+    /// A count we stopped short of, eg: 500+ Replies
+    pub fn count_at_least(&self, count: u64, counted: Counted) -> String {
+        let [_, many] = counted.forms()[self.lang as usize];
+        many.replace("{}", &format!("{count}+"))
+    }
+
+    /// A day in the locale's order, eg: 14 November 2023. Times are UTC, we don't know where
+    /// the viewer is.
+    pub fn date(&self, timestamp: u64) -> String {
+        chrono::DateTime::from_timestamp(timestamp as i64, 0)
+            .unwrap_or_default()
+            .format_localized(date_pattern(self.dates), self.dates)
+            .to_string()
+    }
+
+    /// How long ago `timestamp` was, eg: 3 hours ago. Past a month it's
+    /// the date instead.
+    pub fn relative_time(&self, timestamp: u64, now: u64) -> String {
+        const MINUTE: u64 = 60;
+        const HOUR: u64 = 60 * MINUTE;
+        const DAY: u64 = 24 * HOUR;
+
+        let ago = now.saturating_sub(timestamp);
+        if ago < MINUTE {
+            self.text(Text::JustNow).to_owned()
+        } else if ago < HOUR {
+            self.count(ago / MINUTE, Counted::MinutesAgo)
+        } else if ago < DAY {
+            self.count(ago / HOUR, Counted::HoursAgo)
+        } else if ago < 30 * DAY {
+            self.count(ago / DAY, Counted::DaysAgo)
+        } else {
+            self.date(timestamp)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_accept_language() {
+        let fallback = Locale::default();
+        let negotiate = |header| Locale::negotiate(Some(header), fallback);
+
+        assert_eq!(negotiate("de-CH, en;q=0.5").lang, Lang::De);
+        assert_eq!(negotiate("nl, fr;q=0.9, de;q=0.8").lang, Lang::Fr);
+        assert_eq!(negotiate("en;q=0.1, ja").lang, Lang::Ja);
+        assert_eq!(negotiate("es;q=0, pt-BR").lang, Lang::Pt);
+        assert_eq!(negotiate("nl, *;q=0.5"), fallback);
+        assert_eq!(Locale::negotiate(None, fallback), fallback);
+
+        // a region we know changes the dates, not the translations
+        let british = negotiate("en-GB");
+        assert_eq!(british.lang, Lang::En);
+        assert_ne!(british.key(), negotiate("en").key());
+    }
+
+    #[test]
+    fn localizes() {
+        let german = Locale::from_posix("de_DE");
+        assert_eq!(german.date(1_700_000_000), "14 November 2023");
+        assert_eq!(german.count(3, Counted::Replies), "3 Antworten");
+        assert_eq!(german.count_at_least(500, Counted::Zaps), "500+ Zaps");
+        assert_eq!(german.relative_time(1_000, 1_000 + 7_200), "vor 2 Stunden");
+        assert_eq!(Locale::default().relative_time(1_000, 1_030), "just now");
+
+        // no translations, but dates in the language
+        let dutch = Locale::from_posix("nl_NL");
+        assert_eq!(dutch.lang, Lang::En);
+        assert_eq!(dutch.date(1_700_000_000), "14 november 2023");
+
+        // not every locale puts the day first
+        assert_eq!(
+            Locale::from_posix("ja_JP").date(1_700_000_000),
+            "2023年11月14日"
+        );
+        assert_eq!(
+            Locale::from_posix("zh_TW").date(1_700_000_000),
+            "2023年11月14日"
+        );
+        assert_eq!(
+            Locale::from_posix("ko_KR").date(1_700_000_000),
+            "2023년 11월 14일"
+        );
+        assert_eq!(
+            Locale::from_posix("en_US").date(1_700_000_000),
+            "November 14, 2023"
+        );
+        assert_eq!(
+            Locale::from_posix("en_GB").date(1_700_000_000),
+            "14 November 2023"
+        );
+
+        // cards in a language our fonts can't draw are in English
+        let japanese = Locale::from_posix("ja_JP");
+        assert_eq!(japanese.for_cards(&Lang::ALL), japanese);
+        assert_eq!(japanese.for_cards(&[Lang::En]), Locale::default());
+    }
+}
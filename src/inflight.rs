@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// Work that's running right now, by key, so identical concurrent
+/// requests wait for the first one instead of repeating it. Results
+/// aren't handed over, they're shared through whatever the work fills in
+/// (ndb, the card cache).
+#[derive(Default)]
+pub struct InFlight {
+    running: Mutex<HashMap<String, watch::Receiver<()>>>,
+}
+
+/// Unregisters the work when it finishes or is dropped half way, which
+/// also wakes everyone waiting on it
+struct Running<'a> {
+    inflight: &'a InFlight,
+    key: String,
+    _done: watch::Sender<()>,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.inflight.running.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl InFlight {
+    /// Run `work`, unless work with the same key is already running. Then
+    /// wait for that to finish and return `None`.
+    pub async fn run<F: Future>(&self, key: String, work: F) -> Option<F::Output> {
+        let running = {
+            let mut running = self.running.lock().unwrap();
+            if let Some(waiting) = running.get(&key) {
+                Err(waiting.clone())
+            } else {
+                let (done, waiting) = watch::channel(());
+                running.insert(key.clone(), waiting);
+                Ok(Running {
+                    inflight: self,
+                    key,
+                    _done: done,
+                })
+            }
+        };
+
+        match running {
+            Ok(_running) => Some(work.await),
+            Err(mut waiting) => {
+                // only ever errors, once the sender is dropped
+                let _ = waiting.changed().await;
+                None
+            }
+        }
+    }
+}
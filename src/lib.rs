@@ -0,0 +1,318 @@
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use nostr_sdk::prelude::Keys;
+use nostrdb::Ndb;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio_rustls::TlsAcceptor;
+use tracing::debug;
+
+use crate::cache_backend::CacheBackend;
+
+mod abbrev;
+mod admin;
+mod backfill;
+mod batch;
+pub mod budget;
+mod cache_backend;
+mod card_cache;
+pub mod config;
+mod db_stats;
+mod debug;
+mod embed;
+mod error;
+mod feed;
+mod fetch;
+mod fonts;
+mod geohash;
+mod gradient;
+mod health;
+mod homepage;
+mod html;
+mod http_cache;
+mod i18n;
+mod inflight;
+mod link_preview;
+mod markdown;
+mod media;
+mod meta;
+mod metrics;
+mod music;
+mod nip05;
+mod nip19;
+mod not_found;
+mod nsec;
+mod oembed;
+mod outbox;
+mod page_cache;
+mod pfp;
+mod pfp_cache;
+mod preview_prefs;
+mod profile_relays;
+mod qr;
+mod relay_health;
+mod relay_info;
+mod relay_page;
+mod render;
+mod route;
+pub mod router;
+mod search;
+mod sitemap;
+pub mod telemetry;
+pub mod tls;
+mod unresolved;
+
+pub use error::Error;
+
+#[derive(Clone)]
+pub struct Notecrumbs {
+    pub ndb: Ndb,
+    pub config: Arc<config::Config>,
+    keys: Keys,
+    fonts: egui::FontDefinitions,
+    /// The languages `fonts` can draw card text in
+    card_langs: Arc<Vec<i18n::Lang>>,
+    pfps: Arc<pfp_cache::PfpCache>,
+    default_pfp: egui::ImageData,
+    link_previews: Arc<link_preview::LinkPreviewCache>,
+    unresolved: Arc<unresolved::UnresolvedTracker>,
+    backfill: Arc<backfill::Backfiller>,
+    batcher: Arc<batch::FilterBatcher>,
+    inflight: Arc<inflight::InFlight>,
+    /// Relay lookups we're willing to run at once
+    lookup_slots: Arc<Semaphore>,
+    /// Card renders we're willing to run at once
+    render_slots: Arc<Semaphore>,
+    homepage: Arc<homepage::HomepageFeed>,
+    media: Arc<media::MediaCache>,
+    relays: Arc<relay_health::RelayHealth>,
+    relay_info: Arc<relay_info::RelayInfoCache>,
+    nip05: Arc<nip05::Nip05Cache>,
+    cards: Arc<card_cache::CardCache>,
+    not_found: Arc<not_found::NotFoundCache>,
+    pages: Arc<page_cache::PageCache>,
+    search: Arc<search::SearchPool>,
+}
+
+/// Shown for profiles without a picture, or one we couldn't load
+pub const DEFAULT_PFP_PATH: &str = "assets/default_pfp.jpg";
+
+pub fn get_default_pfp() -> Result<egui::ColorImage, Error> {
+    let img = std::fs::read(DEFAULT_PFP_PATH)?;
+    let mut dyn_image = ::image::load_from_memory(&img)?;
+    Ok(pfp::process_pfp_bitmap(&mut dyn_image))
+}
+
+/// Sets up a [`Notecrumbs`] from a config. The keys used to talk to relays
+/// are generated and the database is opened from the config unless
+/// they're given here.
+pub struct NotecrumbsBuilder {
+    config: config::Config,
+    keys: Option<Keys>,
+    ndb: Option<Ndb>,
+}
+
+impl NotecrumbsBuilder {
+    /// Talk to relays as these keys instead of fresh ones
+    pub fn keys(mut self, keys: Keys) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    /// Use an already open database instead of opening `ndb_dir`
+    pub fn ndb(mut self, ndb: Ndb) -> Self {
+        self.ndb = Some(ndb);
+        self
+    }
+
+    /// Open everything and start the background tasks (relay probes,
+    /// backfill, the homepage feed, ...). Needs a tokio runtime.
+    pub async fn build(self) -> Result<Notecrumbs, Error> {
+        let config = self.config;
+
+        if let Some(proxy) = config.socks5_proxy {
+            fetch::set_proxy(proxy);
+        }
+
+        let ndb = match self.ndb {
+            Some(ndb) => ndb,
+            None => {
+                std::fs::create_dir_all(&config.ndb_dir)?;
+                Ndb::new(&config.ndb_dir, &config.ndb_config())?
+            }
+        };
+        let keys = self.keys.unwrap_or_else(Keys::generate);
+        let pfps = Arc::new(pfp_cache::PfpCache::new(
+            &config.pfp_cache_dir,
+            std::num::NonZeroUsize::new(1024).unwrap(),
+        ));
+        tokio::spawn(pfps.clone().sweep_loop());
+        let default_pfp = egui::ImageData::Color(Arc::new(get_default_pfp()?));
+        let font_data =
+            egui::FontData::from_static(include_bytes!("../fonts/NotoSans-Regular.ttf"));
+        let fonts = fonts::font_definitions(font_data, fonts::load_fallback_fonts(&config).await);
+        let card_langs = Arc::new(i18n::card_langs(&fonts));
+
+        metrics::label_relays(
+            config
+                .relays
+                .iter()
+                .chain(&config.search_relays)
+                .cloned()
+                .collect(),
+        );
+
+        let relay_info = Arc::new(relay_info::RelayInfoCache::new(
+            std::num::NonZeroUsize::new(512).unwrap(),
+        ));
+        let relays = Arc::new(relay_health::RelayHealth::new(
+            keys.clone(),
+            relay_info.clone(),
+            config.relays.clone(),
+            config.max_hinted_relays,
+        ));
+        tokio::spawn(relays.clone().probe_loop());
+        tokio::spawn(metrics::ndb_stats_loop(
+            ndb.clone(),
+            config.ndb_dir.clone().into(),
+        ));
+
+        let unresolved = Arc::new(unresolved::UnresolvedTracker::load(&config.unresolved_path));
+        tokio::spawn(unresolved.clone().flush_loop());
+
+        let backfill = Arc::new(backfill::Backfiller::new(
+            ndb.clone(),
+            keys.clone(),
+            relays.clone(),
+        ));
+        let batcher = Arc::new(batch::FilterBatcher::new(
+            ndb.clone(),
+            keys.clone(),
+            relays.clone(),
+        ));
+
+        let homepage = Arc::new(homepage::HomepageFeed::new(
+            ndb.clone(),
+            keys.clone(),
+            config.homepage_feed.clone(),
+            relays.clone(),
+        ));
+        tokio::spawn(homepage.clone().refresh_loop());
+
+        let shared_cache: Option<Arc<dyn CacheBackend>> = match &config.redis_url {
+            Some(url) => Some(Arc::new(
+                cache_backend::RedisBackend::connect(url)
+                    .await
+                    .map_err(|err| Error::Generic(format!("redis: {err}")))?,
+            )),
+            None => None,
+        };
+        // each cache gets its own memory, or they all share redis
+        let cache = |name: &'static str, capacity: usize| -> Arc<dyn CacheBackend> {
+            match &shared_cache {
+                Some(shared) => shared.clone(),
+                None => Arc::new(cache_backend::MemoryBackend::new(
+                    name,
+                    std::num::NonZeroUsize::new(capacity).unwrap(),
+                )),
+            }
+        };
+
+        let search = Arc::new(search::SearchPool::new(keys.clone()));
+
+        let lookup_slots = Arc::new(Semaphore::new(config.max_lookups.get()));
+        let render_slots = Arc::new(Semaphore::new(config.max_renders.get()));
+
+        let app = Notecrumbs {
+            ndb,
+            config: Arc::new(config),
+            keys,
+            pfps,
+            fonts,
+            card_langs,
+            default_pfp,
+            link_previews: Arc::new(link_preview::LinkPreviewCache::new(
+                std::num::NonZeroUsize::new(1024).unwrap(),
+            )),
+            unresolved,
+            backfill,
+            batcher,
+            inflight: Arc::new(inflight::InFlight::default()),
+            lookup_slots,
+            render_slots,
+            homepage,
+            relays,
+            relay_info,
+            media: Arc::new(media::MediaCache::new(
+                std::num::NonZeroUsize::new(256).unwrap(),
+            )),
+            cards: Arc::new(card_cache::CardCache::new(cache("cards", 256))),
+            nip05: Arc::new(nip05::Nip05Cache::new(
+                std::num::NonZeroUsize::new(1024).unwrap(),
+            )),
+            not_found: Arc::new(not_found::NotFoundCache::new(cache("not_found", 4096))),
+            pages: Arc::new(page_cache::PageCache::new(cache("pages", 512))),
+            search,
+        };
+
+        tokio::spawn(router::warm_up(app.clone()));
+
+        Ok(app)
+    }
+}
+
+impl Notecrumbs {
+    pub fn builder(config: config::Config) -> NotecrumbsBuilder {
+        NotecrumbsBuilder {
+            config,
+            keys: None,
+            ndb: None,
+        }
+    }
+
+    /// Serve http, or https when given a tls acceptor, on a bound listener
+    /// until accepting fails
+    pub async fn serve(
+        self,
+        listener: TcpListener,
+        tls: Option<TlsAcceptor>,
+    ) -> std::io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+
+            let app = self.clone();
+            let tls = tls.clone();
+
+            // Spawn a tokio task to serve multiple connections concurrently
+            tokio::task::spawn(async move {
+                match tls {
+                    Some(tls) => match tls.accept(stream).await {
+                        Ok(stream) => serve_connection(&app, stream).await,
+                        Err(err) => debug!("tls handshake failed: {err}"),
+                    },
+                    None => serve_connection(&app, stream).await,
+                }
+            });
+        }
+    }
+}
+
+async fn serve_connection<S>(app: &Notecrumbs, stream: S)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    // Use an adapter to access something implementing `tokio::io` traits as if they implement
+    // `hyper::rt` IO traits.
+    let io = TokioIo::new(stream);
+
+    // Finally, we bind the incoming connection to our `hello` service
+    if let Err(err) = http1::Builder::new()
+        // `service_fn` converts our function in a `Service`
+        .serve_connection(io, service_fn(|req| router::serve_with_id(app, req)))
+        .await
+    {
+        println!("Error serving connection: {:?}", err);
+    }
+}
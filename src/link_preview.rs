@@ -0,0 +1,232 @@
+use crate::{fetch, metrics, Error};
+use lru::LruCache;
+use nostr_sdk::async_utility::futures_util::future::join_all;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+/// We only need the head of the page, don't download whole documents
+const MAX_PAGE_SIZE: usize = 512 * 1024;
+
+/// How long a single page fetch may take
+const FETCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Opengraph metadata scraped from an external page
+#[derive(Debug, Clone)]
+pub struct LinkPreview {
+    pub title: String,
+    pub description: Option<String>,
+    pub image: Option<String>,
+}
+
+/// Link previews we've fetched, keyed by url. `None` entries are pages we
+/// fetched that didn't have anything worth previewing, so we don't keep
+/// hitting them.
+pub struct LinkPreviewCache {
+    cache: Mutex<LruCache<String, Option<Arc<LinkPreview>>>>,
+    client: reqwest::Client,
+}
+
+impl LinkPreviewCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        let client = fetch::client("notecrumbs (link preview)", FETCH_TIMEOUT, 3);
+
+        LinkPreviewCache {
+            cache: Mutex::new(LruCache::new(capacity)),
+            client,
+        }
+    }
+
+    pub fn get(&self, url: &str) -> Option<Arc<LinkPreview>> {
+        self.cache.lock().unwrap().get(url).cloned().flatten()
+    }
+
+    fn contains(&self, url: &str) -> bool {
+        let cached = self.cache.lock().unwrap().contains(url);
+        metrics::cache_lookup("link_previews", cached);
+        cached
+    }
+
+    /// Fetch previews for any urls we haven't seen yet, waiting at most
+    /// `wait` for them. Fetches that take longer keep going in the
+    /// background and will be there for the next request.
+    pub async fn prefetch(self: &Arc<Self>, urls: Vec<String>, wait: Duration) {
+        let handles: Vec<_> = urls
+            .into_iter()
+            .filter(|url| !self.contains(url))
+            .map(|url| {
+                let previews = self.clone();
+                tokio::spawn(async move {
+                    let preview = match fetch_preview(&previews.client, &url).await {
+                        Ok(preview) => preview.map(Arc::new),
+                        Err(err) => {
+                            debug!("link preview failed for {url}: {err}");
+                            None
+                        }
+                    };
+                    let mut cache = previews.cache.lock().unwrap();
+                    metrics::cache_put("link_previews", &mut cache, url, preview);
+                })
+            })
+            .collect();
+
+        if handles.is_empty() {
+            return;
+        }
+
+        let _ = tokio::time::timeout(wait, join_all(handles)).await;
+    }
+}
+
+async fn fetch_preview(client: &reqwest::Client, url: &str) -> Result<Option<LinkPreview>, Error> {
+    let mut res = fetch::get(client, url).await?;
+
+    let is_html = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .map(|ct| ct.starts_with("text/html"))
+        .unwrap_or(false);
+
+    if !is_html {
+        return Ok(None);
+    }
+
+    let mut page: Vec<u8> = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        page.extend_from_slice(&chunk);
+        if page.len() >= MAX_PAGE_SIZE {
+            page.truncate(MAX_PAGE_SIZE);
+            break;
+        }
+    }
+
+    Ok(parse_preview(&String::from_utf8_lossy(&page), url))
+}
+
+/// The value of an attribute inside a single html tag
+fn tag_attr<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let lower = tag.to_ascii_lowercase();
+    let mut search_from = 0;
+
+    while let Some(pos) = lower[search_from..].find(name) {
+        let start = search_from + pos;
+        search_from = start + name.len();
+
+        // make sure we matched a whole attribute name
+        let before = lower[..start].chars().last();
+        if !matches!(before, Some(c) if c.is_whitespace()) {
+            continue;
+        }
+
+        let rest = lower[search_from..].trim_start();
+        if !rest.starts_with('=') {
+            continue;
+        }
+
+        let value_start = tag.len() - rest.len() + 1;
+        let value = tag[value_start..].trim_start();
+        let quote = value.chars().next()?;
+
+        return if quote == '"' || quote == '\'' {
+            value[1..].split(quote).next()
+        } else {
+            value.split(|c: char| c.is_whitespace() || c == '>').next()
+        };
+    }
+
+    None
+}
+
+fn resolve_url(base: &str, url: &str) -> String {
+    if url.starts_with("https://") || url.starts_with("http://") {
+        return url.to_string();
+    }
+
+    let scheme_end = base.find("://").map(|i| i + 3).unwrap_or(0);
+    let origin_end = base[scheme_end..]
+        .find('/')
+        .map(|i| i + scheme_end)
+        .unwrap_or(base.len());
+
+    if let Some(rest) = url.strip_prefix("//") {
+        format!("{}{}", &base[..scheme_end], rest)
+    } else if url.starts_with('/') {
+        format!("{}{}", &base[..origin_end], url)
+    } else {
+        let dir_end = base
+            .rfind('/')
+            .filter(|i| *i >= origin_end)
+            .unwrap_or(base.len());
+        format!("{}/{}", &base[..dir_end], url)
+    }
+}
+
+fn decode(s: &str) -> String {
+    html_escape::decode_html_entities(s.trim()).into_owned()
+}
+
+/// Scrape opengraph (and plain html) metadata from a page
+pub fn parse_preview(page: &str, url: &str) -> Option<LinkPreview> {
+    let mut title: Option<String> = None;
+    let mut og_title: Option<String> = None;
+    let mut description: Option<String> = None;
+    let mut image: Option<String> = None;
+
+    let lower = page.to_ascii_lowercase();
+    let head_end = lower.find("</head>").unwrap_or(lower.len());
+
+    let mut pos = 0;
+    while pos < head_end {
+        let start = match lower[pos..head_end].find("<meta") {
+            Some(start) => start,
+            None => break,
+        };
+        let start = pos + start;
+        let end = match lower[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        pos = end;
+
+        let tag = &page[start..end];
+        let key = tag_attr(tag, "property").or_else(|| tag_attr(tag, "name"));
+        let content = tag_attr(tag, "content");
+
+        let (key, content) = match (key, content) {
+            (Some(key), Some(content)) if !content.trim().is_empty() => {
+                (key.to_ascii_lowercase(), content)
+            }
+            _ => continue,
+        };
+
+        match key.as_str() {
+            "og:title" | "twitter:title" if og_title.is_none() => og_title = Some(decode(content)),
+            "og:description" | "twitter:description" | "description" if description.is_none() => {
+                description = Some(decode(content))
+            }
+            "og:image" | "twitter:image" | "twitter:image:src" if image.is_none() => {
+                image = Some(resolve_url(url, &decode(content)))
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(start) = lower.find("<title") {
+        if let Some(open_end) = lower[start..].find('>') {
+            let text_start = start + open_end + 1;
+            if let Some(len) = lower[text_start..].find("</title>") {
+                title = Some(decode(&page[text_start..text_start + len]));
+            }
+        }
+    }
+
+    let title = og_title.or(title).filter(|t| !t.is_empty())?;
+
+    Some(LinkPreview {
+        title,
+        description,
+        image,
+    })
+}
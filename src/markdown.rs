@@ -0,0 +1,212 @@
+use latex2mathml::{latex_to_mathml, DisplayStyle};
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use std::sync::OnceLock;
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::highlighted_html_for_string,
+    parsing::SyntaxSet,
+};
+
+/// Theme for highlighted code blocks. Colors are inlined into the spans,
+/// so pages don't need any extra css or js.
+const CODE_THEME: &str = "base16-ocean.dark";
+
+struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+}
+
+/// Loading the syntax definitions is slow, only do it once
+fn highlighter() -> &'static Highlighter {
+    static HIGHLIGHTER: OnceLock<Highlighter> = OnceLock::new();
+    HIGHLIGHTER.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults();
+        Highlighter {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme: themes
+                .themes
+                .remove(CODE_THEME)
+                .expect("syntect default theme"),
+        }
+    })
+}
+
+/// Highlight a fenced code block. `None` if we don't know the language.
+fn highlight(lang: &str, code: &str) -> Option<String> {
+    // fences like ```rust,ignore or ```python title="x"
+    let lang = lang.split(|c: char| c == ',' || c.is_whitespace()).next()?;
+
+    let hl = highlighter();
+    let syntax = hl.syntaxes.find_syntax_by_token(lang)?;
+    highlighted_html_for_string(code, &hl.syntaxes, syntax, &hl.theme).ok()
+}
+
+/// Stand-ins for `<` and `>` while latex goes through latex2mathml,
+/// which copies text like `\text{...}` into its output as is. Private
+/// use characters, so they never mean anything to latex.
+const LT_PLACEHOLDER: char = '\u{E000}';
+const GT_PLACEHOLDER: char = '\u{E001}';
+
+/// Render `$...$` and `$$...$$` to MathML, which browsers display
+/// natively. Broken latex is shown as-is.
+fn render_math(latex: &str, style: DisplayStyle) -> Event<'static> {
+    let delim = match style {
+        DisplayStyle::Block => "$$",
+        DisplayStyle::Inline => "$",
+    };
+
+    // the only markup in the output is what latex2mathml writes itself
+    let hidden: String = latex
+        .chars()
+        .map(|c| match c {
+            '<' => LT_PLACEHOLDER,
+            '>' => GT_PLACEHOLDER,
+            LT_PLACEHOLDER | GT_PLACEHOLDER => char::REPLACEMENT_CHARACTER,
+            c => c,
+        })
+        .collect();
+
+    match latex_to_mathml(&hidden, style) {
+        Ok(mathml) => Event::Html(
+            mathml
+                .replace(LT_PLACEHOLDER, "&lt;")
+                .replace(GT_PLACEHOLDER, "&gt;")
+                .into(),
+        ),
+        Err(_) => Event::Code(format!("{delim}{latex}{delim}").into()),
+    }
+}
+
+/// Schemes a link or image in an article may use. Anything else, like
+/// `javascript:` or `data:`, could run script on our pages.
+const ALLOWED_SCHEMES: &[&str] = &["http", "https", "mailto", "nostr"];
+
+/// Whether an article may link to `url`: relative urls and the
+/// [`ALLOWED_SCHEMES`]
+fn is_safe_url(url: &str) -> bool {
+    // browsers ignore whitespace and control characters in the scheme,
+    // `java\tscript:` still runs
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_ascii_control() && !c.is_ascii_whitespace())
+        .collect();
+
+    let scheme_end = match url.find(|c| matches!(c, ':' | '/' | '?' | '#')) {
+        Some(end) if url[end..].starts_with(':') => end,
+        // no scheme, it's relative
+        _ => return true,
+    };
+
+    ALLOWED_SCHEMES
+        .iter()
+        .any(|scheme| url[..scheme_end].eq_ignore_ascii_case(scheme))
+}
+
+/// Render longform markdown to html. Raw html in the source is escaped,
+/// articles are untrusted.
+pub fn render_markdown(md: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_MATH;
+
+    let mut events: Vec<Event> = Vec::new();
+    let mut code_block: Option<(CowStr, String)> = None;
+    // whether each image we're in was dropped, their alt text stays
+    let mut dropped_images: Vec<bool> = Vec::new();
+
+    for event in Parser::new_ext(md, options) {
+        if let Some((_, code)) = code_block.as_mut() {
+            match event {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => {
+                    let (lang, code) = code_block.take().unwrap();
+                    match highlight(&lang, &code) {
+                        Some(highlighted) => events.push(Event::Html(highlighted.into())),
+                        None => {
+                            events.push(Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))));
+                            events.push(Event::Text(code.into()));
+                            events.push(Event::End(TagEnd::CodeBlock));
+                        }
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))) if !lang.is_empty() => {
+                code_block = Some((lang, String::new()));
+            }
+            Event::InlineMath(latex) => events.push(render_math(&latex, DisplayStyle::Inline)),
+            Event::DisplayMath(latex) => events.push(render_math(&latex, DisplayStyle::Block)),
+            Event::Html(raw) | Event::InlineHtml(raw) => events.push(Event::Text(raw)),
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => events.push(Event::Start(Tag::Link {
+                link_type,
+                dest_url: if is_safe_url(&dest_url) {
+                    dest_url
+                } else {
+                    "#".into()
+                },
+                title,
+                id,
+            })),
+            Event::Start(Tag::Image { ref dest_url, .. }) => {
+                let safe = is_safe_url(dest_url);
+                dropped_images.push(!safe);
+                if safe {
+                    events.push(event);
+                }
+            }
+            Event::End(TagEnd::Image) => {
+                if dropped_images.pop() != Some(true) {
+                    events.push(event);
+                }
+            }
+            event => events.push(event),
+        }
+    }
+
+    let mut out = String::with_capacity(md.len() * 3 / 2);
+    html::push_html(&mut out, events.into_iter());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_script_urls() {
+        let html = render_markdown("[click](javascript:alert(document.cookie))");
+        assert!(!html.contains("javascript"), "{html}");
+        assert!(html.contains("click"), "{html}");
+
+        let html = render_markdown("<JaVaScRiPt:alert(1)>");
+        assert!(!html.contains("href=\"JaVa"), "{html}");
+
+        let html = render_markdown("![pic](data:text/html;base64,PHNjcmlwdD4=)");
+        assert!(!html.contains("data:"), "{html}");
+        assert!(!html.contains("<img"), "{html}");
+        assert!(html.contains("pic"), "{html}");
+
+        let html = render_markdown("[a](https://damus.io) [b](/npub1abc) ![c](http://x.y/c.png)");
+        assert!(html.contains(r#"href="https://damus.io""#), "{html}");
+        assert!(html.contains(r#"href="/npub1abc""#), "{html}");
+        assert!(html.contains(r#"src="http://x.y/c.png""#), "{html}");
+    }
+
+    #[test]
+    fn escapes_math_text() {
+        let html = render_markdown(r"$\text{<script>alert(1)</script>}$");
+        assert!(!html.contains("<script"), "{html}");
+        assert!(html.contains("&lt;script&gt;"), "{html}");
+    }
+}
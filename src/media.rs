@@ -0,0 +1,143 @@
+use crate::{fetch, metrics, Error};
+use egui::ColorImage;
+use image::imageops::FilterType;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+/// Images bigger than this aren't worth decoding for a card
+const MAX_IMAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// How long a single image fetch may take
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wider or taller images are refused, a few kilobytes of png can claim
+/// to be gigapixels
+const MAX_IMAGE_DIMENSION: u32 = 8192;
+
+/// How much memory decoding a single image may take
+const MAX_DECODE_ALLOC: u64 = 128 * 1024 * 1024;
+
+/// Decode a downloaded image, within limits on its size and the memory
+/// decoding it takes, so a decompression bomb fails instead of
+/// allocating
+pub fn decode_image(data: &[u8]) -> Result<image::DynamicImage, Error> {
+    let mut limits = image::io::Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+
+    let mut reader = image::io::Reader::new(std::io::Cursor::new(data)).with_guessed_format()?;
+    reader.limits(limits);
+    Ok(reader.decode()?)
+}
+
+/// Remote images (banners, note media) fetched and scaled for png cards.
+/// Keyed by url and target size, `None` entries are images that failed to
+/// load so we don't keep retrying them.
+pub struct MediaCache {
+    cache: Mutex<LruCache<String, Option<Arc<ColorImage>>>>,
+    client: reqwest::Client,
+}
+
+fn cache_key(url: &str, size: [u32; 2]) -> String {
+    format!("{}x{} {url}", size[0], size[1])
+}
+
+impl MediaCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        let client = fetch::client("notecrumbs (media)", FETCH_TIMEOUT, 3);
+
+        MediaCache {
+            cache: Mutex::new(LruCache::new(capacity)),
+            client,
+        }
+    }
+
+    /// Fetch an image scaled and center cropped to exactly `size`, waiting
+    /// at most `wait`. A fetch that takes longer keeps going in the
+    /// background and will be cached for the next request.
+    pub async fn fetch(
+        self: &Arc<Self>,
+        url: &str,
+        size: [u32; 2],
+        wait: Duration,
+    ) -> Option<Arc<ColorImage>> {
+        let key = cache_key(url, size);
+        let cached = self.cache.lock().unwrap().get(&key).cloned();
+        metrics::cache_lookup("media", cached.is_some());
+        if let Some(cached) = cached {
+            return cached;
+        }
+
+        let media = self.clone();
+        let url = url.to_owned();
+        let handle = tokio::spawn(async move {
+            let image = match fetch_image(&media.client, &url, size).await {
+                Ok(image) => Some(Arc::new(image)),
+                Err(err) => {
+                    debug!("failed to load media {url}: {err}");
+                    None
+                }
+            };
+            metrics::cache_put(
+                "media",
+                &mut media.cache.lock().unwrap(),
+                key,
+                image.clone(),
+            );
+            image
+        });
+
+        tokio::time::timeout(wait, handle).await.ok()?.ok()?
+    }
+}
+
+async fn fetch_image(
+    client: &reqwest::Client,
+    url: &str,
+    size: [u32; 2],
+) -> Result<ColorImage, Error> {
+    let res = fetch::get(client, url).await?;
+    let data = fetch::read_body(res, MAX_IMAGE_SIZE).await?;
+
+    // decoding and scaling is cpu bound, keep it off the runtime threads
+    tokio::task::spawn_blocking(move || -> Result<ColorImage, Error> {
+        let image = decode_image(&data)?.resize_to_fill(size[0], size[1], FilterType::CatmullRom);
+        let buffer = image.into_rgba8();
+        Ok(ColorImage::from_rgba_unmultiplied(
+            [buffer.width() as usize, buffer.height() as usize],
+            buffer.as_flat_samples().as_slice(),
+        ))
+    })
+    .await
+    .map_err(|err| Error::Generic(err.to_string()))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = std::io::Cursor::new(vec![]);
+        image::DynamicImage::new_luma8(width, height)
+            .write_to(&mut data, image::ImageOutputFormat::Png)
+            .unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn decodes_images_within_limits() {
+        let image = decode_image(&png(16, 9)).unwrap();
+        assert_eq!((image.width(), image.height()), (16, 9));
+    }
+
+    #[test]
+    fn refuses_huge_images() {
+        // compresses to almost nothing, would decode to a lot
+        assert!(decode_image(&png(MAX_IMAGE_DIMENSION + 1, 1)).is_err());
+        assert!(decode_image(&png(1, MAX_IMAGE_DIMENSION + 1)).is_err());
+    }
+}
@@ -0,0 +1,431 @@
+use crate::{abbrev::summarize, config::OgImagePolicy};
+use html_escape::encode_double_quoted_attribute as attr;
+use serde_json::json;
+use std::io::Write;
+
+/// Twitter cuts descriptions at 200 characters and facebook not much
+/// later, anything longer is wasted bytes in every unfurl
+const DESCRIPTION_MAX_BYTES: usize = 200;
+
+/// The description for a page, from any kind of content: whitespace
+/// collapsed, control characters dropped and cut to a size every
+/// platform shows in full
+pub fn og_description(text: &str) -> String {
+    summarize(text, DESCRIPTION_MAX_BYTES)
+}
+
+/// The image we end up advertising as `og:image`
+pub enum OgImage {
+    /// Our own rendered png card. We know its dimensions.
+    Card(String),
+    /// An image taken from the content itself
+    Media(String),
+    /// The author's profile picture
+    Avatar(String),
+}
+
+impl OgImage {
+    pub fn url(&self) -> &str {
+        match self {
+            OgImage::Card(url) | OgImage::Media(url) | OgImage::Avatar(url) => url,
+        }
+    }
+
+    /// Avatars are small and square, everything else gets the large
+    /// twitter card
+    fn twitter_card(&self) -> &'static str {
+        match self {
+            OgImage::Avatar(_) => "summary",
+            OgImage::Card(_) | OgImage::Media(_) => "summary_large_image",
+        }
+    }
+}
+
+/// Past this many characters of text alongside its image, a note is
+/// about its words and `auto` shows them on our card
+const AUTO_CAPTION_MAX_CHARS: usize = 80;
+
+/// Pick the og:image for a page according to the configured policy.
+///
+/// `media` is the image belonging to the content (first note image,
+/// article hero image or profile banner), `caption` the text posted with
+/// it, `None` when the image stands for the whole thing. `avatar` is the
+/// author's profile picture.
+pub fn choose_og_image(
+    policy: OgImagePolicy,
+    card_url: String,
+    media: Option<&str>,
+    caption: Option<&str>,
+    avatar: Option<&str>,
+) -> OgImage {
+    match policy {
+        OgImagePolicy::Generated => OgImage::Card(card_url),
+
+        OgImagePolicy::Media => match media {
+            Some(url) => OgImage::Media(url.to_owned()),
+            None => OgImage::Card(card_url),
+        },
+
+        OgImagePolicy::Auto => {
+            let short = caption.map_or(true, |caption| {
+                caption.trim().chars().count() <= AUTO_CAPTION_MAX_CHARS
+            });
+            match media {
+                Some(url) if short => OgImage::Media(url.to_owned()),
+                _ => OgImage::Card(card_url),
+            }
+        }
+
+        OgImagePolicy::Avatar => match avatar {
+            Some(url) => OgImage::Avatar(url.to_owned()),
+            None => OgImage::Card(card_url),
+        },
+    }
+}
+
+/// A video from the note that platforms can play inline from the preview
+pub struct OgVideo {
+    pub url: String,
+    /// `WxH`, when the note's imeta tags tell us
+    pub dim: Option<(u32, u32)>,
+    /// Our page that plays it, see `embed::serve_embed`
+    pub player: String,
+}
+
+/// What twitter sizes the player to when the note doesn't say
+const DEFAULT_PLAYER_DIM: (u32, u32) = (1280, 720);
+
+impl OgVideo {
+    /// Twitter only plays videos in an https page it can put in an
+    /// iframe, and the video has to be https too
+    fn playable_on_twitter(&self) -> bool {
+        self.url.starts_with("https://") && self.player.starts_with("https://")
+    }
+
+    fn mime_type(&self) -> Option<&'static str> {
+        let path = self.url.split(['?', '#']).next().unwrap_or(&self.url);
+        let (_, ext) = path.rsplit_once('.')?;
+        match ext.to_ascii_lowercase().as_str() {
+            "mp4" | "m4v" => Some("video/mp4"),
+            "webm" => Some("video/webm"),
+            "mov" => Some("video/quicktime"),
+            "ogv" => Some("video/ogg"),
+            "m3u8" => Some("application/x-mpegURL"),
+            _ => None,
+        }
+    }
+
+    fn write_tags(&self, data: &mut Vec<u8>) -> std::io::Result<()> {
+        let url = attr(&self.url);
+
+        write!(
+            data,
+            r#"
+          <meta property="og:video" content="{url}" />"#
+        )?;
+
+        if self.url.starts_with("https://") {
+            write!(
+                data,
+                r#"
+          <meta property="og:video:secure_url" content="{url}" />"#
+            )?;
+        }
+
+        if let Some(mime_type) = self.mime_type() {
+            write!(
+                data,
+                r#"
+          <meta property="og:video:type" content="{mime_type}" />"#
+            )?;
+        }
+
+        if let Some((width, height)) = self.dim {
+            write!(
+                data,
+                r#"
+          <meta property="og:video:width" content="{width}" />
+          <meta property="og:video:height" content="{height}" />"#
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// The player card tags, the video plays in place of the image
+    fn write_twitter_tags(&self, data: &mut Vec<u8>) -> std::io::Result<()> {
+        let url = attr(&self.url);
+        let player = attr(&self.player);
+        let (width, height) = self.dim.unwrap_or(DEFAULT_PLAYER_DIM);
+
+        write!(
+            data,
+            r#"
+          <meta name="twitter:player" content="{player}" />
+          <meta name="twitter:player:width" content="{width}" />
+          <meta name="twitter:player:height" content="{height}" />
+          <meta name="twitter:player:stream" content="{url}" />"#
+        )?;
+
+        if let Some(mime_type) = self.mime_type() {
+            write!(
+                data,
+                r#"
+          <meta name="twitter:player:stream:content_type" content="{mime_type}" />"#
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What a page is about, for its schema.org JSON-LD
+pub enum Schema {
+    /// A note, a `SocialMediaPosting`
+    Posting(Authored),
+    /// A longform article
+    Article(Authored),
+    /// A profile
+    Person { name: String },
+}
+
+/// Who wrote a note or article, and when
+pub struct Authored {
+    pub author: String,
+    /// The author's profile page
+    pub author_url: String,
+    pub published: u64,
+}
+
+impl Authored {
+    fn fields(&self) -> serde_json::Value {
+        let published = chrono::DateTime::from_timestamp(self.published as i64, 0)
+            .map(|date| date.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+
+        json!({
+            "datePublished": published,
+            "author": {
+                "@type": "Person",
+                "name": self.author,
+                "url": self.author_url,
+            },
+        })
+    }
+}
+
+/// A machine readable version of a page
+pub struct Alternate {
+    pub mime_type: &'static str,
+    pub url: String,
+}
+
+/// Everything needed to emit the opengraph and twitter tags for a page
+pub struct OgMeta {
+    pub title: String,
+    pub description: String,
+    pub url: String,
+    pub image: OgImage,
+    /// og:type, eg: website, article or profile
+    pub og_type: &'static str,
+    pub video: Option<OgVideo>,
+    pub schema: Schema,
+    pub alternate: Option<Alternate>,
+    /// The page's oEmbed, see [`crate::oembed::oembed_url`]
+    pub oembed: Option<String>,
+}
+
+impl OgMeta {
+    /// The page's schema.org JSON-LD, from the same metadata as its tags
+    fn json_ld(&self) -> serde_json::Value {
+        let image = self.image.url();
+        let mut ld = match &self.schema {
+            Schema::Posting(authored) => {
+                let mut ld = json!({
+                    "@type": "SocialMediaPosting",
+                    "text": self.description,
+                });
+                merge(&mut ld, authored.fields());
+                ld
+            }
+            Schema::Article(authored) => {
+                let mut ld = json!({
+                    "@type": "Article",
+                    "headline": self.title,
+                    "description": self.description,
+                });
+                merge(&mut ld, authored.fields());
+                ld
+            }
+            Schema::Person { name } => json!({
+                "@type": "Person",
+                "name": name,
+                "description": self.description,
+            }),
+        };
+
+        merge(
+            &mut ld,
+            json!({
+                "@context": "https://schema.org",
+                "url": self.url,
+                "mainEntityOfPage": self.url,
+                "image": image,
+            }),
+        );
+        ld
+    }
+
+    /// Where tools can find the page's json and oEmbed
+    fn write_discovery_links(&self, data: &mut Vec<u8>) -> std::io::Result<()> {
+        let title = attr(&self.title);
+
+        if let Some(alternate) = &self.alternate {
+            write!(
+                data,
+                r#"
+          <link rel="alternate" type="{}" href="{}" title="{title}" />"#,
+                alternate.mime_type,
+                attr(&alternate.url)
+            )?;
+        }
+
+        if let Some(oembed) = &self.oembed {
+            write!(
+                data,
+                r#"
+          <link rel="alternate" type="application/json+oembed" href="{}" title="{title}" />"#,
+                attr(oembed)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write_json_ld(&self, data: &mut Vec<u8>) -> std::io::Result<()> {
+        // content can't close the script tag early with a < spelled as an
+        // escape, which is the same string as far as json is concerned
+        let ld = self.json_ld().to_string().replace('<', "\\u003c");
+        write!(
+            data,
+            r#"
+          <script type="application/ld+json">{ld}</script>"#
+        )
+    }
+
+    pub fn write_tags(&self, data: &mut Vec<u8>) -> std::io::Result<()> {
+        let title = attr(&self.title);
+        let description = attr(&self.description);
+        let url = attr(&self.url);
+        let image = attr(self.image.url());
+
+        write!(
+            data,
+            r#"
+          <meta property="og:description" content="{description}" />
+          <meta property="og:image" content="{image}"/>
+          <meta property="og:image:alt" content="{title}: {description}" />"#
+        )?;
+
+        let player = self
+            .video
+            .as_ref()
+            .filter(|video| video.playable_on_twitter());
+        if let Some(video) = &self.video {
+            video.write_tags(data)?;
+        }
+        if let Some(video) = player {
+            video.write_twitter_tags(data)?;
+        }
+
+        if let OgImage::Card(_) = self.image {
+            write!(
+                data,
+                r#"
+          <meta property="og:image:height" content="600" />
+          <meta property="og:image:width" content="1200" />
+          <meta property="og:image:type" content="image/png" />"#
+            )?;
+        }
+
+        write!(
+            data,
+            r#"
+          <link rel="canonical" href="{url}" />
+          <meta property="og:site_name" content="Damus" />
+          <meta property="og:title" content="{title}" />
+          <meta property="og:url" content="{url}"/>
+          <meta name="og:type" content="{og_type}"/>
+          <meta name="twitter:image:src" content="{image}" />
+          <meta name="twitter:site" content="@damusapp" />
+          <meta name="twitter:card" content="{card}" />
+          <meta name="twitter:title" content="{title}" />
+          <meta name="twitter:description" content="{description}" />"#,
+            og_type = self.og_type,
+            card = if player.is_some() {
+                "player"
+            } else {
+                self.image.twitter_card()
+            },
+        )?;
+
+        self.write_json_ld(data)?;
+        self.write_discovery_links(data)?;
+        writeln!(data)
+    }
+}
+
+/// Add `extra`'s fields to the `ld` object
+fn merge(ld: &mut serde_json::Value, extra: serde_json::Value) {
+    if let (Some(ld), serde_json::Value::Object(extra)) = (ld.as_object_mut(), extra) {
+        ld.extend(extra);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn choose(policy: OgImagePolicy, media: Option<&str>, caption: Option<&str>) -> String {
+        let image = choose_og_image(
+            policy,
+            "card.png".to_string(),
+            media,
+            caption,
+            Some("avatar.png"),
+        );
+        image.url().to_owned()
+    }
+
+    #[test]
+    fn auto_shows_images_that_are_the_point() {
+        let essay = "words ".repeat(50);
+
+        // a photo with a caption
+        assert_eq!(
+            choose(OgImagePolicy::Auto, Some("a.jpg"), Some("gm")),
+            "a.jpg"
+        );
+        // an article's hero image
+        assert_eq!(choose(OgImagePolicy::Auto, Some("a.jpg"), None), "a.jpg");
+        // a long note that happens to have an image is about its words
+        assert_eq!(
+            choose(OgImagePolicy::Auto, Some("a.jpg"), Some(&essay)),
+            "card.png"
+        );
+        assert_eq!(choose(OgImagePolicy::Auto, None, Some("gm")), "card.png");
+
+        // media doesn't care
+        assert_eq!(
+            choose(OgImagePolicy::Media, Some("a.jpg"), Some(&essay)),
+            "a.jpg"
+        );
+        assert_eq!(
+            choose(OgImagePolicy::Avatar, Some("a.jpg"), None),
+            "avatar.png"
+        );
+        assert_eq!(
+            choose(OgImagePolicy::Generated, Some("a.jpg"), None),
+            "card.png"
+        );
+    }
+}
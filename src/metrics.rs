@@ -0,0 +1,356 @@
+use crate::{db_stats, Error};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, Response, StatusCode};
+use lru::LruCache;
+use nostrdb::Ndb;
+use prometheus::core::Collector;
+#[cfg(target_os = "linux")]
+use prometheus::process_collector::ProcessCollector;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+struct Metrics {
+    registry: Registry,
+    /// Per relay query metrics, for tuning the default relay set
+    first_event: HistogramVec,
+    events: HistogramVec,
+    /// Per route request metrics, for error rates per output format
+    requests: IntCounterVec,
+    request_seconds: HistogramVec,
+    /// Per cache metrics, for sizing them
+    cache_hits: IntCounterVec,
+    cache_misses: IntCounterVec,
+    cache_evictions: IntCounterVec,
+    cache_entries: IntGaugeVec,
+    /// Whether a page's note and profile were all in ndb already
+    lookups: IntCounterVec,
+    /// Resources we hold, for alerting on unbounded growth
+    relay_connections: IntGauge,
+    ndb_size: IntGauge,
+    ndb_events: IntGaugeVec,
+}
+
+/// How often we count what's in ndb
+const NDB_STATS_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Relays that get their own metric labels. Anything else is "other",
+/// relay hints would otherwise make a new series each.
+static LABELED_RELAYS: OnceLock<Vec<String>> = OnceLock::new();
+
+fn register<M: Collector + Clone + 'static>(registry: &Registry, metric: M) -> M {
+    registry
+        .register(Box::new(metric.clone()))
+        .expect("register metric");
+    metric
+}
+
+fn counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    register(
+        registry,
+        IntCounterVec::new(Opts::new(name, help), labels).expect("counter"),
+    )
+}
+
+fn histogram(
+    registry: &Registry,
+    name: &str,
+    help: &str,
+    buckets: Vec<f64>,
+    labels: &[&str],
+) -> HistogramVec {
+    register(
+        registry,
+        HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets), labels)
+            .expect("histogram"),
+    )
+}
+
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| {
+        let registry = Registry::new();
+        // resident memory, cpu time and open files
+        #[cfg(target_os = "linux")]
+        registry
+            .register(Box::new(ProcessCollector::for_self()))
+            .expect("register process metrics");
+        let request_labels = ["route", "status", "nip19"];
+
+        Metrics {
+            first_event: histogram(
+                &registry,
+                "notecrumbs_relay_first_event_seconds",
+                "Time from sending a query to a relay's first event",
+                vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0],
+                &["relay"],
+            ),
+            events: histogram(
+                &registry,
+                "notecrumbs_relay_events_received",
+                "Events a relay sent us per query",
+                vec![0.0, 1.0, 2.0, 5.0, 10.0, 50.0, 200.0],
+                &["relay"],
+            ),
+            requests: counter(
+                &registry,
+                "notecrumbs_requests_total",
+                "Requests served",
+                &request_labels,
+            ),
+            request_seconds: histogram(
+                &registry,
+                "notecrumbs_request_seconds",
+                "Time to serve a request, relay lookups and renders included",
+                vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0],
+                &request_labels,
+            ),
+            cache_hits: counter(
+                &registry,
+                "notecrumbs_cache_hits_total",
+                "Cache lookups that found an entry",
+                &["cache"],
+            ),
+            cache_misses: counter(
+                &registry,
+                "notecrumbs_cache_misses_total",
+                "Cache lookups that didn't find an entry",
+                &["cache"],
+            ),
+            cache_evictions: counter(
+                &registry,
+                "notecrumbs_cache_evictions_total",
+                "Entries pushed out of a full cache",
+                &["cache"],
+            ),
+            cache_entries: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new("notecrumbs_cache_entries", "Entries in a cache"),
+                    &["cache"],
+                )
+                .expect("gauge"),
+            ),
+            lookups: counter(
+                &registry,
+                "notecrumbs_lookups_total",
+                "Pages served from ndb alone (\"ndb\") or after asking relays (\"relay\")",
+                &["source"],
+            ),
+            relay_connections: register(
+                &registry,
+                IntGauge::new(
+                    "notecrumbs_relay_connections",
+                    "Relay connections we have open",
+                )
+                .expect("gauge"),
+            ),
+            ndb_size: register(
+                &registry,
+                IntGauge::new("notecrumbs_ndb_size_bytes", "Size of the ndb database file")
+                    .expect("gauge"),
+            ),
+            ndb_events: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new(
+                        "notecrumbs_ndb_events",
+                        "Events in ndb by kind, counted every few minutes",
+                    ),
+                    &["kind"],
+                )
+                .expect("gauge"),
+            ),
+            registry,
+        }
+    })
+}
+
+/// Give these relays (our default and search relays) their own labels
+pub fn label_relays(relays: Vec<String>) {
+    let relays = relays
+        .iter()
+        .map(|relay| relay.trim_end_matches('/').to_string())
+        .collect();
+    let _ = LABELED_RELAYS.set(relays);
+}
+
+fn relay_label(relay: &str) -> String {
+    let relay = relay.trim_end_matches('/');
+    match LABELED_RELAYS.get() {
+        Some(labeled) if labeled.iter().any(|labeled| labeled == relay) => relay.to_string(),
+        _ => "other".to_string(),
+    }
+}
+
+/// What each relay sent during a single query. Event counts are
+/// recorded when it's dropped, so relays that sent nothing are counted
+/// too.
+pub struct RelayYields {
+    started: Instant,
+    received: HashMap<String, u64>,
+}
+
+impl RelayYields {
+    pub fn new(relays: &[String]) -> Self {
+        RelayYields {
+            started: Instant::now(),
+            received: relays.iter().map(|relay| (relay.clone(), 0)).collect(),
+        }
+    }
+
+    pub fn record(&mut self, relay: &str) {
+        let received = self.received.entry(relay.to_owned()).or_default();
+        if *received == 0 {
+            metrics()
+                .first_event
+                .with_label_values(&[&relay_label(relay)])
+                .observe(self.started.elapsed().as_secs_f64());
+        }
+        *received += 1;
+    }
+}
+
+impl Drop for RelayYields {
+    fn drop(&mut self) {
+        let metrics = metrics();
+        for (relay, received) in &self.received {
+            metrics
+                .events
+                .with_label_values(&[&relay_label(relay)])
+                .observe(*received as f64);
+        }
+    }
+}
+
+/// What a request path asks for: a rendered card or media grid ("png",
+/// whatever the image format), json, feeds and other files ("asset"), or
+/// a page ("html"). Errors are counted under the route that failed.
+pub fn route_class(path: &str) -> &'static str {
+    if path.starts_with("/admin/") {
+        return "asset";
+    }
+
+    let last = path.rsplit('/').next().unwrap_or(path);
+    match last.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("png" | "jpg" | "jpeg" | "webp") => "png",
+        Some("json" | "jsonfeed") => "json",
+        Some(_) => "asset",
+        None if last == "rss" => "asset",
+        None => "html",
+    }
+}
+
+/// Count a served request and how long it took
+pub fn record_request(route: &str, status: u16, nip19: &str, elapsed: Duration) {
+    let metrics = metrics();
+    let status = status.to_string();
+    let labels = [route, status.as_str(), nip19];
+    metrics.requests.with_label_values(&labels).inc();
+    metrics
+        .request_seconds
+        .with_label_values(&labels)
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Count a lookup in one of our caches
+pub fn cache_lookup(cache: &str, hit: bool) {
+    let metrics = metrics();
+    let counter = if hit {
+        &metrics.cache_hits
+    } else {
+        &metrics.cache_misses
+    };
+    counter.with_label_values(&[cache]).inc();
+}
+
+/// Insert into one of our caches, counting whether that pushed another
+/// entry out and how many entries it holds now
+pub fn cache_put<K: Hash + Eq, V>(name: &str, cache: &mut LruCache<K, V>, key: K, value: V) {
+    let metrics = metrics();
+    if !cache.contains(&key) && cache.len() == cache.cap().get() {
+        metrics.cache_evictions.with_label_values(&[name]).inc();
+    }
+    cache.put(key, value);
+    metrics
+        .cache_entries
+        .with_label_values(&[name])
+        .set(cache.len() as i64);
+}
+
+/// Count a page that needed a relay fetch, or didn't
+pub fn record_lookup(needed_relays: bool) {
+    let source = if needed_relays { "relay" } else { "ndb" };
+    metrics().lookups.with_label_values(&[source]).inc();
+}
+
+/// Relays a client is connected to, counted until this is dropped
+pub struct OpenRelays(i64);
+
+impl OpenRelays {
+    pub async fn count(client: &nostr_sdk::Client) -> Self {
+        let connected = client
+            .relays()
+            .await
+            .values()
+            .filter(|relay| relay.is_connected())
+            .count() as i64;
+        metrics().relay_connections.add(connected);
+        OpenRelays(connected)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl Drop for OpenRelays {
+    fn drop(&mut self) {
+        metrics().relay_connections.sub(self.0);
+    }
+}
+
+fn count_ndb(ndb: &Ndb, dir: &Path) {
+    let metrics = metrics();
+    let stats = db_stats::db_stats(ndb, dir);
+    if let Some(size) = stats.size_bytes {
+        metrics.ndb_size.set(size as i64);
+    }
+    for (kind, count) in stats.events {
+        metrics
+            .ndb_events
+            .with_label_values(&[&kind.to_string()])
+            .set(count as i64);
+    }
+}
+
+/// Keep the ndb size and per kind event gauges up to date
+pub async fn ndb_stats_loop(ndb: Ndb, dir: PathBuf) {
+    let mut interval = tokio::time::interval(NDB_STATS_INTERVAL);
+    loop {
+        interval.tick().await;
+        let (ndb, dir) = (ndb.clone(), dir.clone());
+        // queries walk the database, keep them off the runtime threads
+        let _ = tokio::task::spawn_blocking(move || count_ndb(&ndb, &dir)).await;
+    }
+}
+
+/// `/admin/metrics`: everything we measure, in the prometheus text format
+pub fn serve_metrics() -> Result<Response<Full<Bytes>>, Error> {
+    let encoder = TextEncoder::new();
+    let mut data = Vec::new();
+    encoder
+        .encode(&metrics().registry.gather(), &mut data)
+        .map_err(|err| Error::Generic(err.to_string()))?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, encoder.format_type())
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(data)))?)
+}
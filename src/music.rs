@@ -0,0 +1,116 @@
+/// Music platforms we know how to embed a player for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MusicPlatform {
+    Wavlake,
+    Spotify,
+    Tidal,
+}
+
+impl MusicPlatform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MusicPlatform::Wavlake => "wavlake",
+            MusicPlatform::Spotify => "spotify",
+            MusicPlatform::Tidal => "tidal",
+        }
+    }
+
+    /// Height of the embedded player, single tracks get the compact one
+    pub fn player_height(&self, is_track: bool) -> u32 {
+        match (self, is_track) {
+            (MusicPlatform::Spotify, true) => 152,
+            (MusicPlatform::Spotify, false) => 352,
+            (MusicPlatform::Tidal, true) => 120,
+            (MusicPlatform::Tidal, false) => 300,
+            (MusicPlatform::Wavlake, true) => 380,
+            (MusicPlatform::Wavlake, false) => 480,
+        }
+    }
+}
+
+pub struct MusicEmbed {
+    pub platform: MusicPlatform,
+    pub embed_url: String,
+    pub is_track: bool,
+}
+
+/// Split a url into its lowercased host and path segments
+fn host_and_segments(url: &str) -> Option<(String, Vec<&str>)> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let rest = rest.split(['?', '#']).next().unwrap_or(rest);
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let host = host.to_ascii_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host).to_owned();
+    let segments = path.split('/').filter(|s| !s.is_empty()).collect();
+    Some((host, segments))
+}
+
+fn spotify_embed(segments: &[&str]) -> Option<MusicEmbed> {
+    // open.spotify.com/intl-de/track/<id> style links carry a locale prefix
+    let segments = match segments.first() {
+        Some(first) if first.starts_with("intl-") => &segments[1..],
+        _ => segments,
+    };
+
+    match segments {
+        [kind @ ("track" | "album" | "playlist" | "episode" | "show" | "artist"), id, ..] => {
+            Some(MusicEmbed {
+                platform: MusicPlatform::Spotify,
+                embed_url: format!("https://open.spotify.com/embed/{kind}/{id}"),
+                is_track: matches!(*kind, "track" | "episode"),
+            })
+        }
+        _ => None,
+    }
+}
+
+fn tidal_embed(segments: &[&str]) -> Option<MusicEmbed> {
+    let segments = match segments.first() {
+        Some(&"browse") => &segments[1..],
+        _ => segments,
+    };
+
+    let (kind, id) = match segments {
+        [kind, id, ..] => (*kind, *id),
+        _ => return None,
+    };
+
+    let embed_kind = match kind {
+        "track" => "tracks",
+        "album" => "albums",
+        "playlist" => "playlists",
+        "video" => "videos",
+        _ => return None,
+    };
+
+    Some(MusicEmbed {
+        platform: MusicPlatform::Tidal,
+        embed_url: format!("https://embed.tidal.com/{embed_kind}/{id}"),
+        is_track: kind == "track",
+    })
+}
+
+fn wavlake_embed(segments: &[&str]) -> Option<MusicEmbed> {
+    match segments {
+        [kind @ ("track" | "album" | "playlist"), id, ..] => Some(MusicEmbed {
+            platform: MusicPlatform::Wavlake,
+            embed_url: format!("https://embed.wavlake.com/{kind}/{id}"),
+            is_track: *kind == "track",
+        }),
+        _ => None,
+    }
+}
+
+/// Detect a music platform link and return its embeddable player url
+pub fn music_embed(url: &str) -> Option<MusicEmbed> {
+    let (host, segments) = host_and_segments(url)?;
+
+    match host.as_str() {
+        "open.spotify.com" => spotify_embed(&segments),
+        "tidal.com" | "listen.tidal.com" => tidal_embed(&segments),
+        "wavlake.com" => wavlake_embed(&segments),
+        _ => None,
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{fetch, Error};
+use lru::LruCache;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// How long a nostr.json fetch may take, the visitor is waiting on it
+const FETCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long we trust a resolved address
+const RESOLVED_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Failures are retried sooner, the domain may just have been down
+const FAILED_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// nostr.json files can list a lot of names, but not this many
+const MAX_NOSTR_JSON_SIZE: usize = 1024 * 1024;
+
+#[derive(Deserialize)]
+struct NostrJson {
+    #[serde(default)]
+    names: HashMap<String, String>,
+}
+
+/// Split a NIP-05 address into its name and domain. A bare `@domain` is
+/// the domain's `_` name.
+pub fn parse_address(address: &str) -> Option<(String, String)> {
+    let address = address.replace("%40", "@");
+    let (name, domain) = address.split_once('@')?;
+    let name = if name.is_empty() { "_" } else { name };
+
+    let valid_name = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    let valid_domain = domain.contains('.')
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.'));
+
+    if valid_name && valid_domain {
+        Some((name.to_ascii_lowercase(), domain.to_ascii_lowercase()))
+    } else {
+        None
+    }
+}
+
+/// What an address's nostr.json told us
+#[derive(Clone, Copy)]
+enum Lookup {
+    /// the address points at this pubkey
+    Found([u8; 32]),
+    /// nostr.json doesn't list the name
+    Missing,
+    /// nostr.json couldn't be fetched or read
+    Failed,
+}
+
+impl Lookup {
+    fn pubkey(self) -> Option<[u8; 32]> {
+        match self {
+            Lookup::Found(pubkey) => Some(pubkey),
+            Lookup::Missing | Lookup::Failed => None,
+        }
+    }
+}
+
+/// NIP-05 addresses resolved to pubkeys
+pub struct Nip05Cache {
+    cache: Mutex<LruCache<String, (Instant, Lookup)>>,
+    client: reqwest::Client,
+}
+
+impl Nip05Cache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        // nostr.json must be served directly, a redirect is a failure
+        let client = fetch::client("notecrumbs (nip05)", FETCH_TIMEOUT, 0);
+
+        Nip05Cache {
+            cache: Mutex::new(LruCache::new(capacity)),
+            client,
+        }
+    }
+
+    fn cached(&self, key: &str) -> Option<Lookup> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|(at, lookup)| {
+                at.elapsed()
+                    < match lookup {
+                        Lookup::Found(_) => RESOLVED_TTL,
+                        Lookup::Missing | Lookup::Failed => FAILED_TTL,
+                    }
+            })
+            .map(|(_, lookup)| *lookup)
+    }
+
+    async fn lookup(&self, name: &str, domain: &str) -> Lookup {
+        let key = format!("{name}@{domain}");
+        if let Some(lookup) = self.cached(&key) {
+            return lookup;
+        }
+
+        let lookup = match fetch_pubkey(&self.client, name, domain).await {
+            Ok(Some(pubkey)) => Lookup::Found(pubkey),
+            Ok(None) => Lookup::Missing,
+            Err(err) => {
+                debug!("nip05 lookup failed for {key}: {err}");
+                Lookup::Failed
+            }
+        };
+
+        self.cache
+            .lock()
+            .unwrap()
+            .put(key, (Instant::now(), lookup));
+
+        lookup
+    }
+
+    /// The pubkey a `name@domain` address points at
+    pub async fn resolve(&self, name: &str, domain: &str) -> Option<[u8; 32]> {
+        self.lookup(name, domain).await.pubkey()
+    }
+
+    /// Whether a profile's claimed nip05 address points back at it,
+    /// `None` when its nostr.json couldn't be fetched and we can't tell
+    pub async fn verify(&self, address: &str, pubkey: &[u8; 32]) -> Option<bool> {
+        let (name, domain) = match parse_address(address) {
+            Some(parsed) => parsed,
+            None => return Some(false),
+        };
+
+        match self.lookup(&name, &domain).await {
+            Lookup::Found(found) => Some(&found == pubkey),
+            Lookup::Missing => Some(false),
+            Lookup::Failed => None,
+        }
+    }
+}
+
+async fn fetch_pubkey(
+    client: &reqwest::Client,
+    name: &str,
+    domain: &str,
+) -> Result<Option<[u8; 32]>, Error> {
+    let url = format!("https://{domain}/.well-known/nostr.json?name={name}");
+    let res = fetch::get(client, &url).await?;
+    let data = fetch::read_body(res, MAX_NOSTR_JSON_SIZE).await?;
+
+    let nostr_json: NostrJson = serde_json::from_slice(&data)?;
+
+    // names are case insensitive, but not everyone lowercases them
+    Ok(nostr_json
+        .names
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .and_then(|(_, pubkey)| hex::decode(pubkey).ok())
+        .and_then(|pubkey| pubkey.try_into().ok()))
+}
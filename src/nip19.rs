@@ -11,6 +11,148 @@ pub fn nip19_relays(nip19: &Nip19) -> Vec<RelayUrl> {
             .filter_map(|r| RelayUrl::parse(r).ok())
             .collect(),
         Nip19::Profile(p) => p.relays.clone(),
+        Nip19::Coordinate(coord) => coord
+            .relays
+            .iter()
+            .filter_map(|r| RelayUrl::parse(r).ok())
+            .collect(),
         _ => vec![],
     }
 }
+
+/// Relay filters for everything we need to render a nip19 entity: the
+/// note it points at and its author's profile
+pub fn nip19_filters(nip19: &Nip19) -> Result<Vec<Filter>, crate::Error> {
+    let profile = |pubkey: PublicKey| Filter::new().author(pubkey).kind(Kind::Metadata).limit(1);
+
+    match nip19 {
+        Nip19::Event(nevent) => {
+            let mut filters = vec![Filter::new().id(nevent.event_id).limit(1)];
+            if let Some(author) = nevent.author {
+                filters.push(profile(author));
+            }
+            Ok(filters)
+        }
+        Nip19::EventId(id) => Ok(vec![Filter::new().id(*id).limit(1)]),
+        Nip19::Pubkey(pubkey) => Ok(vec![profile(*pubkey)]),
+        Nip19::Profile(nprofile) => Ok(vec![profile(nprofile.public_key)]),
+        Nip19::Coordinate(coord) => Ok(vec![
+            Filter::new()
+                .author(coord.public_key)
+                .kind(coord.kind)
+                .identifier(coord.identifier.clone())
+                .limit(1),
+            profile(coord.public_key),
+        ]),
+        _ => Err(crate::Error::InvalidNip19),
+    }
+}
+
+/// The pubkey a profile link points at
+pub fn nip19_pubkey(nip19: &Nip19) -> Option<[u8; 32]> {
+    match nip19 {
+        Nip19::Pubkey(public_key) => Some(public_key.serialize()),
+        Nip19::Profile(nprofile) => Some(nprofile.public_key.serialize()),
+        _ => None,
+    }
+}
+
+/// What kind of entity a nip19 is, for logs and metrics
+pub fn nip19_type(nip19: &Nip19) -> &'static str {
+    match nip19 {
+        Nip19::Pubkey(_) => "npub",
+        Nip19::Profile(_) => "nprofile",
+        Nip19::EventId(_) => "note",
+        Nip19::Event(_) => "nevent",
+        Nip19::Coordinate(_) => "naddr",
+        _ => "secret",
+    }
+}
+
+/// Whose note or profile a nip19 entity points at, when it says
+pub fn nip19_author(nip19: &Nip19) -> Option<[u8; 32]> {
+    match nip19 {
+        Nip19::Event(nevent) => nevent.author.map(|author| author.serialize()),
+        Nip19::Coordinate(coord) => Some(coord.public_key.serialize()),
+        _ => nip19_pubkey(nip19),
+    }
+}
+
+/// The note ids and profiles a nip19 entity needs, when they're plain id
+/// and pubkey lookups that can be batched with other requests. `None`
+/// for addresses, which need a filter of their own.
+pub fn nip19_lookups(nip19: &Nip19) -> Option<(Vec<EventId>, Vec<PublicKey>)> {
+    match nip19 {
+        Nip19::Event(nevent) => Some((vec![nevent.event_id], nevent.author.into_iter().collect())),
+        Nip19::EventId(id) => Some((vec![*id], vec![])),
+        Nip19::Pubkey(pubkey) => Some((vec![], vec![*pubkey])),
+        Nip19::Profile(nprofile) => Some((vec![], vec![nprofile.public_key])),
+        _ => None,
+    }
+}
+
+/// The naddr for an addressable note (eg: a longform article), built
+/// from its kind, author and d tag
+pub fn naddr_for_note(note: &nostrdb::Note) -> Option<String> {
+    let identifier = crate::html::note_tag_value(note, "d").unwrap_or("");
+    let pubkey = PublicKey::from_slice(note.pubkey()).ok()?;
+    Coordinate::new(Kind::from_u16(note.kind() as u16), pubkey)
+        .identifier(identifier)
+        .to_bech32()
+        .ok()
+}
+
+/// Strip the NIP-21 `nostr:` scheme people paste along with an entity,
+/// percent-encoded or not, in any case
+pub fn strip_nostr_scheme(entity: &str) -> &str {
+    ["nostr:", "nostr%3a"]
+        .iter()
+        .find_map(|scheme| {
+            let head = entity.get(..scheme.len())?;
+            head.eq_ignore_ascii_case(scheme)
+                .then(|| &entity[scheme.len()..])
+        })
+        .unwrap_or(entity)
+}
+
+/// The one url form we want a note indexed under: its naddr for
+/// addressable notes, otherwise an nevent carrying its author and kind.
+/// Relay hints are left out so every way of linking the note agrees.
+pub fn canonical_bech32(note: &nostrdb::Note) -> Option<String> {
+    if (30000..40000).contains(&note.kind()) {
+        return naddr_for_note(note);
+    }
+
+    let id = EventId::from_slice(note.id()).ok()?;
+    let author = PublicKey::from_slice(note.pubkey()).ok()?;
+    Nip19Event::new(id, Vec::<String>::new())
+        .author(author)
+        .kind(Kind::from_u16(note.kind() as u16))
+        .to_bech32()
+        .ok()
+}
+
+/// The relay url in an `nrelay`. nostr-sdk dropped support for these, but
+/// they're still out there.
+pub fn decode_nrelay(nrelay: &str) -> Option<String> {
+    let (hrp, data) = bech32::decode(nrelay).ok()?;
+    if hrp.as_str() != "nrelay" {
+        return None;
+    }
+
+    // TLV, the relay url is the special (0) entry
+    let mut rest = data.as_slice();
+    while let [kind, len, tail @ ..] = rest {
+        let len = *len as usize;
+        if tail.len() < len {
+            return None;
+        }
+        let (value, tail) = tail.split_at(len);
+        if *kind == 0 {
+            return String::from_utf8(value.to_vec()).ok();
+        }
+        rest = tail;
+    }
+
+    None
+}
@@ -0,0 +1,36 @@
+use crate::cache_backend::CacheBackend;
+use hyper::body::Bytes;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long we answer "not found" without asking the relays again
+const NOT_FOUND_TTL: Duration = Duration::from_secs(2 * 60);
+
+/// Notes and profiles no relay had when we last asked, so repeat
+/// requests get their 404 right away instead of waiting out the relay
+/// timeout again
+pub struct NotFoundCache {
+    backend: Arc<dyn CacheBackend>,
+}
+
+fn backend_key(id: &[u8; 32]) -> String {
+    format!("not_found:{}", hex::encode(id))
+}
+
+impl NotFoundCache {
+    pub fn new(backend: Arc<dyn CacheBackend>) -> Self {
+        NotFoundCache { backend }
+    }
+
+    /// Nobody had this when we asked just now
+    pub async fn insert(&self, id: &[u8; 32]) {
+        self.backend
+            .put(backend_key(id), Bytes::new(), NOT_FOUND_TTL)
+            .await;
+    }
+
+    /// Whether nobody had this the last time we asked, recently
+    pub async fn contains(&self, id: &[u8; 32]) -> bool {
+        self.backend.get(&backend_key(id)).await.is_some()
+    }
+}
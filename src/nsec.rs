@@ -0,0 +1,120 @@
+use crate::Error;
+use http_body_util::Full;
+use hyper::{body::Bytes, header, Response, StatusCode};
+use std::borrow::Cow;
+
+/// What every bech32 private key starts with
+const NSEC_PREFIX: &str = "nsec1";
+
+/// What we log in place of a private key
+const REDACTED: &str = "nsec1[redacted]";
+
+/// Whether `s` has something that looks like a private key in it, in
+/// either case. Keys that don't decode count too, a typo'd nsec is still
+/// mostly someone's secret.
+pub fn contains_nsec(s: &str) -> bool {
+    s.to_ascii_lowercase().contains(NSEC_PREFIX)
+}
+
+/// `s` with every private key in it replaced, for logs
+pub fn redact_nsecs(s: &str) -> Cow<'_, str> {
+    if !contains_nsec(s) {
+        return Cow::Borrowed(s);
+    }
+
+    // lowercasing ascii keeps byte offsets, so they work on `s` too
+    let lower = s.to_ascii_lowercase();
+    let mut redacted = String::with_capacity(s.len());
+    let mut rest = 0;
+    while let Some(found) = lower[rest..].find(NSEC_PREFIX) {
+        let start = rest + found;
+        let data = start + NSEC_PREFIX.len();
+        let end = data
+            + lower[data..]
+                .bytes()
+                .take_while(u8::is_ascii_alphanumeric)
+                .count();
+
+        redacted.push_str(&s[rest..start]);
+        redacted.push_str(REDACTED);
+        rest = end;
+    }
+    redacted.push_str(&s[rest..]);
+
+    Cow::Owned(redacted)
+}
+
+/// What we show someone who pasted their private key into a url or the
+/// search box. We never decode it or look anything up with it, and the
+/// request is logged with the key redacted. Nothing on the page may be
+/// cached or tell other sites where the visitor came from.
+pub fn serve_exposed_key_page() -> Result<Response<Full<Bytes>>, Error> {
+    let page = r#"<html>
+<head>
+  <title>You shared a private key</title>
+  <link rel="stylesheet" href="https://damus.io/css/notecrumbs.css" type="text/css" />
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <meta name="robots" content="noindex">
+  <meta name="referrer" content="no-referrer">
+  <meta charset="UTF-8">
+</head>
+<body>
+  <main>
+    <div class="container">
+      <h1>That was a private key</h1>
+      <p>
+        The link you opened has an <code>nsec</code> in it. That's a nostr
+        private key: whoever has it can post as its owner, change their
+        profile and read their direct messages.
+      </p>
+      <p>
+        We didn't use it, and we don't log or store it. It has still been
+        exposed though: it's in your browser history, wherever it was
+        pasted or shared, and anything that saw the link along the way.
+      </p>
+      <h2>What to do now</h2>
+      <ul>
+        <li>Assume the key is compromised, there is no way to take it back.</li>
+        <li>Make a new key, and move your profile and follows over to it.</li>
+        <li>Post from the old key that you've moved, then stop using it.</li>
+      </ul>
+      <p>
+        To share a profile, use its public key instead, it starts with
+        <code>npub</code> and is safe to give out.
+      </p>
+      <p><a href="/">Back to notecrumbs</a></p>
+    </div>
+  </main>
+</body>
+</html>
+"#;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/html")
+        .header(header::CACHE_CONTROL, "no-store")
+        .header(header::REFERRER_POLICY, "no-referrer")
+        .header("x-robots-tag", "noindex")
+        .status(StatusCode::BAD_REQUEST)
+        .body(Full::new(Bytes::from(page)))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NSEC: &str = "nsec1vl029mgpspedva04g90vltkh6fvh240zqtv9k0t9af8935ke9laqsnlfe5";
+
+    #[test]
+    fn redacts_every_key() {
+        assert_eq!(redact_nsecs("/npub1abc"), "/npub1abc");
+        assert_eq!(redact_nsecs(&format!("/{NSEC}")), "/nsec1[redacted]");
+        assert_eq!(
+            redact_nsecs(&format!("/nostr:{}.png", NSEC.to_ascii_uppercase())),
+            "/nostr:nsec1[redacted].png"
+        );
+        assert_eq!(
+            redact_nsecs(&format!("{NSEC} and nsec1typo")),
+            "nsec1[redacted] and nsec1[redacted]"
+        );
+    }
+}
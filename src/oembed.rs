@@ -0,0 +1,115 @@
+use crate::{
+    feed::profile_name,
+    html::note_tag_value,
+    nip19,
+    render::{self, RenderData},
+    route::{self, PathFormat, Route},
+    search::{form_decode, form_encode},
+    Error, Notecrumbs,
+};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, Response, StatusCode};
+use nostr_sdk::prelude::{Nip19, PublicKey, ToBech32};
+use nostrdb::Transaction;
+use serde_json::json;
+
+/// Where a page's oEmbed lives, for its discovery link
+pub fn oembed_url(base_url: &str, page_url: &str) -> String {
+    format!(
+        "{base_url}/oembed?url={}&format=json",
+        form_encode(page_url)
+    )
+}
+
+/// The nip19 entity an oEmbed `url` is the page for. Only the path
+/// matters, whatever host they know us by.
+fn page_entity(url: &str) -> Option<Nip19> {
+    let url = form_decode(url);
+    let path = match url.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/')?..],
+        None => url.as_str(),
+    };
+    let path = path.split(['?', '#']).next().unwrap_or(path);
+
+    match route::route(path) {
+        Route::Entity(nip19, PathFormat::Html) => Some(nip19),
+        _ => None,
+    }
+}
+
+fn not_found() -> Result<Response<Full<Bytes>>, Error> {
+    Ok(Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Full::new(Bytes::from("Invalid url\n")))?)
+}
+
+/// `/oembed?url=`: an oEmbed (https://oembed.com) photo of a note or
+/// profile page, which is its card. Only what we already have is
+/// embedded, this never goes out to the relays.
+pub fn serve_oembed(
+    app: &Notecrumbs,
+    url: Option<&str>,
+    format: Option<&str>,
+    base_url: &str,
+) -> Result<Response<Full<Bytes>>, Error> {
+    if format.is_some_and(|format| format != "json") {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_IMPLEMENTED)
+            .body(Full::new(Bytes::from("Only json is supported\n")))?);
+    }
+
+    let nip19 = if let Some(nip19) = url.and_then(page_entity) {
+        nip19
+    } else {
+        return not_found();
+    };
+
+    let txn = Transaction::new(&app.ndb)?;
+    let render_data = render::get_render_data(&app.ndb, &txn, &nip19)?;
+    let (author, title) = match &render_data {
+        RenderData::Note(note_rd) => match note_rd.note_rd.lookup(&txn, &app.ndb) {
+            Ok(note) => {
+                let author = *note.pubkey();
+                let name = profile_name(app, &txn, &author);
+                let title = match note_tag_value(&note, "title") {
+                    Some(title) if note.kind() == 30023 => format!("{title} by {name}"),
+                    _ => format!("{name} on nostr"),
+                };
+                (author, title)
+            }
+            Err(_) => return not_found(),
+        },
+        RenderData::Profile(_) => match nip19::nip19_pubkey(&nip19) {
+            Some(pubkey) => (
+                pubkey,
+                format!("{} on nostr", profile_name(app, &txn, &pubkey)),
+            ),
+            None => return not_found(),
+        },
+    };
+
+    let bech32 = nip19.to_bech32()?;
+    let npub = PublicKey::from_slice(&author)
+        .ok()
+        .and_then(|pk| pk.to_bech32().ok())
+        .unwrap_or_default();
+    let (width, height) = render::CardOptions::default().size.pixels();
+
+    let oembed = json!({
+        "version": "1.0",
+        "type": "photo",
+        "title": title,
+        "author_name": profile_name(app, &txn, &author),
+        "author_url": format!("{base_url}/{npub}"),
+        "provider_name": "Damus",
+        "provider_url": base_url,
+        "url": format!("{base_url}/{bech32}.png"),
+        "width": width,
+        "height": height,
+    });
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(serde_json::to_vec(&oembed)?)))?)
+}
@@ -0,0 +1,110 @@
+use crate::budget::wait_until;
+use crate::render::{convert_filter, fetch_events};
+use nostr_sdk::prelude::{Keys, RelayUrl};
+use nostrdb::{Filter, Ndb, NdbStrVariant, Note, Transaction};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::debug;
+
+/// How long we wait on the default relays for a relay list we don't have
+const RELAY_LIST_WAIT: Duration = Duration::from_millis(1000);
+
+/// NIP-65 asks people to keep their lists short, not everyone does
+const MAX_OUTBOX_RELAYS: usize = 5;
+
+/// A relay from a NIP-65 relay list
+pub struct ListedRelay {
+    pub url: String,
+    /// read, write or both when `None`
+    pub marker: Option<String>,
+}
+
+impl ListedRelay {
+    /// Whether the author publishes to this relay
+    pub fn is_write(&self) -> bool {
+        matches!(self.marker.as_deref(), None | Some("write"))
+    }
+}
+
+fn tag_str<'a>(tag: &nostrdb::Tag<'a>, i: u16) -> Option<&'a str> {
+    match tag.get(i)?.variant() {
+        NdbStrVariant::Str(s) => Some(s),
+        NdbStrVariant::Id(_) => None,
+    }
+}
+
+fn listed_relays(relay_list: &Note) -> Vec<ListedRelay> {
+    relay_list
+        .tags()
+        .iter()
+        .filter(|tag| tag.count() >= 2 && tag_str(tag, 0) == Some("r"))
+        .filter_map(|tag| {
+            Some(ListedRelay {
+                url: tag_str(&tag, 1)?.to_owned(),
+                marker: tag_str(&tag, 2).map(|m| m.to_owned()),
+            })
+        })
+        .collect()
+}
+
+fn relay_list_filter(pubkey: &[u8; 32]) -> Filter {
+    Filter::new()
+        .authors([pubkey])
+        .kinds([10002])
+        .limit(1)
+        .build()
+}
+
+/// The relays in a pubkey's kind 10002 relay list, `None` when we don't
+/// have their list
+pub fn relay_list(ndb: &Ndb, txn: &Transaction, pubkey: &[u8; 32]) -> Option<Vec<ListedRelay>> {
+    ndb.query(txn, &[relay_list_filter(pubkey)], 1)
+        .ok()?
+        .first()
+        .map(|result| listed_relays(&result.note))
+}
+
+fn write_relays(relays: Vec<ListedRelay>) -> Vec<String> {
+    relays
+        .into_iter()
+        .filter(|relay| relay.is_write())
+        .filter_map(|relay| RelayUrl::parse(&relay.url).ok())
+        .map(|url| url.to_string())
+        .take(MAX_OUTBOX_RELAYS)
+        .collect()
+}
+
+/// The relays an author publishes to, which is where their notes and
+/// profile are. Asks `relays` for the author's relay list first when we
+/// don't have it, giving up at `deadline`. Empty when they don't have
+/// one.
+pub async fn outbox_relays(
+    ndb: &Ndb,
+    keys: Keys,
+    relays: Vec<String>,
+    pubkey: &[u8; 32],
+    deadline: Instant,
+) -> Vec<String> {
+    let local = Transaction::new(ndb)
+        .ok()
+        .and_then(|txn| relay_list(ndb, &txn, pubkey));
+    if let Some(listed) = local {
+        return write_relays(listed);
+    }
+
+    let filter = convert_filter(&relay_list_filter(pubkey));
+    let wait = wait_until(deadline, RELAY_LIST_WAIT);
+    if let Err(err) = fetch_events(ndb, keys, relays, vec![filter], wait).await {
+        debug!(
+            "couldn't fetch relay list for {}: {err}",
+            hex::encode(pubkey)
+        );
+        return vec![];
+    }
+
+    Transaction::new(ndb)
+        .ok()
+        .and_then(|txn| relay_list(ndb, &txn, pubkey))
+        .map(write_relays)
+        .unwrap_or_default()
+}
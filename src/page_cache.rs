@@ -0,0 +1,36 @@
+use crate::cache_backend::CacheBackend;
+use crate::metrics;
+use hyper::body::Bytes;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a rendered page is served as is. Link previews, reactions
+/// and preview preferences can change under the same key, so not long.
+const PAGE_TTL: Duration = Duration::from_secs(60);
+
+/// Rendered note pages, so a note that's being passed around doesn't go
+/// through block parsing, quote lookups and markdown on every visit.
+/// Keyed by `html::note_page_key`.
+pub struct PageCache {
+    backend: Arc<dyn CacheBackend>,
+}
+
+fn backend_key(key: &str) -> String {
+    format!("page:{key}")
+}
+
+impl PageCache {
+    pub fn new(backend: Arc<dyn CacheBackend>) -> Self {
+        PageCache { backend }
+    }
+
+    pub async fn get(&self, key: &str) -> Option<Bytes> {
+        let page = self.backend.get(&backend_key(key)).await;
+        metrics::cache_lookup("pages", page.is_some());
+        page
+    }
+
+    pub async fn put(&self, key: String, page: Bytes) {
+        self.backend.put(backend_key(&key), page, PAGE_TTL).await;
+    }
+}
@@ -0,0 +1,233 @@
+use crate::{fetch, metrics, pfp, Error};
+use egui::{Color32, ColorImage};
+use lru::LruCache;
+use nostr::hashes::{sha256::Hash as Sha256Hash, Hash};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+/// Profile pictures bigger than this aren't worth decoding for a 64px
+/// avatar
+const MAX_PFP_SIZE: usize = 8 * 1024 * 1024;
+
+/// How long a single avatar fetch may take
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// People change their profile pictures, but not often
+const PFP_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often expired avatars are deleted from disk
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Avatars fetched over https and processed into round `PFP_SIZE`
+/// bitmaps. Processed avatars are kept on disk, keyed by a hash of their
+/// url, so a restart doesn't mean downloading every avatar again.
+pub struct PfpCache {
+    memory: Mutex<LruCache<String, Option<Arc<ColorImage>>>>,
+    dir: PathBuf,
+    client: reqwest::Client,
+}
+
+impl PfpCache {
+    pub fn new(dir: impl Into<PathBuf>, capacity: NonZeroUsize) -> Self {
+        let client = fetch::client("notecrumbs (pfp)", FETCH_TIMEOUT, 3);
+
+        PfpCache {
+            memory: Mutex::new(LruCache::new(capacity)),
+            dir: dir.into(),
+            client,
+        }
+    }
+
+    /// The processed avatar at `url`, waiting at most `wait`. A fetch
+    /// that takes longer keeps going in the background and is cached for
+    /// the next request.
+    pub async fn fetch(self: &Arc<Self>, url: &str, wait: Duration) -> Option<Arc<ColorImage>> {
+        let cached = self.memory.lock().unwrap().get(url).cloned();
+        metrics::cache_lookup("pfp", cached.is_some());
+        if let Some(cached) = cached {
+            return cached;
+        }
+
+        let cache = self.clone();
+        let url = url.to_owned();
+        let handle = tokio::spawn(async move {
+            let image = match cache.load(&url).await {
+                Ok(image) => Some(Arc::new(image)),
+                Err(err) => {
+                    debug!("failed to load pfp {url}: {err}");
+                    None
+                }
+            };
+            metrics::cache_put("pfp", &mut cache.memory.lock().unwrap(), url, image.clone());
+            image
+        });
+
+        tokio::time::timeout(wait, handle).await.ok()?.ok()?
+    }
+
+    async fn load(&self, url: &str) -> Result<ColorImage, Error> {
+        if !url.starts_with("https://") {
+            return Err(Error::InvalidProfilePic);
+        }
+
+        // the disk is only touched from the blocking pool
+        let path = cache_path(&self.dir, url);
+        let cached = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || read_cached(&path)).await
+        };
+        if let Ok(Some(image)) = cached {
+            return Ok(image);
+        }
+
+        let image = download(&self.client, url).await?;
+        let dir = self.dir.clone();
+        let written = image.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(err) = write_cached(&dir, &path, &written) {
+                debug!("couldn't write pfp {} to disk: {err}", path.display());
+            }
+        });
+
+        Ok(image)
+    }
+
+    /// Periodically delete expired avatars from disk, reading them only
+    /// skips them
+    pub async fn sweep_loop(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let cache = self.clone();
+            if let Ok(removed) = tokio::task::spawn_blocking(move || sweep(&cache.dir)).await {
+                debug!("swept {removed} expired pfps");
+            }
+        }
+    }
+}
+
+fn age(path: &Path) -> Option<Duration> {
+    std::fs::metadata(path)
+        .ok()?
+        .modified()
+        .ok()?
+        .elapsed()
+        .ok()
+}
+
+/// Delete the expired avatars in `dir`, returning how many there were
+fn sweep(dir: &Path) -> usize {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    let mut removed = 0;
+    for path in entries.flatten().map(|entry| entry.path()) {
+        let expired = path.extension().is_some_and(|ext| ext == "pfp")
+            && age(&path).is_some_and(|age| age > PFP_TTL);
+        if expired && std::fs::remove_file(&path).is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+fn cache_path(dir: &Path, url: &str) -> PathBuf {
+    let hash = Sha256Hash::hash(url.as_bytes());
+    dir.join(format!("{}.pfp", hex::encode(hash.as_byte_array())))
+}
+
+/// Cached avatars are their width and height as little endian u32s
+/// followed by premultiplied rgba pixels
+fn read_cached(path: &Path) -> Option<ColorImage> {
+    if age(path)? > PFP_TTL {
+        return None;
+    }
+
+    let data = std::fs::read(path).ok()?;
+    if data.len() < 8 {
+        return None;
+    }
+    let (header, pixels) = data.split_at(8);
+    let width = u32::from_le_bytes(header[..4].try_into().ok()?) as usize;
+    let height = u32::from_le_bytes(header[4..].try_into().ok()?) as usize;
+    if pixels.len() != width * height * 4 {
+        return None;
+    }
+
+    Some(ColorImage {
+        size: [width, height],
+        pixels: pixels
+            .chunks_exact(4)
+            .map(|p| Color32::from_rgba_premultiplied(p[0], p[1], p[2], p[3]))
+            .collect(),
+    })
+}
+
+fn write_cached(dir: &Path, path: &Path, image: &ColorImage) -> std::io::Result<()> {
+    let mut data = Vec::with_capacity(8 + image.pixels.len() * 4);
+    data.extend((image.size[0] as u32).to_le_bytes());
+    data.extend((image.size[1] as u32).to_le_bytes());
+    for pixel in &image.pixels {
+        data.extend(pixel.to_array());
+    }
+
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(path, data)
+}
+
+async fn download(client: &reqwest::Client, url: &str) -> Result<ColorImage, Error> {
+    let res = fetch::get(client, url).await?;
+
+    let content_type = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+
+    let data = fetch::read_body(res, MAX_PFP_SIZE).await?;
+
+    // decoding and scaling is cpu bound, keep it off the runtime threads
+    tokio::task::spawn_blocking(move || pfp::decode_pfp(&data, &content_type))
+        .await
+        .map_err(|err| Error::Generic(err.to_string()))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    #[test]
+    fn sweeps_expired_avatars_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = ColorImage::new([2, 2], Color32::RED);
+
+        let fresh = cache_path(dir.path(), "https://example.com/fresh.png");
+        let expired = cache_path(dir.path(), "https://example.com/expired.png");
+        write_cached(dir.path(), &fresh, &image).unwrap();
+        write_cached(dir.path(), &expired, &image).unwrap();
+        let other = dir.path().join("notes.txt");
+        std::fs::write(&other, "not an avatar").unwrap();
+
+        let old = SystemTime::now() - PFP_TTL - Duration::from_secs(60);
+        for path in [&expired, &other] {
+            std::fs::File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+
+        assert_eq!(sweep(dir.path()), 1);
+        assert!(read_cached(&fresh).is_some());
+        assert!(!expired.exists());
+        assert!(other.exists());
+    }
+}
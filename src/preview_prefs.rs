@@ -0,0 +1,82 @@
+use crate::html::{note_tag_id, note_tag_value};
+use nostrdb::{Filter, Ndb, Transaction};
+
+/// NIP-78 arbitrary app data
+pub const PREVIEW_PREFS_KIND: u64 = 30078;
+
+/// The `d` tag that marks a preview preferences event
+pub const PREVIEW_PREFS_D: &str = "notecrumbs/preview";
+
+/// How an author wants their content to unfurl. Published as a NIP-78 app
+/// data event with the `d` tag `notecrumbs/preview`:
+///
+/// ```json
+/// ["image", "https://example.com/preview.png"]  preferred og:image
+/// ["color", "#ff7a00"]                           accent color
+/// ["pinned", "<hex note id>"]                     note shown first on the profile
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PreviewPrefs {
+    /// Preferred og:image for the author's profile
+    pub image: Option<String>,
+    /// Accent color, always a valid `#rrggbb`
+    pub accent: Option<String>,
+    /// A note to show first on the author's profile
+    pub pinned: Option<[u8; 32]>,
+}
+
+impl PreviewPrefs {
+    /// The `style` attribute for a page's `<body>` carrying the accent color
+    pub fn body_style(&self) -> String {
+        match &self.accent {
+            Some(color) => format!(r#" style="--accent-color: {color}""#),
+            None => String::new(),
+        }
+    }
+}
+
+fn valid_color(color: &str) -> Option<String> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(format!("#{}", hex.to_ascii_lowercase()))
+    } else {
+        None
+    }
+}
+
+fn valid_image(url: &str) -> Option<String> {
+    if url.starts_with("https://") {
+        Some(url.to_owned())
+    } else {
+        None
+    }
+}
+
+/// An author's preview preferences event. Authors can have any number of
+/// other app data events, so this has to filter on the d tag.
+pub fn preview_prefs_filter(pubkey: &[u8; 32]) -> Filter {
+    Filter::new()
+        .authors([pubkey])
+        .kinds([PREVIEW_PREFS_KIND])
+        .tags([PREVIEW_PREFS_D], 'd')
+        .limit(1)
+        .build()
+}
+
+/// The author's latest preview preferences, if they published any
+pub fn preview_prefs(ndb: &Ndb, txn: &Transaction, pubkey: &[u8; 32]) -> Option<PreviewPrefs> {
+    let filter = preview_prefs_filter(pubkey);
+
+    let results = ndb.query(txn, &[filter], 1).ok()?;
+    let note = results
+        .iter()
+        .map(|result| &result.note)
+        .filter(|note| note_tag_value(note, "d") == Some(PREVIEW_PREFS_D))
+        .max_by_key(|note| note.created_at())?;
+
+    Some(PreviewPrefs {
+        image: note_tag_value(note, "image").and_then(valid_image),
+        accent: note_tag_value(note, "color").and_then(valid_color),
+        pinned: note_tag_id(note, "pinned"),
+    })
+}
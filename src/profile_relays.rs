@@ -0,0 +1,118 @@
+use crate::{nip19::nip19_pubkey, outbox, relay_page::write_relay_details, Error, Notecrumbs};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, Response, StatusCode};
+use nostr_sdk::prelude::{Nip19, ToBech32};
+use nostrdb::Transaction;
+use std::io::Write;
+use std::time::Duration;
+
+/// How long the relays page waits for NIP-11 documents
+const RELAY_INFO_WAIT: Duration = Duration::from_millis(1500);
+
+/// `/{npub}/relays`: the relays a profile publishes to and reads from,
+/// with what each relay says about itself
+pub async fn serve_profile_relays(
+    app: &Notecrumbs,
+    nip19: &Nip19,
+) -> Result<Response<Full<Bytes>>, Error> {
+    let pubkey = if let Some(pubkey) = nip19_pubkey(nip19) {
+        pubkey
+    } else {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::from("Invalid url\n")))?);
+    };
+
+    let relays = {
+        let txn = Transaction::new(&app.ndb)?;
+        outbox::relay_list(&app.ndb, &txn, &pubkey)
+    };
+
+    // we'll have it next time
+    if relays.is_none() {
+        app.backfill.schedule(pubkey);
+    }
+    let relays = relays.unwrap_or_default();
+
+    let urls = app
+        .config
+        .relay_policy
+        .filter(relays.iter().map(|r| r.url.clone()).collect());
+    app.relay_info.prefetch(&urls, RELAY_INFO_WAIT).await;
+
+    let mut data = Vec::new();
+    let bech32 = nip19.to_bech32()?;
+    let name = {
+        let txn = Transaction::new(&app.ndb)?;
+        app.ndb
+            .get_profile_by_pubkey(&txn, &pubkey)
+            .ok()
+            .and_then(|pr| {
+                pr.record()
+                    .profile()
+                    .and_then(|p| p.name())
+                    .map(|n| n.to_owned())
+            })
+            .unwrap_or_else(|| "nostrich".to_owned())
+    };
+
+    write!(
+        data,
+        r#"<html>
+<head>
+  <title>{0}'s relays</title>
+  <link rel="stylesheet" href="https://damus.io/css/notecrumbs.css" type="text/css" />
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <meta charset="UTF-8">
+</head>
+<body>
+  <main>
+    <div class="container">
+      <h3 class="page-heading"><a href="/{1}">{0}</a>'s relays</h3>
+"#,
+        html_escape::encode_text(&name),
+        bech32,
+    )?;
+
+    if relays.is_empty() {
+        write!(data, r#"<p>No relay list found yet.</p>"#)?;
+    }
+
+    for relay in &relays {
+        write!(
+            data,
+            r#"<div class="relay"><div class="relay-url">{}</div>"#,
+            html_escape::encode_text(&relay.url)
+        )?;
+
+        let marker = match relay.marker.as_deref() {
+            Some("read") => "read",
+            Some("write") => "write",
+            _ => "read & write",
+        };
+        write!(data, r#"<div class="relay-marker">{marker}</div>"#)?;
+
+        let status = write_relay_details(app, &mut data, &relay.url)?;
+
+        write!(
+            data,
+            r#"<div class="relay-status">{}</div></div>"#,
+            html_escape::encode_text(&status)
+        )?;
+    }
+
+    write!(
+        data,
+        r#"
+    </div>
+  </main>
+</body>
+</html>
+"#
+    )?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/html")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(data)))?)
+}
@@ -0,0 +1,40 @@
+use qrcode::{render::svg, EcLevel, QrCode};
+
+/// An inline svg QR code for `data`, or `None` if it doesn't fit in one
+pub fn qr_svg(data: &str, size: u32) -> Option<String> {
+    let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M).ok()?;
+    Some(
+        code.render::<svg::Color>()
+            .min_dimensions(size, size)
+            .dark_color(svg::Color("#000000"))
+            .light_color(svg::Color("#ffffff"))
+            .build(),
+    )
+}
+
+/// A QR code for `data` drawn onto png cards, with a two module quiet
+/// zone and each module `module_size` pixels wide
+pub fn qr_image(data: &str, module_size: usize) -> Option<egui::ColorImage> {
+    let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M).ok()?;
+    let quiet_zone = 2;
+    let modules = code.width() + quiet_zone * 2;
+    let size = modules * module_size;
+    let colors = code.to_colors();
+
+    let mut pixels = vec![egui::Color32::WHITE; size * size];
+    for (i, color) in colors.iter().enumerate() {
+        if *color != qrcode::Color::Dark {
+            continue;
+        }
+        let x = (i % code.width() + quiet_zone) * module_size;
+        let y = (i / code.width() + quiet_zone) * module_size;
+        for row in y..y + module_size {
+            pixels[row * size + x..row * size + x + module_size].fill(egui::Color32::BLACK);
+        }
+    }
+
+    Some(egui::ColorImage {
+        size: [size, size],
+        pixels,
+    })
+}
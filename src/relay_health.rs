@@ -0,0 +1,318 @@
+use crate::{fetch, metrics, relay_info::RelayInfoCache};
+use lru::LruCache;
+use nostr::event::kind::Kind;
+use nostr_sdk::async_utility::futures_util::StreamExt;
+use nostr_sdk::prelude::Keys;
+use std::collections::{HashMap, VecDeque};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How often we probe the default relays
+const PROBE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How many probes we judge a relay on, a day's worth
+const PROBE_WINDOW: usize = 24;
+
+/// How long a probe round waits for the relays' NIP-11 documents
+const INFO_WAIT: Duration = Duration::from_secs(5);
+
+/// A probe slower than this counts as no answer
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Relays answering less often than this are left out of fetches until
+/// they recover
+const MIN_ANSWER_RATE: f64 = 0.5;
+
+/// Relays from hints and relay lists aren't judged on fewer connection
+/// attempts than this
+const MIN_HINTED_ATTEMPTS: usize = 3;
+
+/// A failing relay from a hint gets another chance after this long
+const HINTED_RETRY: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Relays from hints we haven't seen in this long are forgotten
+const HINTED_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Default)]
+struct RelayStats {
+    /// Recent probes, `None` when the relay didn't answer
+    probes: VecDeque<Option<Duration>>,
+    last_probe: Option<Instant>,
+}
+
+impl RelayStats {
+    fn record(&mut self, probe: Option<Duration>) {
+        if self.probes.len() == PROBE_WINDOW {
+            self.probes.pop_front();
+        }
+        self.probes.push_back(probe);
+        self.last_probe = Some(Instant::now());
+    }
+
+    fn is_failing(&self) -> bool {
+        self.probes.len() >= MIN_HINTED_ATTEMPTS
+            && self.answer_rate() < MIN_ANSWER_RATE
+            && self
+                .last_probe
+                .is_some_and(|at| at.elapsed() < HINTED_RETRY)
+    }
+
+    fn answer_rate(&self) -> f64 {
+        if self.probes.is_empty() {
+            return 1.0;
+        }
+        self.probes.iter().filter(|p| p.is_some()).count() as f64 / self.probes.len() as f64
+    }
+
+    fn median_latency(&self) -> Duration {
+        let mut answered: Vec<Duration> = self.probes.iter().flatten().copied().collect();
+        if answered.is_empty() {
+            return PROBE_TIMEOUT;
+        }
+        answered.sort();
+        answered[answered.len() / 2]
+    }
+}
+
+/// Keeps the default relays ordered by how well they've been answering,
+/// so cold fetches go to the fast ones first and skip the ones that are
+/// down. Relays we only know from hints and relay lists are judged on
+/// whether we could connect to them when we used them.
+pub struct RelayHealth {
+    keys: Keys,
+    info: Arc<RelayInfoCache>,
+    configured: Vec<String>,
+    stats: RwLock<HashMap<String, RelayStats>>,
+    /// Least recently used relays are forgotten first
+    hinted: Mutex<LruCache<String, RelayStats>>,
+    active: RwLock<Vec<String>>,
+    /// Whether our latest lookup on the default relays connected to any
+    /// of them
+    reachable: AtomicBool,
+}
+
+fn relay_key(relay: &str) -> String {
+    relay.trim_end_matches('/').to_string()
+}
+
+impl RelayHealth {
+    pub fn new(
+        keys: Keys,
+        info: Arc<RelayInfoCache>,
+        configured: Vec<String>,
+        max_hinted: NonZeroUsize,
+    ) -> Self {
+        RelayHealth {
+            keys,
+            info,
+            active: RwLock::new(configured.clone()),
+            configured,
+            stats: RwLock::new(HashMap::new()),
+            hinted: Mutex::new(LruCache::new(max_hinted)),
+            reachable: AtomicBool::new(true),
+        }
+    }
+
+    fn is_configured(&self, relay: &str) -> bool {
+        let relay = relay.trim_end_matches('/');
+        self.configured
+            .iter()
+            .any(|configured| configured.trim_end_matches('/') == relay)
+    }
+
+    /// Record how connecting to a relay from a hint or relay list went,
+    /// `None` when it didn't connect. Default relays are judged by their
+    /// probes instead.
+    pub fn record_hinted(&self, relay: &str, connect: Option<Duration>) {
+        if self.is_configured(relay) {
+            return;
+        }
+
+        self.hinted
+            .lock()
+            .unwrap()
+            .get_or_insert_mut(relay_key(relay), RelayStats::default)
+            .record(connect);
+    }
+
+    /// Whether a relay from a hint or relay list is worth connecting to.
+    /// Relays that keep failing are skipped for a while.
+    pub fn usable(&self, relay: &str) -> bool {
+        !self
+            .hinted
+            .lock()
+            .unwrap()
+            .peek(&relay_key(relay))
+            .is_some_and(|stats| stats.is_failing())
+    }
+
+    /// The relays fetches should use, best first
+    pub fn relays(&self) -> Vec<String> {
+        self.active.read().unwrap().clone()
+    }
+
+    /// How a default relay has been doing lately: its answer rate and
+    /// median latency. `None` for relays we don't probe.
+    pub fn status(&self, relay: &str) -> Option<(f64, Duration)> {
+        let relay = relay.trim_end_matches('/');
+        let stats = self.stats.read().unwrap();
+        stats
+            .iter()
+            .find(|(url, _)| url.trim_end_matches('/') == relay)
+            .map(|(_, s)| (s.answer_rate(), s.median_latency()))
+    }
+
+    /// Record whether a lookup on the default relays could connect to
+    /// any of them
+    pub fn record_reachable(&self, reachable: bool) {
+        if self.reachable.swap(reachable, Ordering::Relaxed) != reachable {
+            if reachable {
+                info!("relays are reachable again");
+            } else {
+                warn!("couldn't connect to any default relay");
+            }
+        }
+    }
+
+    /// Whether our latest lookup could connect to any default relay. When
+    /// it couldn't, whatever we're missing can't be fetched right now.
+    pub fn reachable(&self) -> bool {
+        self.reachable.load(Ordering::Relaxed)
+    }
+
+    /// Whether any default relay answered its latest probe. False until
+    /// the first probe round finishes.
+    pub fn any_answering(&self) -> bool {
+        self.stats
+            .read()
+            .unwrap()
+            .values()
+            .any(|stats| stats.probes.back().is_some_and(|probe| probe.is_some()))
+    }
+
+    pub async fn probe_loop(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(PROBE_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.probe_all().await;
+        }
+    }
+
+    async fn probe_all(&self) {
+        self.info.prefetch(&self.configured, INFO_WAIT).await;
+        self.prune_hinted();
+
+        for relay in &self.configured {
+            let probe = probe(self.keys.clone(), relay).await;
+            self.stats
+                .write()
+                .unwrap()
+                .entry(relay.clone())
+                .or_default()
+                .record(probe);
+        }
+
+        self.select();
+    }
+
+    /// Forget relays from hints we haven't needed in a while
+    fn prune_hinted(&self) {
+        let mut hinted = self.hinted.lock().unwrap();
+        let stale: Vec<String> = hinted
+            .iter()
+            .filter(|(_, stats)| !stats.last_probe.is_some_and(|at| at.elapsed() < HINTED_TTL))
+            .map(|(relay, _)| relay.clone())
+            .collect();
+        for relay in &stale {
+            hinted.pop(relay);
+        }
+
+        let pruned = stale.len();
+        if pruned > 0 {
+            info!("forgot {pruned} relays from hints we haven't used lately");
+        }
+    }
+
+    fn select(&self) {
+        let stats = self.stats.read().unwrap();
+
+        let mut ranked: Vec<(&String, f64, Duration)> = self
+            .configured
+            .iter()
+            .map(|relay| match stats.get(relay) {
+                Some(s) => (relay, s.answer_rate(), s.median_latency()),
+                None => (relay, 1.0, PROBE_TIMEOUT),
+            })
+            .collect();
+        ranked.sort_by_key(|(_, _, latency)| *latency);
+
+        for (relay, rate, latency) in &ranked {
+            info!(
+                "relay {relay}: {:.0}% answered, {}ms median",
+                rate * 100.0,
+                latency.as_millis()
+            );
+        }
+
+        // a paid relay answers probes but won't give us anyone's notes
+        let mut active: Vec<String> = ranked
+            .iter()
+            .filter(|(relay, _, _)| !self.info.info(relay).is_some_and(|info| info.is_paid()))
+            .filter(|(_, rate, _)| *rate >= MIN_ANSWER_RATE)
+            .map(|(relay, _, _)| (*relay).clone())
+            .collect();
+
+        // every relay doing badly is more likely our network than theirs
+        if active.is_empty() {
+            warn!("no relay is answering reliably, keeping all of them");
+            active = ranked
+                .iter()
+                .map(|(relay, _, _)| (*relay).clone())
+                .collect();
+        }
+
+        for (relay, _, _) in &ranked {
+            if !active.contains(relay) {
+                warn!("dropping relay {relay} from fetches until it recovers");
+            }
+        }
+
+        let mut current = self.active.write().unwrap();
+        if *current != active {
+            info!("relay order: {}", active.join(", "));
+            *current = active;
+        }
+    }
+}
+
+/// Time how long a relay takes to connect and answer a trivial query.
+/// `None` if it didn't.
+async fn probe(keys: Keys, relay: &str) -> Option<Duration> {
+    let start = Instant::now();
+
+    let client = fetch::relay_client(keys);
+    client.add_relay(relay).await.ok()?;
+    client.connect_with_timeout(PROBE_TIMEOUT).await;
+    let _open = metrics::OpenRelays::count(&client).await;
+
+    let filter = nostr::Filter::new().kind(Kind::TextNote).limit(1);
+    let answered = match client
+        .stream_events(vec![filter], Some(PROBE_TIMEOUT))
+        .await
+    {
+        Ok(mut events) => events.next().await.is_some(),
+        Err(_) => false,
+    };
+
+    let elapsed = start.elapsed();
+    let _ = client.disconnect().await;
+
+    if answered && elapsed < PROBE_TIMEOUT * 2 {
+        Some(elapsed)
+    } else {
+        None
+    }
+}
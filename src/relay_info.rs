@@ -0,0 +1,258 @@
+use crate::{fetch, Error};
+use lru::LruCache;
+use nostr_sdk::async_utility::futures_util::future::join_all;
+use serde::Deserialize;
+use std::io::Write;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// How long a single NIP-11 fetch may take
+const FETCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Relay info rarely changes, don't ask more than once an hour
+const INFO_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// NIP-11 info documents are small, anything bigger isn't one
+const MAX_INFO_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RelayLimitation {
+    pub max_message_length: Option<u64>,
+    pub max_subscriptions: Option<u64>,
+    pub max_limit: Option<u64>,
+    #[serde(default)]
+    pub auth_required: bool,
+    #[serde(default)]
+    pub payment_required: bool,
+}
+
+/// A relay's NIP-11 information document
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RelayInfo {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub software: Option<String>,
+    pub version: Option<String>,
+    /// Usually numbers, but some relays list them as strings
+    pub supported_nips: Option<Vec<serde_json::Value>>,
+    pub contact: Option<String>,
+    pub limitation: Option<RelayLimitation>,
+}
+
+impl RelayInfo {
+    pub fn supports_nip(&self, nip: u16) -> bool {
+        self.supported_nips.iter().flatten().any(|n| match n {
+            serde_json::Value::Number(n) => n.as_u64() == Some(nip as u64),
+            serde_json::Value::String(s) => s.trim().parse::<u16>() == Ok(nip),
+            _ => false,
+        })
+    }
+
+    /// Relays that want payment won't serve us anything
+    pub fn is_paid(&self) -> bool {
+        self.limitation
+            .as_ref()
+            .is_some_and(|limits| limits.payment_required)
+    }
+
+    /// Relays that want us to authenticate (NIP-42) before reading
+    pub fn requires_auth(&self) -> bool {
+        self.limitation
+            .as_ref()
+            .is_some_and(|limits| limits.auth_required)
+    }
+}
+
+/// NIP-11 documents by relay url. `None` entries are relays that didn't
+/// give us one, which usually means they're down.
+pub struct RelayInfoCache {
+    cache: Mutex<LruCache<String, (Instant, Option<Arc<RelayInfo>>)>>,
+    client: reqwest::Client,
+}
+
+/// NIP-11 is served over http from the relay's own url
+fn info_url(relay: &str) -> Option<String> {
+    if let Some(rest) = relay.strip_prefix("wss://") {
+        Some(format!("https://{rest}"))
+    } else {
+        relay
+            .strip_prefix("ws://")
+            .map(|rest| format!("http://{rest}"))
+    }
+}
+
+impl RelayInfoCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        let client = fetch::client("notecrumbs (relay info)", FETCH_TIMEOUT, 3);
+
+        RelayInfoCache {
+            cache: Mutex::new(LruCache::new(capacity)),
+            client,
+        }
+    }
+
+    /// Have we fetched this relay's info, and did it have any?
+    pub fn get(&self, relay: &str) -> Option<Option<Arc<RelayInfo>>> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(relay)
+            .map(|(_, info)| info.clone())
+    }
+
+    /// The cached info for a relay, if it gave us any
+    pub fn info(&self, relay: &str) -> Option<Arc<RelayInfo>> {
+        self.get(relay).flatten()
+    }
+
+    /// Whether a relay says it supports a NIP. Relays we have no info
+    /// for get the benefit of the doubt.
+    pub fn supports_nip(&self, relay: &str, nip: u16) -> bool {
+        match self.info(relay) {
+            Some(info) => info.supports_nip(nip),
+            None => true,
+        }
+    }
+
+    fn is_fresh(&self, relay: &str) -> bool {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(relay)
+            .map(|(at, _)| at.elapsed() < INFO_TTL)
+            .unwrap_or(false)
+    }
+
+    /// Fetch info for relays we don't have fresh info for, waiting at
+    /// most `wait`
+    pub async fn prefetch(self: &Arc<Self>, relays: &[String], wait: Duration) {
+        let handles: Vec<_> = relays
+            .iter()
+            .filter(|relay| !self.is_fresh(relay))
+            .cloned()
+            .map(|relay| {
+                let infos = self.clone();
+                tokio::spawn(async move {
+                    let info = match fetch_info(&infos.client, &relay).await {
+                        Ok(info) => Some(Arc::new(info)),
+                        Err(err) => {
+                            debug!("relay info failed for {relay}: {err}");
+                            None
+                        }
+                    };
+                    infos
+                        .cache
+                        .lock()
+                        .unwrap()
+                        .put(relay, (Instant::now(), info));
+                })
+            })
+            .collect();
+
+        if handles.is_empty() {
+            return;
+        }
+
+        let _ = tokio::time::timeout(wait, join_all(handles)).await;
+    }
+}
+
+async fn fetch_info(client: &reqwest::Client, relay: &str) -> Result<RelayInfo, Error> {
+    let url = info_url(relay).ok_or(Error::InvalidUri)?;
+    fetch::check_url(&url)?;
+    let res = client
+        .get(url)
+        .header(reqwest::header::ACCEPT, "application/nostr+json")
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let data = fetch::read_body(res, MAX_INFO_SIZE).await?;
+
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// The parts of a relay's info document worth showing a visitor
+pub fn write_relay_info(data: &mut Vec<u8>, info: &RelayInfo) -> std::io::Result<()> {
+    if let Some(name) = &info.name {
+        write!(
+            data,
+            r#"<div class="relay-name">{}</div>"#,
+            html_escape::encode_text(name)
+        )?;
+    }
+
+    if let Some(description) = &info.description {
+        write!(
+            data,
+            r#"<div class="relay-description">{}</div>"#,
+            html_escape::encode_text(description)
+        )?;
+    }
+
+    if let Some(software) = &info.software {
+        write!(
+            data,
+            r#"<div class="relay-software">{} {}</div>"#,
+            html_escape::encode_text(software),
+            html_escape::encode_text(info.version.as_deref().unwrap_or(""))
+        )?;
+    }
+
+    if let Some(contact) = &info.contact {
+        write!(
+            data,
+            r#"<div class="relay-contact">{}</div>"#,
+            html_escape::encode_text(contact)
+        )?;
+    }
+
+    let nips: Vec<String> = info
+        .supported_nips
+        .iter()
+        .flatten()
+        .filter_map(|nip| match nip {
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            serde_json::Value::String(s) => Some(s.clone()),
+            _ => None,
+        })
+        .collect();
+    if !nips.is_empty() {
+        write!(
+            data,
+            r#"<div class="relay-nips">NIPs: {}</div>"#,
+            html_escape::encode_text(&nips.join(", "))
+        )?;
+    }
+
+    if let Some(limits) = &info.limitation {
+        let mut notes: Vec<String> = vec![];
+        if info.requires_auth() {
+            notes.push("auth required".to_string());
+        }
+        if info.is_paid() {
+            notes.push("paid".to_string());
+        }
+        if let Some(len) = limits.max_message_length {
+            notes.push(format!("max message {len} bytes"));
+        }
+        if let Some(subs) = limits.max_subscriptions {
+            notes.push(format!("max {subs} subscriptions"));
+        }
+        if let Some(limit) = limits.max_limit {
+            notes.push(format!("max {limit} events per query"));
+        }
+
+        if !notes.is_empty() {
+            write!(
+                data,
+                r#"<div class="relay-limits">{}</div>"#,
+                html_escape::encode_text(&notes.join(" · "))
+            )?;
+        }
+    }
+
+    Ok(())
+}
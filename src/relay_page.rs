@@ -0,0 +1,120 @@
+use crate::{relay_info::write_relay_info, Error, Notecrumbs};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, Response, StatusCode};
+use std::io::Write;
+use std::time::Duration;
+
+/// How long the relay page waits for the NIP-11 document
+const RELAY_INFO_WAIT: Duration = Duration::from_millis(2000);
+
+/// A websocket url from an nrelay or a `/relay/` path. Bare hostnames
+/// are assumed to be `wss://`.
+pub fn relay_url(relay: &str) -> Option<String> {
+    let relay = relay.replace("%3A", ":").replace("%2F", "/");
+    let url = if relay.starts_with("wss://") || relay.starts_with("ws://") {
+        relay
+    } else {
+        format!("wss://{relay}")
+    };
+
+    let host = url.split_once("://")?.1.split('/').next()?;
+    let valid = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | ':'));
+
+    if valid {
+        Some(url)
+    } else {
+        None
+    }
+}
+
+/// Write what we know about a relay, returning a one line summary of
+/// how reachable it is
+pub fn write_relay_details(
+    app: &Notecrumbs,
+    data: &mut Vec<u8>,
+    relay: &str,
+) -> std::io::Result<String> {
+    let status = match app.relay_info.get(relay) {
+        Some(Some(info)) => {
+            write_relay_info(data, &info)?;
+            "reachable".to_string()
+        }
+        Some(None) => "unreachable".to_string(),
+        None => "checking".to_string(),
+    };
+
+    let status = match app.relays.status(relay) {
+        Some((rate, latency)) => format!(
+            "{status} · {:.0}% answered, {}ms median",
+            rate * 100.0,
+            latency.as_millis()
+        ),
+        None => status,
+    };
+
+    Ok(status)
+}
+
+/// `/nrelay1...` or `/relay/{url}`: what a relay says about itself and
+/// how it's been answering us
+pub async fn serve_relay_page(
+    app: &Notecrumbs,
+    relay: &str,
+) -> Result<Response<Full<Bytes>>, Error> {
+    // relays the operator doesn't let us talk to don't get fetched for
+    // anyone else either
+    let relay = if let Some(relay) =
+        relay_url(relay).filter(|relay| app.config.relay_policy.allows(relay))
+    {
+        relay
+    } else {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::from("Invalid url\n")))?);
+    };
+
+    app.relay_info
+        .prefetch(std::slice::from_ref(&relay), RELAY_INFO_WAIT)
+        .await;
+
+    let mut data = Vec::new();
+    write!(
+        data,
+        r#"<html>
+<head>
+  <title>{0} on nostr</title>
+  <link rel="stylesheet" href="https://damus.io/css/notecrumbs.css" type="text/css" />
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <meta charset="UTF-8">
+</head>
+<body>
+  <main>
+    <div class="container">
+      <div class="relay">
+        <h3 class="page-heading relay-url">{0}</h3>
+"#,
+        html_escape::encode_text(&relay),
+    )?;
+
+    let status = write_relay_details(app, &mut data, &relay)?;
+
+    write!(
+        data,
+        r#"<div class="relay-status">{}</div>
+      </div>
+    </div>
+  </main>
+</body>
+</html>
+"#,
+        html_escape::encode_text(&status)
+    )?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/html")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(data)))?)
+}
@@ -1,35 +1,120 @@
-use crate::{abbrev::abbrev_str, error::Result, fonts, nip19, Error, Notecrumbs};
+use crate::{
+    abbrev::abbrev_str,
+    budget::{wait_until, Deadlines},
+    error::Result,
+    fetch, fonts,
+    html::{self, note_tag_value},
+    i18n::{Counted, Locale, Text},
+    metrics, nip19, outbox, pfp, qr,
+    relay_health::RelayHealth,
+    Error, Notecrumbs,
+};
 use egui::epaint::Shadow;
 use egui::{
     pos2,
     text::{LayoutJob, TextFormat},
-    Color32, FontFamily, FontId, Mesh, Rect, RichText, Rounding, Shape, TextureHandle, Vec2,
-    Visuals,
+    Color32, ColorImage, FontFamily, FontId, ImageData, Mesh, Rect, RichText, Rounding, Shape,
+    TextureHandle, TextureOptions, Vec2,
 };
 use nostr::event::kind::Kind;
 use nostr::types::{SingleLetterTag, Timestamp};
-use nostr_sdk::async_utility::futures_util::StreamExt;
+use nostr_sdk::async_utility::futures_util::{
+    future,
+    stream::{select_all, BoxStream},
+    StreamExt,
+};
 use nostr_sdk::nips::nip19::Nip19;
-use nostr_sdk::prelude::{Client, EventId, Keys, PublicKey};
+use nostr_sdk::prelude::{
+    Client, Coordinate, Event, EventId, Keys, Nip19Profile, PublicKey, ToBech32,
+};
 use nostrdb::{
-    Block, BlockType, Blocks, FilterElement, FilterField, Mention, Ndb, Note, NoteKey, ProfileKey,
-    ProfileRecord, Transaction,
+    Block, BlockType, Blocks, FilterElement, FilterField, Mention, Ndb, NdbStrVariant, Note,
+    NoteKey, ProfileKey, ProfileRecord, Transaction,
 };
-use std::collections::{BTreeMap, BTreeSet};
-use tokio::time::{timeout, Duration};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::Arc;
+use tokio::time::{timeout_at, Duration, Instant};
 use tracing::{debug, error, warn};
 
+mod bidi;
+mod kind_card;
+pub mod theme;
+
+use kind_card::{kind_card_ui, zap_sender, KindCard};
+use theme::Theme;
+
 const PURPLE: Color32 = Color32::from_rgb(0xcc, 0x43, 0xc5);
 
+/// The largest card side we'll rasterize, in pixels
+const MAX_CARD_PIXELS: f32 = 4096.0;
+
+/// The size of a rendered card. The default is the usual 1200x600
+/// opengraph size, integrators can ask for others with `?w=&h=&scale=`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CardSize {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels per point, for sharper cards at the same layout
+    pub scale: f32,
+}
+
+impl Default for CardSize {
+    fn default() -> Self {
+        CardSize {
+            width: 1200,
+            height: 600,
+            scale: 1.0,
+        }
+    }
+}
+
+impl CardSize {
+    /// A card size from user input, clamped to something we can lay out
+    /// and afford to render
+    pub fn new(width: Option<u32>, height: Option<u32>, scale: Option<f32>) -> Self {
+        let default = CardSize::default();
+        let width = width.unwrap_or(default.width).clamp(400, 2400);
+        let height = height.unwrap_or(default.height).clamp(300, 2400);
+        let scale = scale
+            .filter(|scale| scale.is_finite())
+            .unwrap_or(default.scale)
+            .clamp(0.5, 3.0)
+            .min(MAX_CARD_PIXELS / width.max(height) as f32);
+
+        CardSize {
+            width,
+            height,
+            scale,
+        }
+    }
+
+    /// The size of the rendered image in pixels
+    pub fn pixels(&self) -> (u32, u32) {
+        (
+            (self.width as f32 * self.scale).round() as u32,
+            (self.height as f32 * self.scale).round() as u32,
+        )
+    }
+
+    fn options(&self) -> egui_skia::RasterizeOptions {
+        egui_skia::RasterizeOptions {
+            pixels_per_point: self.scale,
+            frames_before_screenshot: 1,
+        }
+    }
+}
+
 pub enum NoteRenderData {
     Missing([u8; 32]),
+    /// An addressable note (eg: an naddr) we don't have yet
+    MissingAddress(Coordinate),
     Note(NoteKey),
 }
 
 impl NoteRenderData {
     pub fn needs_note(&self) -> bool {
         match self {
-            NoteRenderData::Missing(_) => true,
+            NoteRenderData::Missing(_) | NoteRenderData::MissingAddress(_) => true,
             NoteRenderData::Note(_) => false,
         }
     }
@@ -41,11 +126,42 @@ impl NoteRenderData {
     ) -> std::result::Result<Note<'a>, nostrdb::Error> {
         match self {
             NoteRenderData::Missing(note_id) => ndb.get_note_by_id(txn, note_id),
+            NoteRenderData::MissingAddress(coord) => lookup_address(ndb, txn, coord),
             NoteRenderData::Note(note_key) => ndb.get_note_by_key(txn, *note_key),
         }
     }
 }
 
+/// How many of an author's notes of a kind we look through for one
+/// without a d tag
+const ADDRESS_SCAN_LIMIT: i32 = 50;
+
+/// The latest version of an addressable note
+pub fn lookup_address<'a>(
+    ndb: &Ndb,
+    txn: &'a Transaction,
+    coord: &Coordinate,
+) -> std::result::Result<Note<'a>, nostrdb::Error> {
+    let filter = nostrdb::Filter::new()
+        .authors([&coord.public_key.serialize()])
+        .kinds([coord.kind.as_u16() as u64]);
+
+    // a note without a d tag has an empty identifier, but no tag to
+    // filter on
+    let (filter, limit) = if coord.identifier.is_empty() {
+        (filter, ADDRESS_SCAN_LIMIT)
+    } else {
+        (filter.tags([coord.identifier.as_str()], 'd'), 1)
+    };
+
+    ndb.query(txn, &[filter.limit(limit as u64).build()], limit)?
+        .into_iter()
+        .map(|result| result.note)
+        .filter(|note| note_tag_value(note, "d").unwrap_or("") == coord.identifier)
+        .max_by_key(|note| note.created_at())
+        .ok_or(nostrdb::Error::NotFound)
+}
+
 pub struct NoteAndProfileRenderData {
     pub note_rd: NoteRenderData,
     pub profile_rd: Option<ProfileRenderData>,
@@ -138,6 +254,12 @@ impl RenderData {
             RenderData::Note(rd) => rd.note_rd.needs_note(),
         }
     }
+
+    /// A note page whose note we have, even if its author's profile is
+    /// missing
+    pub fn has_note(&self) -> bool {
+        matches!(self, RenderData::Note(rd) if !rd.note_rd.needs_note())
+    }
 }
 
 fn renderdata_to_filter(render_data: &RenderData) -> Vec<nostrdb::Filter> {
@@ -151,6 +273,15 @@ fn renderdata_to_filter(render_data: &RenderData) -> Vec<nostrdb::Filter> {
         Some(NoteRenderData::Missing(note_id)) => {
             filters.push(nostrdb::Filter::new().ids([note_id]).limit(1).build());
         }
+        Some(NoteRenderData::MissingAddress(coord)) => {
+            // we can't ask ndb for the d tag here, complete checks it
+            filters.push(
+                nostrdb::Filter::new()
+                    .authors([&coord.public_key.serialize()])
+                    .kinds([coord.kind.as_u16() as u64])
+                    .build(),
+            );
+        }
         None | Some(NoteRenderData::Note(_)) => {}
     }
 
@@ -170,7 +301,7 @@ fn renderdata_to_filter(render_data: &RenderData) -> Vec<nostrdb::Filter> {
     filters
 }
 
-fn convert_filter(ndb_filter: &nostrdb::Filter) -> nostr::types::Filter {
+pub fn convert_filter(ndb_filter: &nostrdb::Filter) -> nostr::types::Filter {
     let mut filter = nostr::types::Filter::new();
 
     for element in ndb_filter {
@@ -238,51 +369,275 @@ fn convert_filter(ndb_filter: &nostrdb::Filter) -> nostr::types::Filter {
     filter
 }
 
+/// Look for a note on the default relays and the nip19's relay hints,
+/// and at the same time on its author's outbox relays (NIP-65), which is
+/// where it should be if it's anywhere. Hints and outbox relays come
+/// from events, so they have to pass the relay policy, and relays that
+/// keep failing us are skipped. Plain id and pubkey lookups on the
+/// default relays are batched with other requests. Finding the outbox
+/// relays has to be done by the discovery deadline, the note by the note
+/// deadline.
 pub async fn find_note(
-    ndb: Ndb,
-    keys: Keys,
+    app: Notecrumbs,
     filters: Vec<nostr::Filter>,
     nip19: &Nip19,
+    deadlines: Deadlines,
 ) -> Result<()> {
-    use nostr_sdk::JsonUtil;
+    let relays = &app.relays;
+    let defaults = relays.relays();
+    let usable = |candidates: Vec<String>| -> Vec<String> {
+        app.config
+            .relay_policy
+            .filter(candidates)
+            .into_iter()
+            .filter(|relay| relays.usable(relay))
+            .collect()
+    };
 
-    let client = Client::builder().signer(keys).build();
+    let hints: Vec<String> = usable(
+        nip19::nip19_relays(nip19)
+            .into_iter()
+            .map(|relay| relay.to_string())
+            .collect(),
+    )
+    .into_iter()
+    .filter(|relay| !defaults.contains(relay))
+    .collect();
+
+    let direct = async {
+        let (ids, profiles) = if let Some(lookups) = nip19::nip19_lookups(nip19) {
+            lookups
+        } else {
+            let mut all = defaults.clone();
+            all.extend(hints.iter().cloned());
+            let found = stream_note(
+                &app.ndb,
+                app.keys.clone(),
+                relays,
+                all,
+                filters.clone(),
+                deadlines.note,
+            )
+            .await;
+            relays.record_reachable(!matches!(found, Err(Error::RelaysUnreachable)));
+            return found;
+        };
+
+        let hinted = async {
+            if hints.is_empty() {
+                return Ok(());
+            }
+            stream_note(
+                &app.ndb,
+                app.keys.clone(),
+                relays,
+                hints.clone(),
+                filters.clone(),
+                deadlines.note,
+            )
+            .await
+        };
+
+        let ((), found) = tokio::join!(app.batcher.fetch(ids, profiles), hinted);
+        found
+    };
+
+    let outbox = async {
+        let author = if let Some(author) = nip19::nip19_author(nip19) {
+            author
+        } else {
+            return Ok(());
+        };
 
-    let _ = client.add_relay("wss://relay.damus.io").await;
-    let _ = client.add_relay("wss://nostr.wine").await;
-    let _ = client.add_relay("wss://nos.lol").await;
-    let expected_events = filters.len();
+        let outbox_relays: Vec<String> = usable(
+            outbox::outbox_relays(
+                &app.ndb,
+                app.keys.clone(),
+                defaults.clone(),
+                &author,
+                deadlines.discovery,
+            )
+            .await,
+        )
+        .into_iter()
+        .filter(|relay| !defaults.contains(relay) && !hints.contains(relay))
+        .collect();
+        if outbox_relays.is_empty() {
+            return Ok(());
+        }
 
-    let other_relays = nip19::nip19_relays(nip19);
-    for relay in other_relays {
+        debug!("looking for note on outbox relays {:?}", outbox_relays);
+        stream_note(
+            &app.ndb,
+            app.keys.clone(),
+            relays,
+            outbox_relays,
+            filters.clone(),
+            deadlines.note,
+        )
+        .await
+    };
+
+    let (found, outbox_found) = tokio::join!(direct, outbox);
+    if let Err(err) = outbox_found {
+        debug!("outbox search failed: {err}");
+    }
+
+    found
+}
+
+/// The longest we let relays stream a note lookup, when the budget
+/// allows that much
+const NOTE_WAIT: Duration = Duration::from_millis(2000);
+
+#[tracing::instrument(name = "relay_fetch", skip_all, fields(relays = relays.len()))]
+async fn stream_note(
+    ndb: &Ndb,
+    keys: Keys,
+    health: &RelayHealth,
+    relays: Vec<String>,
+    filters: Vec<nostr::Filter>,
+    deadline: Instant,
+) -> Result<()> {
+    use nostr_sdk::JsonUtil;
+
+    let client = fetch::relay_client(keys);
+
+    for relay in relays {
         let _ = client.add_relay(relay).await;
     }
 
+    let connect_start = std::time::Instant::now();
     client
         .connect_with_timeout(std::time::Duration::from_millis(800))
         .await;
+    let connect_time = connect_start.elapsed();
+    let open = metrics::OpenRelays::count(&client).await;
+
+    for (url, relay) in client.relays().await {
+        health.record_hinted(
+            &url.to_string(),
+            relay.is_connected().then_some(connect_time),
+        );
+    }
+
+    if open.is_empty() {
+        let _ = client.disconnect().await;
+        return Err(Error::RelaysUnreachable);
+    }
 
     debug!("finding note(s) with filters: {:?}", filters);
 
-    let mut streamed_events = client
-        .stream_events(filters, Some(std::time::Duration::from_millis(2000)))
-        .await?;
+    // ends once every relay has sent EOSE, or at the deadline. Counting
+    // events instead would stop early on duplicates and wait out the
+    // deadline when a relay has fewer than we asked for.
+    let mut streamed_events =
+        stream_events_by_relay(&client, filters, wait_until(deadline, NOTE_WAIT)).await;
 
-    let mut num_loops = 0;
     while let Some(event) = streamed_events.next().await {
         debug!("processing event {:?}", event);
         if let Err(err) = ndb.process_event(&event.as_json()) {
             error!("error processing event: {err}");
         }
+    }
+
+    let _ = client.disconnect().await;
 
-        num_loops += 1;
+    Ok(())
+}
 
-        if num_loops == expected_events {
-            break;
+/// Fetch everything matching `filters` into ndb, waiting until the relays
+/// are done or `wait` runs out. Returns the events we got.
+#[tracing::instrument(name = "relay_fetch", skip_all, fields(relays = relays.len()))]
+pub async fn fetch_events(
+    ndb: &Ndb,
+    keys: Keys,
+    relays: Vec<String>,
+    filters: Vec<nostr::Filter>,
+    wait: Duration,
+) -> Result<Vec<Event>> {
+    let client = fetch::relay_client(keys);
+    for relay in relays {
+        let _ = client.add_relay(relay).await;
+    }
+    client
+        .connect_with_timeout(std::time::Duration::from_millis(800))
+        .await;
+
+    let events = fetch_events_from(ndb, &client, filters, wait).await;
+
+    let _ = client.disconnect().await;
+
+    events
+}
+
+/// `fetch_events` through a client that's already connected, from the
+/// relays it's connected to
+pub async fn fetch_events_from(
+    ndb: &Ndb,
+    client: &Client,
+    filters: Vec<nostr::Filter>,
+    wait: Duration,
+) -> Result<Vec<Event>> {
+    use nostr_sdk::JsonUtil;
+
+    let open = metrics::OpenRelays::count(client).await;
+    if open.is_empty() {
+        return Err(Error::RelaysUnreachable);
+    }
+
+    debug!("fetching events with filters: {:?}", filters);
+
+    let mut stream = stream_events_by_relay(client, filters, wait).await;
+
+    let mut events = vec![];
+    while let Some(event) = stream.next().await {
+        if let Err(err) = ndb.process_event(&event.as_json()) {
+            error!("error processing event: {err}");
         }
+        events.push(event);
     }
 
-    Ok(())
+    Ok(events)
+}
+
+/// Stream events from each of the client's connected relays separately,
+/// so we know which relays answer and how fast (see `metrics`). Each
+/// relay's stream ends at its EOSE or `wait`, the whole stream when they
+/// all have. Events that more than one relay sends are only passed on
+/// once.
+async fn stream_events_by_relay(
+    client: &Client,
+    filters: Vec<nostr::Filter>,
+    wait: Duration,
+) -> BoxStream<'static, Event> {
+    let relays: Vec<String> = client
+        .relays()
+        .await
+        .into_iter()
+        .filter(|(_, relay)| relay.is_connected())
+        .map(|(url, _)| url.to_string())
+        .collect();
+
+    let mut yields = metrics::RelayYields::new(&relays);
+    let mut streams = vec![];
+    for relay in relays {
+        match client
+            .stream_events_from([relay.as_str()], filters.clone(), Some(wait))
+            .await
+        {
+            Ok(stream) => streams.push(stream.map(move |event| (relay.clone(), event)).boxed()),
+            Err(err) => debug!("couldn't query {relay}: {err}"),
+        }
+    }
+
+    let mut seen = HashSet::new();
+    select_all(streams)
+        .filter_map(move |(relay, event)| {
+            yields.record(&relay);
+            future::ready(seen.insert(event.id).then_some(event))
+        })
+        .boxed()
 }
 
 impl RenderData {
@@ -297,6 +652,17 @@ impl RenderData {
         };
     }
 
+    fn set_missing_profile(&mut self, pubkey: [u8; 32]) {
+        match self {
+            RenderData::Profile(pk) => {
+                *pk = Some(ProfileRenderData::Missing(pubkey));
+            }
+            RenderData::Note(note_rd) => {
+                note_rd.profile_rd = Some(ProfileRenderData::Missing(pubkey));
+            }
+        };
+    }
+
     fn set_note_key(&mut self, key: NoteKey) {
         match self {
             RenderData::Profile(_pk) => {}
@@ -306,7 +672,26 @@ impl RenderData {
         };
     }
 
-    pub async fn complete(&mut self, ndb: Ndb, keys: Keys, nip19: Nip19) -> Result<()> {
+    /// Is this note the one we're missing? Only addressable notes can be
+    /// anything other than what we asked for.
+    fn wants_note(&self, note: &Note) -> bool {
+        match self.note_render_data() {
+            Some(NoteRenderData::MissingAddress(coord)) => {
+                note_tag_value(note, "d").unwrap_or("") == coord.identifier
+            }
+            _ => true,
+        }
+    }
+
+    pub async fn complete(
+        &mut self,
+        app: &Notecrumbs,
+        nip19: Nip19,
+        deadlines: Deadlines,
+    ) -> Result<()> {
+        use nostr_sdk::JsonUtil;
+
+        let ndb = &app.ndb;
         let mut stream = {
             let filter = renderdata_to_filter(self);
             if filter.is_empty() {
@@ -316,33 +701,53 @@ impl RenderData {
             }
             let sub_id = ndb.subscribe(&filter)?;
 
-            let stream = sub_id.stream(&ndb).notes_per_await(2);
+            let stream = sub_id.stream(ndb).notes_per_await(2);
 
-            let filters = filter.iter().map(convert_filter).collect();
-            let ndb = ndb.clone();
-            tokio::spawn(async move { find_note(ndb, keys, filters, &nip19).await });
+            // the relays can filter on the d tag, so ask them with filters
+            // made from the nip19 itself when we can
+            let filters = match nip19::nip19_filters(&nip19) {
+                Ok(filters) => filters,
+                Err(_) => filter.iter().map(convert_filter).collect(),
+            };
+            // everyone asking for the same thing at once shares one lookup,
+            // the others see its results through their subscriptions
+            let key = filters
+                .iter()
+                .map(|filter| filter.as_json())
+                .collect::<Vec<_>>()
+                .join(",");
+            let app = app.clone();
+            tokio::spawn(async move {
+                let lookups = app.inflight.clone();
+                lookups
+                    .run(
+                        format!("lookup {key}"),
+                        find_note(app, filters, &nip19, deadlines),
+                    )
+                    .await
+            });
             stream
         };
 
-        let wait_for = Duration::from_secs(1);
-        let mut loops = 0;
-
-        loop {
-            if loops == 2 {
-                break;
-            }
+        // the note gets until the note deadline, and once we have it its
+        // author's profile gets until the profile deadline
+        while !self.is_complete() {
+            let deadline = if self.needs_note() {
+                deadlines.note
+            } else {
+                deadlines.profile
+            };
 
-            let note_keys = if let Some(note_keys) = timeout(wait_for, stream.next()).await? {
+            let note_keys = if let Some(note_keys) = timeout_at(deadline, stream.next()).await? {
                 note_keys
             } else {
                 // end of stream?
                 break;
             };
 
-            let note_keys_len = note_keys.len();
-
+            let mut late_author = None;
             {
-                let txn = Transaction::new(&ndb)?;
+                let txn = Transaction::new(ndb)?;
 
                 for note_key in note_keys {
                     let note = if let Ok(note) = ndb.get_note_by_key(&txn, note_key) {
@@ -356,32 +761,64 @@ impl RenderData {
                         if let Ok(profile_key) = ndb.get_profilekey_by_pubkey(&txn, note.pubkey()) {
                             self.set_profile_key(profile_key);
                         }
-                    } else {
+                    } else if self.wants_note(&note) {
                         self.set_note_key(note_key);
+                        // a zap's nevent hint is the receipt's signer, not
+                        // the sender we draw
+                        if self.profile_render_data().is_none() || note.kind() == 9735 {
+                            late_author = Some(card_author(&note));
+                        }
                     }
                 }
             }
 
-            if note_keys_len >= 2 {
-                break;
+            // a nevent's author hint gets its profile asked for along with
+            // the note, without one we only learn the author now. Ask right
+            // away instead of waiting out the profile deadline for nothing.
+            if let Some(author) = late_author {
+                let filter = nostrdb::Filter::new()
+                    .authors([&author])
+                    .kinds([0])
+                    .limit(1)
+                    .build();
+                stream = ndb.subscribe(&[filter])?.stream(ndb).notes_per_await(1);
+                self.set_missing_profile(author);
+
+                let txn = Transaction::new(ndb)?;
+                if let Ok(profile_key) = ndb.get_profilekey_by_pubkey(&txn, &author) {
+                    self.set_profile_key(profile_key);
+                } else if let Ok(pubkey) = PublicKey::from_slice(&author) {
+                    let app = app.clone();
+                    tokio::spawn(async move { app.batcher.fetch(vec![], vec![pubkey]).await });
+                }
             }
-
-            loops += 1;
         }
 
         Ok(())
     }
 }
 
+/// Whose profile goes on a note's card. That's its author, except for
+/// zaps where it's whoever sent the zap.
+pub fn card_author(note: &Note) -> [u8; 32] {
+    if note.kind() == 9735 {
+        if let Some(sender) = zap_sender(note) {
+            return sender;
+        }
+    }
+    *note.pubkey()
+}
+
 /// Attempt to locate the render data locally. Anything missing from
 /// render data will be fetched.
+#[tracing::instrument(name = "ndb_query", skip_all)]
 pub fn get_render_data(ndb: &Ndb, txn: &Transaction, nip19: &Nip19) -> Result<RenderData> {
     match nip19 {
         Nip19::Event(nevent) => {
             let m_note = ndb.get_note_by_id(txn, nevent.event_id.as_bytes()).ok();
 
-            let pk = if let Some(pk) = m_note.as_ref().map(|note| note.pubkey()) {
-                Some(*pk)
+            let pk = if let Some(note) = m_note.as_ref() {
+                Some(card_author(note))
             } else {
                 nevent.author.map(|a| a.serialize())
             };
@@ -406,13 +843,13 @@ pub fn get_render_data(ndb: &Ndb, txn: &Transaction, nip19: &Nip19) -> Result<Re
         Nip19::EventId(evid) => {
             let m_note = ndb.get_note_by_id(txn, evid.as_bytes()).ok();
             let note_key = m_note.as_ref().and_then(|n| n.key());
-            let pk = m_note.map(|note| note.pubkey());
+            let pk = m_note.map(|note| card_author(&note));
 
             let profile_rd = pk.map(|pubkey| {
-                if let Ok(profile_key) = ndb.get_profilekey_by_pubkey(txn, pubkey) {
+                if let Ok(profile_key) = ndb.get_profilekey_by_pubkey(txn, &pubkey) {
                     ProfileRenderData::Profile(profile_key)
                 } else {
-                    ProfileRenderData::Missing(*pubkey)
+                    ProfileRenderData::Missing(pubkey)
                 }
             });
 
@@ -447,25 +884,51 @@ pub fn get_render_data(ndb: &Ndb, txn: &Transaction, nip19: &Nip19) -> Result<Re
             Ok(RenderData::profile(Some(profile_rd)))
         }
 
+        Nip19::Coordinate(coord) => {
+            let pubkey = coord.public_key.serialize();
+            let note_rd = match lookup_address(ndb, txn, coord).ok().and_then(|n| n.key()) {
+                Some(note_key) => NoteRenderData::Note(note_key),
+                None => NoteRenderData::MissingAddress(coord.clone()),
+            };
+
+            let profile_rd = if let Ok(profile_key) = ndb.get_profilekey_by_pubkey(txn, &pubkey) {
+                ProfileRenderData::Profile(profile_key)
+            } else {
+                ProfileRenderData::Missing(pubkey)
+            };
+
+            Ok(RenderData::note(note_rd, Some(profile_rd)))
+        }
+
         _ => Err(Error::CantRender),
     }
 }
 
-fn render_username(ui: &mut egui::Ui, profile: Option<&ProfileRecord>) {
+fn render_username(ui: &mut egui::Ui, profile: Option<&ProfileRecord>, theme: &Theme) {
     let name = format!(
         "@{}",
         profile
             .and_then(|pr| pr.record().profile().and_then(|p| p.name()))
             .unwrap_or("nostrich")
     );
-    ui.label(RichText::new(&name).size(40.0).color(Color32::LIGHT_GRAY));
+    ui.label(RichText::new(&name).size(40.0).color(theme.muted));
+}
+
+/// The check next to a username whose nip05 we verified
+fn verified_badge(ui: &mut egui::Ui, theme: &Theme) {
+    let (rect, _) = ui.allocate_exact_size(Vec2::splat(34.0), egui::Sense::hover());
+    let painter = ui.painter();
+    painter.circle_filled(rect.center(), rect.width() / 2.0, theme.accent);
+
+    let stroke = egui::Stroke::new(4.0, Color32::WHITE);
+    let at = |x: f32, y: f32| rect.min + Vec2::new(x, y) * rect.width();
+    painter.line_segment([at(0.28, 0.52), at(0.44, 0.68)], stroke);
+    painter.line_segment([at(0.44, 0.68), at(0.73, 0.36)], stroke);
 }
 
-fn setup_visuals(font_data: &egui::FontData, ctx: &egui::Context) {
-    let mut visuals = Visuals::dark();
-    visuals.override_text_color = Some(Color32::WHITE);
-    ctx.set_visuals(visuals);
-    fonts::setup_fonts(font_data, ctx);
+fn setup_visuals(fonts: &egui::FontDefinitions, ctx: &egui::Context, theme: &Theme) {
+    ctx.set_visuals(theme.visuals());
+    fonts::setup_fonts(fonts, ctx);
 }
 
 fn push_job_text(job: &mut LayoutJob, s: &str, color: Color32) {
@@ -486,18 +949,33 @@ fn push_job_user_mention(
     block: &Block,
     txn: &Transaction,
     pk: &[u8; 32],
+    theme: &Theme,
 ) {
-    let record = ndb.get_profile_by_pubkey(txn, pk);
-    if let Ok(record) = record {
-        let profile = record.record().profile().unwrap();
-        push_job_text(
-            job,
-            &format!("@{}", &abbrev_str(profile.name().unwrap_or("nostrich"))),
-            PURPLE,
-        );
-    } else {
-        push_job_text(job, &format!("@{}", &abbrev_str(block.as_str())), PURPLE);
-    }
+    let name = mention_name(ndb, txn, pk).unwrap_or(block.as_str());
+    push_job_text(job, &format!("@{}", &abbrev_str(name)), theme.accent);
+}
+
+/// The name a mention of `pk` is drawn with, once we have their profile
+fn mention_name<'a>(ndb: &Ndb, txn: &'a Transaction, pk: &[u8; 32]) -> Option<&'a str> {
+    let profile = ndb
+        .get_profile_by_pubkey(txn, pk)
+        .ok()?
+        .record()
+        .profile()?;
+    Some(profile.name().unwrap_or("nostrich"))
+}
+
+/// The profiles a note mentions, in the order they come up
+fn mentioned_pubkeys(note: &Note, blocks: &Blocks) -> Vec<[u8; 32]> {
+    blocks
+        .iter(note)
+        .filter(|block| matches!(block.blocktype(), BlockType::MentionBech32))
+        .filter_map(|block| match block.as_mention()? {
+            Mention::Profile(nprofile) => Some(*nprofile.pubkey()),
+            Mention::Pubkey(npub) => Some(*npub.pubkey()),
+            _ => None,
+        })
+        .collect()
 }
 
 fn wrapped_body_blocks(
@@ -506,7 +984,10 @@ fn wrapped_body_blocks(
     note: &Note,
     blocks: &Blocks,
     txn: &Transaction,
+    theme: &Theme,
+    thumbnail_url: Option<&str>,
 ) {
+    let accent = theme.accent;
     let mut job = LayoutJob {
         justify: false,
         halign: egui::Align::LEFT,
@@ -521,11 +1002,13 @@ fn wrapped_body_blocks(
 
     for block in blocks.iter(note) {
         match block.blocktype() {
-            BlockType::Url => push_job_text(&mut job, block.as_str(), PURPLE),
+            // already shown as the thumbnail
+            BlockType::Url if Some(block.as_str()) == thumbnail_url => {}
+            BlockType::Url => push_job_text(&mut job, block.as_str(), accent),
 
             BlockType::Hashtag => {
-                push_job_text(&mut job, "#", PURPLE);
-                push_job_text(&mut job, block.as_str(), PURPLE);
+                push_job_text(&mut job, "#", accent);
+                push_job_text(&mut job, block.as_str(), accent);
             }
 
             BlockType::MentionBech32 => {
@@ -533,47 +1016,64 @@ fn wrapped_body_blocks(
                     Mention::Event(_ev) => push_job_text(
                         &mut job,
                         &format!("@{}", &abbrev_str(block.as_str())),
-                        PURPLE,
+                        accent,
                     ),
                     Mention::Note(_ev) => {
                         push_job_text(
                             &mut job,
                             &format!("@{}", &abbrev_str(block.as_str())),
-                            PURPLE,
+                            accent,
                         );
                     }
                     Mention::Profile(nprofile) => {
-                        push_job_user_mention(&mut job, ndb, &block, txn, nprofile.pubkey())
+                        push_job_user_mention(&mut job, ndb, &block, txn, nprofile.pubkey(), theme)
                     }
                     Mention::Pubkey(npub) => {
-                        push_job_user_mention(&mut job, ndb, &block, txn, npub.pubkey())
+                        push_job_user_mention(&mut job, ndb, &block, txn, npub.pubkey(), theme)
                     }
-                    Mention::Secret(_sec) => push_job_text(&mut job, "--redacted--", PURPLE),
+                    Mention::Secret(_sec) => push_job_text(&mut job, "--redacted--", accent),
                     Mention::Relay(_relay) => {
-                        push_job_text(&mut job, &abbrev_str(block.as_str()), PURPLE)
+                        push_job_text(&mut job, &abbrev_str(block.as_str()), accent)
                     }
                     Mention::Addr(_addr) => {
-                        push_job_text(&mut job, &abbrev_str(block.as_str()), PURPLE)
+                        push_job_text(&mut job, &abbrev_str(block.as_str()), accent)
                     }
                 };
             }
 
-            _ => push_job_text(&mut job, block.as_str(), Color32::WHITE),
+            _ => push_job_text(&mut job, block.as_str(), theme.text),
         };
     }
 
     ui.label(job);
 }
 
-fn wrapped_body_text(ui: &mut egui::Ui, text: &str) {
+fn wrapped_body_text(ui: &mut egui::Ui, text: &str, theme: &Theme) {
     let format = TextFormat {
         font_id: FontId::proportional(52.0),
-        color: Color32::WHITE,
+        color: theme.text,
         extra_letter_spacing: 0.0,
         line_height: Some(50.0),
         ..Default::default()
     };
 
+    if bidi::is_rtl(text) {
+        let max_width = ui.available_width();
+        let lines = bidi::visual_lines(text, max_width, |line| {
+            ui.fonts(|fonts| {
+                fonts
+                    .layout_no_wrap(line.to_owned(), format.font_id.clone(), format.color)
+                    .size()
+                    .x
+            })
+        });
+        let job = LayoutJob::single_section(lines.join("\n"), format);
+        ui.with_layout(egui::Layout::top_down(egui::Align::Max), |ui| {
+            ui.label(job);
+        });
+        return;
+    }
+
     let job = LayoutJob::single_section(text.to_owned(), format);
     ui.label(job);
 }
@@ -604,17 +1104,36 @@ fn note_frame_align() -> egui::Layout {
     }
 }
 
-fn note_ui(app: &Notecrumbs, ctx: &egui::Context, rd: &NoteAndProfileRenderData) -> Result<()> {
-    setup_visuals(&app.font_data, ctx);
+fn note_ui(
+    app: &Notecrumbs,
+    ctx: &egui::Context,
+    rd: &NoteAndProfileRenderData,
+    media: &CardMedia,
+    theme: &Theme,
+    locale: Locale,
+) -> Result<()> {
+    let hero = media.image.clone();
+    // text goes on top of a darkened photo, only light text works there
+    let theme = if hero.is_some() && !theme.dark {
+        &theme::DARK
+    } else {
+        theme
+    };
+    setup_visuals(&app.fonts, ctx, theme);
 
     let outer_margin = 60.0;
     let inner_margin = 40.0;
-    let canvas_width = 1200.0;
-    let canvas_height = 600.0;
+    let canvas_width = ctx.screen_rect().width();
+    let canvas_height = ctx.screen_rect().height();
     //let canvas_size = Vec2::new(canvas_width, canvas_height);
 
     let total_margin = outer_margin + inner_margin;
     let txn = Transaction::new(&app.ndb)?;
+    let note = rd.note_rd.lookup(&txn, &app.ndb).ok();
+    let engagement = note
+        .as_ref()
+        .and_then(|note| note_engagement(&app.ndb, &txn, note.id(), locale));
+    let date = note.as_ref().map(|note| locale.date(note.created_at()));
     let profile_record = rd
         .profile_rd
         .as_ref()
@@ -625,20 +1144,41 @@ fn note_ui(app: &Notecrumbs, ctx: &egui::Context, rd: &NoteAndProfileRenderData)
     //let _profile = profile_record.and_then(|pr| pr.record().profile());
     //let pfp_url = profile.and_then(|p| p.picture());
 
-    // TODO: async pfp loading using notedeck browser context?
-    let pfp = ctx.load_texture("pfp", app.default_pfp.clone(), Default::default());
-    let bg = ctx.load_texture("background", app.background.clone(), Default::default());
+    let pfp = pfp_texture(app, ctx, media);
+    let has_hero = hero.is_some();
+    let qr = qr_texture(ctx, media);
+    let thumbnail = media.thumbnail.as_ref().map(|image| {
+        load_texture(
+            ctx,
+            "thumbnail",
+            ImageData::Color(image.clone()),
+            Default::default(),
+        )
+    });
+    let bg = load_texture(
+        ctx,
+        "background",
+        ImageData::Color(hero.unwrap_or_else(|| Arc::new(theme.background()))),
+        Default::default(),
+    );
+
+    // a scrim over the hero image so the title stays readable
+    let (bg_tint, card_fill) = if has_hero {
+        (Color32::from_gray(140), Color32::from_black_alpha(170))
+    } else {
+        (Color32::WHITE, theme.card)
+    };
 
     egui::CentralPanel::default()
         .frame(
             egui::Frame::default()
                 //.fill(Color32::from_rgb(0x43, 0x20, 0x62)
-                .fill(Color32::from_rgb(0x00, 0x00, 0x00)),
+                .fill(theme.backdrop),
         )
         .show(ctx, |ui| {
-            background_texture(ui, &bg);
+            background_texture(ui, &bg, bg_tint);
             egui::Frame::none()
-                .fill(Color32::from_rgb(0x0F, 0x0F, 0x0F))
+                .fill(card_fill)
                 .shadow(Shadow {
                     extrusion: 50.0,
                     color: Color32::from_black_alpha(60),
@@ -656,27 +1196,58 @@ fn note_ui(app: &Notecrumbs, ctx: &egui::Context, rd: &NoteAndProfileRenderData)
                         //egui::ScrollArea::vertical().show(ui, |ui| {
                         ui.spacing_mut().item_spacing = Vec2::new(10.0, 50.0);
 
-                        ui.vertical(|ui| {
-                            let desired = Vec2::new(desired_width, desired_height / 1.5);
-                            ui.set_max_size(desired);
-                            ui.set_min_size(desired);
-
-                            if let Ok(note) = rd.note_rd.lookup(&txn, &app.ndb) {
-                                if let Some(blocks) = note
-                                    .key()
-                                    .and_then(|nk| app.ndb.get_blocks_by_key(&txn, nk).ok())
-                                {
-                                    wrapped_body_blocks(ui, &app.ndb, &note, &blocks, &txn);
-                                } else {
-                                    wrapped_body_text(ui, note.content());
-                                }
+                        ui.horizontal_top(|ui| {
+                            let body_height = desired_height / 1.5;
+                            let thumbnail_size = Vec2::splat(body_height);
+                            let text_width = match &thumbnail {
+                                Some(_) => desired_width - thumbnail_size.x - inner_margin,
+                                None => desired_width,
+                            };
+
+                            ui.vertical(|ui| {
+                                let desired = Vec2::new(text_width, body_height);
+                                ui.set_max_size(desired);
+                                ui.set_min_size(desired);
+                                note_body(app, ui, &txn, rd, theme, thumbnail.is_some());
+                            });
+
+                            if let Some(thumbnail) = &thumbnail {
+                                ui.add(
+                                    egui::Image::new(thumbnail)
+                                        .fit_to_exact_size(thumbnail_size)
+                                        .rounding(Rounding::same(16.0)),
+                                );
                             }
                         });
 
                         ui.horizontal(|ui| {
                             ui.image(&pfp);
-                            render_username(ui, profile_record.as_ref());
-                            ui.with_layout(right_aligned(), discuss_on_damus);
+                            render_username(ui, profile_record.as_ref(), theme);
+                            if media.nip05_verified {
+                                verified_badge(ui, theme);
+                            }
+                            ui.vertical(|ui| {
+                                ui.spacing_mut().item_spacing = Vec2::new(10.0, 4.0);
+                                if let Some(date) = &date {
+                                    ui.label(RichText::new(date).size(26.0).color(theme.muted));
+                                }
+                                if let Some(engagement) = &engagement {
+                                    ui.label(
+                                        RichText::new(engagement).size(26.0).color(theme.muted),
+                                    );
+                                }
+                            });
+                            ui.with_layout(right_aligned(), |ui| match &qr {
+                                // the code is the way in on a screenshot
+                                Some(qr) => {
+                                    ui.add(
+                                        egui::Image::new(qr)
+                                            .fit_to_exact_size(Vec2::splat(QR_SIZE))
+                                            .rounding(Rounding::same(8.0)),
+                                    );
+                                }
+                                None => discuss_on_damus(ui, theme, locale),
+                            });
                         });
                     });
                 });
@@ -685,7 +1256,125 @@ fn note_ui(app: &Notecrumbs, ctx: &egui::Context, rd: &NoteAndProfileRenderData)
     Ok(())
 }
 
-fn background_texture(ui: &mut egui::Ui, texture: &TextureHandle) {
+/// Replies, reposts and zaps we have for a note, or `None` if it has
+/// none. Only what's in ndb is counted, so these are lower bounds.
+fn note_engagement(
+    ndb: &Ndb,
+    txn: &Transaction,
+    note_id: &[u8; 32],
+    locale: Locale,
+) -> Option<String> {
+    // each kind is counted on its own so one busy kind can't crowd the
+    // others out of the limit
+    let count = |kind: u32| {
+        let filter = nostrdb::Filter::new()
+            .kinds([kind as u64])
+            .event(note_id)
+            .limit(ENGAGEMENT_COUNT_MAX as u64)
+            .build();
+        let results = ndb.query(txn, &[filter], ENGAGEMENT_COUNT_MAX).ok()?;
+        let capped = results.len() >= ENGAGEMENT_COUNT_MAX as usize;
+
+        // notes that only mention this one in passing aren't replies
+        let counted = results
+            .iter()
+            .filter(|r| {
+                if kind != 1 {
+                    return true;
+                }
+                let thread = html::thread_refs(&r.note);
+                thread.root.as_ref() == Some(note_id) || thread.parent.as_ref() == Some(note_id)
+            })
+            .count();
+        Some((counted as u64, capped))
+    };
+    let counts = [
+        (count(1)?, Counted::Replies),
+        (count(6)?, Counted::Reposts),
+        (count(9735)?, Counted::Zaps),
+    ];
+
+    let parts: Vec<String> = counts
+        .iter()
+        .filter(|((count, _), _)| *count > 0)
+        .map(|((count, capped), counted)| {
+            if *capped {
+                locale.count_at_least(*count, *counted)
+            } else {
+                locale.count(*count, *counted)
+            }
+        })
+        .collect();
+
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" · "))
+    }
+}
+
+/// The title of an article, or the text of anything else
+fn note_body(
+    app: &Notecrumbs,
+    ui: &mut egui::Ui,
+    txn: &Transaction,
+    rd: &NoteAndProfileRenderData,
+    theme: &Theme,
+    has_thumbnail: bool,
+) {
+    if let Ok(note) = rd.note_rd.lookup(txn, &app.ndb) {
+        let title = if note.kind() == 30023 {
+            note_tag_value(&note, "title")
+        } else {
+            None
+        };
+
+        // mentions and links aren't colored in right to left notes, only
+        // plain text goes through bidi
+        if let Some(title) = title {
+            wrapped_body_text(ui, title, theme);
+        } else if bidi::is_rtl(note.content()) {
+            wrapped_body_text(ui, note.content(), theme);
+        } else if let Some(blocks) = note
+            .key()
+            .and_then(|nk| app.ndb.get_blocks_by_key(txn, nk).ok())
+        {
+            let thumbnail_url = if has_thumbnail {
+                html::first_image(&note, &blocks)
+            } else {
+                None
+            };
+            wrapped_body_blocks(ui, &app.ndb, &note, &blocks, txn, theme, thumbnail_url);
+        } else {
+            wrapped_body_text(ui, note.content(), theme);
+        }
+    }
+}
+
+/// egui_skia uploads texture pixels in skia's native BGRA order, but egui
+/// hands them over as RGBA. Every texture goes through here so pictures
+/// and gradients come out in the colors they were decoded in.
+fn load_texture(
+    ctx: &egui::Context,
+    name: impl Into<String>,
+    image: impl Into<ImageData>,
+    options: TextureOptions,
+) -> TextureHandle {
+    let image = match image.into() {
+        ImageData::Color(image) => ImageData::Color(Arc::new(ColorImage {
+            size: image.size,
+            pixels: image
+                .pixels
+                .iter()
+                .map(|p| Color32::from_rgba_premultiplied(p.b(), p.g(), p.r(), p.a()))
+                .collect(),
+        })),
+        font => font,
+    };
+    ctx.load_texture(name, image, options)
+}
+
+fn background_texture(ui: &mut egui::Ui, texture: &TextureHandle, tint: Color32) {
     // Get the size of the panel
     let size = ui.available_size();
 
@@ -705,7 +1394,7 @@ fn background_texture(ui: &mut egui::Ui, texture: &TextureHandle) {
     let mut mesh = Mesh::with_texture(texture.into());
 
     // Define vertices for a rectangle
-    mesh.add_rect_with_uv(rect, uv, Color32::WHITE);
+    mesh.add_rect_with_uv(rect, uv, tint);
 
     //let origin = pos2(600.0, 300.0);
     //let angle = Rot2::from_angle(45.0);
@@ -717,66 +1406,734 @@ fn background_texture(ui: &mut egui::Ui, texture: &TextureHandle) {
     //painter.image(texture.into(), rect, uv_skewed, tint);
 }
 
-fn discuss_on_damus(ui: &mut egui::Ui) {
+fn discuss_on_damus(ui: &mut egui::Ui, theme: &Theme, locale: Locale) {
     let button = egui::Button::new(
-        RichText::new("Discuss on Damus ➡")
+        RichText::new(locale.text(Text::DiscussOnDamus))
             .size(30.0)
-            .color(Color32::BLACK),
+            .color(theme.button_text),
     )
     .rounding(50.0)
     .min_size(Vec2::new(330.0, 75.0))
-    .fill(Color32::WHITE);
+    .fill(theme.button);
 
     ui.add(button);
 }
 
-fn profile_ui(app: &Notecrumbs, ctx: &egui::Context, profile_rd: Option<&ProfileRenderData>) {
-    let pfp = ctx.load_texture("pfp", app.default_pfp.clone(), Default::default());
-    setup_visuals(&app.font_data, ctx);
+/// The banner strip across the top of profile cards
+pub const BANNER_SIZE: [u32; 2] = [1200, 240];
+
+/// Past this many reactions to a note we stop counting
+const ENGAGEMENT_COUNT_MAX: i32 = 500;
+
+/// Size of the QR code drawn on cards with `?qr=1`
+const QR_SIZE: f32 = 150.0;
+const QR_MODULE_SIZE: usize = 4;
+
+/// Note thumbnails are square and as tall as the note text
+pub const THUMBNAIL_SIZE: [u32; 2] = [320, 320];
+
+/// The hero image of a longform article, if it has one
+pub fn article_hero_url(ndb: &Ndb, render_data: &RenderData) -> Option<String> {
+    let note_rd = match render_data {
+        RenderData::Note(note_rd) => &note_rd.note_rd,
+        RenderData::Profile(_) => return None,
+    };
+
+    let txn = Transaction::new(ndb).ok()?;
+    let note = note_rd.lookup(&txn, ndb).ok()?;
+    if note.kind() != 30023 {
+        return None;
+    }
+
+    note_tag_value(&note, "image")
+        .filter(|url| url.starts_with("https://") || url.starts_with("http://"))
+        .map(|url| url.to_owned())
+}
+
+/// The first image linked in a note, shown as a thumbnail on its card.
+/// Articles have their hero image instead.
+pub fn note_image_url(ndb: &Ndb, render_data: &RenderData) -> Option<String> {
+    let note_rd = match render_data {
+        RenderData::Note(note_rd) => &note_rd.note_rd,
+        RenderData::Profile(_) => return None,
+    };
+
+    let txn = Transaction::new(ndb).ok()?;
+    let note = note_rd.lookup(&txn, ndb).ok()?;
+    if note.kind() == 30023 {
+        return None;
+    }
+
+    let blocks = ndb.get_blocks_by_key(&txn, note.key()?).ok()?;
+    html::first_image(&note, &blocks)
+        .filter(|url| url.starts_with("https://") || url.starts_with("http://"))
+        .map(|url| url.to_owned())
+}
+
+/// The banner from a profile's kind 0 metadata, if it has one
+pub fn profile_banner_url(ndb: &Ndb, render_data: &RenderData) -> Option<String> {
+    let profile_rd = match render_data {
+        RenderData::Profile(profile_rd) => profile_rd.as_ref()?,
+        RenderData::Note(_) => return None,
+    };
+
+    let txn = Transaction::new(ndb).ok()?;
+    let profile = profile_rd.lookup(&txn, ndb).ok()?;
+    let banner = profile.record().profile()?.banner()?;
+    Some(banner.to_owned())
+}
+
+/// The profile card's avatar, in points
+pub const AVATAR_SIZE: u32 = 160;
+
+/// Notes we count on a profile card before just saying "500+"
+const PROFILE_NOTE_COUNT_MAX: i32 = 500;
+
+/// The picture from a profile's kind 0 metadata, if it has one
+pub fn profile_picture_url(ndb: &Ndb, render_data: &RenderData) -> Option<String> {
+    let profile_rd = match render_data {
+        RenderData::Profile(profile_rd) => profile_rd.as_ref()?,
+        RenderData::Note(_) => return None,
+    };
+
+    let txn = Transaction::new(ndb).ok()?;
+    let profile = profile_rd.lookup(&txn, ndb).ok()?;
+    let picture = profile.record().profile()?.picture()?;
+    Some(picture.to_owned())
+}
+
+fn profile_pubkey(
+    ndb: &Ndb,
+    txn: &Transaction,
+    profile_rd: &ProfileRenderData,
+) -> Option<[u8; 32]> {
+    match profile_rd {
+        ProfileRenderData::Missing(pk) => Some(*pk),
+        ProfileRenderData::Profile(_) => {
+            let record = profile_rd.lookup(txn, ndb).ok()?;
+            let note_key = NoteKey::new(record.record().note_key());
+            Some(*ndb.get_note_by_key(txn, note_key).ok()?.pubkey())
+        }
+    }
+}
+
+/// What the `?qr=1` code on a card points at: the note or profile, in a
+/// form nostr clients open directly
+fn card_qr_data(ndb: &Ndb, render_data: &RenderData) -> Option<String> {
+    let txn = Transaction::new(ndb).ok()?;
+    let bech32 = match render_data {
+        RenderData::Note(note_rd) => {
+            nip19::canonical_bech32(&note_rd.note_rd.lookup(&txn, ndb).ok()?)?
+        }
+        RenderData::Profile(profile_rd) => {
+            let pubkey = profile_pubkey(ndb, &txn, profile_rd.as_ref()?)?;
+            let profile = Nip19Profile {
+                public_key: PublicKey::from_slice(&pubkey).ok()?,
+                relays: Vec::new(),
+            };
+            profile.to_bech32().ok()?
+        }
+    };
+    Some(format!("nostr:{bech32}"))
+}
+
+/// The author's avatar, or the default one if it didn't load
+fn pfp_texture(app: &Notecrumbs, ctx: &egui::Context, media: &CardMedia) -> TextureHandle {
+    match &media.pfp {
+        Some(pfp) => load_texture(
+            ctx,
+            "pfp",
+            ImageData::Color(pfp.clone()),
+            Default::default(),
+        ),
+        None => load_texture(ctx, "pfp", app.default_pfp.clone(), Default::default()),
+    }
+}
+
+fn qr_texture(ctx: &egui::Context, media: &CardMedia) -> Option<TextureHandle> {
+    media.qr.as_ref().map(|qr| {
+        load_texture(
+            ctx,
+            "qr",
+            ImageData::Color(qr.clone()),
+            TextureOptions::NEAREST,
+        )
+    })
+}
+
+/// The profile picture of a note's author
+pub fn author_picture_url(ndb: &Ndb, render_data: &RenderData) -> Option<String> {
+    let note_rd = match render_data {
+        RenderData::Note(note_rd) => note_rd,
+        RenderData::Profile(_) => return None,
+    };
 
-    egui::CentralPanel::default().show(ctx, |ui| {
-        ui.vertical(|ui| {
-            ui.horizontal(|ui| {
-                ui.image(&pfp);
-                if let Ok(txn) = Transaction::new(&app.ndb) {
-                    let profile = profile_rd.and_then(|prd| prd.lookup(&txn, &app.ndb).ok());
-                    render_username(ui, profile.as_ref());
+    let txn = Transaction::new(ndb).ok()?;
+    let pubkey = card_author(&note_rd.note_rd.lookup(&txn, ndb).ok()?);
+    let profile = ndb.get_profile_by_pubkey(&txn, &pubkey).ok()?;
+    let picture = profile.record().profile()?.picture()?;
+    Some(picture.to_owned())
+}
+
+/// The nip05 a note's author claims, along with their pubkey so the
+/// claim can be checked
+pub fn author_nip05(ndb: &Ndb, render_data: &RenderData) -> Option<(String, [u8; 32])> {
+    let note_rd = match render_data {
+        RenderData::Note(note_rd) => note_rd,
+        RenderData::Profile(_) => return None,
+    };
+
+    let txn = Transaction::new(ndb).ok()?;
+    let pubkey = card_author(&note_rd.note_rd.lookup(&txn, ndb).ok()?);
+    let profile = ndb.get_profile_by_pubkey(&txn, &pubkey).ok()?;
+    let nip05 = profile.record().profile()?.nip05()?;
+    Some((nip05.to_owned(), pubkey))
+}
+
+/// How many notes we have from a profile and how many people they follow,
+/// from whatever is cached
+fn profile_stats(ndb: &Ndb, txn: &Transaction, pubkey: &[u8; 32]) -> (String, Option<usize>) {
+    let notes = nostrdb::Filter::new()
+        .authors([pubkey])
+        .kinds([1])
+        .limit(PROFILE_NOTE_COUNT_MAX as u64)
+        .build();
+    let notes = match ndb.query(txn, &[notes], PROFILE_NOTE_COUNT_MAX) {
+        Ok(results) if results.len() >= PROFILE_NOTE_COUNT_MAX as usize => {
+            format!("{PROFILE_NOTE_COUNT_MAX}+")
+        }
+        Ok(results) => results.len().to_string(),
+        Err(_) => "0".to_string(),
+    };
+
+    let contacts = nostrdb::Filter::new()
+        .authors([pubkey])
+        .kinds([3])
+        .limit(1)
+        .build();
+    let following = ndb.query(txn, &[contacts], 1).ok().and_then(|results| {
+        results.first().map(|result| {
+            result
+                .note
+                .tags()
+                .iter()
+                .filter(|tag| {
+                    tag.count() >= 2
+                        && matches!(
+                            tag.get(0).map(|t| t.variant()),
+                            Some(NdbStrVariant::Str("p"))
+                        )
+                })
+                .count()
+        })
+    });
+
+    (notes, following)
+}
+
+fn profile_ui(
+    app: &Notecrumbs,
+    ctx: &egui::Context,
+    profile_rd: Option<&ProfileRenderData>,
+    media: &CardMedia,
+    theme: &Theme,
+    locale: Locale,
+) {
+    setup_visuals(&app.fonts, ctx, theme);
+
+    let avatar = match &media.avatar {
+        Some(avatar) => {
+            let mut avatar = (**avatar).clone();
+            pfp::round_image(&mut avatar);
+            ImageData::Color(Arc::new(avatar))
+        }
+        None => app.default_pfp.clone(),
+    };
+    let avatar = load_texture(ctx, "avatar", avatar, Default::default());
+    let banner = match &media.image {
+        Some(banner) => ImageData::Color(banner.clone()),
+        None => ImageData::Color(Arc::new(theme.background())),
+    };
+    let banner = load_texture(ctx, "banner", banner, Default::default());
+
+    let txn = Transaction::new(&app.ndb).ok();
+    let profile_rec = txn
+        .as_ref()
+        .and_then(|txn| profile_rd.and_then(|prd| prd.lookup(txn, &app.ndb).ok()));
+    let profile = profile_rec.as_ref().and_then(|pr| pr.record().profile());
+
+    let name = profile.and_then(|p| p.name()).unwrap_or("nostrich");
+    let display_name = profile
+        .and_then(|p| p.display_name())
+        .filter(|n| !n.is_empty())
+        .unwrap_or(name);
+    let nip05 = profile.and_then(|p| p.nip05());
+    let about = crate::abbrev::summarize(profile.and_then(|p| p.about()).unwrap_or(""), 200);
+
+    let stats = txn.as_ref().and_then(|txn| {
+        let pubkey = profile_pubkey(&app.ndb, txn, profile_rd?)?;
+        Some(profile_stats(&app.ndb, txn, &pubkey))
+    });
+    let qr = qr_texture(ctx, media);
+
+    egui::CentralPanel::default()
+        .frame(egui::Frame::default().fill(theme.card))
+        .show(ctx, |ui| {
+            let width = ctx.screen_rect().width();
+            let banner_height = BANNER_SIZE[1] as f32;
+            let uv = Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0));
+
+            // the banner is cropped for the default card, stretch it across
+            // wider ones
+            ui.painter().image(
+                banner.id(),
+                Rect::from_min_size(pos2(0.0, 0.0), Vec2::new(width, banner_height)),
+                uv,
+                Color32::WHITE,
+            );
+
+            // the avatar sits half over the banner, with a ring in the card
+            // color around it
+            let avatar_size = AVATAR_SIZE as f32;
+            let margin = 60.0;
+            let avatar_rect = Rect::from_min_size(
+                pos2(margin, banner_height - avatar_size / 2.0),
+                Vec2::splat(avatar_size),
+            );
+            ui.painter()
+                .circle_filled(avatar_rect.center(), avatar_size / 2.0 + 6.0, theme.card);
+            ui.painter()
+                .image(avatar.id(), avatar_rect, uv, Color32::WHITE);
+
+            // the banner corner is empty, the code goes there
+            if let Some(qr) = &qr {
+                let qr_rect =
+                    Rect::from_min_size(pos2(width - margin - QR_SIZE, 40.0), Vec2::splat(QR_SIZE));
+                ui.painter().image(qr.id(), qr_rect, uv, Color32::WHITE);
+            }
+
+            let text_rect = Rect::from_min_max(
+                pos2(margin, avatar_rect.bottom() + 20.0),
+                pos2(width - margin, ctx.screen_rect().height() - 40.0),
+            );
+            ui.allocate_ui_at_rect(text_rect, |ui| {
+                ui.spacing_mut().item_spacing = Vec2::new(10.0, 12.0);
+
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new(display_name).size(50.0).color(theme.text));
+                    if let Some(nip05) = nip05 {
+                        ui.label(RichText::new(nip05).size(28.0).color(theme.muted));
+                    }
+                });
+
+                if !about.is_empty() {
+                    let mut job = LayoutJob::single_section(
+                        about.clone(),
+                        TextFormat {
+                            font_id: FontId::proportional(30.0),
+                            color: theme.text,
+                            ..Default::default()
+                        },
+                    );
+                    job.wrap = egui::text::TextWrapping {
+                        max_rows: 2,
+                        break_anywhere: false,
+                        overflow_character: Some('…'),
+                        ..Default::default()
+                    };
+                    ui.label(job);
                 }
+
+                ui.horizontal(|ui| {
+                    if let Some((notes, following)) = &stats {
+                        let notes = format!("{notes} {}", locale.text(Text::Notes));
+                        let stats = match following {
+                            Some(following) => {
+                                format!("{notes} · {following} {}", locale.text(Text::Following))
+                            }
+                            None => notes,
+                        };
+                        ui.label(RichText::new(stats).size(28.0).color(theme.muted));
+                    }
+                    ui.with_layout(right_aligned(), |ui| {
+                        ui.label(RichText::new("damus").size(40.0).color(theme.accent));
+                    });
+                });
             });
-            //body(ui, &profile.about);
+        });
+}
+
+/// Placeholder cards for when there is no note to render
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingCard {
+    /// We asked around and nobody had it
+    NotFound,
+    /// Relays didn't get back to us in time
+    Timeout,
+    /// We couldn't connect to any relay to ask
+    Unavailable,
+}
+
+impl MissingCard {
+    fn title(&self) -> &'static str {
+        match self {
+            MissingCard::NotFound => "Note not found",
+            MissingCard::Timeout => "Still looking for this note",
+            MissingCard::Unavailable => "Live data unavailable",
+        }
+    }
+
+    fn subtitle(&self) -> &'static str {
+        match self {
+            MissingCard::NotFound => "It may have been deleted, or no relay we know of has it.",
+            MissingCard::Timeout => "Relays took too long to respond. Try again in a bit.",
+            MissingCard::Unavailable => "We can't reach any relays right now. Try again in a bit.",
+        }
+    }
+}
+
+/// The dark rounded frame on top of the gradient background, used by
+/// cards that don't have a note body
+fn simple_card_ui(
+    app: &Notecrumbs,
+    ctx: &egui::Context,
+    theme: &Theme,
+    add_contents: impl FnOnce(&mut egui::Ui),
+) {
+    setup_visuals(&app.fonts, ctx, theme);
+
+    let bg = load_texture(
+        ctx,
+        "background",
+        ImageData::Color(Arc::new(theme.background())),
+        Default::default(),
+    );
+
+    egui::CentralPanel::default()
+        .frame(egui::Frame::default().fill(theme.backdrop))
+        .show(ctx, |ui| {
+            background_texture(ui, &bg, Color32::WHITE);
+            egui::Frame::none()
+                .fill(theme.card)
+                .shadow(Shadow {
+                    extrusion: 50.0,
+                    color: Color32::from_black_alpha(60),
+                })
+                .rounding(Rounding::same(20.0))
+                .outer_margin(60.0)
+                .inner_margin(40.0)
+                .show(ui, |ui| {
+                    let desired_size = ctx.screen_rect().size() - Vec2::splat(200.0);
+                    ui.set_min_size(desired_size);
+                    ui.set_max_size(desired_size);
+
+                    add_contents(ui);
+                });
+        });
+}
+
+fn missing_ui(app: &Notecrumbs, ctx: &egui::Context, card: MissingCard, theme: &Theme) {
+    simple_card_ui(app, ctx, theme, |ui| {
+        ui.vertical_centered(|ui| {
+            ui.add_space(60.0);
+            ui.label(RichText::new(card.title()).size(60.0).color(theme.text));
+            ui.add_space(20.0);
+            ui.label(RichText::new(card.subtitle()).size(30.0).color(theme.muted));
+            ui.add_space(40.0);
+            ui.label(RichText::new("damus").size(40.0).color(theme.accent));
         });
     });
 }
 
-pub fn render_note(ndb: &Notecrumbs, render_data: &RenderData) -> Vec<u8> {
-    use egui_skia::{rasterize, RasterizeOptions};
+/// Image formats we can encode cards as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CardFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+}
+
+/// Lossy formats are for saving bandwidth, they don't need to be perfect
+const LOSSY_QUALITY: i32 = 85;
+
+impl CardFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            CardFormat::Png => "image/png",
+            CardFormat::Jpeg => "image/jpeg",
+            CardFormat::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CardFormat::Png => ".png",
+            CardFormat::Jpeg => ".jpg",
+            CardFormat::Webp => ".webp",
+        }
+    }
+}
+
+/// Remote images and lookups fetched ahead of rendering a card
+#[derive(Debug, Clone, Default)]
+pub struct CardMedia {
+    /// The profile banner, or an article's hero image
+    pub image: Option<Arc<ColorImage>>,
+    /// The profile picture on profile cards
+    pub avatar: Option<Arc<ColorImage>>,
+    /// The first image in a note, shown next to its text
+    pub thumbnail: Option<Arc<ColorImage>>,
+    /// The note author's avatar, from the pfp cache
+    pub pfp: Option<Arc<ColorImage>>,
+    /// The author's nip05 resolves to them
+    pub nip05_verified: bool,
+    /// Filled in by `render_note` when the card asks for a QR code
+    pub qr: Option<Arc<ColorImage>>,
+}
+
+/// How a card should look and be encoded
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CardOptions {
+    pub size: CardSize,
+    pub theme: Theme,
+    pub format: CardFormat,
+    /// Draw a QR code of the note or profile in the corner
+    pub qr: bool,
+    /// The language of the card's text and dates
+    pub locale: Locale,
+}
+
+impl CardOptions {
+    /// Identifies the rendered output for a given subject, see
+    /// `card_cache_key`
+    fn cache_key(&self) -> String {
+        format!(
+            "{}x{}@{}{}{}/{}{}",
+            self.size.width,
+            self.size.height,
+            self.size.scale,
+            self.theme.name,
+            if self.qr { "+qr" } else { "" },
+            self.locale.key(),
+            self.format.extension(),
+        )
+    }
+}
+
+/// The card cache key for rendering `render_data` with `options`, or
+/// `None` if there's nothing to render yet. It's a hash of everything
+/// that ends up on the card, so a profile or article edit is a new key
+/// and stale cards just age out of the cache.
+pub fn card_cache_key(
+    ndb: &Ndb,
+    render_data: &RenderData,
+    options: &CardOptions,
+) -> Option<String> {
+    use nostr::hashes::{sha256, Hash, HashEngine};
+
+    let txn = Transaction::new(ndb).ok()?;
+    let mut engine = sha256::Hash::engine();
+    let mut input = |field: &[u8]| {
+        engine.input(field);
+        engine.input(&[0]);
+    };
+
+    let pubkey = match render_data {
+        RenderData::Note(note_rd) => {
+            let note = note_rd.note_rd.lookup(&txn, ndb).ok()?;
+            input(note.id());
+            input(note.content().as_bytes());
+            for tag in ["title", "image"] {
+                input(note_tag_value(&note, tag).unwrap_or("").as_bytes());
+            }
+            input(
+                note_engagement(ndb, &txn, note.id(), options.locale)
+                    .unwrap_or_default()
+                    .as_bytes(),
+            );
+            // mentions are drawn with the mentioned profile's name
+            if let Some(blocks) = note
+                .key()
+                .and_then(|key| ndb.get_blocks_by_key(&txn, key).ok())
+            {
+                for pk in mentioned_pubkeys(&note, &blocks) {
+                    input(mention_name(ndb, &txn, &pk).unwrap_or("").as_bytes());
+                }
+            }
+            card_author(&note)
+        }
+        RenderData::Profile(profile_rd) => {
+            let pubkey = profile_pubkey(ndb, &txn, profile_rd.as_ref()?)?;
+            let (notes, following) = profile_stats(ndb, &txn, &pubkey);
+            input(notes.as_bytes());
+            input(&following.unwrap_or(0).to_le_bytes());
+            pubkey
+        }
+    };
+
+    input(&pubkey);
+    let profile = ndb
+        .get_profile_by_pubkey(&txn, &pubkey)
+        .ok()
+        .and_then(|pr| pr.record().profile());
+    let fields = [
+        profile.and_then(|p| p.name()),
+        profile.and_then(|p| p.display_name()),
+        profile.and_then(|p| p.about()),
+        profile.and_then(|p| p.picture()),
+        profile.and_then(|p| p.banner()),
+        profile.and_then(|p| p.nip05()),
+    ];
+    for field in fields {
+        input(field.unwrap_or("").as_bytes());
+    }
+    input(options.cache_key().as_bytes());
+
+    Some(hex::encode(
+        sha256::Hash::from_engine(engine).as_byte_array(),
+    ))
+}
+
+fn encode(surface: &mut skia_safe::Surface, format: CardFormat) -> Vec<u8> {
     use skia_safe::EncodedImageFormat;
 
+    let image = surface.image_snapshot();
+    let data = match format {
+        CardFormat::Png => image.encode_to_data(EncodedImageFormat::PNG),
+        CardFormat::Jpeg => {
+            image.encode_to_data_with_quality(EncodedImageFormat::JPEG, LOSSY_QUALITY)
+        }
+        CardFormat::Webp => {
+            image.encode_to_data_with_quality(EncodedImageFormat::WEBP, LOSSY_QUALITY)
+        }
+    };
+
+    data.expect("expected image").as_bytes().into()
+}
+
+/// Render the placeholder card we show when the note is missing
+pub fn render_missing(app: &Notecrumbs, card: MissingCard, options: &CardOptions) -> Vec<u8> {
+    let mut surface = egui_skia::rasterize(
+        options.size.pixels(),
+        |ctx| missing_ui(app, ctx, card, &options.theme),
+        Some(options.size.options()),
+    );
+
+    encode(&mut surface, options.format)
+}
+
+/// Tiles in the profile media grid, 3x3
+pub const MEDIA_GRID_TILES: usize = 9;
+
+/// The size of a single media grid tile
+pub const MEDIA_GRID_TILE_SIZE: [u32; 2] = [400, 200];
+
+fn media_grid_ui(ctx: &egui::Context, tiles: &[Arc<ColorImage>]) {
+    let textures: Vec<TextureHandle> = tiles
+        .iter()
+        .enumerate()
+        .map(|(i, tile)| {
+            load_texture(
+                ctx,
+                format!("tile{i}"),
+                ImageData::Color(tile.clone()),
+                Default::default(),
+            )
+        })
+        .collect();
+
+    egui::CentralPanel::default()
+        .frame(egui::Frame::default().fill(Color32::from_rgb(0x0F, 0x0F, 0x0F)))
+        .show(ctx, |ui| {
+            let size = Vec2::new(
+                MEDIA_GRID_TILE_SIZE[0] as f32,
+                MEDIA_GRID_TILE_SIZE[1] as f32,
+            );
+            let uv = Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0));
+
+            for (i, texture) in textures.iter().enumerate() {
+                let min = pos2((i % 3) as f32 * size.x, (i / 3) as f32 * size.y);
+                // a thin gap between tiles
+                let rect = Rect::from_min_size(min, size).shrink(2.0);
+                ui.painter().image(texture.id(), rect, uv, Color32::WHITE);
+            }
+        });
+}
+
+/// Render a 3x3 collage of an author's recent images
+pub fn render_media_grid(tiles: &[Arc<ColorImage>]) -> Vec<u8> {
+    use egui_skia::{rasterize, RasterizeOptions};
+
     let options = RasterizeOptions {
         pixels_per_point: 1.0,
         frames_before_screenshot: 1,
     };
 
+    let mut surface = rasterize((1200, 600), |ctx| media_grid_ui(ctx, tiles), Some(options));
+
+    encode(&mut surface, CardFormat::Png)
+}
+
+/// Render a note or profile card. Fails with [`Error::NotFound`] when we
+/// don't have the note, so the caller can pick a placeholder card.
+#[tracing::instrument(name = "render", skip_all)]
+pub fn render_note(
+    ndb: &Notecrumbs,
+    render_data: &RenderData,
+    media: &CardMedia,
+    options: &CardOptions,
+) -> Result<Vec<u8>> {
+    use egui_skia::rasterize;
+
+    let size = options.size;
+    let theme = &options.theme;
+
+    let kind_card = if let RenderData::Note(note_render_data) = render_data {
+        let txn = Transaction::new(&ndb.ndb)?;
+        match note_render_data.note_rd.lookup(&txn, &ndb.ndb) {
+            Ok(note) => KindCard::from_kind(note.kind()),
+            Err(_) => return Err(Error::NotFound),
+        }
+    } else {
+        None
+    };
+
+    let raster_options = size.options();
+
+    let mut media = media.clone();
+    if options.qr {
+        media.qr = card_qr_data(&ndb.ndb, render_data)
+            .and_then(|data| qr::qr_image(&data, QR_MODULE_SIZE))
+            .map(Arc::new);
+    }
+    let media = &media;
+
     let mut surface = match render_data {
+        RenderData::Note(note_render_data) if kind_card.is_some() => rasterize(
+            size.pixels(),
+            |ctx| {
+                if let Some(card) = kind_card {
+                    let _ = kind_card_ui(ndb, ctx, note_render_data, media, card, theme);
+                }
+            },
+            Some(raster_options),
+        ),
+
         RenderData::Note(note_render_data) => rasterize(
-            (1200, 600),
+            size.pixels(),
             |ctx| {
-                let _ = note_ui(ndb, ctx, note_render_data);
+                let _ = note_ui(ndb, ctx, note_render_data, media, theme, options.locale);
             },
-            Some(options),
+            Some(raster_options),
         ),
 
         RenderData::Profile(profile_rd) => rasterize(
-            (1200, 600),
-            |ctx| profile_ui(ndb, ctx, profile_rd.as_ref()),
-            Some(options),
+            size.pixels(),
+            |ctx| profile_ui(ndb, ctx, profile_rd.as_ref(), media, theme, options.locale),
+            Some(raster_options),
         ),
     };
 
-    surface
-        .image_snapshot()
-        .encode_to_data(EncodedImageFormat::PNG)
-        .expect("expected image")
-        .as_bytes()
-        .into()
+    Ok(encode(&mut surface, options.format))
 }
@@ -0,0 +1,249 @@
+use unicode_bidi::{BidiInfo, Level};
+
+/// Presentation forms of a joining letter: isolated, final, initial,
+/// medial. Letters that don't join to the next one only have the first
+/// two.
+fn arabic_forms(c: char) -> Option<&'static [u32]> {
+    let forms: &'static [u32] = match c {
+        '\u{0621}' => &[0xFE80],
+        '\u{0622}' => &[0xFE81, 0xFE82],
+        '\u{0623}' => &[0xFE83, 0xFE84],
+        '\u{0624}' => &[0xFE85, 0xFE86],
+        '\u{0625}' => &[0xFE87, 0xFE88],
+        '\u{0626}' => &[0xFE89, 0xFE8A, 0xFE8B, 0xFE8C],
+        '\u{0627}' => &[0xFE8D, 0xFE8E],
+        '\u{0628}' => &[0xFE8F, 0xFE90, 0xFE91, 0xFE92],
+        '\u{0629}' => &[0xFE93, 0xFE94],
+        '\u{062A}' => &[0xFE95, 0xFE96, 0xFE97, 0xFE98],
+        '\u{062B}' => &[0xFE99, 0xFE9A, 0xFE9B, 0xFE9C],
+        '\u{062C}' => &[0xFE9D, 0xFE9E, 0xFE9F, 0xFEA0],
+        '\u{062D}' => &[0xFEA1, 0xFEA2, 0xFEA3, 0xFEA4],
+        '\u{062E}' => &[0xFEA5, 0xFEA6, 0xFEA7, 0xFEA8],
+        '\u{062F}' => &[0xFEA9, 0xFEAA],
+        '\u{0630}' => &[0xFEAB, 0xFEAC],
+        '\u{0631}' => &[0xFEAD, 0xFEAE],
+        '\u{0632}' => &[0xFEAF, 0xFEB0],
+        '\u{0633}' => &[0xFEB1, 0xFEB2, 0xFEB3, 0xFEB4],
+        '\u{0634}' => &[0xFEB5, 0xFEB6, 0xFEB7, 0xFEB8],
+        '\u{0635}' => &[0xFEB9, 0xFEBA, 0xFEBB, 0xFEBC],
+        '\u{0636}' => &[0xFEBD, 0xFEBE, 0xFEBF, 0xFEC0],
+        '\u{0637}' => &[0xFEC1, 0xFEC2, 0xFEC3, 0xFEC4],
+        '\u{0638}' => &[0xFEC5, 0xFEC6, 0xFEC7, 0xFEC8],
+        '\u{0639}' => &[0xFEC9, 0xFECA, 0xFECB, 0xFECC],
+        '\u{063A}' => &[0xFECD, 0xFECE, 0xFECF, 0xFED0],
+        '\u{0641}' => &[0xFED1, 0xFED2, 0xFED3, 0xFED4],
+        '\u{0642}' => &[0xFED5, 0xFED6, 0xFED7, 0xFED8],
+        '\u{0643}' => &[0xFED9, 0xFEDA, 0xFEDB, 0xFEDC],
+        '\u{0644}' => &[0xFEDD, 0xFEDE, 0xFEDF, 0xFEE0],
+        '\u{0645}' => &[0xFEE1, 0xFEE2, 0xFEE3, 0xFEE4],
+        '\u{0646}' => &[0xFEE5, 0xFEE6, 0xFEE7, 0xFEE8],
+        '\u{0647}' => &[0xFEE9, 0xFEEA, 0xFEEB, 0xFEEC],
+        '\u{0648}' => &[0xFEED, 0xFEEE],
+        '\u{0649}' => &[0xFEEF, 0xFEF0],
+        '\u{064A}' => &[0xFEF1, 0xFEF2, 0xFEF3, 0xFEF4],
+        // persian and urdu
+        '\u{067E}' => &[0xFB56, 0xFB57, 0xFB58, 0xFB59],
+        '\u{0686}' => &[0xFB7A, 0xFB7B, 0xFB7C, 0xFB7D],
+        '\u{0698}' => &[0xFB8A, 0xFB8B],
+        '\u{06A9}' => &[0xFB8E, 0xFB8F, 0xFB90, 0xFB91],
+        '\u{06AF}' => &[0xFB92, 0xFB93, 0xFB94, 0xFB95],
+        '\u{06CC}' => &[0xFBFC, 0xFBFD, 0xFBFE, 0xFBFF],
+        _ => return None,
+    };
+    Some(forms)
+}
+
+/// Lam-alef is written as a single ligature: isolated, final
+fn lam_alef(alef: char) -> Option<[u32; 2]> {
+    match alef {
+        '\u{0622}' => Some([0xFEF5, 0xFEF6]),
+        '\u{0623}' => Some([0xFEF7, 0xFEF8]),
+        '\u{0625}' => Some([0xFEF9, 0xFEFA]),
+        '\u{0627}' => Some([0xFEFB, 0xFEFC]),
+        _ => None,
+    }
+}
+
+const TATWEEL: char = '\u{0640}';
+
+/// Harakat and other marks sit on a letter without breaking joins
+fn is_transparent(c: char) -> bool {
+    matches!(c, '\u{064B}'..='\u{065F}' | '\u{0670}' | '\u{06D6}'..='\u{06ED}')
+}
+
+fn joins_next(c: char) -> bool {
+    c == TATWEEL || arabic_forms(c).is_some_and(|forms| forms.len() == 4)
+}
+
+fn joins_prev(c: char) -> bool {
+    c == TATWEEL || arabic_forms(c).is_some_and(|forms| forms.len() >= 2)
+}
+
+/// Replace arabic letters with the presentation form for their position
+/// in the word. egui draws one glyph per character and does no shaping
+/// of its own.
+pub fn shape_arabic(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let letter = |c: &&char| !is_transparent(**c);
+
+    let mut shaped = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let forms = if let Some(forms) = arabic_forms(c) {
+            forms
+        } else {
+            shaped.push(c);
+            i += 1;
+            continue;
+        };
+
+        let prev = chars[..i].iter().rev().find(letter).copied();
+        let joined_prev = joins_prev(c) && prev.is_some_and(joins_next);
+
+        if c == '\u{0644}' {
+            // harakat on the lam come between it and the alef
+            let marks = chars[i + 1..]
+                .iter()
+                .take_while(|c| is_transparent(**c))
+                .count();
+            let alef = i + 1 + marks;
+            if let Some(ligature) = chars.get(alef).and_then(|alef| lam_alef(*alef)) {
+                let form = ligature[joined_prev as usize];
+                shaped.extend(char::from_u32(form));
+                shaped.extend(&chars[i + 1..alef]);
+                i = alef + 1;
+                continue;
+            }
+        }
+
+        let next = chars[i + 1..].iter().find(letter).copied();
+        let joined_next = joins_next(c) && next.is_some_and(joins_prev);
+
+        let form = match (joined_prev, joined_next) {
+            (true, true) => forms[3],
+            (false, true) => forms[2],
+            (true, false) => forms[1],
+            (false, false) => forms[0],
+        };
+        shaped.extend(char::from_u32(form));
+        i += 1;
+    }
+
+    shaped
+}
+
+/// Whether text starts out right to left, by its first strong character
+pub fn is_rtl(text: &str) -> bool {
+    BidiInfo::new(text, None)
+        .paragraphs
+        .first()
+        .is_some_and(|para| para.level.is_rtl())
+}
+
+/// Break right to left text into lines no wider than `max_width`, shaped
+/// and in visual order, since egui lays everything out left to right.
+/// Lines have to be broken before reordering, otherwise the end of the
+/// text would land on the first line.
+pub fn visual_lines(text: &str, max_width: f32, measure: impl Fn(&str) -> f32) -> Vec<String> {
+    let mut lines = vec![];
+
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_owned()
+            } else {
+                format!("{line} {word}")
+            };
+
+            if !line.is_empty() && measure(&shape_arabic(&candidate)) > max_width {
+                lines.push(std::mem::replace(&mut line, word.to_owned()));
+            } else {
+                line = candidate;
+            }
+        }
+        lines.push(line);
+    }
+
+    lines
+        .iter()
+        .map(|line| {
+            let shaped = shape_arabic(line);
+            let info = BidiInfo::new(&shaped, Some(Level::rtl()));
+            info.paragraphs
+                .first()
+                .map(|para| info.reorder_line(para, para.range.clone()).into_owned())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn letter_forms() {
+        // beh alone, then starting, inside and ending a word
+        assert_eq!(shape_arabic("\u{0628}"), "\u{FE8F}");
+        assert_eq!(
+            shape_arabic("\u{0628}\u{0628}\u{0628}"),
+            "\u{FE91}\u{FE92}\u{FE90}"
+        );
+        // alef doesn't join the letter after it
+        assert_eq!(shape_arabic("\u{0627}\u{0628}"), "\u{FE8D}\u{FE8F}");
+        // spaces and latin text break words
+        assert_eq!(shape_arabic("\u{0628} a\u{0628}"), "\u{FE8F} a\u{FE8F}");
+    }
+
+    #[test]
+    fn harakat_dont_break_joins() {
+        assert_eq!(
+            shape_arabic("\u{0628}\u{064E}\u{0628}"),
+            "\u{FE91}\u{064E}\u{FE90}"
+        );
+    }
+
+    #[test]
+    fn lam_alef() {
+        assert_eq!(shape_arabic("\u{0644}\u{0627}"), "\u{FEFB}");
+        assert_eq!(shape_arabic("\u{0628}\u{0644}\u{0627}"), "\u{FE91}\u{FEFC}");
+        // a fatha on the lam stays, after the ligature
+        assert_eq!(shape_arabic("\u{0644}\u{064E}\u{0627}"), "\u{FEFB}\u{064E}");
+        assert_eq!(
+            shape_arabic("\u{0628}\u{0644}\u{064E}\u{0651}\u{0622}"),
+            "\u{FE91}\u{FEF6}\u{064E}\u{0651}"
+        );
+    }
+
+    #[test]
+    fn rtl_detection() {
+        assert!(is_rtl("\u{05E9}\u{05DC}\u{05D5}\u{05DD} hello"));
+        assert!(!is_rtl("hello \u{05E9}\u{05DC}\u{05D5}\u{05DD}"));
+    }
+
+    #[test]
+    fn mixed_text_in_visual_order() {
+        let chars = |line: &str| line.chars().count() as f32;
+        // the hebrew word comes first, so it ends up on the right, and
+        // the latin one keeps its own order
+        assert_eq!(
+            visual_lines("\u{05E9}\u{05DC}\u{05D5}\u{05DD} hello", 100.0, chars),
+            ["hello \u{05DD}\u{05D5}\u{05DC}\u{05E9}"]
+        );
+        assert_eq!(
+            visual_lines("hello \u{05E9}\u{05DC}\u{05D5}\u{05DD}", 100.0, chars),
+            ["\u{05DD}\u{05D5}\u{05DC}\u{05E9} hello"]
+        );
+    }
+
+    #[test]
+    fn lines_break_before_reordering() {
+        let chars = |line: &str| line.chars().count() as f32;
+        assert_eq!(
+            visual_lines("\u{05D0}\u{05D1} \u{05D2}\u{05D3}", 4.0, chars),
+            ["\u{05D1}\u{05D0}", "\u{05D3}\u{05D2}"]
+        );
+    }
+}
@@ -0,0 +1,339 @@
+use super::{
+    pfp_texture, render_username, simple_card_ui, theme::Theme, CardMedia,
+    NoteAndProfileRenderData, PURPLE,
+};
+use crate::{error::Result, html::note_tag_value, Notecrumbs};
+use egui::{pos2, Color32, Painter, Pos2, Rect, RichText, Rounding, Sense, Shape, Stroke, Vec2};
+use nostrdb::{Note, Transaction};
+
+/// Event kinds that don't have rich content of their own. We draw a
+/// dedicated card for these instead of the generic note layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KindCard {
+    Zap,
+    Reaction,
+    Repost,
+    List,
+    DvmRequest,
+    DvmResult,
+}
+
+impl KindCard {
+    pub fn from_kind(kind: u32) -> Option<Self> {
+        match kind {
+            9735 => Some(KindCard::Zap),
+            7 => Some(KindCard::Reaction),
+            6 | 16 => Some(KindCard::Repost),
+            3 | 10000..=10102 | 30000..=30004 | 30015 | 30030 => Some(KindCard::List),
+            5000..=5999 => Some(KindCard::DvmRequest),
+            6000..=6999 => Some(KindCard::DvmResult),
+            _ => None,
+        }
+    }
+
+    fn label(&self, note: &Note) -> String {
+        match self {
+            KindCard::Zap => match zap_amount(note) {
+                Some(msats) => format!("Zapped {} sats", thousands(msats / 1000)),
+                None => "Zap".to_string(),
+            },
+            KindCard::Reaction => match note.content() {
+                "" | "+" => "Liked a note".to_string(),
+                "-" => "Disliked a note".to_string(),
+                _ => "Reacted to a note".to_string(),
+            },
+            KindCard::Repost => "Reposted a note".to_string(),
+            KindCard::List => match note_tag_value(note, "title") {
+                Some(title) => title.to_string(),
+                None if note.kind() == 3 => "Follow list".to_string(),
+                None => "List".to_string(),
+            },
+            KindCard::DvmRequest => "Data vending machine request".to_string(),
+            KindCard::DvmResult => "Data vending machine result".to_string(),
+        }
+    }
+
+    fn paint_icon(&self, painter: &Painter, rect: Rect) {
+        // icons are drawn in a unit box, mapped onto rect
+        let p = |x: f32, y: f32| {
+            pos2(
+                rect.min.x + x * rect.width(),
+                rect.min.y + y * rect.height(),
+            )
+        };
+
+        painter.circle_filled(rect.center(), rect.width() / 2.0, Color32::from_gray(0x22));
+
+        match self {
+            KindCard::Zap => {
+                let color = Color32::from_rgb(0xff, 0xb2, 0x00);
+                convex(
+                    painter,
+                    vec![p(0.58, 0.15), p(0.30, 0.55), p(0.52, 0.55)],
+                    color,
+                );
+                convex(
+                    painter,
+                    vec![p(0.30, 0.55), p(0.48, 0.45), p(0.70, 0.45), p(0.52, 0.55)],
+                    color,
+                );
+                convex(
+                    painter,
+                    vec![p(0.48, 0.45), p(0.70, 0.45), p(0.42, 0.85)],
+                    color,
+                );
+            }
+
+            KindCard::Reaction => {
+                let color = Color32::from_rgb(0xe0, 0x31, 0x5b);
+                let r = rect.width() * 0.14;
+                painter.circle_filled(p(0.38, 0.40), r, color);
+                painter.circle_filled(p(0.62, 0.40), r, color);
+                convex(
+                    painter,
+                    vec![p(0.25, 0.46), p(0.75, 0.46), p(0.5, 0.78)],
+                    color,
+                );
+            }
+
+            KindCard::Repost => {
+                let stroke = Stroke::new(rect.width() * 0.05, Color32::from_rgb(0x2e, 0xc4, 0x6d));
+                painter.line_segment([p(0.30, 0.38), p(0.70, 0.38)], stroke);
+                convex(
+                    painter,
+                    vec![p(0.64, 0.28), p(0.78, 0.38), p(0.64, 0.48)],
+                    stroke.color,
+                );
+                painter.line_segment([p(0.30, 0.62), p(0.70, 0.62)], stroke);
+                convex(
+                    painter,
+                    vec![p(0.36, 0.52), p(0.22, 0.62), p(0.36, 0.72)],
+                    stroke.color,
+                );
+            }
+
+            KindCard::List => {
+                let r = rect.width() * 0.04;
+                for y in [0.33, 0.5, 0.67] {
+                    painter.circle_filled(p(0.28, y), r, PURPLE);
+                    painter.rect_filled(
+                        Rect::from_min_max(p(0.38, y - 0.03), p(0.74, y + 0.03)),
+                        Rounding::same(r),
+                        Color32::WHITE,
+                    );
+                }
+            }
+
+            KindCard::DvmRequest | KindCard::DvmResult => {
+                // a little gear
+                let center = rect.center();
+                let r = rect.width() * 0.22;
+                for i in 0..8 {
+                    let angle = i as f32 * std::f32::consts::TAU / 8.0;
+                    let tooth = center + Vec2::angled(angle) * r;
+                    painter.circle_filled(tooth, r * 0.3, PURPLE);
+                }
+                painter.circle_filled(center, r, PURPLE);
+                painter.circle_filled(center, r * 0.45, Color32::from_gray(0x22));
+            }
+        }
+    }
+}
+
+/// The zap request a zap receipt carries in its `description` tag
+fn zap_request(receipt: &Note) -> Option<serde_json::Value> {
+    parse_zap_request(note_tag_value(receipt, "description")?)
+}
+
+fn parse_zap_request(description: &str) -> Option<serde_json::Value> {
+    let request: serde_json::Value = serde_json::from_str(description).ok()?;
+    (request["kind"].as_u64() == Some(9734)).then_some(request)
+}
+
+/// Who sent a zap. The receipt itself is signed by the recipient's
+/// lightning server, the sender signed the zap request inside it.
+pub fn zap_sender(receipt: &Note) -> Option<[u8; 32]> {
+    zap_request_sender(&zap_request(receipt)?)
+}
+
+fn zap_request_sender(request: &serde_json::Value) -> Option<[u8; 32]> {
+    hex::decode(request["pubkey"].as_str()?)
+        .ok()?
+        .try_into()
+        .ok()
+}
+
+/// How many millisats were zapped, from the invoice or failing that the
+/// zap request's `amount` tag
+fn zap_amount(receipt: &Note) -> Option<u64> {
+    note_tag_value(receipt, "bolt11")
+        .and_then(bolt11_msats)
+        .or_else(|| zap_request_amount(&zap_request(receipt)?))
+}
+
+fn zap_request_amount(request: &serde_json::Value) -> Option<u64> {
+    request["tags"]
+        .as_array()?
+        .iter()
+        .filter_map(|tag| tag.as_array())
+        .find(|tag| tag.first().and_then(|name| name.as_str()) == Some("amount"))?
+        .get(1)?
+        .as_str()?
+        .parse()
+        .ok()
+}
+
+/// The amount in a bolt11 invoice's human readable part, eg: lnbc10u is
+/// 10 micro-bitcoin. Invoices without an amount have none.
+fn bolt11_msats(invoice: &str) -> Option<u64> {
+    let invoice = invoice.to_ascii_lowercase();
+    let hrp = &invoice[..invoice.rfind('1')?];
+    let amount = ["lnbcrt", "lnbc", "lntbs", "lntb", "lnsb"]
+        .iter()
+        .find_map(|prefix| hrp.strip_prefix(prefix))?;
+
+    let (digits, multiplier) = match amount.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&amount[..i], Some(c)),
+        _ => (amount, None),
+    };
+    let value: u64 = digits.parse().ok()?;
+
+    // msats per unit of the multiplier
+    let (mul, div) = match multiplier {
+        None => (100_000_000_000, 1),
+        Some('m') => (100_000_000, 1),
+        Some('u') => (100_000, 1),
+        Some('n') => (100, 1),
+        Some('p') => (1, 10),
+        Some(_) => return None,
+    };
+    value.checked_mul(mul).map(|msats| msats / div)
+}
+
+fn thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn convex(painter: &Painter, points: Vec<Pos2>, color: Color32) {
+    painter.add(Shape::convex_polygon(points, color, Stroke::NONE));
+}
+
+pub fn kind_card_ui(
+    app: &Notecrumbs,
+    ctx: &egui::Context,
+    rd: &NoteAndProfileRenderData,
+    media: &CardMedia,
+    card: KindCard,
+    theme: &Theme,
+) -> Result<()> {
+    let txn = Transaction::new(&app.ndb)?;
+    let note = rd.note_rd.lookup(&txn, &app.ndb)?;
+    let profile_record = rd
+        .profile_rd
+        .as_ref()
+        .and_then(|profile_rd| profile_rd.lookup(&txn, &app.ndb).ok());
+
+    let pfp = pfp_texture(app, ctx, media);
+    let label = card.label(&note);
+
+    simple_card_ui(app, ctx, theme, |ui| {
+        ui.horizontal(|ui| {
+            let (rect, _) = ui.allocate_exact_size(Vec2::splat(220.0), Sense::hover());
+            card.paint_icon(ui.painter(), rect);
+
+            ui.add_space(40.0);
+            ui.vertical(|ui| {
+                ui.add_space(60.0);
+                ui.label(RichText::new(&label).size(60.0).color(theme.text));
+                ui.add_space(30.0);
+                ui.horizontal(|ui| {
+                    ui.image(&pfp);
+                    render_username(ui, profile_record.as_ref(), theme);
+                });
+            });
+        });
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The zap receipt from NIP-57
+    const RECEIPT: &str = r#"{
+        "id": "67b48a14fb66c60c8f9070bdeb37afdfcc3d08ad01989460448e4081eddda446",
+        "pubkey": "9630f464cca6a5147aa8a35f0bcdd3ce485324e732fd39e09233b1d848238f31",
+        "created_at": 1674164545,
+        "kind": 9735,
+        "tags": [
+            ["p", "32e1827635450ebb3c5a7d12c1f8e7b2b514439ac10a67eef3d9fd9c5c68e245"],
+            ["P", "97c70a44366a6535c145b333f973ea86dfdc2d7a99da618c40c64705ad98e322"],
+            ["e", "3624762a1274dd9636e0c552b53086d70bc88c165bc4dc0f9e836a1eaf86c3b8"],
+            ["bolt11", "lnbc10u1p3unwfusp5t9r3yymhpfqculx78u027lxspgxcr2n2987mx2j55nnfs95nxnzqpp5jmrh92pfld78spqs78v9euf2385t83uvpwk9ldrlvf6ch7tpascqhp5zvkrmemgth3tufcvflmzjzfvjt023nazlhljz2n9hattj4f8jq8qxqyjw5qcqpjrzjqtc4fc44feggv7065fqe5m4ytjarg3repr5j9el35xhmtfexc42yczarjuqqfzqqqqqqqqlgqqqqqqgq9q9qxpqysgq079nkq507a5tw7xgttmj4u990j7wfggtrasah5gd4ywfr2pjcn29383tphp4t48gquelz9z78p4cq7ml3nrrphw5w6eckhjwmhezhnqpy6gyf0"],
+            ["description", "{\"pubkey\":\"97c70a44366a6535c145b333f973ea86dfdc2d7a99da618c40c64705ad98e322\",\"content\":\"\",\"id\":\"d9cc14d50fcb8c27539aacf776882942c1a11ea4472f8cdec1dea82fab66279d\",\"created_at\":1674164539,\"sig\":\"77127f636577e9029276be060332ea565deaf89ff215a494ccff16ae3f757065e2bc59b2e8c113dd407917a010b3abd36c8d7ad84c0e3ab7dab3a0b0caa9835d\",\"kind\":9734,\"tags\":[[\"e\",\"3624762a1274dd9636e0c552b53086d70bc88c165bc4dc0f9e836a1eaf86c3b8\"],[\"p\",\"32e1827635450ebb3c5a7d12c1f8e7b2b514439ac10a67eef3d9fd9c5c68e245\"],[\"relays\",\"wss://relayer.fiatjaf.com\",\"wss://nostr.satstacker.cloud\"]]}"],
+            ["preimage", "5d006d2cf1e73c7148e7519a4c68adc81642ce0e25a432b2434c99f97344c15f"]
+        ],
+        "content": ""
+    }"#;
+
+    fn receipt_tag(name: &str) -> String {
+        let receipt: serde_json::Value = serde_json::from_str(RECEIPT).unwrap();
+        receipt["tags"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|tag| tag[0] == name)
+            .map(|tag| tag[1].as_str().unwrap().to_owned())
+            .unwrap()
+    }
+
+    #[test]
+    fn zap_receipts() {
+        let request = parse_zap_request(&receipt_tag("description")).unwrap();
+
+        // the sender signed the request, not the receipt
+        let sender = zap_request_sender(&request).unwrap();
+        assert_eq!(
+            hex::encode(sender),
+            "97c70a44366a6535c145b333f973ea86dfdc2d7a99da618c40c64705ad98e322"
+        );
+
+        assert_eq!(bolt11_msats(&receipt_tag("bolt11")), Some(1_000_000));
+        // this request didn't say how much
+        assert_eq!(zap_request_amount(&request), None);
+
+        // receipts only carry zap requests
+        assert!(parse_zap_request(r#"{"kind":1,"pubkey":"00"}"#).is_none());
+        assert!(parse_zap_request("not json").is_none());
+    }
+
+    #[test]
+    fn zap_amounts() {
+        let request = parse_zap_request(r#"{"kind":9734,"tags":[["amount","21000"]]}"#).unwrap();
+        assert_eq!(zap_request_amount(&request), Some(21_000));
+
+        assert_eq!(bolt11_msats("lnbc2500u1pvjluez"), Some(250_000_000));
+        assert_eq!(bolt11_msats("LNBC1M1PVJLUEZ"), Some(100_000_000));
+        assert_eq!(bolt11_msats("lnbc10n1pvjluez"), Some(1_000));
+        assert_eq!(bolt11_msats("lnbc15p1pvjluez"), Some(1));
+        assert_eq!(bolt11_msats("lntb11pvjluez"), Some(100_000_000_000));
+        // no amount, or not an invoice
+        assert_eq!(bolt11_msats("lnbc1pvjluez"), None);
+        assert_eq!(bolt11_msats("hello"), None);
+
+        assert_eq!(thousands(0), "0");
+        assert_eq!(thousands(999), "999");
+        assert_eq!(thousands(1000), "1,000");
+        assert_eq!(thousands(1234567), "1,234,567");
+    }
+}
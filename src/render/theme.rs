@@ -0,0 +1,112 @@
+use crate::gradient::Gradient;
+use egui::{Color32, ColorImage, Visuals};
+
+/// Card color schemes, picked with `?theme=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// What `?theme=` calls it
+    pub name: &'static str,
+    /// Left to right colors of the background gradient
+    pub gradient: [Color32; 3],
+    /// Behind the gradient, only visible while it loads
+    pub backdrop: Color32,
+    /// The rounded frame the note sits in
+    pub card: Color32,
+    pub text: Color32,
+    /// Usernames and subtitles
+    pub muted: Color32,
+    /// Mentions, links and hashtags
+    pub accent: Color32,
+    pub button: Color32,
+    pub button_text: Color32,
+    pub dark: bool,
+}
+
+pub const DARK: Theme = Theme {
+    name: "dark",
+    gradient: [
+        Color32::from_rgb(0x1C, 0x55, 0xFF),
+        Color32::from_rgb(0x7F, 0x35, 0xAB),
+        Color32::from_rgb(0xC0, 0x2A, 0xBE),
+    ],
+    backdrop: Color32::from_rgb(0x00, 0x00, 0x00),
+    card: Color32::from_rgb(0x0F, 0x0F, 0x0F),
+    text: Color32::WHITE,
+    muted: Color32::LIGHT_GRAY,
+    accent: Color32::from_rgb(0xcc, 0x43, 0xc5),
+    button: Color32::WHITE,
+    button_text: Color32::BLACK,
+    dark: true,
+};
+
+pub const LIGHT: Theme = Theme {
+    name: "light",
+    gradient: [
+        Color32::from_rgb(0xB8, 0xCC, 0xFF),
+        Color32::from_rgb(0xD9, 0xC2, 0xF0),
+        Color32::from_rgb(0xF5, 0xC6, 0xEC),
+    ],
+    backdrop: Color32::from_rgb(0xFF, 0xFF, 0xFF),
+    card: Color32::from_rgb(0xFA, 0xFA, 0xFA),
+    text: Color32::from_rgb(0x11, 0x11, 0x11),
+    muted: Color32::from_rgb(0x66, 0x66, 0x66),
+    accent: Color32::from_rgb(0x9B, 0x2C, 0x95),
+    button: Color32::from_rgb(0x11, 0x11, 0x11),
+    button_text: Color32::WHITE,
+    dark: false,
+};
+
+pub const PURPLE: Theme = Theme {
+    name: "purple",
+    gradient: [
+        Color32::from_rgb(0x3A, 0x0C, 0x5C),
+        Color32::from_rgb(0x7F, 0x35, 0xAB),
+        Color32::from_rgb(0xCC, 0x43, 0xC5),
+    ],
+    backdrop: Color32::from_rgb(0x1A, 0x05, 0x2B),
+    card: Color32::from_rgb(0x26, 0x0B, 0x3D),
+    text: Color32::WHITE,
+    muted: Color32::from_rgb(0xD6, 0xC3, 0xEE),
+    accent: Color32::from_rgb(0xFF, 0x9C, 0xF4),
+    button: Color32::from_rgb(0xCC, 0x43, 0xC5),
+    button_text: Color32::WHITE,
+    dark: true,
+};
+
+impl Default for Theme {
+    fn default() -> Self {
+        DARK
+    }
+}
+
+impl Theme {
+    pub fn from_name(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(DARK),
+            "light" => Some(LIGHT),
+            "purple" => Some(PURPLE),
+            _ => None,
+        }
+    }
+
+    pub fn visuals(&self) -> Visuals {
+        let mut visuals = if self.dark {
+            Visuals::dark()
+        } else {
+            Visuals::light()
+        };
+        visuals.override_text_color = Some(self.text);
+        visuals
+    }
+
+    /// A one pixel high row of the background gradient, stretched across
+    /// the card when drawn
+    pub fn background(&self) -> ColorImage {
+        let pixels = Gradient::linear_many(self.gradient.to_vec()).to_pixel_row();
+
+        ColorImage {
+            size: [pixels.len(), 1],
+            pixels,
+        }
+    }
+}
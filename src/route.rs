@@ -0,0 +1,468 @@
+use crate::{
+    nip05,
+    nip19::{decode_nrelay, nip19_type, strip_nostr_scheme},
+    nsec,
+    render::CardFormat,
+};
+use nostr_sdk::prelude::{FromBech32, Nip19};
+
+/// What a request path asks us to render a nip19 entity as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathFormat {
+    Html,
+    /// A share card image
+    Card(CardFormat),
+    Json,
+}
+
+impl PathFormat {
+    /// The path extension that asks for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PathFormat::Html => "",
+            PathFormat::Card(format) => format.extension(),
+            PathFormat::Json => ".json",
+        }
+    }
+}
+
+/// The pages under a profile, `/{npub}/...`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfilePage {
+    /// `media.png`, a grid of their recent images
+    Media,
+    /// `relays`, where they read and write
+    Relays,
+    /// `rss`, an Atom feed of their notes
+    Atom,
+    /// `articles.xml`, an RSS feed of their longform articles
+    ArticlesRss,
+}
+
+impl ProfilePage {
+    fn from_name(name: &str) -> Option<ProfilePage> {
+        match name {
+            "media.png" => Some(ProfilePage::Media),
+            "relays" => Some(ProfilePage::Relays),
+            "rss" => Some(ProfilePage::Atom),
+            "articles.xml" => Some(ProfilePage::ArticlesRss),
+            _ => None,
+        }
+    }
+}
+
+/// Where a request goes, worked out from its path alone
+#[derive(Debug, Clone, PartialEq)]
+pub enum Route {
+    Homepage,
+    Liveness,
+    Readiness,
+    Search,
+    NewsSitemap,
+    /// `/oembed?url=`
+    Oembed,
+    /// `/admin/{page}`
+    Admin(String),
+    /// `/relay/{url}` or an nrelay
+    Relay(String),
+    /// `/{npub}.jsonfeed`
+    ProfileJsonFeed(Nip19),
+    ProfilePage(Nip19, ProfilePage),
+    /// `/{nevent}/embed`, the video player for a video note
+    Embed(Nip19),
+    /// A raw hex id, which could be a note or a pubkey
+    HexId([u8; 32], PathFormat),
+    /// A NIP-05 address, `name@domain`
+    Nip05 {
+        name: String,
+        domain: String,
+        format: PathFormat,
+    },
+    Entity(Nip19, PathFormat),
+    /// Someone's private key, anywhere in the path
+    Nsec,
+    NotFound,
+}
+
+impl Route {
+    /// The type of the nip19 entity the route is for, for logs and
+    /// metrics. "none" for everything else.
+    pub fn nip19_type(&self) -> &'static str {
+        match self {
+            Route::ProfileJsonFeed(nip19)
+            | Route::ProfilePage(nip19, _)
+            | Route::Embed(nip19)
+            | Route::Entity(nip19, _) => nip19_type(nip19),
+            Route::Nsec => "secret",
+            _ => "none",
+        }
+    }
+
+    /// Whether the response is in the language the request asks for,
+    /// see `i18n::request_locale`
+    pub fn is_localized(&self) -> bool {
+        match self {
+            Route::Homepage | Route::Search => true,
+            Route::Nip05 { format, .. } | Route::Entity(_, format) => {
+                matches!(format, PathFormat::Html | PathFormat::Card(_))
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the response depends on the device asking: note pages hand
+    /// the note off to a phone with a QR code on desktops, see
+    /// `html::is_desktop`
+    pub fn is_device_specific(&self) -> bool {
+        match self {
+            Route::Entity(nip19, PathFormat::Html) => {
+                !matches!(nip19, Nip19::Pubkey(_) | Nip19::Profile(_))
+            }
+            Route::HexId(_, PathFormat::Html) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Route a request path. Links pick things up on their way around, so
+/// this tolerates trailing and doubled slashes, the `nostr:` scheme, upper
+/// case bech32 (from QR codes) and upper case extensions.
+pub fn route(path: &str) -> Route {
+    if nsec::contains_nsec(path) {
+        return Route::Nsec;
+    }
+
+    let path = path.trim_start_matches('/');
+
+    // relay urls have slashes of their own
+    if let Some(relay) = path.strip_prefix("relay/") {
+        let relay = relay.trim_end_matches('/');
+        return if relay.is_empty() {
+            Route::NotFound
+        } else {
+            Route::Relay(relay.to_string())
+        };
+    }
+
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match segments.as_slice() {
+        [] => Route::Homepage,
+        ["healthz"] => Route::Liveness,
+        ["readyz"] => Route::Readiness,
+        ["search"] => Route::Search,
+        ["sitemap-news.xml"] => Route::NewsSitemap,
+        ["oembed"] => Route::Oembed,
+        ["admin", page] => Route::Admin(page.to_string()),
+        [entity, "embed"] => match parse_entity(entity) {
+            Some(nip19) => Route::Embed(nip19),
+            None => Route::NotFound,
+        },
+        [entity, page] => match (parse_entity(entity), ProfilePage::from_name(page)) {
+            (Some(nip19), Some(page)) => Route::ProfilePage(nip19, page),
+            _ => Route::NotFound,
+        },
+        [entity] => route_entity(entity),
+        _ => Route::NotFound,
+    }
+}
+
+fn route_entity(segment: &str) -> Route {
+    if let Some(profile) = strip_suffix_ignore_case(segment, ".jsonfeed") {
+        return match parse_entity(profile) {
+            Some(nip19) => Route::ProfileJsonFeed(nip19),
+            None => Route::NotFound,
+        };
+    }
+
+    let (entity, format) = split_format(segment);
+    let entity = strip_nostr_scheme(entity);
+
+    if let Some(relay) = decode_nrelay(&entity.to_ascii_lowercase()) {
+        return Route::Relay(relay);
+    }
+
+    if entity.len() == 64 {
+        if let Some(id) = hex::decode(entity).ok().and_then(|id| id.try_into().ok()) {
+            return Route::HexId(id, format);
+        }
+    }
+
+    if let Some((name, domain)) = nip05::parse_address(entity) {
+        return Route::Nip05 {
+            name,
+            domain,
+            format,
+        };
+    }
+
+    match parse_entity(entity) {
+        Some(nip19) => Route::Entity(nip19, format),
+        None => Route::NotFound,
+    }
+}
+
+/// Split a path segment like `nevent1...png` into the entity and the
+/// format it should be rendered as
+fn split_format(segment: &str) -> (&str, PathFormat) {
+    let formats = [
+        (".png", PathFormat::Card(CardFormat::Png)),
+        (".jpg", PathFormat::Card(CardFormat::Jpeg)),
+        (".jpeg", PathFormat::Card(CardFormat::Jpeg)),
+        (".webp", PathFormat::Card(CardFormat::Webp)),
+        (".json", PathFormat::Json),
+    ];
+
+    formats
+        .into_iter()
+        .find_map(|(extension, format)| {
+            strip_suffix_ignore_case(segment, extension).map(|entity| (entity, format))
+        })
+        .unwrap_or((segment, PathFormat::Html))
+}
+
+fn strip_suffix_ignore_case<'a>(s: &'a str, suffix: &str) -> Option<&'a str> {
+    let split = s.len().checked_sub(suffix.len())?;
+    let (head, tail) = (s.get(..split)?, s.get(split..)?);
+    tail.eq_ignore_ascii_case(suffix).then_some(head)
+}
+
+/// A nip19 entity, with or without the `nostr:` scheme. bech32 is either
+/// all lower or all upper case, never mixed.
+fn parse_entity(entity: &str) -> Option<Nip19> {
+    let entity = strip_nostr_scheme(entity);
+    if entity.bytes().any(|b| b.is_ascii_lowercase()) {
+        Nip19::from_bech32(entity).ok()
+    } else {
+        Nip19::from_bech32(&entity.to_ascii_lowercase()).ok()
+    }
+}
+
+/// The query parameters we act on. Values that don't parse count as
+/// missing, and the first of repeated parameters wins.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params {
+    /// `q`, the search query, still form encoded
+    pub q: Option<String>,
+    /// `debug=1`, the fetch timeline instead of the page
+    pub debug: bool,
+    /// `w`, `h` and `scale`, the card size
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub scale: Option<f32>,
+    /// `theme`, the card theme name
+    pub theme: Option<String>,
+    /// `qr=1`, put a qr code on the card
+    pub qr: bool,
+    /// `url` and `format` for oEmbed, the url still form encoded
+    pub url: Option<String>,
+    pub format: Option<String>,
+}
+
+impl Params {
+    pub fn parse(query: Option<&str>) -> Params {
+        let param = |key| query_param(query, key);
+
+        Params {
+            q: param("q").map(str::to_string),
+            debug: param("debug") == Some("1"),
+            width: param("w").and_then(|w| w.parse().ok()),
+            height: param("h").and_then(|h| h.parse().ok()),
+            scale: param("scale").and_then(|scale| scale.parse().ok()),
+            theme: param("theme").map(str::to_string),
+            qr: param("qr") == Some("1"),
+            url: param("url").map(str::to_string),
+            format: param("format").map(str::to_string),
+        }
+    }
+}
+
+/// The value of a query parameter, eg: `debug=1`
+fn query_param<'a>(query: Option<&'a str>, key: &str) -> Option<&'a str> {
+    query?.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        (k == key).then_some(v)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NPUB: &str = "npub1xtscya34g58tk0z605fvr788k263gsu6cy9x0mhnm87echrgufzsevkk5s";
+    const NOTE: &str = "note1qqqqqx7tez3j2mnvktj7j3tn6rv05hpuxklge9hyrg0z3snmzraq4y4yml";
+
+    fn entity(route: Route) -> (Nip19, PathFormat) {
+        match route {
+            Route::Entity(nip19, format) => (nip19, format),
+            other => panic!("expected an entity, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn plain_entities() {
+        let (nip19, format) = entity(route(&format!("/{NPUB}")));
+        assert!(matches!(nip19, Nip19::Pubkey(_)));
+        assert_eq!(format, PathFormat::Html);
+
+        let (nip19, format) = entity(route(&format!("/{NOTE}.png")));
+        assert!(matches!(nip19, Nip19::EventId(_)));
+        assert_eq!(format, PathFormat::Card(CardFormat::Png));
+
+        let (_, format) = entity(route(&format!("/{NOTE}.json")));
+        assert_eq!(format, PathFormat::Json);
+
+        let (_, format) = entity(route(&format!("/{NOTE}.jpeg")));
+        assert_eq!(format, PathFormat::Card(CardFormat::Jpeg));
+    }
+
+    #[test]
+    fn trailing_and_doubled_slashes() {
+        assert_eq!(route(&format!("/{NPUB}/")), route(&format!("/{NPUB}")));
+        assert_eq!(route(&format!("//{NPUB}")), route(&format!("/{NPUB}")));
+        assert_eq!(
+            route(&format!("/{NPUB}/rss/")),
+            route(&format!("/{NPUB}/rss"))
+        );
+        assert_eq!(route("/healthz/"), Route::Liveness);
+    }
+
+    #[test]
+    fn upper_case() {
+        let upper = NPUB.to_ascii_uppercase();
+        assert_eq!(route(&format!("/{upper}")), route(&format!("/{NPUB}")));
+        assert_eq!(
+            route(&format!("/{NOTE}.PNG")),
+            route(&format!("/{NOTE}.png"))
+        );
+
+        // bech32 doesn't mix case
+        let mixed = format!("nPub{}", &NPUB[4..]);
+        assert_eq!(route(&format!("/{mixed}")), Route::NotFound);
+    }
+
+    #[test]
+    fn nostr_scheme() {
+        let expected = route(&format!("/{NPUB}"));
+        assert_eq!(route(&format!("/nostr:{NPUB}")), expected);
+        assert_eq!(route(&format!("/nostr%3A{NPUB}")), expected);
+        assert_eq!(route(&format!("/NOSTR:{NPUB}")), expected);
+    }
+
+    #[test]
+    fn profile_pages() {
+        for (page, expected) in [
+            ("media.png", ProfilePage::Media),
+            ("relays", ProfilePage::Relays),
+            ("rss", ProfilePage::Atom),
+            ("articles.xml", ProfilePage::ArticlesRss),
+        ] {
+            match route(&format!("/{NPUB}/{page}")) {
+                Route::ProfilePage(Nip19::Pubkey(_), found) => assert_eq!(found, expected),
+                other => panic!("{page}: {other:?}"),
+            }
+        }
+
+        assert!(matches!(
+            route(&format!("/{NPUB}.jsonfeed")),
+            Route::ProfileJsonFeed(_)
+        ));
+        assert_eq!(route(&format!("/{NPUB}/nope")), Route::NotFound);
+        assert_eq!(route(&format!("/{NPUB}/rss/extra")), Route::NotFound);
+    }
+
+    #[test]
+    fn other_routes() {
+        assert_eq!(route("/"), Route::Homepage);
+        assert_eq!(route(""), Route::Homepage);
+        assert_eq!(route("/readyz"), Route::Readiness);
+        assert_eq!(route("/search"), Route::Search);
+        assert_eq!(route("/sitemap-news.xml"), Route::NewsSitemap);
+        assert_eq!(route("/oembed"), Route::Oembed);
+        assert!(matches!(
+            route(&format!("/{NOTE}/embed")),
+            Route::Embed(Nip19::EventId(_))
+        ));
+        assert_eq!(route("/admin/db"), Route::Admin("db".to_string()));
+        assert_eq!(
+            route("/relay/wss://relay.damus.io/"),
+            Route::Relay("wss://relay.damus.io".to_string())
+        );
+        assert_eq!(route("/relay/"), Route::NotFound);
+
+        let hex = "ab".repeat(32);
+        assert_eq!(
+            route(&format!("/{hex}.png")),
+            Route::HexId([0xab; 32], PathFormat::Card(CardFormat::Png))
+        );
+
+        assert_eq!(
+            route("/jb55@jb55.com"),
+            Route::Nip05 {
+                name: "jb55".to_string(),
+                domain: "jb55.com".to_string(),
+                format: PathFormat::Html,
+            }
+        );
+    }
+
+    #[test]
+    fn device_specific() {
+        assert!(route(&format!("/{NOTE}")).is_device_specific());
+        assert!(!route(&format!("/{NOTE}.png")).is_device_specific());
+        assert!(!route(&format!("/{NOTE}.json")).is_device_specific());
+        assert!(!route(&format!("/{NPUB}")).is_device_specific());
+        assert!(!route("/").is_device_specific());
+    }
+
+    #[test]
+    fn malformed() {
+        for path in [
+            "/npub1",
+            "/npub1notbech32",
+            "/note1qqqq",
+            "/favicon.ico",
+            "/.png",
+            "/nostr:",
+            "/ab",
+            &format!("/{}", "zz".repeat(32)),
+            &format!("/{NOTE}.gif"),
+            &format!("/{NOTE}\u{e9}"),
+            "/\u{1f4a9}.png",
+            "/a/b/c",
+        ] {
+            assert_eq!(route(path), Route::NotFound, "{path}");
+        }
+    }
+
+    #[test]
+    fn nsecs() {
+        let nsec = "nsec1vl029mgpspedva04g90vltkh6fvh240zqtv9k0t9af8935ke9laqsnlfe5";
+        for path in [
+            format!("/{nsec}"),
+            format!("/nostr:{nsec}.png"),
+            format!("/{}", nsec.to_ascii_uppercase()),
+            format!("/{NPUB}/{nsec}"),
+            "/nsec1notbech32".to_string(),
+        ] {
+            assert_eq!(route(&path), Route::Nsec, "{path}");
+        }
+    }
+
+    #[test]
+    fn params() {
+        let params = Params::parse(Some(
+            "w=800&h=abc&scale=1.5&theme=dark&qr=1&qr=0&q=gm+nostr",
+        ));
+        assert_eq!(params.width, Some(800));
+        assert_eq!(params.height, None);
+        assert_eq!(params.scale, Some(1.5));
+        assert_eq!(params.theme.as_deref(), Some("dark"));
+        assert!(params.qr);
+        assert!(!params.debug);
+        assert_eq!(params.q.as_deref(), Some("gm+nostr"));
+
+        assert_eq!(Params::parse(None), Params::default());
+        assert_eq!(Params::parse(Some("")), Params::default());
+        assert_eq!(Params::parse(Some("&&=&debug")), Params::default());
+    }
+}
@@ -0,0 +1,967 @@
+use crate::{
+    admin,
+    budget::wait_until,
+    config::OgImagePolicy,
+    debug, embed,
+    error::Error,
+    feed, health, homepage, html, http_cache,
+    i18n::{Locale, Text},
+    meta::{self, choose_og_image, Alternate, OgImage, OgMeta, Schema},
+    metrics, nip19, nsec, oembed, preview_prefs, profile_relays, relay_page,
+    render::{self, MissingCard, NoteRenderData, ProfileRenderData, RenderData},
+    route::{self, PathFormat, ProfilePage, Route},
+    search, sitemap, unresolved, Notecrumbs,
+};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header;
+use hyper::{Request, Response, StatusCode};
+use nostr_sdk::async_utility::futures_util::future::join_all;
+use nostr_sdk::prelude::*;
+use nostrdb::Transaction;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use tracing::{debug, error, info, warn, Instrument};
+
+/// How long an html request waits for link previews before rendering
+/// without them
+const LINK_PREVIEW_WAIT: Duration = Duration::from_millis(1500);
+
+/// How long a png request waits for remote images before rendering
+/// without them
+const MEDIA_WAIT: Duration = Duration::from_millis(1500);
+
+/// How long a request queues for a relay lookup or render slot before we
+/// turn it away
+const SLOT_WAIT: Duration = Duration::from_millis(500);
+
+/// Wait for a slot to do something expensive in, `None` when we're too
+/// busy
+async fn take_slot(slots: &Arc<Semaphore>) -> Option<OwnedSemaphorePermit> {
+    tokio::time::timeout(SLOT_WAIT, slots.clone().acquire_owned())
+        .await
+        .ok()?
+        .ok()
+}
+
+/// What we answer when we're too busy, cheap enough to send under any
+/// load
+fn overloaded() -> Result<Response<Full<Bytes>>, Error> {
+    Ok(Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(header::RETRY_AFTER, "5")
+        .body(Full::new(Bytes::from("Too busy, try again shortly\n")))?)
+}
+
+fn serve_profile_html(
+    app: &Notecrumbs,
+    nip: &Nip19,
+    profile_rd: Option<&ProfileRenderData>,
+    unavailable: bool,
+    r: Request<hyper::body::Incoming>,
+) -> Result<Response<Full<Bytes>>, Error> {
+    let mut data = Vec::new();
+
+    let profile_key = match profile_rd {
+        None | Some(ProfileRenderData::Missing(_)) => {
+            return html::not_found_page("Profile", unavailable);
+        }
+
+        Some(ProfileRenderData::Profile(profile_key)) => *profile_key,
+    };
+
+    let txn = Transaction::new(&app.ndb)?;
+
+    let profile_rec = if let Ok(profile_rec) = app.ndb.get_profile_by_key(&txn, profile_key) {
+        profile_rec
+    } else {
+        return html::not_found_page("Profile", unavailable);
+    };
+
+    let hostname = &app.config.request_base_url(r.headers());
+    let locale = html::request_locale(app, &r);
+    let bech32 = nip.to_bech32()?;
+    let profile = profile_rec.record().profile();
+    let name = profile.and_then(|p| p.name()).unwrap_or("nostrich");
+    let about = profile.and_then(|p| p.about()).unwrap_or("");
+
+    let pubkey = nip19::nip19_pubkey(nip);
+    let prefs = pubkey
+        .and_then(|pk| preview_prefs::preview_prefs(&app.ndb, &txn, &pk))
+        .unwrap_or_default();
+
+    // nprofiles with different relay hints are all the same profile
+    let url = format!(
+        "{hostname}/{}",
+        pubkey
+            .and_then(|pk| PublicKey::from_slice(&pk).ok())
+            .and_then(|pk| pk.to_bech32().ok())
+            .unwrap_or_else(|| bech32.clone())
+    );
+
+    // profiles don't have content images, the banner is the closest
+    // thing. auto only picks it when explicitly asking for media. an
+    // image the author picked themselves beats both.
+    let og_meta = OgMeta {
+        title: format!("{name} on nostr"),
+        description: meta::og_description(about),
+        // profiles don't have a .json, their feed is the closest thing
+        alternate: Some(Alternate {
+            mime_type: "application/feed+json",
+            url: format!("{hostname}/{bech32}.jsonfeed"),
+        }),
+        oembed: Some(oembed::oembed_url(hostname, &url)),
+        url,
+        image: match &prefs.image {
+            Some(image) => OgImage::Media(image.clone()),
+            None => choose_og_image(
+                if app.config.og_image == OgImagePolicy::Auto {
+                    OgImagePolicy::Generated
+                } else {
+                    app.config.og_image
+                },
+                format!("{hostname}/{bech32}.png"),
+                profile.and_then(|p| p.banner()),
+                None,
+                profile.and_then(|p| p.picture()),
+            ),
+        },
+        og_type: "profile",
+        video: None,
+        schema: Schema::Person {
+            name: name.to_owned(),
+        },
+    };
+
+    let _ = write!(
+        data,
+        r#"
+        <html lang="{1}">
+        <head>
+          <title>{0} on nostr</title>
+          <meta name="viewport" content="width=device-width, initial-scale=1">
+          <meta charset="UTF-8">
+"#,
+        html_escape::encode_text(name),
+        locale.html_lang(),
+    );
+
+    og_meta.write_tags(&mut data)?;
+
+    let _ = write!(
+        data,
+        r#"
+        </head>
+        <body{}>
+"#,
+        prefs.body_style(),
+    );
+
+    if let Some(banner) = profile.and_then(|p| p.banner()) {
+        let _ = write!(
+            data,
+            r#"          <img class="profile-banner" src="{}" />
+"#,
+            html_escape::encode_double_quoted_attribute(banner),
+        );
+    }
+
+    let _ = write!(
+        data,
+        r#"          <h1>{0}</h1>
+          <a href="/{1}/relays" class="muted-link">{2}</a>
+"#,
+        html_escape::encode_text(name),
+        bech32,
+        locale.text(Text::Relays),
+    );
+
+    if let Some(pinned) = prefs
+        .pinned
+        .and_then(|id| app.ndb.get_note_by_id(&txn, &id).ok())
+    {
+        let _ = write!(data, r#"<div class="pinned-note">"#);
+        html::write_feed_note(&mut data, &pinned, Some(name), locale)?;
+        let _ = write!(data, "</div>");
+    }
+
+    if let Some(pubkey) = pubkey {
+        let cached = html::render_profile_feed(&mut data, &app.ndb, &txn, &pubkey, locale)?;
+
+        // a thin feed is probably just what we happened to see, go get
+        // more for next time without holding up this response
+        if cached < html::PROFILE_FEED_RECENT_LIMIT as usize {
+            app.backfill.schedule(pubkey);
+        }
+    }
+
+    let _ = write!(
+        data,
+        r#"
+        </body>
+        </html>
+"#
+    );
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/html")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(data)))?)
+}
+
+/// What we were missing before going to the relays
+struct MissingIds {
+    note: Option<[u8; 32]>,
+    profile: Option<[u8; 32]>,
+}
+
+impl MissingIds {
+    fn from_render_data(render_data: &RenderData) -> Self {
+        MissingIds {
+            note: match render_data.note_render_data() {
+                Some(NoteRenderData::Missing(id)) => Some(*id),
+                _ => None,
+            },
+            profile: match render_data.profile_render_data() {
+                Some(ProfileRenderData::Missing(pk)) => Some(*pk),
+                _ => None,
+            },
+        }
+    }
+
+    /// Whether everything we're missing was just looked for and not
+    /// found, so asking again would only make the visitor wait
+    async fn recently_not_found(&self, app: &Notecrumbs) -> bool {
+        let missing: Vec<&[u8; 32]> = [&self.note, &self.profile].into_iter().flatten().collect();
+        if missing.is_empty() {
+            return false;
+        }
+        for id in missing {
+            if !app.not_found.contains(id).await {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Remember what we still couldn't find after asking the relays.
+    /// Relays that timed out may still have it, that's not a not found.
+    async fn track(
+        &self,
+        app: &Notecrumbs,
+        render_data: &RenderData,
+        nip19: &Nip19,
+        timed_out: bool,
+    ) {
+        let mut relays = app.relays.relays();
+        relays.extend(nip19::nip19_relays(nip19).iter().map(|r| r.to_string()));
+
+        if let Some(id) = &self.note {
+            if render_data.needs_note() {
+                app.unresolved
+                    .record_failure(unresolved::EntityKind::Note, id, &relays);
+                if !timed_out {
+                    app.not_found.insert(id).await;
+                }
+            } else {
+                app.unresolved.record_success(id);
+            }
+        }
+
+        if let Some(pk) = &self.profile {
+            if render_data.needs_profile() {
+                app.unresolved
+                    .record_failure(unresolved::EntityKind::Profile, pk, &relays);
+                if !timed_out {
+                    app.not_found.insert(pk).await;
+                }
+            } else {
+                app.unresolved.record_success(pk);
+            }
+        }
+    }
+}
+
+/// Look up whatever a page is still missing without making the visitor
+/// wait on it, the next request gets the complete page. Skipped when
+/// we're already running as many lookups as we want to.
+fn complete_in_background(app: &Notecrumbs, nip19: Nip19) {
+    let slot = if let Ok(slot) = app.lookup_slots.clone().try_acquire_owned() {
+        slot
+    } else {
+        return;
+    };
+
+    let app = app.clone();
+    tokio::spawn(async move {
+        let _slot = slot;
+        if let Err(err) = fetch_missing(&app, &nip19).await {
+            debug!("background lookup failed: {err}");
+        }
+    });
+}
+
+/// Ask the relays for whatever we don't have yet to render `nip19`
+async fn fetch_missing(app: &Notecrumbs, nip19: &Nip19) -> Result<(), Error> {
+    let mut render_data = {
+        let txn = Transaction::new(&app.ndb)?;
+        render::get_render_data(&app.ndb, &txn, nip19)?
+    };
+    if render_data.is_complete() {
+        return Ok(());
+    }
+    render_data
+        .complete(app, nip19.clone(), app.config.budget.start())
+        .await
+}
+
+/// Fetch and render the configured entities once at startup, so their
+/// first visitors after a deploy don't wait on relays or the renderer.
+/// Pages depend on who's asking, only cards are rendered ahead.
+pub(crate) async fn warm_up(app: Notecrumbs) {
+    for entity in &app.config.warmup {
+        let nip19 = match Nip19::from_bech32(nip19::strip_nostr_scheme(entity)) {
+            Ok(nip19) => nip19,
+            Err(err) => {
+                warn!("can't warm up {entity}: {err}");
+                continue;
+            }
+        };
+        if let Err(err) = fetch_missing(&app, &nip19).await {
+            warn!("warming up {entity} failed: {err}");
+            continue;
+        }
+        prerender_card(&app, &nip19);
+    }
+
+    if !app.config.warmup.is_empty() {
+        info!("warmed up {} entities", app.config.warmup.len());
+    }
+}
+
+/// `/{npub}/media.png`: a collage of the author's latest images
+async fn serve_media_grid(app: &Notecrumbs, nip19: &Nip19) -> Result<Response<Full<Bytes>>, Error> {
+    let pubkey = if let Some(pubkey) = nip19::nip19_pubkey(nip19) {
+        pubkey
+    } else {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::from("Invalid url\n")))?);
+    };
+
+    let urls = {
+        let txn = Transaction::new(&app.ndb)?;
+        html::recent_media_urls(&app.ndb, &txn, &pubkey, render::MEDIA_GRID_TILES)?
+    };
+
+    // go find more for next time
+    if urls.len() < render::MEDIA_GRID_TILES {
+        app.backfill.schedule(pubkey);
+    }
+
+    let tiles: Vec<_> = join_all(urls.iter().map(|url| {
+        app.media
+            .fetch(url, render::MEDIA_GRID_TILE_SIZE, MEDIA_WAIT)
+    }))
+    .await
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let (status, data) = if tiles.is_empty() {
+        (
+            StatusCode::NOT_FOUND,
+            render::render_missing(app, MissingCard::NotFound, &Default::default()),
+        )
+    } else {
+        (StatusCode::OK, render::render_media_grid(&tiles))
+    };
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "image/png")
+        .status(status)
+        .body(Full::new(Bytes::from(data)))?)
+}
+
+/// How long we look on relays for a hex id we don't know
+const HEX_LOOKUP_WAIT: Duration = Duration::from_secs(2);
+
+/// Many tools emit raw hex ids. Work out whether it's a note id or a
+/// pubkey and send the visitor to its bech32 url.
+async fn redirect_hex(
+    app: &Notecrumbs,
+    id: [u8; 32],
+    format: PathFormat,
+) -> Result<Response<Full<Bytes>>, Error> {
+    let event_id = EventId::from_byte_array(id);
+    let pubkey = PublicKey::from_slice(&id).ok();
+
+    let (mut is_note, mut is_profile) = {
+        let txn = Transaction::new(&app.ndb)?;
+        (
+            app.ndb.get_note_by_id(&txn, &id).is_ok(),
+            app.ndb.get_profile_by_pubkey(&txn, &id).is_ok(),
+        )
+    };
+
+    if !is_note && !is_profile {
+        let mut filters = vec![Filter::new().id(event_id).limit(1)];
+        if let Some(pubkey) = pubkey {
+            filters.push(Filter::new().author(pubkey).kind(Kind::Metadata).limit(1));
+        }
+
+        let events = match render::fetch_events(
+            &app.ndb,
+            app.keys.clone(),
+            app.relays.relays(),
+            filters,
+            HEX_LOOKUP_WAIT,
+        )
+        .await
+        {
+            Err(Error::RelaysUnreachable) => {
+                app.relays.record_reachable(false);
+                return html::not_found_page("Note or profile", true);
+            }
+            fetched => fetched?,
+        };
+        app.relays.record_reachable(true);
+
+        is_note = events.iter().any(|ev| ev.id == event_id);
+        is_profile = events.iter().any(|ev| Some(ev.pubkey) == pubkey);
+    }
+
+    let bech32 = if is_note {
+        event_id.to_bech32()?
+    } else if let Some(pubkey) = pubkey.filter(|_| is_profile) {
+        pubkey.to_bech32()?
+    } else {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::from("Not found\n")))?);
+    };
+
+    Ok(Response::builder()
+        .status(StatusCode::MOVED_PERMANENTLY)
+        .header(header::LOCATION, format!("/{bech32}{}", format.extension()))
+        .body(Full::new(Bytes::new()))?)
+}
+
+/// A short id for each request, unique for the life of the process
+fn request_id() -> String {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    static STARTED: OnceLock<u64> = OnceLock::new();
+
+    let started = STARTED.get_or_init(|| Timestamp::now().as_u64());
+    let n = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    format!("{started:x}-{n:x}")
+}
+
+/// Serve a request in a tracing span carrying its id, and send the id
+/// back as `X-Request-Id` so a user's report can be matched to our logs
+pub async fn serve_with_id(
+    app: &Notecrumbs,
+    r: Request<hyper::body::Incoming>,
+) -> Result<Response<Full<Bytes>>, Error> {
+    let id = request_id();
+    // private keys stay out of the logs, even when they're the whole path
+    let path = nsec::redact_nsecs(r.uri().path()).into_owned();
+    let span = tracing::info_span!(
+        "request",
+        id = %id,
+        path = %path,
+        nip19 = tracing::field::Empty
+    );
+    let route = metrics::route_class(&path);
+    let nip19_type = route::route(&path).nip19_type();
+    let localized = route::route(&path).is_localized();
+    let device_specific = route::route(&path).is_device_specific();
+    let start = std::time::Instant::now();
+
+    let mut timeline = debug::FetchTimeline::new();
+    let result = serve(app, r, &mut timeline).instrument(span.clone()).await;
+    let elapsed = start.elapsed();
+    if elapsed > app.config.slow_request {
+        let _entered = span.enter();
+        timeline.log_slow(&path, nip19_type);
+    }
+    let status = match &result {
+        Ok(response) => response.status(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    metrics::record_request(route, status.as_u16(), nip19_type, elapsed);
+    let elapsed = elapsed.as_millis();
+
+    match result {
+        Ok(mut response) => {
+            info!(parent: &span, status = status.as_u16(), elapsed_ms = elapsed, "served");
+            if let Ok(id) = header::HeaderValue::from_str(&id) {
+                response.headers_mut().insert("x-request-id", id);
+            }
+            // caches in front of us must keep each language apart
+            if localized {
+                response.headers_mut().append(
+                    header::VARY,
+                    header::HeaderValue::from_static("accept-language"),
+                );
+            }
+            // and desktops apart from phones
+            if device_specific {
+                response
+                    .headers_mut()
+                    .append(header::VARY, header::HeaderValue::from_static("user-agent"));
+            }
+            Ok(response)
+        }
+        Err(err) => {
+            error!(parent: &span, elapsed_ms = elapsed, "failed: {err}");
+            Err(err)
+        }
+    }
+}
+
+async fn serve(
+    app: &Notecrumbs,
+    r: Request<hyper::body::Incoming>,
+    timeline: &mut debug::FetchTimeline,
+) -> Result<Response<Full<Bytes>>, Error> {
+    let deadlines = app.config.budget.start();
+    let params = route::Params::parse(r.uri().query());
+    let locale = html::request_locale(app, &r);
+    let (nip19, format) = match route::route(r.uri().path()) {
+        Route::Homepage => return homepage::serve_homepage(app, locale),
+        Route::Liveness => return health::serve_liveness(),
+        Route::Readiness => return health::serve_readiness(app),
+        Route::Search => {
+            return search::serve_search(app, params.q.as_deref().unwrap_or(""), locale).await;
+        }
+        Route::NewsSitemap => {
+            return sitemap::serve_news_sitemap(app, &app.config.request_base_url(r.headers()));
+        }
+        Route::Oembed => {
+            return oembed::serve_oembed(
+                app,
+                params.url.as_deref(),
+                params.format.as_deref(),
+                &app.config.request_base_url(r.headers()),
+            );
+        }
+        Route::Admin(page) => {
+            if !admin::is_authorized(app, &r) {
+                return admin::unauthorized();
+            }
+
+            return match page.as_str() {
+                "unresolved" => admin::serve_unresolved(app),
+                "db" => admin::serve_db(app).await,
+                "metrics" => metrics::serve_metrics(),
+                _ => admin::unauthorized(),
+            };
+        }
+        Route::Relay(relay) => return relay_page::serve_relay_page(app, &relay).await,
+        Route::Embed(nip19) => return embed::serve_embed(app, &nip19),
+        Route::ProfileJsonFeed(nip19) => {
+            return feed::serve_profile_json_feed(
+                app,
+                &nip19,
+                &app.config.request_base_url(r.headers()),
+            );
+        }
+        Route::ProfilePage(nip19, page) => {
+            let base_url = app.config.request_base_url(r.headers());
+            return match page {
+                ProfilePage::Media => serve_media_grid(app, &nip19).await,
+                ProfilePage::Relays => profile_relays::serve_profile_relays(app, &nip19).await,
+                ProfilePage::Atom => feed::serve_profile_atom(app, &nip19, &base_url),
+                ProfilePage::ArticlesRss => feed::serve_articles_rss(app, &nip19, &base_url),
+            };
+        }
+        Route::HexId(id, format) => return redirect_hex(app, id, format).await,
+        // NIP-05 addresses render the profile they point at
+        Route::Nip05 {
+            name,
+            domain,
+            format,
+        } => match app
+            .nip05
+            .resolve(&name, &domain)
+            .await
+            .and_then(|pubkey| PublicKey::from_slice(&pubkey).ok())
+        {
+            Some(pubkey) => (Nip19::Pubkey(pubkey), format),
+            None => {
+                return Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Full::new(Bytes::from("Unknown nostr address\n")))?);
+            }
+        },
+        Route::Entity(nip19, format) => (nip19, format),
+        Route::Nsec => return nsec::serve_exposed_key_page(),
+        Route::NotFound => {
+            return Ok(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Full::new(Bytes::from("Invalid url\n")))?);
+        }
+    };
+
+    tracing::Span::current().record("nip19", nip19::nip19_type(&nip19));
+
+    // render_data is always returned, it just might be empty
+    let mut render_data = {
+        let txn = Transaction::new(&app.ndb)?;
+        match render::get_render_data(&app.ndb, &txn, &nip19) {
+            Err(_err) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Full::new(Bytes::from("Invalid url\n")))?);
+            }
+            Ok(render_data) => render_data,
+        }
+    };
+
+    timeline.mark(format!(
+        "local lookup: note {}, profile {}",
+        if render_data.needs_note() {
+            "missing"
+        } else {
+            "cached"
+        },
+        if render_data.needs_profile() {
+            "missing"
+        } else {
+            "cached"
+        },
+    ));
+
+    // fetch extra data if we are missing it
+    let mut timed_out = false;
+    let missing = MissingIds::from_render_data(&render_data);
+    metrics::record_lookup(!render_data.is_complete());
+    if missing.recently_not_found(app).await {
+        timeline.mark("not found moments ago, not asking the relays again");
+    } else if !render_data.is_complete() && format == PathFormat::Html && render_data.has_note() {
+        complete_in_background(app, nip19.clone());
+        timeline.mark("serving what we have, fetching the rest in the background");
+    } else if !render_data.is_complete() {
+        let _slot = if let Some(slot) = take_slot(&app.lookup_slots).await {
+            slot
+        } else {
+            warn!("too many relay lookups, turning away {}", r.uri().path());
+            return overloaded();
+        };
+        timeline.mark("relay fetch started");
+        let fetch_start = std::time::Instant::now();
+        let completed = render_data.complete(app, nip19.clone(), deadlines).await;
+        timeline.add_relay_wait(fetch_start.elapsed());
+        if let Err(err) = completed {
+            timed_out = matches!(err, Error::Timeout(_));
+            error!("Error fetching completion data: {err}");
+            timeline.mark(format!("relay fetch failed: {err}"));
+        } else {
+            timeline.mark("relay fetch finished");
+        }
+        missing.track(app, &render_data, &nip19, timed_out).await;
+        timeline.mark(format!(
+            "after fetch: note {}, profile {}",
+            if render_data.needs_note() {
+                "missing"
+            } else {
+                "found"
+            },
+            if render_data.needs_profile() {
+                "missing"
+            } else {
+                "found"
+            },
+        ));
+    }
+
+    // what we're still missing can't be fetched right now, say so instead
+    // of claiming it doesn't exist
+    let unavailable = !render_data.is_complete() && !app.relays.reachable();
+    if unavailable {
+        timeline.mark("relays unreachable, serving what we have");
+    }
+
+    if params.debug {
+        if let RenderData::Note(note_rd) = &render_data {
+            return debug::serve_note_debug(app, &nip19, note_rd, timeline);
+        }
+    }
+
+    if let PathFormat::Card(card_format) = format {
+        let options = render::CardOptions {
+            size: render::CardSize::new(params.width, params.height, params.scale),
+            theme: params
+                .theme
+                .as_deref()
+                .and_then(render::theme::Theme::from_name)
+                .unwrap_or_default(),
+            format: card_format,
+            qr: params.qr,
+            locale: locale.for_cards(&app.card_langs),
+        };
+
+        let key = render::card_cache_key(&app.ndb, &render_data, &options);
+        let cached = match &key {
+            Some(key) => app.cards.get(key).await,
+            None => None,
+        };
+        if let Some(data) = cached {
+            return Ok(Response::builder()
+                .header(header::CONTENT_TYPE, card_format.content_type())
+                .status(StatusCode::OK)
+                .body(Full::new(data))?);
+        }
+
+        let render_start = std::time::Instant::now();
+        let rendered = match &key {
+            Some(key) => {
+                let render = render_card(app, &render_data, &options, deadlines.render);
+                match app.inflight.run(format!("card {key}"), render).await {
+                    Some(rendered) => rendered,
+                    // someone else was rendering the same card, it's cached
+                    // unless it was missing images
+                    None => match app.cards.get(key).await {
+                        Some(data) => {
+                            return Ok(Response::builder()
+                                .header(header::CONTENT_TYPE, card_format.content_type())
+                                .status(StatusCode::OK)
+                                .body(Full::new(data))?)
+                        }
+                        None => render_card(app, &render_data, &options, deadlines.render).await,
+                    },
+                }
+            }
+            None => render_card(app, &render_data, &options, deadlines.render).await,
+        };
+        timeline.add_render(render_start.elapsed());
+        timeline.mark("card rendered");
+
+        let (status, data) = match rendered {
+            Ok((data, complete)) => {
+                let data = Bytes::from(data);
+                if let Some(key) = key.filter(|_| complete) {
+                    app.cards.put(key, data.clone()).await;
+                }
+                (StatusCode::OK, data)
+            }
+            Err(Error::Overloaded) => {
+                warn!("too many card renders, turning away {}", r.uri().path());
+                return overloaded();
+            }
+            Err(Error::NotFound) => {
+                let (status, card) = if unavailable {
+                    (StatusCode::SERVICE_UNAVAILABLE, MissingCard::Unavailable)
+                } else if timed_out {
+                    (StatusCode::NOT_FOUND, MissingCard::Timeout)
+                } else {
+                    (StatusCode::NOT_FOUND, MissingCard::NotFound)
+                };
+                (
+                    status,
+                    Bytes::from(render::render_missing(app, card, &options)),
+                )
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(Response::builder()
+            .header(header::CONTENT_TYPE, card_format.content_type())
+            .status(status)
+            .body(Full::new(data))?)
+    } else {
+        let modified = http_cache::last_modified(&app.ndb, &render_data);
+        if let Some(modified) = modified {
+            if http_cache::is_fresh(r.headers(), modified) {
+                return http_cache::not_modified(modified);
+            }
+        }
+
+        let mut response = if format == PathFormat::Json {
+            match render_data {
+                RenderData::Note(note_rd) => html::serve_note_json(&app.ndb, &note_rd)?,
+                RenderData::Profile(_profile_rd) => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Full::new(Bytes::from("todo: profile json")))?);
+                }
+            }
+        } else {
+            match render_data {
+                RenderData::Note(note_rd) => {
+                    // a bare note1 doesn't say who wrote it, send crawlers
+                    // to the one url we want indexed
+                    if let Nip19::EventId(_) = nip19 {
+                        if let Some(redirect) =
+                            html::canonical_note_redirect(&app.ndb, &note_rd, r.uri().query())?
+                        {
+                            return Ok(redirect);
+                        }
+                    }
+
+                    let page_key = html::note_page_key(app, &nip19, &note_rd, &r);
+                    let cached = match &page_key {
+                        Some(key) => app.pages.get(key).await,
+                        None => None,
+                    };
+                    if let Some(page) = cached {
+                        html::html_response(page)?
+                    } else {
+                        let urls = html::note_link_urls(&app.ndb, &note_rd);
+                        if !urls.is_empty() {
+                            let wait = wait_until(deadlines.render, LINK_PREVIEW_WAIT);
+                            app.link_previews.prefetch(urls, wait).await;
+                        }
+                        let page = timeline
+                            .time_render(|| html::note_page(app, &nip19, &note_rd, unavailable, r));
+                        match page {
+                            Ok(page) => {
+                                // pages missing data are only good until the
+                                // relays are back
+                                if let Some(key) = page_key.filter(|_| !unavailable) {
+                                    app.pages.put(key, page.clone()).await;
+                                }
+                                html::html_response(page)?
+                            }
+                            Err(Error::NotFound) => html::not_found_page("Note", unavailable)?,
+                            Err(err) => return Err(err),
+                        }
+                    }
+                }
+                RenderData::Profile(profile_rd) => timeline.time_render(|| {
+                    serve_profile_html(app, &nip19, profile_rd.as_ref(), unavailable, r)
+                })?,
+            }
+        };
+
+        if let Some(modified) = modified {
+            if response.status() == StatusCode::OK {
+                http_cache::set_last_modified(&mut response, modified);
+            }
+        }
+
+        if format == PathFormat::Html && response.status() == StatusCode::OK {
+            prerender_card(app, &nip19);
+        }
+
+        Ok(response)
+    }
+}
+
+/// Fetch the remote images and lookups a card needs, then render it.
+/// Images still missing at `deadline` are left out. Also says whether
+/// every image made it, a card missing one shouldn't be cached under a
+/// key that will never change.
+async fn render_card(
+    app: &Notecrumbs,
+    render_data: &RenderData,
+    options: &render::CardOptions,
+    deadline: Instant,
+) -> Result<(Vec<u8>, bool), Error> {
+    let (width, height) = options.size.pixels();
+    let image_url = render::profile_banner_url(&app.ndb, render_data)
+        .map(|url| (url, render::BANNER_SIZE))
+        .or_else(|| {
+            render::article_hero_url(&app.ndb, render_data).map(|url| (url, [width, height]))
+        });
+    let avatar_url = render::profile_picture_url(&app.ndb, render_data);
+    let thumbnail_url = render::note_image_url(&app.ndb, render_data);
+
+    let fetch = |url: Option<String>, size: [u32; 2]| async move {
+        match url {
+            Some(url) => {
+                app.media
+                    .fetch(&url, size, wait_until(deadline, MEDIA_WAIT))
+                    .await
+            }
+            None => None,
+        }
+    };
+    let image = match &image_url {
+        Some((url, size)) => fetch(Some(url.clone()), *size),
+        None => fetch(None, render::BANNER_SIZE),
+    };
+    let avatar = fetch(
+        avatar_url.clone(),
+        [render::AVATAR_SIZE, render::AVATAR_SIZE],
+    );
+    let thumbnail = fetch(thumbnail_url.clone(), render::THUMBNAIL_SIZE);
+    let pfp_url = render::author_picture_url(&app.ndb, render_data);
+    let pfp = async {
+        match &pfp_url {
+            Some(url) => app.pfps.fetch(url, wait_until(deadline, MEDIA_WAIT)).await,
+            None => None,
+        }
+    };
+    let nip05_verified = async {
+        match render::author_nip05(&app.ndb, render_data) {
+            Some((nip05, pubkey)) => app.nip05.verify(&nip05, &pubkey).await,
+            None => Some(false),
+        }
+    };
+    let (image, avatar, thumbnail, pfp, nip05_verified) =
+        tokio::join!(image, avatar, thumbnail, pfp, nip05_verified);
+
+    let complete = image.is_some() == image_url.is_some()
+        && avatar.is_some() == avatar_url.is_some()
+        && thumbnail.is_some() == thumbnail_url.is_some()
+        && pfp.is_some() == pfp_url.is_some()
+        // a card missing the badge because nostr.json was down shouldn't stick
+        && nip05_verified.is_some();
+    let media = render::CardMedia {
+        image,
+        avatar,
+        thumbnail,
+        pfp,
+        nip05_verified: nip05_verified.unwrap_or(false),
+        ..Default::default()
+    };
+
+    let _slot = take_slot(&app.render_slots)
+        .await
+        .ok_or(Error::Overloaded)?;
+    let data = render::render_note(app, render_data, &media, options)?;
+    Ok((data, complete))
+}
+
+/// Render the default card for a page we just served. Crawlers fetch
+/// the og:image right after the html, this makes that a cache hit.
+fn prerender_card(app: &Notecrumbs, nip19: &Nip19) {
+    let app = app.clone();
+    let nip19 = nip19.clone();
+
+    tokio::spawn(async move {
+        // crawlers rarely send an Accept-Language, they get the fallback
+        let options = render::CardOptions {
+            locale: Locale::from_posix(&app.config.card_locale).for_cards(&app.card_langs),
+            ..Default::default()
+        };
+        let render_data = match Transaction::new(&app.ndb)
+            .ok()
+            .and_then(|txn| render::get_render_data(&app.ndb, &txn, &nip19).ok())
+        {
+            Some(render_data) => render_data,
+            None => return,
+        };
+
+        let key = match render::card_cache_key(&app.ndb, &render_data, &options) {
+            Some(key) => key,
+            None => return,
+        };
+        if app.cards.contains(&key).await {
+            return;
+        }
+
+        let deadline = app.config.budget.start().render;
+        match render_card(&app, &render_data, &options, deadline).await {
+            Ok((data, true)) => app.cards.put(key, Bytes::from(data)).await,
+            Ok((_, false)) => {}
+            Err(err) => debug!("prerendering card {key} failed: {err}"),
+        }
+    });
+}
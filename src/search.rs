@@ -0,0 +1,246 @@
+use crate::{
+    fetch,
+    html::write_feed_note,
+    i18n::{Locale, Text},
+    nsec,
+    render::fetch_events_from,
+    Error, Notecrumbs,
+};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, Response, StatusCode};
+use nostr::event::kind::Kind;
+use nostr_sdk::prelude::{Client, Event, Keys, RelayUrl};
+use nostrdb::Ndb;
+use std::io::Write;
+use std::time::Duration;
+use tracing::warn;
+
+/// How many results we ask each search relay for
+const SEARCH_LIMIT: usize = 30;
+
+/// How long we wait on search relays
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long we wait for search relays' NIP-11 documents, they're
+/// usually cached
+const SEARCH_INFO_WAIT: Duration = Duration::from_millis(500);
+
+/// Longer queries are cut, nobody searches for essays
+const MAX_QUERY_LEN: usize = 200;
+
+/// A client kept connected to the search relays, so a search doesn't
+/// open new websockets to all of them
+pub struct SearchPool {
+    client: Client,
+}
+
+impl SearchPool {
+    pub fn new(keys: Keys) -> Self {
+        SearchPool {
+            client: fetch::relay_client(keys),
+        }
+    }
+
+    /// Search `relays`, connecting to the ones we aren't connected to yet
+    /// and dropping any we don't search anymore
+    async fn search(
+        &self,
+        ndb: &Ndb,
+        relays: &[String],
+        filter: nostr::Filter,
+    ) -> Result<Vec<Event>, Error> {
+        let wanted: Vec<RelayUrl> = relays
+            .iter()
+            .filter_map(|relay| RelayUrl::parse(relay).ok())
+            .collect();
+
+        for url in self.client.relays().await.into_keys() {
+            if !wanted.contains(&url) {
+                let _ = self.client.remove_relay(url).await;
+            }
+        }
+        for url in wanted {
+            let _ = self.client.add_relay(url).await;
+        }
+        self.client
+            .connect_with_timeout(Duration::from_millis(800))
+            .await;
+
+        fetch_events_from(ndb, &self.client, vec![filter], SEARCH_TIMEOUT).await
+    }
+}
+
+/// Decode an `application/x-www-form-urlencoded` value
+pub fn form_decode(value: &str) -> String {
+    fn hex(byte: u8) -> Option<u8> {
+        (byte as char).to_digit(16).map(|d| d as u8)
+    }
+
+    let bytes = value.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                (Some(hi), Some(lo)) => {
+                    out.push((hi << 4) | lo);
+                    i += 2;
+                }
+                _ => out.push(b'%'),
+            },
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Encode a value for a query string, the other way around
+pub fn form_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+/// `/search?q=`: NIP-50 full text search through search capable relays
+pub async fn serve_search(
+    app: &Notecrumbs,
+    query: &str,
+    locale: Locale,
+) -> Result<Response<Full<Bytes>>, Error> {
+    let query = form_decode(query);
+    // never send someone's private key off to the search relays
+    if nsec::contains_nsec(&query) {
+        return nsec::serve_exposed_key_page();
+    }
+    let query = crate::abbrev::abbreviate(query.trim(), MAX_QUERY_LEN);
+
+    // only ask relays the policy allows that do NIP-50, the rest would
+    // answer with whatever they have
+    let allowed = app
+        .config
+        .relay_policy
+        .filter(app.config.search_relays.clone());
+    app.relay_info.prefetch(&allowed, SEARCH_INFO_WAIT).await;
+    let search_relays: Vec<String> = allowed
+        .into_iter()
+        .filter(|relay| app.relay_info.supports_nip(relay, 50))
+        .collect();
+
+    let mut ids: Vec<[u8; 32]> = vec![];
+    if !query.is_empty() && !search_relays.is_empty() {
+        let filter = nostr::Filter::new()
+            .search(query)
+            .kind(Kind::TextNote)
+            .limit(SEARCH_LIMIT);
+
+        match app.search.search(&app.ndb, &search_relays, filter).await {
+            Ok(events) => {
+                for event in events {
+                    let id = event.id.to_bytes();
+                    if !ids.contains(&id) {
+                        ids.push(id);
+                    }
+                }
+            }
+            Err(err) => warn!("search for '{query}' failed: {err}"),
+        }
+    }
+
+    let mut data = Vec::new();
+    write!(
+        data,
+        r#"<html lang="{0}">
+<head>
+  <title>{1}</title>
+  <link rel="stylesheet" href="https://damus.io/css/notecrumbs.css" type="text/css" />
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <meta name="robots" content="noindex">
+  <meta charset="UTF-8">
+</head>
+<body>
+  <main>
+    <div class="container">
+      <form class="search-form" action="/search" method="get">
+        <input type="search" name="q" value="{2}" placeholder="{1}" />
+      </form>
+      <div class="search-results">"#,
+        locale.html_lang(),
+        locale.text(Text::SearchPlaceholder),
+        html_escape::encode_double_quoted_attribute(query)
+    )?;
+
+    if !query.is_empty() && ids.is_empty() {
+        write!(data, r#"<p>{}</p>"#, locale.text(Text::NoResults))?;
+    }
+
+    {
+        let txn = nostrdb::Transaction::new(&app.ndb)?;
+        for id in &ids {
+            let note = if let Ok(note) = app.ndb.get_note_by_id(&txn, id) {
+                note
+            } else {
+                continue;
+            };
+
+            let author = app
+                .ndb
+                .get_profile_by_pubkey(&txn, note.pubkey())
+                .ok()
+                .and_then(|pr| pr.record().profile().and_then(|p| p.name()));
+            write_feed_note(&mut data, &note, Some(author.unwrap_or("nostrich")), locale)?;
+        }
+    }
+
+    write!(
+        data,
+        r#"
+      </div>
+    </div>
+  </main>
+</body>
+</html>
+"#
+    )?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/html")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(data)))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn form_decoding() {
+        assert_eq!(form_decode("gm+nostr"), "gm nostr");
+        assert_eq!(form_decode("caf%C3%A9%20au+lait"), "café au lait");
+        assert_eq!(form_decode("%2b%2B"), "++");
+        // escapes that aren't are kept as they are
+        assert_eq!(form_decode("100%"), "100%");
+        assert_eq!(form_decode("%4"), "%4");
+        assert_eq!(form_decode("%zz%4g"), "%zz%4g");
+        // bytes that aren't utf-8 are replaced, not dropped
+        assert_eq!(form_decode("a%FFb"), "a\u{FFFD}b");
+        assert_eq!(form_decode("%E6%97"), "\u{FFFD}");
+    }
+
+    #[test]
+    fn form_round_trip() {
+        for value in ["gm nostr", "café + lait", "#nostr/ok?&=", ""] {
+            assert_eq!(form_decode(&form_encode(value)), value);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{html::note_tag_value, nip19::naddr_for_note, Error, Notecrumbs};
+use http_body_util::Full;
+use hyper::{body::Bytes, header, Response, StatusCode};
+use nostr::types::Timestamp;
+use nostrdb::{Filter, Transaction};
+use std::io::Write;
+
+/// Google News only wants articles published in the last two days
+const NEWS_WINDOW_SECS: u64 = 60 * 60 * 48;
+
+/// Google News sitemaps are capped at 1000 urls
+const NEWS_MAX_URLS: i32 = 1000;
+
+fn w3c_date(timestamp: u64) -> Option<String> {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0).map(|dt| dt.to_rfc3339())
+}
+
+/// `/sitemap-news.xml`: a Google News sitemap of recent longform articles
+pub fn serve_news_sitemap(
+    app: &Notecrumbs,
+    base_url: &str,
+) -> Result<Response<Full<Bytes>>, Error> {
+    let mut body: Vec<u8> = vec![];
+    let since = Timestamp::now().as_u64().saturating_sub(NEWS_WINDOW_SECS);
+
+    let txn = Transaction::new(&app.ndb)?;
+    let filter = Filter::new()
+        .kinds([30023])
+        .since(since)
+        .limit(NEWS_MAX_URLS as u64)
+        .build();
+    let results = app.ndb.query(&txn, &[filter], NEWS_MAX_URLS)?;
+
+    let publication = html_escape::encode_text(&app.config.news_publication);
+    let language = html_escape::encode_text(&app.config.news_language);
+
+    write!(
+        body,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9" xmlns:news="http://www.google.com/schemas/sitemap-news/0.9">
+"#
+    )?;
+
+    for result in results {
+        let note = result.note;
+
+        let naddr = if let Some(naddr) = naddr_for_note(&note) {
+            naddr
+        } else {
+            continue;
+        };
+
+        // published_at is when the article first went out, created_at is
+        // bumped on every edit
+        let published_at = note_tag_value(&note, "published_at")
+            .and_then(|ts| ts.parse::<u64>().ok())
+            .unwrap_or(note.created_at());
+
+        if published_at < since {
+            continue;
+        }
+
+        let date = if let Some(date) = w3c_date(published_at) {
+            date
+        } else {
+            continue;
+        };
+
+        let title = note_tag_value(&note, "title").unwrap_or("Untitled");
+
+        write!(
+            body,
+            r#"  <url>
+    <loc>{}/{}</loc>
+    <news:news>
+      <news:publication>
+        <news:name>{}</news:name>
+        <news:language>{}</news:language>
+      </news:publication>
+      <news:publication_date>{}</news:publication_date>
+      <news:title>{}</news:title>
+    </news:news>
+  </url>
+"#,
+            html_escape::encode_text(base_url),
+            naddr,
+            publication,
+            language,
+            date,
+            html_escape::encode_text(title),
+        )?;
+    }
+
+    writeln!(body, "</urlset>")?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+        .status(StatusCode::OK)
+        .body(Full::new(Bytes::from(body)))?)
+}
@@ -0,0 +1,42 @@
+use crate::Error;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Set up logging, and when there's an OTLP endpoint (eg: a collector at
+/// http://localhost:4317), export our spans to it as traces
+pub fn init(otlp_endpoint: Option<&str>) -> Result<(), Error> {
+    let registry = tracing_subscriber::registry()
+        .with(tracing_subscriber::filter::LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer());
+
+    let endpoint = if let Some(endpoint) = otlp_endpoint {
+        endpoint
+    } else {
+        registry.init();
+        return Ok(());
+    };
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|err| Error::Generic(format!("otlp exporter: {err}")))?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            "notecrumbs",
+        )]))
+        .build();
+    let tracer = provider.tracer("notecrumbs");
+    opentelemetry::global::set_tracer_provider(provider);
+
+    registry
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .init();
+
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use nostr::types::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info};
+
+/// Don't let a flood of garbage ids grow the store forever
+const MAX_ENTRIES: usize = 10_000;
+
+/// How often we write the store to disk, if it changed
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntityKind {
+    Note,
+    Profile,
+}
+
+/// A note or profile we went looking for and couldn't find
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnresolvedEntry {
+    /// hex event id or pubkey
+    pub id: String,
+    pub kind: EntityKind,
+    pub count: u64,
+    pub first_attempt: u64,
+    pub last_attempt: u64,
+    pub relays: BTreeSet<String>,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<String, UnresolvedEntry>,
+    dirty: bool,
+}
+
+/// Ids and pubkeys that repeatedly fail to resolve, persisted so we can
+/// spot systematic gaps (like a popular relay we don't know about)
+pub struct UnresolvedTracker {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+impl UnresolvedTracker {
+    /// Load the tracker from disk, starting fresh if there's nothing there
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+
+        let entries: Vec<UnresolvedEntry> = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|err| {
+                error!(
+                    "ignoring corrupt unresolved store {}: {err}",
+                    path.display()
+                );
+                vec![]
+            }),
+            Err(_) => vec![],
+        };
+
+        info!("loaded {} unresolved entities", entries.len());
+
+        UnresolvedTracker {
+            path,
+            state: Mutex::new(State {
+                entries: entries.into_iter().map(|e| (e.id.clone(), e)).collect(),
+                dirty: false,
+            }),
+        }
+    }
+
+    pub fn record_failure(&self, kind: EntityKind, id: &[u8; 32], relays: &[String]) {
+        let id = hex::encode(id);
+        let now = Timestamp::now().as_u64();
+        let mut state = self.state.lock().unwrap();
+
+        let entry = state
+            .entries
+            .entry(id.clone())
+            .or_insert_with(|| UnresolvedEntry {
+                id,
+                kind,
+                count: 0,
+                first_attempt: now,
+                last_attempt: now,
+                relays: BTreeSet::new(),
+            });
+
+        entry.count += 1;
+        entry.last_attempt = now;
+        entry.relays.extend(relays.iter().cloned());
+
+        if state.entries.len() > MAX_ENTRIES {
+            // forget whatever we gave up on the longest time ago
+            if let Some(oldest) = state
+                .entries
+                .values()
+                .min_by_key(|e| e.last_attempt)
+                .map(|e| e.id.clone())
+            {
+                state.entries.remove(&oldest);
+            }
+        }
+
+        state.dirty = true;
+    }
+
+    /// We found it after all, it's no longer a gap
+    pub fn record_success(&self, id: &[u8; 32]) {
+        let mut state = self.state.lock().unwrap();
+        if state.entries.remove(&hex::encode(id)).is_some() {
+            state.dirty = true;
+        }
+    }
+
+    /// Everything we couldn't resolve, most frequent first
+    pub fn entries(&self) -> Vec<UnresolvedEntry> {
+        let mut entries: Vec<UnresolvedEntry> = self
+            .state
+            .lock()
+            .unwrap()
+            .entries
+            .values()
+            .cloned()
+            .collect();
+        entries.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then(b.last_attempt.cmp(&a.last_attempt))
+        });
+        entries
+    }
+
+    fn flush(&self) {
+        let data = {
+            let mut state = self.state.lock().unwrap();
+            if !state.dirty {
+                return;
+            }
+            state.dirty = false;
+            let entries: Vec<&UnresolvedEntry> = state.entries.values().collect();
+            serde_json::to_vec(&entries)
+        };
+
+        let data = match data {
+            Ok(data) => data,
+            Err(err) => {
+                error!("failed to serialize unresolved store: {err}");
+                return;
+            }
+        };
+
+        // write then rename so a crash never leaves a half written store
+        let tmp = self.path.with_extension("tmp");
+        if let Err(err) = std::fs::write(&tmp, data).and_then(|_| std::fs::rename(&tmp, &self.path))
+        {
+            error!("failed to write {}: {err}", self.path.display());
+        }
+    }
+
+    /// Periodically persist the store
+    pub async fn flush_loop(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let tracker = self.clone();
+            let _ = tokio::task::spawn_blocking(move || tracker.flush()).await;
+        }
+    }
+}
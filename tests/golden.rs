@@ -0,0 +1,203 @@
+//! Renders fixture notes and profiles to png cards and compares them
+//! with the images in `tests/golden`, so layout and font regressions
+//! (text overflowing the card, a fallback font going missing) show up.
+//!
+//! A missing golden image fails the test. Run with `NOTECRUMBS_BLESS=1`
+//! to write them, and after an intended change to the cards to replace
+//! them, then check the new images in. Blessing is refused when `CI` is
+//! set. Failed comparisons leave
+//! the rendered card and a diff under cargo's target tmp dir.
+
+mod support;
+
+use image::{Rgba, RgbaImage};
+use nostr_sdk::prelude::*;
+use std::path::PathBuf;
+use support::TestApp;
+
+/// Channel difference below which pixels count as the same, anti-aliasing
+/// and rounding move edges around by a little
+const PIXEL_TOLERANCE: u8 = 24;
+
+/// The fraction of pixels that may differ by more than that
+const MAX_DIFFERING: f64 = 0.002;
+
+/// Fixtures need stable ids and authors, or the cards would change
+const FIXTURE_SECRET: &str = "6c36b4b3ec4fa8fe1e3bc4d5e8c2f5a3f1d0a9e8b7c6d5e4f3a2b1c0d9e8f7a6";
+
+/// 2023-11-14, so the card dates don't move either
+const FIXTURE_TIME: u64 = 1_700_000_000;
+
+fn fixture_keys() -> Keys {
+    Keys::parse(FIXTURE_SECRET).unwrap()
+}
+
+fn fixture_profile(keys: &Keys) -> Event {
+    let metadata = Metadata::new()
+        .name("golden")
+        .display_name("Golden Fixture")
+        .about("Renders the same card every time");
+    EventBuilder::metadata(&metadata)
+        .custom_created_at(Timestamp::from(FIXTURE_TIME))
+        .sign_with_keys(keys)
+        .unwrap()
+}
+
+fn fixture_note(keys: &Keys, content: &str) -> Event {
+    EventBuilder::text_note(content)
+        .custom_created_at(Timestamp::from(FIXTURE_TIME))
+        .sign_with_keys(keys)
+        .unwrap()
+}
+
+fn nevent(note: &Event) -> String {
+    Nip19Event::new(note.id, Vec::<String>::new())
+        .author(note.pubkey)
+        .to_bech32()
+        .unwrap()
+}
+
+/// Render `path` and compare it with `tests/golden/{name}.png`
+async fn assert_golden(app: &TestApp, path: &str, name: &str) {
+    let (status, png) = app.get_bytes(path).await;
+    assert_eq!(status, 200, "{path}: {}", String::from_utf8_lossy(&png));
+    let actual = image::load_from_memory(&png)
+        .unwrap_or_else(|err| panic!("{path} is not an image: {err}"))
+        .to_rgba8();
+
+    let golden_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"));
+    let bless = std::env::var("NOTECRUMBS_BLESS").is_ok_and(|bless| bless == "1");
+    // CI has to compare against what's checked in, never write it
+    assert!(
+        !(bless && std::env::var_os("CI").is_some()),
+        "NOTECRUMBS_BLESS=1 is for blessing images locally, not in CI"
+    );
+    if bless {
+        std::fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        actual.save(&golden_path).unwrap();
+        eprintln!("wrote golden image {}", golden_path.display());
+        return;
+    }
+    assert!(
+        golden_path.exists(),
+        "{} is missing, run with NOTECRUMBS_BLESS=1 to write it and check it in",
+        golden_path.display()
+    );
+
+    let golden = image::open(&golden_path).unwrap().to_rgba8();
+    if let Err(problem) = compare(&golden, &actual, name) {
+        panic!("{name} doesn't match {}: {problem}", golden_path.display());
+    }
+}
+
+/// Compare a rendered card with its golden image, within the tolerances.
+/// On a mismatch the card and a diff (differing pixels in red) are saved
+/// for a look.
+fn compare(golden: &RgbaImage, actual: &RgbaImage, name: &str) -> Result<(), String> {
+    if golden.dimensions() != actual.dimensions() {
+        save_failure(name, actual, None);
+        return Err(format!(
+            "rendered {:?}, expected {:?}",
+            actual.dimensions(),
+            golden.dimensions()
+        ));
+    }
+
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut differing = 0u64;
+    for (x, y, pixel) in actual.enumerate_pixels() {
+        let expected = golden.get_pixel(x, y);
+        let distance = pixel
+            .0
+            .iter()
+            .zip(expected.0)
+            .map(|(a, b)| a.abs_diff(b))
+            .max()
+            .unwrap_or(0);
+
+        if distance > PIXEL_TOLERANCE {
+            differing += 1;
+            diff.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+        } else {
+            // faded, so the differences stand out
+            let luma = expected.0[..3].iter().map(|c| *c as u32).sum::<u32>() / 3;
+            let faded = (255 - (255 - luma) / 4) as u8;
+            diff.put_pixel(x, y, Rgba([faded, faded, faded, 255]));
+        }
+    }
+
+    let (width, height) = actual.dimensions();
+    let fraction = differing as f64 / (width as u64 * height as u64) as f64;
+    if fraction > MAX_DIFFERING {
+        save_failure(name, actual, Some(&diff));
+        return Err(format!(
+            "{differing} pixels ({:.2}%) differ, at most {:.2}% may",
+            fraction * 100.0,
+            MAX_DIFFERING * 100.0
+        ));
+    }
+
+    Ok(())
+}
+
+fn save_failure(name: &str, actual: &RgbaImage, diff: Option<&RgbaImage>) {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&dir).unwrap();
+    actual.save(dir.join(format!("{name}.actual.png"))).unwrap();
+    if let Some(diff) = diff {
+        diff.save(dir.join(format!("{name}.diff.png"))).unwrap();
+    }
+    eprintln!("rendered card and diff are in {}", dir.display());
+}
+
+#[tokio::test]
+async fn short_note_card() {
+    let app = TestApp::start().await;
+    let keys = fixture_keys();
+    let note = fixture_note(&keys, "gm nostr, this card should never change");
+    app.relay.add_event(fixture_profile(&keys));
+    app.relay.add_event(note.clone());
+
+    assert_golden(&app, &format!("/{}.png", nevent(&note)), "short_note").await;
+}
+
+#[tokio::test]
+async fn long_note_card() {
+    let app = TestApp::start().await;
+    let keys = fixture_keys();
+    let content =
+        "A long note has to be cut off before it runs past the bottom of the card. ".repeat(20);
+    let note = fixture_note(&keys, &content);
+    app.relay.add_event(fixture_profile(&keys));
+    app.relay.add_event(note.clone());
+
+    assert_golden(&app, &format!("/{}.png", nevent(&note)), "long_note").await;
+}
+
+#[tokio::test]
+async fn dark_note_card() {
+    let app = TestApp::start().await;
+    let keys = fixture_keys();
+    let note = fixture_note(&keys, "the same card, with the lights off");
+    app.relay.add_event(fixture_profile(&keys));
+    app.relay.add_event(note.clone());
+
+    assert_golden(
+        &app,
+        &format!("/{}.png?theme=dark", nevent(&note)),
+        "dark_note",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn profile_card() {
+    let app = TestApp::start().await;
+    let keys = fixture_keys();
+    app.relay.add_event(fixture_profile(&keys));
+
+    let npub = keys.public_key().to_bech32().unwrap();
+    assert_golden(&app, &format!("/{npub}.png"), "profile").await;
+}
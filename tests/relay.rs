@@ -0,0 +1,171 @@
+//! End to end lookups against the in-memory relay in `support`
+
+mod support;
+
+use nostr_sdk::prelude::*;
+use std::time::Duration;
+use support::{MockRelay, TestApp};
+
+fn profile(keys: &Keys, name: &str) -> Event {
+    EventBuilder::metadata(&Metadata::new().name(name))
+        .sign_with_keys(keys)
+        .unwrap()
+}
+
+fn text_note(keys: &Keys, content: &str) -> Event {
+    EventBuilder::text_note(content)
+        .sign_with_keys(keys)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn mock_relay_answers_reqs() {
+    let relay = MockRelay::start().await;
+    let keys = Keys::generate();
+    let note = text_note(&keys, "hello mock relay");
+    relay.add_event(note.clone());
+    relay.add_event(text_note(&Keys::generate(), "someone else"));
+
+    let client = Client::default();
+    client.add_relay(relay.url()).await.unwrap();
+    client.connect().await;
+
+    let events = client
+        .fetch_events(
+            vec![Filter::new().author(keys.public_key())],
+            Duration::from_secs(2),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        events.into_iter().map(|event| event.id).collect::<Vec<_>>(),
+        vec![note.id]
+    );
+}
+
+#[tokio::test]
+async fn missing_note_is_fetched_from_relays() {
+    let app = TestApp::start().await;
+    let keys = Keys::generate();
+    let note = text_note(&keys, "a note only the mock relay has");
+    app.relay.add_event(profile(&keys, "mock author"));
+    app.relay.add_event(note.clone());
+
+    let nevent = Nip19Event::new(note.id, Vec::<String>::new())
+        .author(keys.public_key())
+        .to_bech32()
+        .unwrap();
+
+    let (status, body) = app.get(&format!("/{nevent}.json")).await;
+    assert_eq!(status, 200, "{body}");
+    assert!(body.contains("a note only the mock relay has"), "{body}");
+
+    // it's in ndb now, along with its author
+    let (status, body) = app.get(&format!("/{nevent}")).await;
+    assert_eq!(status, 200, "{body}");
+    assert!(body.contains("mock author"), "{body}");
+}
+
+#[tokio::test]
+async fn missing_profile_is_fetched_from_relays() {
+    let app = TestApp::start().await;
+    let keys = Keys::generate();
+    app.relay.add_event(profile(&keys, "relay only profile"));
+
+    let npub = keys.public_key().to_bech32().unwrap();
+    let (status, body) = app.get(&format!("/{npub}")).await;
+    assert_eq!(status, 200, "{body}");
+    assert!(body.contains("relay only profile"), "{body}");
+}
+
+#[tokio::test]
+async fn profile_feed_is_backfilled() {
+    let app = TestApp::start().await;
+    let keys = Keys::generate();
+    app.relay.add_event(profile(&keys, "feed author"));
+    app.relay
+        .add_event(text_note(&keys, "first backfilled note"));
+    app.relay
+        .add_event(text_note(&keys, "second backfilled note"));
+
+    // the first view schedules the backfill, later ones see its notes
+    let npub = keys.public_key().to_bech32().unwrap();
+    let feed = app
+        .get_until(
+            &format!("/{npub}/rss"),
+            "second backfilled note",
+            Duration::from_secs(15),
+        )
+        .await;
+    assert!(feed.contains("first backfilled note"), "{feed}");
+    assert!(feed.contains("second backfilled note"), "{feed}");
+}
+
+#[tokio::test]
+async fn note_json_has_thread_context() {
+    let app = TestApp::start().await;
+    let keys = Keys::generate();
+    let root = text_note(&keys, "the start of a thread");
+    let reply = EventBuilder::text_note_reply("a reply to it", &root, None, None)
+        .sign_with_keys(&keys)
+        .unwrap();
+    app.relay.add_event(profile(&keys, "thread author"));
+    app.relay.add_event(root.clone());
+    app.relay.add_event(reply.clone());
+
+    let nevent = |note: &Event| {
+        Nip19Event::new(note.id, Vec::<String>::new())
+            .author(note.pubkey)
+            .to_bech32()
+            .unwrap()
+    };
+
+    // fetch both so they're cached
+    let (status, _) = app.get(&format!("/{}.json", nevent(&root))).await;
+    assert_eq!(status, 200);
+    let (status, body) = app.get(&format!("/{}.json", nevent(&reply))).await;
+    assert_eq!(status, 200, "{body}");
+
+    let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(json["parent"]["id"], root.id.to_hex(), "{body}");
+    assert_eq!(json["root"]["id"], root.id.to_hex(), "{body}");
+    assert_eq!(json["parent"]["note"]["content"], "the start of a thread");
+    assert!(json["parent"]["parsed_content"].is_array(), "{body}");
+
+    let (_, body) = app.get(&format!("/{}.json", nevent(&root))).await;
+    let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert!(json["parent"].is_null(), "{body}");
+    assert_eq!(
+        json["replies"][0]["note"]["id"],
+        reply.id.to_hex(),
+        "{body}"
+    );
+}
+
+#[tokio::test]
+async fn note1_author_profile_is_fetched() {
+    let app = TestApp::start().await;
+    let keys = Keys::generate();
+    let note = text_note(&keys, "a note1 doesn't say who wrote it");
+    app.relay.add_event(profile(&keys, "unhinted author"));
+    app.relay.add_event(note.clone());
+
+    let note1 = note.id.to_bech32().unwrap();
+    let (status, body) = app.get(&format!("/{note1}.json")).await;
+    assert_eq!(status, 200, "{body}");
+    assert!(body.contains("unhinted author"), "{body}");
+}
+
+#[tokio::test]
+async fn blocked_relay_page_is_not_found() {
+    let app = TestApp::start_with(|config| {
+        config.relay_policy.block = vec!["blocked.example".to_string()];
+    })
+    .await;
+
+    let (status, _) = app.get("/relay/blocked.example").await;
+    assert_eq!(status, 404);
+    let (status, _) = app.get("/relay/wss%3A%2F%2Frelay.blocked.example").await;
+    assert_eq!(status, 404);
+}
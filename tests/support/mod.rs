@@ -0,0 +1,210 @@
+//! Test support: a minimal nostr relay kept in memory, and a notecrumbs
+//! instance that only talks to it, so tests never hit the public network
+
+// every test crate builds this module, and uses a different part of it
+#![allow(dead_code)]
+
+use nostr_sdk::async_utility::futures_util::{SinkExt, StreamExt};
+use nostr_sdk::prelude::{Event, Filter};
+use notecrumbs::{config::Config, Notecrumbs};
+use serde_json::{json, Value};
+use std::cmp::Reverse;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Speaks just enough of NIP-01 for our lookups: REQs are answered from
+/// the events it holds followed by EOSE, EVENTs are stored. Negentropy
+/// (NIP-77) is refused so syncs fall back to plain REQs.
+pub struct MockRelay {
+    url: String,
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl MockRelay {
+    pub async fn start() -> MockRelay {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind mock relay");
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let events = Arc::new(Mutex::new(vec![]));
+
+        let relay_events = events.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_client(stream, relay_events.clone()));
+            }
+        });
+
+        MockRelay { url, events }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn add_event(&self, event: Event) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+async fn serve_client(stream: TcpStream, events: Arc<Mutex<Vec<Event>>>) {
+    let ws = match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(_) => return,
+    };
+    let (mut sink, mut messages) = ws.split();
+
+    while let Some(Ok(message)) = messages.next().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        for reply in handle_message(&text, &events) {
+            if sink.send(Message::Text(reply.to_string())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn handle_message(text: &str, events: &Mutex<Vec<Event>>) -> Vec<Value> {
+    let message: Vec<Value> = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(_) => return vec![json!(["NOTICE", "could not parse message"])],
+    };
+    if message.len() < 2 {
+        return vec![json!(["NOTICE", "message is too short"])];
+    }
+    let sub_id = &message[1];
+
+    match message.first().and_then(Value::as_str) {
+        Some("REQ") => {
+            let filters: Vec<Filter> = message
+                .iter()
+                .skip(2)
+                .filter_map(|filter| serde_json::from_value(filter.clone()).ok())
+                .collect();
+            let mut replies: Vec<Value> = matching(&events.lock().unwrap(), &filters)
+                .into_iter()
+                .map(|event| json!(["EVENT", sub_id, event]))
+                .collect();
+            replies.push(json!(["EOSE", sub_id]));
+            replies
+        }
+        Some("EVENT") => match serde_json::from_value::<Event>(message[1].clone()) {
+            Ok(event) => {
+                let id = event.id.to_hex();
+                events.lock().unwrap().push(event);
+                vec![json!(["OK", id, true, ""])]
+            }
+            Err(_) => vec![json!(["NOTICE", "invalid event"])],
+        },
+        Some("NEG-OPEN") => vec![json!(["NEG-ERR", sub_id, "negentropy is not supported"])],
+        // CLOSE, we don't keep subscriptions open anyway
+        _ => vec![],
+    }
+}
+
+/// The events matching any of the filters, newest first within each
+/// filter's limit, each event once
+fn matching(events: &[Event], filters: &[Filter]) -> Vec<Event> {
+    let mut found: Vec<Event> = vec![];
+
+    for filter in filters {
+        let mut matches: Vec<&Event> = events
+            .iter()
+            .filter(|event| filter.match_event(event))
+            .collect();
+        matches.sort_by_key(|event| Reverse(event.created_at));
+
+        for event in matches.into_iter().take(filter.limit.unwrap_or(usize::MAX)) {
+            if !found.iter().any(|seen| seen.id == event.id) {
+                found.push(event.clone());
+            }
+        }
+    }
+
+    found
+}
+
+/// A notecrumbs served on a local port, with the mock relay as its only
+/// relay and everything it writes kept in a temporary directory
+pub struct TestApp {
+    pub relay: MockRelay,
+    base_url: String,
+    _dir: tempfile::TempDir,
+}
+
+impl TestApp {
+    pub async fn start() -> TestApp {
+        TestApp::start_with(|_| {}).await
+    }
+
+    /// Start with a config changed by `configure`
+    pub async fn start_with(configure: impl FnOnce(&mut Config)) -> TestApp {
+        let relay = MockRelay::start().await;
+        let dir = tempfile::tempdir().expect("temp dir");
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+
+        let mut config = Config {
+            relays: vec![relay.url().to_string()],
+            search_relays: vec![relay.url().to_string()],
+            ndb_dir: path("db"),
+            pfp_cache_dir: path("pfp-cache"),
+            font_cache_dir: path("font-cache"),
+            unresolved_path: path("unresolved.json"),
+            ..Config::default()
+        };
+        configure(&mut config);
+        std::fs::create_dir_all(&config.ndb_dir).expect("create db dir");
+
+        let app = Notecrumbs::builder(config)
+            .build()
+            .await
+            .expect("build notecrumbs");
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind notecrumbs");
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(app.serve(listener, None));
+
+        TestApp {
+            relay,
+            base_url,
+            _dir: dir,
+        }
+    }
+
+    /// The status and body of a GET
+    pub async fn get(&self, path: &str) -> (u16, String) {
+        let (status, body) = self.get_bytes(path).await;
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// The status and raw body of a GET, for images
+    pub async fn get_bytes(&self, path: &str) -> (u16, Vec<u8>) {
+        let response = reqwest::get(format!("{}{path}", self.base_url))
+            .await
+            .expect("request notecrumbs");
+        let status = response.status().as_u16();
+        let body = response.bytes().await.unwrap_or_default();
+        (status, body.to_vec())
+    }
+
+    /// GET until the body has `needle` in it, for things that are fetched
+    /// in the background. Returns the last body either way.
+    pub async fn get_until(&self, path: &str, needle: &str, wait: Duration) -> String {
+        let deadline = Instant::now() + wait;
+        loop {
+            let (_, body) = self.get(path).await;
+            if body.contains(needle) || Instant::now() > deadline {
+                return body;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+}